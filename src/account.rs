@@ -1,11 +1,15 @@
-struct Account {
+use crate::encoding::{self, Decode, DecodeError, Encode, Reader};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
     address: String,
     balance: u64,
     nonce: u64,
 }
 //Need to scan the chain for information about the account
 impl Account {
-    fn new(address: String) -> Self {
+    pub fn new(address: String) -> Self {
         Account {
             address,
             balance: 0,
@@ -13,25 +17,75 @@ impl Account {
         }
     }
 
-    fn update_balance(&mut self, amount: i64) {
-        self.balance = (self.balance as i64 + amount) as u64;
+    // Adds `amount` to the balance, failing if it would overflow
+    pub fn credit(&mut self, amount: u64) -> Result<(), String> {
+        self.balance = self
+            .balance
+            .checked_add(amount)
+            .ok_or_else(|| format!("balance of {} would overflow", self.address))?;
+        Ok(())
+    }
+
+    // Takes `amount` off the balance, failing if the balance is too low
+    pub fn debit(&mut self, amount: u64) -> Result<(), String> {
+        self.balance = self.balance.checked_sub(amount).ok_or_else(|| {
+            format!(
+                "{} has a balance of {}, needs {}",
+                self.address, self.balance, amount
+            )
+        })?;
+        Ok(())
+    }
+
+    pub fn set_nonce(&mut self, nonce: u64) {
+        self.nonce = nonce;
     }
 
     // Increments the nonce
-    fn increment_nonce(&mut self) {
+    pub fn increment_nonce(&mut self) {
         self.nonce += 1;
     }
 
     // Validates if a transaction can be processed
-    fn validate_transaction(&self, transaction_amount: u64, transaction_nonce: u64) -> bool {
+    pub fn validate_transaction(&self, transaction_amount: u64, transaction_nonce: u64) -> bool {
         self.balance >= transaction_amount && self.nonce == transaction_nonce
     }
 
     // Displays account information
-    fn display_info(&self) {
+    pub fn display_info(&self) {
         println!(
             "Address: {}\nBalance: {}\nNonce: {}",
             self.address, self.balance, self.nonce
         );
     }
+
+    pub fn get_address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn get_balance(&self) -> u64 {
+        self.balance
+    }
+
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
+impl Encode for Account {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encoding::put_str(out, &self.address);
+        encoding::put_u64(out, self.balance);
+        encoding::put_u64(out, self.nonce);
+    }
+}
+
+impl Decode for Account {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Account {
+            address: reader.read_string()?,
+            balance: reader.read_u64()?,
+            nonce: reader.read_u64()?,
+        })
+    }
 }
@@ -0,0 +1,188 @@
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bumped whenever the layout of the address book file changes.
+const ADDRESS_BOOK_VERSION: u32 = 1;
+// Peers remembered at most; the ones seen longest ago make room first.
+const MAX_PEERS: usize = 1000;
+const MAX_ADDRESSES_PER_PEER: usize = 8;
+
+/// Peers we've talked to and where they listen, kept across restarts so a
+/// node can find its way back into the network without being told where.
+#[derive(Debug, Clone, Default)]
+pub struct AddressBook {
+    peers: HashMap<PeerId, PeerRecord>,
+}
+
+#[derive(Debug, Clone)]
+struct PeerRecord {
+    addresses: Vec<Multiaddr>, // Most recently learned first.
+    last_seen: u64,            // Unix time we last heard of the peer.
+}
+
+#[derive(Serialize, Deserialize)]
+struct AddressBookFile {
+    version: u32,
+    peers: Vec<PeerEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PeerEntry {
+    peer_id: String,
+    addresses: Vec<String>,
+    last_seen: u64,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `peer` listens on `address`, returning whether the
+    /// address is new. A trailing `/p2p/<peer id>` is dropped from it.
+    pub fn add_address(&mut self, peer: PeerId, address: Multiaddr) -> bool {
+        let mut address = address;
+        if let Some(Protocol::P2p(_)) = address.iter().last() {
+            address.pop();
+        }
+        if !self.peers.contains_key(&peer) && self.peers.len() >= MAX_PEERS {
+            self.evict_oldest();
+        }
+
+        let record = self.peers.entry(peer).or_insert(PeerRecord {
+            addresses: Vec::new(),
+            last_seen: 0,
+        });
+        record.last_seen = now();
+        let is_new = !record.addresses.contains(&address);
+        record.addresses.retain(|known| *known != address);
+        record.addresses.insert(0, address);
+        record.addresses.truncate(MAX_ADDRESSES_PER_PEER);
+        is_new
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) -> bool {
+        self.peers.remove(peer).is_some()
+    }
+
+    pub fn get_addresses(&self, peer: &PeerId) -> &[Multiaddr] {
+        self.peers
+            .get(peer)
+            .map(|record| record.addresses.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Every known peer with its addresses, most recently seen first.
+    pub fn get_peers(&self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let mut peers: Vec<(&PeerId, &PeerRecord)> = self.peers.iter().collect();
+        peers.sort_by(|a, b| b.1.last_seen.cmp(&a.1.last_seen).then(a.0.cmp(b.0)));
+        peers
+            .into_iter()
+            .map(|(peer, record)| (*peer, record.addresses.clone()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Writes the address book to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut peers: Vec<PeerEntry> = self
+            .peers
+            .iter()
+            .map(|(peer, record)| PeerEntry {
+                peer_id: peer.to_string(),
+                addresses: record.addresses.iter().map(|a| a.to_string()).collect(),
+                last_seen: record.last_seen,
+            })
+            .collect();
+        peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        let file = AddressBookFile {
+            version: ADDRESS_BOOK_VERSION,
+            peers,
+        };
+        let data = serde_json::to_vec(&file)?;
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Reads an address book written by [`AddressBook::save`]. A missing or
+    /// unreadable file yields an empty book, and entries that don't parse
+    /// are skipped, so a bad file never keeps the node from starting.
+    pub fn load(path: &Path) -> Self {
+        let mut book = AddressBook::new();
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("ignoring unreadable address book: {}", e);
+                }
+                return book;
+            }
+        };
+        let file: AddressBookFile = match serde_json::from_slice(&data) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("ignoring unreadable address book: {}", e);
+                return book;
+            }
+        };
+        if file.version != ADDRESS_BOOK_VERSION {
+            warn!("ignoring address book with version {}", file.version);
+            return book;
+        }
+
+        for entry in file.peers.into_iter().take(MAX_PEERS) {
+            let Ok(peer) = entry.peer_id.parse::<PeerId>() else {
+                continue;
+            };
+            let addresses: Vec<Multiaddr> = entry
+                .addresses
+                .iter()
+                .filter_map(|address| address.parse().ok())
+                .take(MAX_ADDRESSES_PER_PEER)
+                .collect();
+            if !addresses.is_empty() {
+                book.peers.insert(
+                    peer,
+                    PeerRecord {
+                        addresses,
+                        last_seen: entry.last_seen,
+                    },
+                );
+            }
+        }
+        book
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(oldest) = self
+            .peers
+            .iter()
+            .min_by_key(|(_, record)| record.last_seen)
+            .map(|(peer, _)| *peer)
+        {
+            self.peers.remove(&oldest);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
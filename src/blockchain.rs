@@ -1,28 +1,105 @@
-use crate::transaction::Transaction;
+use crate::chain_spec::ChainSpec;
+use crate::encoding::{self, Decode, DecodeError, Encode, Reader};
+use crate::events::{EventBus, NodeEvent};
+use crate::index::{ChainIndex, TxLocation};
+use crate::mempool::Mempool;
+use crate::snapshot::StateSnapshot;
+use crate::state::WorldState;
+use crate::storage::{BlockStore, StorageError};
+use crate::transaction::{Transaction, COINBASE_SIGNATURE};
 use log::{error, info};
-use rs_merkle::{algorithms::Sha256 as mk_Sha256, Hasher, MerkleTree};
+use rs_merkle::{algorithms::Sha256 as mk_Sha256, MerkleTree};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256 as Sha2_256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 
-#[derive(Clone)]
-pub struct Block {
+// Blocks added between two saves of the chain index. Blocks added since the
+// last save are indexed again when the chain is opened.
+const INDEX_SAVE_INTERVAL: usize = 64;
+
+/// Fields committed to by the block hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
     index: u128,
     timestamp: u64,
     merkle_root: [u8; 32],
-    data: Vec<Transaction>,
+    state_root: [u8; 32], // Commits to the world state after applying the block.
     previous_hash: String,
-    hash: String,
+    difficulty: u32,
     nonce: u64,
 }
 
+/// Transactions carried by a block, committed to by the header's merkle root.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockBody {
+    transactions: Vec<Transaction>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Block {
+    header: BlockHeader,
+    body: BlockBody,
+    hash: String,
+}
+
 /// Represents a blockchain.
 #[derive(Clone)]
 pub struct Blockchain {
-    chain: Vec<Block>, // The chain of blocks in the blockchain.
-    difficulty: usize, // The difficulty level for mining new blocks.
+    chain: Vec<Block>,                         // The chain of blocks in the blockchain.
+    spec: ChainSpec,                           // Consensus parameters, including the difficulty.
+    state: WorldState,                         // Account balances and nonces at the tip.
+    store: Option<Arc<Mutex<dyn BlockStore>>>, // Where blocks are written through to, if anywhere.
+    index: ChainIndex,                         // Transaction, address and block hash lookups.
+    index_path: Option<PathBuf>,               // Where the index is persisted, if anywhere.
+    base_snapshot: Option<StateSnapshot>,      // State at the newest block without a body, if any.
+    snapshot_policy: Option<(PathBuf, u64)>,   // Directory and interval of periodic snapshots.
+    pruning: PruningMode,                      // How many block bodies are kept.
+    bus: Option<EventBus>,                     // Where chain changes are announced, if anywhere.
+}
+
+/// How much history a node keeps around.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PruningMode {
+    /// Keeps every block body and can replay the state from genesis.
+    #[default]
+    Archive,
+    /// Keeps every header but only the bodies, and with them the state
+    /// history, of the last `keep_blocks` blocks.
+    Pruned { keep_blocks: u64 },
+}
+
+/// Proof that a transaction is committed to by the merkle root of the
+/// block it's in, checkable without the rest of the block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionProof {
+    pub transaction_id: String,
+    pub block_hash: String,
+    pub height: u64,
+    pub position: usize,       // Index of the transaction in the block.
+    pub leaf_count: usize,     // Transactions in the block.
+    pub merkle_root: [u8; 32], // Root the proof leads up to.
+    pub hashes: Vec<[u8; 32]>, // Sibling hashes on the way up.
+}
+
+impl TransactionProof {
+    /// Checks that the sibling hashes lead from the transaction to the root.
+    pub fn verify(&self) -> bool {
+        let mut leaf = [0u8; 32];
+        if hex::decode_to_slice(&self.transaction_id, &mut leaf).is_err() {
+            return false;
+        }
+        rs_merkle::MerkleProof::<mk_Sha256>::new(self.hashes.clone()).verify(
+            self.merkle_root,
+            &[self.position],
+            &[leaf],
+            self.leaf_count,
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -32,6 +109,9 @@ pub enum BlockchainError {
     MerkleProofError(String),
     ChainInvalid,
     TransactionNotFound,
+    BlockNotFound,
+    Pruned(u64),
+    Storage(String),
 }
 
 impl std::fmt::Display for BlockchainError {
@@ -44,6 +124,15 @@ impl std::fmt::Display for BlockchainError {
             }
             BlockchainError::ChainInvalid => write!(f, "Blockchain is invalid"),
             BlockchainError::TransactionNotFound => write!(f, "Transaction not found"),
+            BlockchainError::BlockNotFound => write!(f, "Block not found"),
+            BlockchainError::Pruned(height) => {
+                write!(
+                    f,
+                    "Block {} has been pruned, only its header is kept",
+                    height
+                )
+            }
+            BlockchainError::Storage(ref err) => write!(f, "Storage error: {}", err),
         }
     }
 }
@@ -53,53 +142,215 @@ impl std::error::Error for BlockchainError {}
 impl Blockchain {
     /// Creates a new instance of the blockchain with a genesis block.
     pub fn new() -> Blockchain {
+        Blockchain::with_spec(ChainSpec::default())
+    }
+
+    /// Creates a new blockchain following the consensus rules in `spec`.
+    pub fn with_spec(spec: ChainSpec) -> Blockchain {
+        let genesis_block = Blockchain::create_genesis_block(&spec);
+        let state = WorldState::from_blocks(std::slice::from_ref(&genesis_block))
+            .expect("the genesis block only mints");
+        let index = ChainIndex::from_blocks(std::slice::from_ref(&genesis_block));
         Blockchain {
-            chain: vec![Blockchain::create_genesis_block()],
-            difficulty: 4,
+            chain: vec![genesis_block],
+            spec,
+            state,
+            store: None,
+            index,
+            index_path: None,
+            base_snapshot: None,
+            snapshot_policy: None,
+            pruning: PruningMode::Archive,
+            bus: None,
         }
     }
 
-    /// Creates the genesis block of the blockchain.
-    fn create_genesis_block() -> Block {
-        let mut genesis_block = Block {
+    /// Opens the blockchain kept in `store`, writing a genesis block first if
+    /// the store is empty. Every later block is written through to the store.
+    pub fn open(store: impl BlockStore + 'static) -> Result<Blockchain, BlockchainError> {
+        Blockchain::open_with_spec(ChainSpec::default(), store)
+    }
+
+    /// Like [`Blockchain::open`] for a chain following `spec`.
+    pub fn open_with_spec(
+        spec: ChainSpec,
+        store: impl BlockStore + 'static,
+    ) -> Result<Blockchain, BlockchainError> {
+        let mut store = store;
+        let blocks = store
+            .load_blocks()
+            .map_err(|e| BlockchainError::Storage(e.to_string()))?;
+
+        // Reuse the persisted index when it was saved at one of the stored
+        // blocks; only the blocks after that one are indexed again.
+        let index_path = store.index_path();
+        let persisted_index = index_path.as_deref().and_then(ChainIndex::load);
+        let indexed_height = persisted_index.as_ref().and_then(|index| {
+            let tip = index.tip()?;
+            let height = index.get_block_height(tip)?;
+            let block = blocks.get(height as usize)?;
+            (block.get_hash() == tip).then_some(height)
+        });
+
+        // Blocks up to a bootstrap snapshot are stored without their bodies.
+        let base_snapshot = match store.snapshot_path().filter(|path| path.exists()) {
+            Some(path) => Some(
+                StateSnapshot::load(&path).map_err(|e| BlockchainError::Storage(e.to_string()))?,
+            ),
+            None => None,
+        };
+
+        let mut blockchain = Blockchain::with_spec(spec);
+        if blocks.is_empty() {
+            store
+                .append_block(&blockchain.chain[0])
+                .map_err(|e| BlockchainError::Storage(e.to_string()))?;
+        } else {
+            // A data directory of another network or genesis isn't ours to
+            // build on.
+            let genesis = blockchain.chain[0].get_hash();
+            if blocks[0].get_hash() != genesis {
+                return Err(BlockchainError::BlockInvalid(format!(
+                    "stored genesis block {} doesn't match the chain spec's {}",
+                    blocks[0].get_hash(),
+                    genesis
+                )));
+            }
+            blockchain.chain.clear();
+            blockchain.state = WorldState::new();
+            blockchain.index = persisted_index
+                .filter(|_| indexed_height.is_some())
+                .unwrap_or_default();
+        }
+        for block in blocks {
+            let height = blockchain.chain.len() as u64;
+            match &base_snapshot {
+                Some(snapshot) if height <= snapshot.get_height() => {
+                    if let Some(parent) = blockchain.chain.last() {
+                        blockchain.validate_header_on(block.get_header(), parent.get_header())?;
+                    }
+                    if height == snapshot.get_height() {
+                        snapshot
+                            .verify(block.get_header())
+                            .map_err(|e| BlockchainError::Storage(e.to_string()))?;
+                        blockchain.state = snapshot.get_state().clone();
+                    }
+                }
+                _ => blockchain.state = blockchain.validate_next_block(&block)?,
+            }
+            let update_index = indexed_height.is_none_or(|indexed| height > indexed);
+            blockchain.connect_block(block, update_index);
+        }
+        if let Some(snapshot) = &base_snapshot {
+            if snapshot.get_height() >= blockchain.chain.len() as u64 {
+                return Err(BlockchainError::Storage(
+                    "state snapshot is above the stored blocks".into(),
+                ));
+            }
+        }
+        blockchain.base_snapshot = base_snapshot;
+
+        info!("loaded {} blocks from storage", blockchain.chain.len());
+        blockchain.store = Some(Arc::new(Mutex::new(store)));
+        blockchain.index_path = index_path;
+        if indexed_height != Some(blockchain.chain.len() as u64 - 1) {
+            blockchain.persist_index();
+        }
+        Ok(blockchain)
+    }
+
+    /// Starts a chain at a state snapshot instead of replaying every block.
+    ///
+    /// `headers` runs from genesis up to the block the snapshot was taken at,
+    /// whose state root the snapshot has to match. Only the headers are kept
+    /// for those blocks; later blocks are added as usual with `add_block`.
+    pub fn bootstrap(
+        spec: ChainSpec,
+        snapshot: StateSnapshot,
+        headers: Vec<BlockHeader>,
+        store: impl BlockStore + 'static,
+    ) -> Result<Blockchain, BlockchainError> {
+        let mut store = store;
+        if !store.is_empty() {
+            return Err(BlockchainError::Storage(
+                "can only bootstrap into an empty store".into(),
+            ));
+        }
+        let mut blockchain = Blockchain::with_spec(spec);
+        if headers.len() as u64 != snapshot.get_height() + 1 {
+            return Err(BlockchainError::BlockInvalid(
+                "headers don't end at the snapshot block".into(),
+            ));
+        }
+        if headers[0].calculate_hash() != blockchain.chain[0].hash {
+            return Err(BlockchainError::BlockInvalid(
+                "headers start at a different genesis block".into(),
+            ));
+        }
+        for pair in headers.windows(2) {
+            blockchain.validate_header_on(&pair[1], &pair[0])?;
+        }
+        snapshot
+            .verify(&headers[headers.len() - 1])
+            .map_err(|e| BlockchainError::BlockInvalid(e.to_string()))?;
+
+        let blocks: Vec<Block> = headers
+            .into_iter()
+            .map(|header| Block::new(header, BlockBody::default()))
+            .collect();
+        blocks
+            .iter()
+            .try_for_each(|block| store.append_block(block))
+            .map_err(|e| BlockchainError::Storage(e.to_string()))?;
+        if let Some(path) = store.snapshot_path() {
+            snapshot
+                .save(&path)
+                .map_err(|e| BlockchainError::Storage(e.to_string()))?;
+        }
+
+        info!(
+            "bootstrapped from the state snapshot at height {}",
+            snapshot.get_height()
+        );
+        blockchain.index = ChainIndex::from_blocks(&blocks);
+        blockchain.chain = blocks;
+        blockchain.state = snapshot.get_state().clone();
+        blockchain.base_snapshot = Some(snapshot);
+        blockchain.index_path = store.index_path();
+        blockchain.store = Some(Arc::new(Mutex::new(store)));
+        blockchain.persist_index();
+        Ok(blockchain)
+    }
+
+    /// Creates the genesis block of the blockchain. It only depends on the
+    /// chain spec, so every node following the same spec shares it.
+    fn create_genesis_block(spec: &ChainSpec) -> Block {
+        let transactions = vec![Transaction::new(
+            "me".to_string(),
+            "me".to_string(),
+            10,
+            0,
+            "coinbase".into(),
+        )];
+        let mut state = WorldState::new();
+        state
+            .apply_transactions(&transactions)
+            .expect("the genesis block only mints");
+        let header = BlockHeader {
             index: 0,
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_else(|e| {
-                    eprintln!("Error getting time since UNIX EPOCH: {:?}", e);
-                    Duration::from_secs(0)
-                })
-                .as_secs(),
-            data: vec![Transaction::new(
-                "me".to_string(),
-                "me".to_string(),
-                10,
-                0,
-                "coinbase".into(),
-            )],
-            merkle_root: Self::calculate_merkle_root(&vec![Transaction::new(
-                "me".to_string(),
-                "me".to_string(),
-                10,
-                0,
-                "coinbase".into(),
-            )])
-            .unwrap(),
+            timestamp: spec.genesis_timestamp,
+            merkle_root: Self::calculate_merkle_root(&transactions).unwrap(),
+            state_root: state.state_root(),
             previous_hash: "0".to_string(),
-            hash: "0".to_string(),
+            difficulty: spec.difficulty as u32,
             nonce: 0,
         };
 
-        genesis_block.hash = genesis_block.calculate_hash();
-
-        genesis_block
+        Block::new(header, BlockBody::new(transactions))
     }
 
     fn calculate_merkle_tree(tx_list: &[Transaction]) -> Result<MerkleTree<mk_Sha256>, &str> {
-        let leaves: Vec<[u8; 32]> = tx_list
-            .iter()
-            .map(|x| mk_Sha256::hash(x.serialize().as_bytes()))
-            .collect();
+        let leaves: Vec<[u8; 32]> = tx_list.iter().map(|x| x.calculate_hash()).collect();
         Ok(MerkleTree::<mk_Sha256>::from_leaves(&leaves))
     }
 
@@ -127,12 +378,12 @@ impl Blockchain {
         let leaves: Vec<[u8; 32]> = block
             .get_data_raw()
             .iter()
-            .map(|x| mk_Sha256::hash(x.serialize().as_bytes()))
+            .map(|x| x.calculate_hash())
             .collect();
 
         let merkle_tree = MerkleTree::<mk_Sha256>::from_leaves(&leaves);
         let proof = merkle_tree.proof(&[tx_index]);
-        let leave_to_prove = [mk_Sha256::hash(transaction.serialize().as_bytes())];
+        let leave_to_prove = [transaction.calculate_hash()];
         let root = merkle_tree.root().ok_or_else(|| {
             BlockchainError::MerkleProofError("Couldn't get the Merkle root.".into())
         })?;
@@ -140,91 +391,608 @@ impl Blockchain {
         Ok(proof.verify(root, &[tx_index], &leave_to_prove, leaves.len()))
     }
 
-    fn find_block(&self, transaction: &Transaction) -> Result<(usize, usize), BlockchainError> {
-        for (block_index, block) in self.chain.iter().enumerate() {
-            // Use the index variable here
-            for (tx_index, tx) in block.get_data_raw().iter().enumerate() {
-                if tx.get_signature() == transaction.get_signature() {
-                    return Ok((block_index, tx_index));
-                }
-            }
-        }
-        Err(BlockchainError::TransactionNotFound)
+    fn find_block(&self, transaction_id: &str) -> Result<(usize, usize), BlockchainError> {
+        self.index
+            .get_transaction_location(transaction_id)
+            .map(|location| (location.height as usize, location.position))
+            .ok_or(BlockchainError::TransactionNotFound)
+    }
+
+    /// Merkle proof of the transaction with ID `transaction_id` against
+    /// the block it was mined in.
+    pub fn get_merkle_proof(
+        &self,
+        transaction_id: &str,
+    ) -> Result<TransactionProof, BlockchainError> {
+        let location = self
+            .index
+            .get_transaction_location(transaction_id)
+            .ok_or(BlockchainError::TransactionNotFound)?;
+        let block = self.get_block(location.height as usize)?;
+        let merkle_tree = Self::calculate_merkle_tree(block.get_data_raw())
+            .map_err(|e| BlockchainError::MerkleProofError(e.to_string()))?;
+        Ok(TransactionProof {
+            transaction_id: transaction_id.to_string(),
+            block_hash: block.hash.clone(),
+            height: location.height,
+            position: location.position,
+            leaf_count: block.get_data_raw().len(),
+            merkle_root: block.header.merkle_root,
+            hashes: merkle_tree
+                .proof(&[location.position])
+                .proof_hashes()
+                .to_vec(),
+        })
     }
 
     pub fn check_transaction_validity(
         &mut self,
         transaction: &Transaction,
     ) -> Result<bool, BlockchainError> {
-        let (block_index, tx_index) = self.find_block(transaction)?;
+        let (block_index, tx_index) = self.find_block(&transaction.id())?;
+        self.check_body(block_index)?;
         self.merkle_transaction_proof(transaction, block_index, tx_index)
     }
 
     /// Adds a new block to the blockchain.
     pub fn add_block(&mut self, new_block: Block) -> bool {
-        //checks if the blockchain is empty
-        if let Some(last_block) = self.chain.last() {
-            if !self.is_block_valid(&new_block.calculate_hash())
-                || new_block.index != last_block.index + 1
-            {
-                error!("Block is not valid or does not follow the last block in the chain.");
-                return false;
+        match self.try_add_block(new_block) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("{}", e);
+                false
             }
         }
+    }
+
+    /// Like [`Blockchain::add_block`], but says why a block was refused.
+    pub fn try_add_block(&mut self, new_block: Block) -> Result<(), BlockchainError> {
+        let state = self.validate_next_block(&new_block)?;
+        self.check_new_transactions(std::slice::from_ref(&new_block), self.chain.len() - 1)?;
+        self.with_store(|store| store.append_block(&new_block))
+            .map_err(|e| BlockchainError::Storage(e.to_string()))?;
 
-        self.chain.push(new_block);
-        true
+        self.state = state;
+        if let Some(bus) = &self.bus {
+            bus.publish(NodeEvent::BlockConnected(new_block.clone()));
+        }
+        self.connect_block(new_block, true);
+        if self.chain.len().is_multiple_of(INDEX_SAVE_INTERVAL) {
+            self.persist_index();
+        }
+        self.write_periodic_snapshot();
+        self.prune();
+        Ok(())
     }
 
-    /// Checks if the blockchain is valid.
-    pub fn is_chain_valid(&self) -> bool {
-        if self.chain.len() <= 1 {
-            return self
-                .chain
-                .last()
-                .map(|block| self.is_block_valid(&block.calculate_hash()))
-                .unwrap_or(true);
-        }
-        self.chain.windows(2).all(|window| {
-            if let [first, second] = window {
-                self.is_blockpair_valid(second, first).is_ok()
-            } else {
-                false
+    /// Switches to a branch with more work forking off after the block at
+    /// `fork_height`. Returns the blocks that were disconnected, oldest first.
+    pub fn reorganize(
+        &mut self,
+        fork_height: usize,
+        blocks: Vec<Block>,
+    ) -> Result<Vec<Block>, BlockchainError> {
+        if fork_height >= self.chain.len() {
+            return Err(BlockchainError::BlockInvalid(
+                "fork point is above the tip".into(),
+            ));
+        }
+        let work = blocks.iter().fold(0u128, |work, block| {
+            work.saturating_add(block.header.get_work())
+        });
+        if work <= self.get_work_after(fork_height) {
+            return Err(BlockchainError::BlockInvalid(
+                "new branch doesn't have more work than the active chain".into(),
+            ));
+        }
+        let mut state = self.state_at(fork_height)?;
+        let mut parent = &self.chain[fork_height];
+        for block in &blocks {
+            self.validate_block_on(block, parent)?;
+            state = Self::next_state(&state, block)?;
+            parent = block;
+        }
+        self.check_new_transactions(&blocks, fork_height)?;
+
+        self.with_store(|store| {
+            store.truncate(fork_height + 1)?;
+            blocks
+                .iter()
+                .try_for_each(|block| store.append_block(block))
+        })
+        .map_err(|e| BlockchainError::Storage(e.to_string()))?;
+
+        let disconnected = self.chain.split_off(fork_height + 1);
+        for block in disconnected.iter().rev() {
+            self.index.disconnect_block(block);
+        }
+        self.state = state;
+        if let Some(bus) = &self.bus {
+            bus.publish(NodeEvent::Reorganized {
+                fork_height: fork_height as u64,
+                disconnected: disconnected.clone(),
+                connected: blocks.clone(),
+            });
+        }
+        for block in blocks {
+            self.connect_block(block, true);
+        }
+        self.persist_index();
+        self.write_periodic_snapshot();
+        self.prune();
+
+        info!(
+            "reorganized: disconnected {} blocks, new tip at height {}",
+            disconnected.len(),
+            self.chain.len() - 1
+        );
+        Ok(disconnected)
+    }
+
+    // Rejects `blocks` if they repeat a transaction of the chain up to
+    // `height`, or one another's, so every transaction ID points at exactly
+    // one place
+    fn check_new_transactions(
+        &self,
+        blocks: &[Block],
+        height: usize,
+    ) -> Result<(), BlockchainError> {
+        let mut seen = HashSet::new();
+        for transaction in blocks.iter().flat_map(|block| block.get_data_raw()) {
+            let id = transaction.id();
+            let indexed = self
+                .index
+                .get_transaction_location(&id)
+                .is_some_and(|location| location.height <= height as u64);
+            if indexed || !seen.insert(id.clone()) {
+                return Err(BlockchainError::BlockInvalid(format!(
+                    "transaction {} is already in the chain",
+                    id
+                )));
             }
-        }) && self
+        }
+        Ok(())
+    }
+
+    // Checks that `block` can be appended to the current tip and returns the
+    // state after applying it
+    fn validate_next_block(&self, block: &Block) -> Result<WorldState, BlockchainError> {
+        if let Some(last_block) = self.chain.last() {
+            self.validate_block_on(block, last_block)?;
+        }
+        Self::next_state(&self.state, block)
+    }
+
+    fn validate_block_on(&self, block: &Block, parent: &Block) -> Result<(), BlockchainError> {
+        self.validate_header_on(&block.header, &parent.header)?;
+        self.validate_body(block)
+    }
+
+    /// Checks everything about `block` that doesn't depend on its parent:
+    /// proof of work, difficulty, merkle root, block limits and transaction
+    /// signatures. Used to vet blocks from peers before relaying them.
+    pub fn check_block(&self, block: &Block) -> Result<(), BlockchainError> {
+        if !self.is_block_valid(&block.calculate_hash())
+            || block.header.difficulty as usize != self.spec.difficulty
+        {
+            return Err(BlockchainError::BlockInvalid(
+                "insufficient proof of work".into(),
+            ));
+        }
+        self.validate_body(block)?;
+        match block
+            .get_data_raw()
+            .iter()
+            .find(|tx| !tx.is_coinbase() && !tx.verify_signature())
+        {
+            Some(tx) => Err(BlockchainError::BlockInvalid(format!(
+                "transaction {} has an invalid signature",
+                tx.id()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that `headers` follow on from `parent` one after the other,
+    /// each with valid proof of work. Lets a syncing node vet headers before
+    /// fetching any bodies.
+    pub fn check_header_chain(
+        &self,
+        parent: &BlockHeader,
+        headers: &[BlockHeader],
+    ) -> Result<(), BlockchainError> {
+        let mut parent = parent;
+        for header in headers {
+            self.validate_header_on(header, parent)?;
+            parent = header;
+        }
+        Ok(())
+    }
+
+    // Checks the body against the header's merkle root, the block limits and
+    // the block reward
+    fn validate_body(&self, block: &Block) -> Result<(), BlockchainError> {
+        if !block.has_valid_merkle_root() {
+            return Err(BlockchainError::BlockInvalid(
+                "merkle root doesn't match the block's transactions".into(),
+            ));
+        }
+        self.spec
+            .check_block_limits(block)
+            .and_then(|_| self.spec.check_block_reward(block))
+            .map_err(BlockchainError::BlockInvalid)
+    }
+
+    fn validate_header_on(
+        &self,
+        header: &BlockHeader,
+        parent: &BlockHeader,
+    ) -> Result<(), BlockchainError> {
+        if !self.is_block_valid(&header.calculate_hash())
+            || header.difficulty as usize != self.spec.difficulty
+            || header.index != parent.index + 1
+            || header.previous_hash != parent.calculate_hash()
+        {
+            return Err(BlockchainError::BlockInvalid(
+                "Block is not valid or does not follow the last block in the chain.".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Applies `block` to a copy of `state`, checking that the header commits
+    // to the result
+    fn next_state(state: &WorldState, block: &Block) -> Result<WorldState, BlockchainError> {
+        let mut state = state.clone();
+        Self::apply_checked(&mut state, block.get_data_raw())?;
+        if state.state_root() != block.header.state_root {
+            return Err(BlockchainError::BlockInvalid(
+                "state root doesn't match the block's transactions".into(),
+            ));
+        }
+        Ok(state)
+    }
+
+    // Applies `transactions` to `state` in order, each transfer only if it's
+    // signed by its sender, carries the sender's next nonce and is covered
+    // by the sender's balance.
+    fn apply_checked(
+        state: &mut WorldState,
+        transactions: &[Transaction],
+    ) -> Result<(), BlockchainError> {
+        for transaction in transactions {
+            if !transaction.is_coinbase() {
+                let sender = transaction.get_sender();
+                let problem = if !transaction.verify_signature() {
+                    Some("has an invalid signature".to_string())
+                } else if transaction.get_nonce() != state.get_nonce(sender) {
+                    Some(format!(
+                        "has nonce {}, expected {}",
+                        transaction.get_nonce(),
+                        state.get_nonce(sender)
+                    ))
+                } else if transaction
+                    .get_amount()
+                    .checked_add(transaction.get_fee())
+                    .is_none_or(|cost| cost > state.get_balance(sender))
+                {
+                    Some("costs more than the sender's balance".to_string())
+                } else {
+                    None
+                };
+                if let Some(problem) = problem {
+                    return Err(BlockchainError::BlockInvalid(format!(
+                        "transaction {} {}",
+                        transaction.id(),
+                        problem
+                    )));
+                }
+            }
+            transaction
+                .execute(state)
+                .map_err(BlockchainError::BlockInvalid)?;
+        }
+        Ok(())
+    }
+
+    // Replays the state right after the block at `height` from the state at
+    // the newest block without a body, or from genesis
+    fn state_at(&self, height: usize) -> Result<WorldState, BlockchainError> {
+        let (mut state, start) = match &self.base_snapshot {
+            Some(snapshot) if (height as u64) < snapshot.get_height() => {
+                return Err(BlockchainError::Pruned(height as u64))
+            }
+            Some(snapshot) => (
+                snapshot.get_state().clone(),
+                snapshot.get_height() as usize + 1,
+            ),
+            None => (WorldState::new(), 0),
+        };
+        for block in &self.chain[start..=height] {
+            state
+                .apply_block(block)
+                .map_err(BlockchainError::BlockInvalid)?;
+        }
+        Ok(state)
+    }
+
+    /// Transactions for the next block: a coinbase paying `miner` the block
+    /// reward plus the fees, followed by the mempool transactions that fit
+    /// within the block limits of the chain spec. The coinbase nonce is the
+    /// block height, which keeps coinbase IDs unique.
+    pub fn block_template(&self, mempool: &Mempool, miner: &str) -> Vec<Transaction> {
+        let tip = self
             .chain
             .last()
-            .map(|block| self.is_block_valid(&block.calculate_hash()))
-            .unwrap_or(false)
+            .expect("the chain always has a genesis block");
+        let coinbase = |amount| {
+            Transaction::new(
+                "coinbase".to_string(),
+                miner.to_string(),
+                amount,
+                tip.header.index as u64 + 1,
+                COINBASE_SIGNATURE.to_vec(),
+            )
+        };
+        let header = BlockHeader {
+            index: tip.header.index + 1,
+            timestamp: 0,
+            merkle_root: [0; 32],
+            state_root: [0; 32],
+            previous_hash: tip.hash.clone(),
+            difficulty: self.spec.difficulty as u32,
+            nonce: 0,
+        };
+        // The coinbase takes up room like any other transaction. Its amount
+        // is fixed width, so adding the fees later doesn't change its size.
+        let reward = coinbase(self.spec.block_reward);
+        let limits = ChainSpec {
+            max_block_transactions: self.spec.max_block_transactions.saturating_sub(1),
+            max_block_gas: self
+                .spec
+                .max_block_gas
+                .saturating_sub(self.spec.transaction_gas(&reward)),
+            ..self.spec.clone()
+        };
+        let reserved_bytes = Block::new(header, BlockBody::new(vec![reward]))
+            .encode()
+            .len();
+        let selected = mempool.select_block_transactions(&self.state, &limits, reserved_bytes);
+
+        let fees = selected
+            .iter()
+            .fold(0u64, |fees, tx| fees.saturating_add(tx.get_fee()));
+        let mut transactions = vec![coinbase(self.spec.block_reward.saturating_add(fees))];
+        transactions.extend(selected);
+        transactions
+    }
+
+    // Appends an already validated block whose state has been applied and,
+    // unless the persisted index is already up to date, indexes it
+    fn connect_block(&mut self, block: Block, update_index: bool) {
+        if update_index {
+            self.index.connect_block(&block);
+        }
+        self.chain.push(block);
+    }
+
+    fn with_store<T>(
+        &self,
+        f: impl FnOnce(&mut dyn BlockStore) -> Result<T, StorageError>,
+    ) -> Result<Option<T>, StorageError> {
+        match &self.store {
+            Some(store) => {
+                let mut store = store.lock().map_err(|e| StorageError::Io(e.to_string()))?;
+                f(&mut *store).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn persist_index(&self) {
+        if let Some(path) = &self.index_path {
+            if let Err(e) = self.index.save(path) {
+                error!("Failed to persist the chain index: {}", e);
+            }
+        }
+    }
+
+    /// Switches between keeping every block body and only the most recent ones.
+    pub fn set_pruning(&mut self, mode: PruningMode) {
+        self.pruning = mode;
+        self.prune();
+    }
+
+    /// Announces blocks connected and reorganizations from now on to `bus`.
+    /// Clones of the chain announce to the same bus.
+    pub fn set_event_bus(&mut self, bus: EventBus) {
+        self.bus = Some(bus);
+    }
+
+    pub fn get_pruning(&self) -> PruningMode {
+        self.pruning
+    }
+
+    /// Height of the newest block whose body is no longer kept, if any.
+    pub fn get_pruned_height(&self) -> Option<u64> {
+        self.base_snapshot.as_ref().map(|s| s.get_height())
+    }
+
+    // Lowest height whose body is still kept
+    fn body_start(&self) -> usize {
+        self.get_pruned_height()
+            .map_or(0, |height| height as usize + 1)
+    }
+
+    fn check_body(&self, height: usize) -> Result<(), BlockchainError> {
+        if height < self.body_start() {
+            return Err(BlockchainError::Pruned(height as u64));
+        }
+        Ok(())
     }
 
-    /// Checks if a block pair is valid.
-    fn is_blockpair_valid(&self, new: &Block, old: &Block) -> Result<(), &str> {
-        // Genesis block edge case
-        if old.index == 0 {
-            return Ok(());
+    // Drops the bodies that fell out of the pruning window. They are dropped
+    // `keep_blocks` at a time so the block file is only rewritten that often,
+    // and the state at the newest dropped body is persisted first so the node
+    // can restart from it.
+    fn prune(&mut self) {
+        let PruningMode::Pruned { keep_blocks } = self.pruning else {
+            return;
+        };
+        let keep = keep_blocks.max(1) as usize;
+        let start = self.body_start();
+        if self.chain.len() - start < 2 * keep {
+            return;
         }
+        let new_start = self.chain.len() - keep;
 
-        if new.index != old.index + 1 {
-            panic!("Invalid block index");
+        let snapshot = match self.state_at(new_start - 1) {
+            Ok(state) => StateSnapshot::new(
+                new_start as u64 - 1,
+                self.chain[new_start - 1].hash.clone(),
+                state,
+            ),
+            Err(e) => {
+                error!("Failed to prune: {}", e);
+                return;
+            }
+        };
+        // Indexing again after a restart needs the bodies about to go.
+        self.persist_index();
+        let stored = self.with_store(|store| {
+            if let Some(path) = store.snapshot_path() {
+                snapshot
+                    .save(&path)
+                    .map_err(|e| StorageError::Io(e.to_string()))?;
+            }
+            store.prune_bodies(new_start)
+        });
+        if let Err(e) = stored {
+            error!("Failed to prune the block store: {}", e);
+            return;
         }
 
-        if !self.is_block_valid(&old.calculate_hash()) {
-            panic!("Invalid previous block hash");
+        for block in &mut self.chain[start..new_start] {
+            block.body = BlockBody::default();
         }
+        self.base_snapshot = Some(snapshot);
+        info!("pruned block bodies below height {}", new_start);
+    }
+
+    /// Snapshot of the state at the current tip.
+    pub fn snapshot(&self) -> StateSnapshot {
+        let tip = self
+            .chain
+            .last()
+            .expect("the chain always has a genesis block");
+        StateSnapshot::new(
+            tip.header.index as u64,
+            tip.hash.clone(),
+            self.state.clone(),
+        )
+    }
+
+    /// Writes a snapshot into `dir` whenever the tip height is a multiple of
+    /// `interval`, for bootstrapping other nodes.
+    pub fn enable_snapshots(&mut self, dir: impl Into<PathBuf>, interval: u64) {
+        self.snapshot_policy = Some((dir.into(), interval.max(1)));
+    }
+
+    /// Path of the periodic snapshot taken at `height` in `dir`.
+    pub fn snapshot_file(dir: &Path, height: u64) -> PathBuf {
+        dir.join(format!("snapshot-{}.bin", height))
+    }
+
+    fn write_periodic_snapshot(&self) {
+        let Some((dir, interval)) = &self.snapshot_policy else {
+            return;
+        };
+        let height = self.chain.len() as u64 - 1;
+        if !height.is_multiple_of(*interval) {
+            return;
+        }
+        let path = Self::snapshot_file(dir, height);
+        let result = std::fs::create_dir_all(dir)
+            .map_err(|e| e.to_string())
+            .and_then(|_| self.snapshot().save(&path).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => info!("wrote state snapshot {}", path.display()),
+            Err(e) => error!("Failed to write state snapshot: {}", e),
+        }
+    }
 
-        if new.previous_hash != old.calculate_hash() {
-            if new.previous_hash != old.calculate_hash() {
-                panic!("Invalid previous hash");
+    /// Checks if the blockchain is valid.
+    pub fn is_chain_valid(&self) -> bool {
+        self.validate_chain().is_ok()
+    }
+
+    /// Replays the whole chain against the spec: the genesis block, every
+    /// header on its parent, and every body with its transactions. Blocks
+    /// whose bodies were pruned only have their headers checked, the state
+    /// is replayed from the snapshot taken at the newest of them.
+    pub fn validate_chain(&self) -> Result<(), BlockchainError> {
+        let at = |height: usize, e: BlockchainError| match e {
+            BlockchainError::BlockInvalid(reason) => {
+                BlockchainError::BlockInvalid(format!("block {}: {}", height, reason))
             }
+            e => e,
+        };
+        let genesis = self.chain.first().ok_or(BlockchainError::BlockNotFound)?;
+        if genesis.hash != Self::create_genesis_block(&self.spec).hash {
+            return Err(BlockchainError::BlockInvalid(
+                "genesis block doesn't match the chain spec".into(),
+            ));
+        }
+        for (height, pair) in self.chain.windows(2).enumerate() {
+            self.validate_header_on(&pair[1].header, &pair[0].header)
+                .map_err(|e| at(height + 1, e))?;
         }
 
+        let (mut state, start) = match &self.base_snapshot {
+            Some(snapshot) => {
+                let height = snapshot.get_height() as usize;
+                let block = self
+                    .chain
+                    .get(height)
+                    .ok_or(BlockchainError::BlockNotFound)?;
+                if block.hash != snapshot.get_block_hash()
+                    || block.header.state_root != snapshot.get_state().state_root()
+                {
+                    return Err(at(
+                        height,
+                        BlockchainError::BlockInvalid("state snapshot doesn't match".into()),
+                    ));
+                }
+                (snapshot.get_state().clone(), height + 1)
+            }
+            None => (WorldState::new(), 0),
+        };
+        let mut seen = HashSet::new();
+        for (height, block) in self.chain.iter().enumerate().skip(start) {
+            if height > 0 {
+                self.validate_body(block).map_err(|e| at(height, e))?;
+            }
+            state = Self::next_state(&state, block).map_err(|e| at(height, e))?;
+            if let Some(transaction) = block
+                .get_data_raw()
+                .iter()
+                .find(|transaction| !seen.insert(transaction.id()))
+            {
+                return Err(at(
+                    height,
+                    BlockchainError::BlockInvalid(format!(
+                        "transaction {} is already in the chain",
+                        transaction.id()
+                    )),
+                ));
+            }
+        }
         Ok(())
     }
 
     /// Checks if a block is valid.
     fn is_block_valid(&self, hash: &str) -> bool {
-        hash.starts_with(&"0".repeat(self.difficulty as usize))
+        hash.starts_with(&"0".repeat(self.spec.difficulty))
     }
 
     pub fn mine_block(
@@ -251,8 +1019,9 @@ impl Blockchain {
             }
         };
 
-        let last_index = last_block.index + 1;
+        let last_index = last_block.header.index + 1;
         let last_hash = last_block.hash.clone();
+        let difficulty = bc.spec.difficulty as u32;
         let merkleroot = match Self::calculate_merkle_root(&data) {
             Ok(root) => root,
             Err(e) => {
@@ -261,6 +1030,31 @@ impl Blockchain {
             }
         };
 
+        let mut next_state = bc.state.clone();
+        if let Err(e) = next_state.apply_transactions(&data) {
+            log::error!("Refusing to mine block: {}", e);
+            return false;
+        }
+        let state_root = next_state.state_root();
+
+        // Don't waste work on a block that would be rejected anyway
+        let candidate = Block::new(
+            BlockHeader {
+                index: last_index,
+                timestamp: 0,
+                merkle_root: merkleroot,
+                state_root,
+                previous_hash: last_hash.clone(),
+                difficulty,
+                nonce: 0,
+            },
+            BlockBody::new((*data).clone()),
+        );
+        if let Err(e) = bc.spec.check_block_limits(&candidate) {
+            log::error!("Refusing to mine block: {}", e);
+            return false;
+        }
+
         // Drop the lock to allow threads to use the blockchain later
         drop(bc);
 
@@ -268,7 +1062,7 @@ impl Blockchain {
             let data_clone = Arc::clone(&data);
             let blockchain_clone = Arc::clone(&blockchain);
             let last_hash_clone = last_hash.clone();
-            let merkleroot_clone = merkleroot.clone();
+            let merkleroot_clone = merkleroot;
 
             let handler = thread::spawn(move || {
                 let mut nonce = i as u64;
@@ -281,17 +1075,21 @@ impl Blockchain {
                     })
                     .as_secs();
                 let mut new_block = Block {
-                    index: last_index,
-                    timestamp: _timestamp,
-                    data: (*data_clone).clone(),
-                    merkle_root: merkleroot_clone.clone(),
-                    previous_hash: last_hash_clone.clone(),
+                    header: BlockHeader {
+                        index: last_index,
+                        timestamp: _timestamp,
+                        merkle_root: merkleroot_clone,
+                        state_root,
+                        previous_hash: last_hash_clone.clone(),
+                        difficulty,
+                        nonce,
+                    },
+                    body: BlockBody::new((*data_clone).clone()),
                     hash: String::new(),
-                    nonce: nonce,
                 };
 
                 loop {
-                    new_block.nonce = nonce;
+                    new_block.header.nonce = nonce;
 
                     let hash = new_block.calculate_hash();
 
@@ -312,8 +1110,8 @@ impl Blockchain {
 
                     nonce += nonce_step as u64;
 
-                    if nonce % 1000000 == 0 {
-                        new_block.timestamp = SystemTime::now()
+                    if nonce.is_multiple_of(1000000) {
+                        new_block.header.timestamp = SystemTime::now()
                             .duration_since(UNIX_EPOCH)
                             .unwrap_or_else(|e| {
                                 eprintln!("Error getting time since UNIX EPOCH: {:?}", e);
@@ -336,15 +1134,14 @@ impl Blockchain {
                 }
             }
         }
-        
 
         false
     }
 
     //Legacy function
-    pub fn mine_block_singlethread(&mut self, data: &Vec<Transaction>) -> bool {
+    pub fn mine_block_singlethread(&mut self, data: &[Transaction]) -> bool {
         info!("mining block...");
-        let mut nonce = 0;
+        let mut nonce: u64 = 0;
         let mut _timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_else(|e| {
@@ -352,22 +1149,32 @@ impl Blockchain {
                 Duration::from_secs(0)
             })
             .as_secs();
-        let merkleroot = Self::calculate_merkle_root(&data);
+        let merkleroot = Self::calculate_merkle_root(data);
+        let mut next_state = self.state.clone();
+        if let Err(e) = next_state.apply_transactions(data) {
+            error!("Refusing to mine block: {}", e);
+            return false;
+        }
+        let state_root = next_state.state_root();
         loop {
-            if nonce % 10000 == 0 {
+            if nonce.is_multiple_of(10000) {
                 info!("nonce: {}", nonce);
             }
 
-            let last_block = self.chain.get(self.chain.len() - 1).unwrap();
+            let last_block = self.chain.last().unwrap();
 
             let mut new_block = Block {
-                index: last_block.index + 1,
-                timestamp: _timestamp,
-                data: data.clone(),
-                merkle_root: merkleroot.clone().unwrap(),
-                previous_hash: last_block.hash.clone(),
+                header: BlockHeader {
+                    index: last_block.header.index + 1,
+                    timestamp: _timestamp,
+                    merkle_root: merkleroot.clone().unwrap(),
+                    state_root,
+                    previous_hash: last_block.hash.clone(),
+                    difficulty: self.spec.difficulty as u32,
+                    nonce,
+                },
+                body: BlockBody::new(data.to_vec()),
                 hash: String::new(),
-                nonce: nonce,
             };
 
             let hash = new_block.calculate_hash();
@@ -376,8 +1183,8 @@ impl Blockchain {
             if self.is_block_valid(&hash) {
                 return self.add_block(new_block);
             }
-            if nonce % 100000 == 0 {
-                new_block.timestamp = SystemTime::now()
+            if nonce.is_multiple_of(100000) {
+                new_block.header.timestamp = SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap_or_else(|e| {
                         eprintln!("Error getting time since UNIX EPOCH: {:?}", e);
@@ -390,6 +1197,57 @@ impl Blockchain {
         }
     }
 
+    /// Mines the block following the tip with `transactions` and the given
+    /// `timestamp`, without adding it. The nonce search runs on the calling
+    /// thread starting from zero, so the same chain and inputs always give
+    /// the same block. Signatures and nonces aren't checked here, only when
+    /// the block is added.
+    pub fn mine_next_block(
+        &self,
+        transactions: Vec<Transaction>,
+        timestamp: u64,
+    ) -> Result<Block, BlockchainError> {
+        let mut block = self.prepare_next_block(transactions, timestamp)?;
+        block.mine();
+        Ok(block)
+    }
+
+    /// The block following the tip with `transactions` and the given
+    /// `timestamp`, before any proof of work. It only needs [`Block::mine`]
+    /// to become valid, so the nonce search can run without the chain.
+    pub fn prepare_next_block(
+        &self,
+        transactions: Vec<Transaction>,
+        timestamp: u64,
+    ) -> Result<Block, BlockchainError> {
+        let tip = self
+            .chain
+            .last()
+            .expect("the chain always has a genesis block");
+        let mut next_state = self.state.clone();
+        next_state
+            .apply_transactions(&transactions)
+            .map_err(BlockchainError::BlockInvalid)?;
+        let block = Block::new(
+            BlockHeader {
+                index: tip.header.index + 1,
+                timestamp,
+                merkle_root: Self::calculate_merkle_root(&transactions)?,
+                state_root: next_state.state_root(),
+                previous_hash: tip.hash.clone(),
+                difficulty: self.spec.difficulty as u32,
+                nonce: 0,
+            },
+            BlockBody::new(transactions),
+        );
+        self.spec
+            .check_block_limits(&block)
+            .map_err(BlockchainError::BlockInvalid)?;
+        Ok(block)
+    }
+
+    /// Every block of the active chain. Pruned blocks only carry their
+    /// header, use [`Blockchain::get_block`] to tell them apart.
     pub fn get_chain(&self) -> &Vec<Block> {
         &self.chain
     }
@@ -399,14 +1257,120 @@ impl Blockchain {
     }
 
     pub fn get_difficulty(&self) -> usize {
-        self.difficulty
+        self.spec.difficulty
+    }
+
+    /// Total work behind the active chain, what peers compare tips by.
+    pub fn get_cumulative_work(&self) -> u128 {
+        self.chain.iter().fold(0u128, |work, block| {
+            work.saturating_add(block.header.get_work())
+        })
+    }
+
+    /// Work behind the blocks above `height`, what a branch forking off
+    /// there has to beat.
+    pub fn get_work_after(&self, height: usize) -> u128 {
+        self.chain
+            .iter()
+            .skip(height + 1)
+            .fold(0u128, |work, block| {
+                work.saturating_add(block.header.get_work())
+            })
+    }
+
+    pub fn get_spec(&self) -> &ChainSpec {
+        &self.spec
+    }
+
+    pub fn get_index(&self) -> &ChainIndex {
+        &self.index
+    }
+
+    pub fn get_header(&self, height: usize) -> Option<&BlockHeader> {
+        self.chain.get(height).map(|block| &block.header)
+    }
+
+    /// Up to `max` headers starting at height `start`, without any transactions.
+    pub fn get_headers(&self, start: usize, max: usize) -> Vec<BlockHeader> {
+        self.chain
+            .iter()
+            .skip(start)
+            .take(max)
+            .map(|block| block.header.clone())
+            .collect()
+    }
+
+    /// The block at `height`, failing with `Pruned` once its body is gone.
+    pub fn get_block(&self, height: usize) -> Result<&Block, BlockchainError> {
+        let block = self
+            .chain
+            .get(height)
+            .ok_or(BlockchainError::BlockNotFound)?;
+        self.check_body(height)?;
+        Ok(block)
+    }
+
+    pub fn get_block_body(&self, block_hash: &str) -> Result<&BlockBody, BlockchainError> {
+        self.get_block_by_hash(block_hash).map(|block| &block.body)
+    }
+
+    pub fn get_block_by_hash(&self, block_hash: &str) -> Result<&Block, BlockchainError> {
+        let height = self
+            .index
+            .get_block_height(block_hash)
+            .ok_or(BlockchainError::BlockNotFound)?;
+        self.get_block(height as usize)
+    }
+
+    /// Looks up a transaction by the hex ID it is indexed under.
+    pub fn get_transaction_by_id(
+        &self,
+        transaction_id: &str,
+    ) -> Result<(&Transaction, &TxLocation), BlockchainError> {
+        let location = self
+            .index
+            .get_transaction_location(transaction_id)
+            .ok_or(BlockchainError::TransactionNotFound)?;
+        Ok((self.transaction_at(location)?, location))
+    }
+
+    /// Every transaction sending to or from `address`, oldest first. Fails
+    /// with `Pruned` if part of the history is no longer kept.
+    pub fn get_address_history(
+        &self,
+        address: &str,
+    ) -> Result<Vec<(&Transaction, &TxLocation)>, BlockchainError> {
+        self.index
+            .get_address_history(address)
+            .iter()
+            .map(|location| Ok((self.transaction_at(location)?, location)))
+            .collect()
+    }
+
+    fn transaction_at(&self, location: &TxLocation) -> Result<&Transaction, BlockchainError> {
+        self.get_block(location.height as usize)?
+            .get_data_raw()
+            .get(location.position)
+            .ok_or(BlockchainError::TransactionNotFound)
+    }
+
+    pub fn get_state(&self) -> &WorldState {
+        &self.state
+    }
+
+    pub fn get_balance(&self, address: &str) -> u64 {
+        self.state.get_balance(address)
+    }
+
+    pub fn get_nonce(&self, address: &str) -> u64 {
+        self.state.get_nonce(address)
     }
 
     pub fn print_chain(&self) {
         for block in &self.chain {
             println!("Block Index: {}", block.get_index());
             println!("Timestamp: {}", block.get_timestamp());
-            println!("Merkle Root: {:?}", block.merkle_root);
+            println!("Merkle Root: {:?}", block.header.merkle_root);
             println!("Previous Hash: {}", block.get_previous_hash());
             println!("Hash: {}", block.get_hash());
             println!("Nonce: {}", block.get_nonce());
@@ -419,14 +1383,17 @@ impl Blockchain {
     }
 }
 
-impl Block {
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockHeader {
+    /// Hashes the encoded header; transactions are covered by the merkle root.
     pub fn calculate_hash(&self) -> String {
         let mut hasher = Sha2_256::new();
-        hasher.update(&self.index.to_string().as_bytes());
-        hasher.update(&self.timestamp.to_string().as_bytes());
-        hasher.update(&self.merkle_root);
-        hasher.update(&self.previous_hash.as_bytes());
-        hasher.update(&self.nonce.to_be_bytes());
+        hasher.update(self.encode());
 
         format!("{:x}", hasher.finalize())
     }
@@ -439,8 +1406,93 @@ impl Block {
         self.timestamp
     }
 
+    pub fn get_merkle_root(&self) -> &[u8; 32] {
+        &self.merkle_root
+    }
+
+    pub fn get_state_root(&self) -> &[u8; 32] {
+        &self.state_root
+    }
+
+    pub fn get_previous_hash(&self) -> &str {
+        &self.previous_hash
+    }
+
+    pub fn get_difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Expected number of hashes it took to find this header: one in 16
+    /// hashes has a leading zero hex digit.
+    pub fn get_work(&self) -> u128 {
+        16u128.saturating_pow(self.difficulty)
+    }
+}
+
+impl BlockBody {
+    pub fn new(transactions: Vec<Transaction>) -> Self {
+        BlockBody { transactions }
+    }
+
+    pub fn get_transactions(&self) -> &Vec<Transaction> {
+        &self.transactions
+    }
+}
+
+impl Block {
+    /// Assembles a block, computing its hash from the header.
+    pub fn new(header: BlockHeader, body: BlockBody) -> Self {
+        let hash = header.calculate_hash();
+        Block { header, body, hash }
+    }
+
+    pub fn calculate_hash(&self) -> String {
+        self.header.calculate_hash()
+    }
+
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn get_body(&self) -> &BlockBody {
+        &self.body
+    }
+
+    /// Counts the nonce up from its current value until the hash has as many
+    /// leading zeros as the header's difficulty asks for.
+    pub fn mine(&mut self) {
+        let target = "0".repeat(self.header.difficulty as usize);
+        while !self.hash.starts_with(&target) {
+            self.header.nonce += 1;
+            self.hash = self.calculate_hash();
+        }
+    }
+
+    /// Whether the body holds exactly the transactions the header commits to.
+    pub fn has_valid_merkle_root(&self) -> bool {
+        Blockchain::calculate_merkle_root(&self.body.transactions).ok()
+            == Some(self.header.merkle_root)
+    }
+
+    /// Splits the block into its header and body.
+    pub fn into_parts(self) -> (BlockHeader, BlockBody) {
+        (self.header, self.body)
+    }
+
+    pub fn get_index(&self) -> u128 {
+        self.header.index
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.header.timestamp
+    }
+
     pub fn get_data_raw(&self) -> &Vec<Transaction> {
-        &self.data
+        &self.body.transactions
     }
 
     pub fn get_hash(&self) -> &str {
@@ -448,10 +1500,78 @@ impl Block {
     }
 
     pub fn get_previous_hash(&self) -> &str {
-        &self.previous_hash
+        &self.header.previous_hash
     }
 
     pub fn get_nonce(&self) -> u64 {
-        self.nonce
+        self.header.nonce
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encoding::put_u8(out, encoding::ENCODING_VERSION);
+        encoding::put_u128(out, self.index);
+        encoding::put_u64(out, self.timestamp);
+        out.extend_from_slice(&self.merkle_root);
+        out.extend_from_slice(&self.state_root);
+        encoding::put_str(out, &self.previous_hash);
+        encoding::put_u32(out, self.difficulty);
+        encoding::put_u64(out, self.nonce);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        Ok(BlockHeader {
+            index: reader.read_u128()?,
+            timestamp: reader.read_u64()?,
+            merkle_root: reader.read_array()?,
+            state_root: reader.read_array()?,
+            previous_hash: reader.read_string()?,
+            difficulty: reader.read_u32()?,
+            nonce: reader.read_u64()?,
+        })
+    }
+}
+
+impl Encode for BlockBody {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encoding::put_u32(out, self.transactions.len() as u32);
+        for transaction in &self.transactions {
+            transaction.encode_to(out);
+        }
+    }
+}
+
+impl Decode for BlockBody {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let count = reader.read_u32()? as usize;
+        // Every transaction takes well over one byte, so a count larger than
+        // the remaining input can't be honest.
+        if count > reader.remaining() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut transactions = Vec::with_capacity(count);
+        for _ in 0..count {
+            transactions.push(Transaction::decode_from(reader)?);
+        }
+        Ok(BlockBody { transactions })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.body.encode_to(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let header = BlockHeader::decode_from(reader)?;
+        let body = BlockBody::decode_from(reader)?;
+        Ok(Block::new(header, body))
     }
 }
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::blockchain::Block;
+use crate::encoding::Encode;
+use crate::transaction::Transaction;
+
+/// Consensus parameters every node on a chain has to agree on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChainSpec {
+    pub chain_id: u64,                 // Tells networks sharing a genesis apart.
+    pub genesis_timestamp: u64,        // Timestamp of the genesis block.
+    pub difficulty: usize,             // Leading zeros required in a block hash.
+    pub max_block_size: usize,         // Upper bound on an encoded block in bytes.
+    pub max_block_transactions: usize, // Upper bound on transactions per block.
+    pub max_block_gas: u64,            // Upper bound on the summed gas of a block.
+    pub base_transaction_gas: u64,     // Gas every transaction costs.
+    pub gas_per_byte: u64,             // Gas per byte of encoded transaction.
+    pub block_reward: u64,             // Most a coinbase mints on top of the block's fees.
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        ChainSpec {
+            chain_id: 1,
+            genesis_timestamp: 1_704_067_200,
+            difficulty: 4,
+            max_block_size: 1_000_000,
+            max_block_transactions: 10_000,
+            max_block_gas: 30_000_000,
+            base_transaction_gas: 21_000,
+            gas_per_byte: 16,
+            block_reward: 50,
+        }
+    }
+}
+
+impl ChainSpec {
+    /// Reads a JSON chain spec. Missing fields take their default value.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        serde_json::from_slice(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| e.to_string())
+    }
+
+    /// Gas charged for including `transaction` in a block.
+    pub fn transaction_gas(&self, transaction: &Transaction) -> u64 {
+        let size = transaction.encode().len() as u64;
+        self.base_transaction_gas
+            .saturating_add(self.gas_per_byte.saturating_mul(size))
+    }
+
+    /// Checks the size, transaction count and gas limits of a block.
+    pub fn check_block_limits(&self, block: &Block) -> Result<(), String> {
+        let transactions = block.get_data_raw();
+        if transactions.len() > self.max_block_transactions {
+            return Err(format!(
+                "block has {} transactions, limit is {}",
+                transactions.len(),
+                self.max_block_transactions
+            ));
+        }
+        let size = block.encode().len();
+        if size > self.max_block_size {
+            return Err(format!(
+                "block is {} bytes, limit is {}",
+                size, self.max_block_size
+            ));
+        }
+        let gas = transactions
+            .iter()
+            .fold(0u64, |gas, tx| gas.saturating_add(self.transaction_gas(tx)));
+        if gas > self.max_block_gas {
+            return Err(format!(
+                "block uses {} gas, limit is {}",
+                gas, self.max_block_gas
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that a block starts with its only coinbase, and that the
+    /// coinbase pays at most the block reward plus the fees of the block's
+    /// other transactions.
+    pub fn check_block_reward(&self, block: &Block) -> Result<(), String> {
+        let Some((coinbase, transactions)) = block.get_data_raw().split_first() else {
+            return Err("block has no coinbase".into());
+        };
+        if !coinbase.is_coinbase() {
+            return Err("block doesn't start with a coinbase".into());
+        }
+        if transactions.iter().any(Transaction::is_coinbase) {
+            return Err("block has more than one coinbase".into());
+        }
+        let fees = transactions
+            .iter()
+            .fold(0u64, |fees, tx| fees.saturating_add(tx.get_fee()));
+        let limit = self.block_reward.saturating_add(fees);
+        if coinbase.get_amount() > limit {
+            return Err(format!(
+                "coinbase pays {}, limit is {}",
+                coinbase.get_amount(),
+                limit
+            ));
+        }
+        Ok(())
+    }
+}
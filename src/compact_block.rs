@@ -0,0 +1,252 @@
+//! Compact block relay.
+//!
+//! Most transactions of a new block have already reached a node's mempool
+//! by gossip, so a block is announced as its header plus a short ID per
+//! transaction. The receiver rebuilds it from its mempool and only asks
+//! for the transactions it doesn't have. Coinbase transactions are never in
+//! a mempool and are sent in full.
+
+use crate::blockchain::{Block, BlockBody, BlockHeader};
+use crate::encoding::{self, Decode, DecodeError, Encode, Reader};
+use crate::mempool::Mempool;
+use crate::transaction::Transaction;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+// Bytes of a short transaction ID on the wire.
+const SHORT_ID_LEN: usize = 6;
+
+/// A block announced by its header, short IDs of the transactions the
+/// receiver likely has and the transactions it surely doesn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactBlock {
+    header: BlockHeader,
+    short_ids: Vec<u64>, // Transactions not sent along, in block order.
+    prefilled: Vec<(u32, Transaction)>, // Transactions sent along, by position.
+}
+
+/// Why a compact block couldn't be completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactBlockError {
+    TransactionCount { expected: usize, got: usize },
+    MerkleRootMismatch,
+}
+
+impl std::fmt::Display for CompactBlockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompactBlockError::TransactionCount { expected, got } => {
+                write!(f, "Expected {} missing transactions, got {}", expected, got)
+            }
+            CompactBlockError::MerkleRootMismatch => {
+                write!(f, "Rebuilt block doesn't match its merkle root")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompactBlockError {}
+
+/// Outcome of rebuilding a compact block from the mempool.
+pub enum Reconstruction {
+    Complete(Block),
+    Incomplete(PartialBlock),
+}
+
+/// A compact block with some transactions still missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    slots: Vec<Option<Transaction>>,
+}
+
+impl CompactBlock {
+    /// Compact form of `block`, sending only its coinbase transactions in full.
+    pub fn from_block(block: &Block) -> Self {
+        let hash = block.get_hash();
+        let mut short_ids = Vec::new();
+        let mut prefilled = Vec::new();
+        for (index, transaction) in block.get_data_raw().iter().enumerate() {
+            if transaction.is_coinbase() {
+                prefilled.push((index as u32, transaction.clone()));
+            } else {
+                short_ids.push(short_id(hash, transaction));
+            }
+        }
+        CompactBlock {
+            header: block.get_header().clone(),
+            short_ids,
+            prefilled,
+        }
+    }
+
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn get_hash(&self) -> String {
+        self.header.calculate_hash()
+    }
+
+    pub fn get_transaction_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+
+    /// Rebuilds the block from the transactions in `mempool`. Transactions
+    /// that aren't there, or whose short ID is shared by several pool
+    /// transactions, are left missing.
+    pub fn reconstruct(&self, mempool: &Mempool) -> Reconstruction {
+        let hash = self.get_hash();
+        let mut candidates: HashMap<u64, Option<&Transaction>> = HashMap::new();
+        for transaction in mempool.iter() {
+            candidates
+                .entry(short_id(&hash, transaction))
+                .and_modify(|found| *found = None)
+                .or_insert(Some(transaction));
+        }
+
+        let mut slots: Vec<Option<Transaction>> = vec![None; self.get_transaction_count()];
+        for (index, transaction) in &self.prefilled {
+            slots[*index as usize] = Some(transaction.clone());
+        }
+        let mut short_ids = self.short_ids.iter();
+        for slot in slots.iter_mut().filter(|slot| slot.is_none()) {
+            let id = short_ids.next().expect("one short ID per empty slot");
+            *slot = candidates.get(id).copied().flatten().cloned();
+        }
+
+        let partial = PartialBlock {
+            header: self.header.clone(),
+            slots,
+        };
+        if !partial.get_missing().is_empty() {
+            return Reconstruction::Incomplete(partial);
+        }
+        match partial.fill(Vec::new()) {
+            Ok(block) => Reconstruction::Complete(block),
+            // Some short ID matched the wrong transaction; ask for all of them.
+            Err(_) => Reconstruction::Incomplete(self.empty()),
+        }
+    }
+
+    // The block with only the prefilled transactions in place.
+    fn empty(&self) -> PartialBlock {
+        let mut slots = vec![None; self.get_transaction_count()];
+        for (index, transaction) in &self.prefilled {
+            slots[*index as usize] = Some(transaction.clone());
+        }
+        PartialBlock {
+            header: self.header.clone(),
+            slots,
+        }
+    }
+}
+
+impl PartialBlock {
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// Positions of the transactions still missing, in block order.
+    pub fn get_missing(&self) -> Vec<u32> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_none())
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+
+    /// Completes the block with the missing transactions, given in block
+    /// order, checking the result against the header's merkle root.
+    pub fn fill(&self, transactions: Vec<Transaction>) -> Result<Block, CompactBlockError> {
+        let missing = self.slots.iter().filter(|slot| slot.is_none()).count();
+        if transactions.len() != missing {
+            return Err(CompactBlockError::TransactionCount {
+                expected: missing,
+                got: transactions.len(),
+            });
+        }
+        let mut transactions = transactions.into_iter();
+        let body = self
+            .slots
+            .iter()
+            .map(|slot| match slot {
+                Some(transaction) => transaction.clone(),
+                None => transactions.next().expect("counted above"),
+            })
+            .collect();
+        let block = Block::new(self.header.clone(), BlockBody::new(body));
+        if !block.has_valid_merkle_root() {
+            return Err(CompactBlockError::MerkleRootMismatch);
+        }
+        Ok(block)
+    }
+}
+
+/// Short ID of `transaction` within the block with hash `block_hash`. The
+/// block hash is mixed in so IDs that collide in one block don't in the next.
+pub fn short_id(block_hash: &str, transaction: &Transaction) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(block_hash.as_bytes());
+    hasher.update(transaction.calculate_hash());
+    let digest = hasher.finalize();
+    let mut id = [0u8; 8];
+    id[8 - SHORT_ID_LEN..].copy_from_slice(&digest[..SHORT_ID_LEN]);
+    u64::from_be_bytes(id)
+}
+
+impl Encode for CompactBlock {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        encoding::put_u32(out, self.short_ids.len() as u32);
+        for id in &self.short_ids {
+            out.extend_from_slice(&id.to_be_bytes()[8 - SHORT_ID_LEN..]);
+        }
+        encoding::put_u32(out, self.prefilled.len() as u32);
+        for (index, transaction) in &self.prefilled {
+            encoding::put_u32(out, *index);
+            transaction.encode_to(out);
+        }
+    }
+}
+
+impl Decode for CompactBlock {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let header = BlockHeader::decode_from(reader)?;
+        let count = reader.read_u32()? as usize;
+        if count > reader.remaining() / SHORT_ID_LEN {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut short_ids = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut id = [0u8; 8];
+            id[8 - SHORT_ID_LEN..].copy_from_slice(&reader.read_array::<SHORT_ID_LEN>()?);
+            short_ids.push(u64::from_be_bytes(id));
+        }
+
+        let count = reader.read_u32()? as usize;
+        if count > reader.remaining() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut prefilled: Vec<(u32, Transaction)> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let index = reader.read_u32()?;
+            // Positions have to be ascending and inside the block.
+            if prefilled.last().is_some_and(|(last, _)| index <= *last)
+                || index as usize >= short_ids.len() + count
+            {
+                return Err(DecodeError::Invalid(format!(
+                    "prefilled transaction at position {}",
+                    index
+                )));
+            }
+            prefilled.push((index, Transaction::decode_from(reader)?));
+        }
+        Ok(CompactBlock {
+            header,
+            short_ids,
+            prefilled,
+        })
+    }
+}
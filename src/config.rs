@@ -0,0 +1,272 @@
+//! Node configuration, read from a TOML file.
+//!
+//! Everything a node keeps on disk lives in its data directory: the chain
+//! spec, the block file with its index and snapshots, the mempool dump, the
+//! wallet, the peer files and the network key. Missing settings take their
+//! default value, so an empty file is a valid config.
+
+use crate::blockchain::{Blockchain, PruningMode};
+use crate::chain_spec::ChainSpec;
+use crate::limits::ConnectionLimits;
+use crate::p2p::NetworkConfig;
+use crate::storage::FileBlockStore;
+use libp2p::identity::Keypair;
+use libp2p::Multiaddr;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(ref err) => write!(f, "Config IO error: {}", err),
+            ConfigError::Parse(ref err) => write!(f, "Config parse error: {}", err),
+            ConfigError::Invalid(ref err) => write!(f, "Invalid config: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e.to_string())
+    }
+}
+
+/// Settings of a full node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    pub data_dir: PathBuf,         // Where the chain, wallet and peer files live.
+    pub network: NetworkSettings,  // How the node talks to peers.
+    pub rpc: ServiceSettings,      // JSON-RPC over HTTP.
+    pub ws: ServiceSettings,       // WebSocket subscriptions.
+    pub explorer: ServiceSettings, // Read-only REST API.
+    pub mining: MiningSettings,    // Whether and for whom blocks are mined.
+    pub storage: StorageSettings,  // How much history is kept.
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            data_dir: PathBuf::from("data"),
+            network: NetworkSettings::default(),
+            rpc: ServiceSettings::enabled("127.0.0.1:8545"),
+            ws: ServiceSettings::enabled("127.0.0.1:8546"),
+            explorer: ServiceSettings {
+                enabled: false,
+                ..ServiceSettings::enabled("127.0.0.1:8080")
+            },
+            mining: MiningSettings::default(),
+            storage: StorageSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    pub listen: String,               // Multiaddr to accept connections on.
+    pub peers: Vec<String>,           // Multiaddrs dialed right after starting.
+    pub bootstrap_peers: Vec<String>, // DHT entry points, each ending in /p2p/<peer id>.
+    pub kademlia: bool,               // Whether to find peers through the DHT.
+    pub mdns: bool,                   // Whether to look for peers on the local network.
+    pub max_inbound: usize,           // Peers that may connect to us.
+    pub max_outbound: usize,          // Peers we connect to.
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        let limits = ConnectionLimits::default();
+        NetworkSettings {
+            listen: "/ip4/0.0.0.0/tcp/30333".to_string(),
+            peers: Vec::new(),
+            bootstrap_peers: Vec::new(),
+            kademlia: true,
+            mdns: false,
+            max_inbound: limits.max_inbound,
+            max_outbound: limits.max_outbound,
+        }
+    }
+}
+
+/// An API server the node may run. `listen` must be a loopback address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceSettings {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    pub listen: SocketAddr,
+}
+
+impl ServiceSettings {
+    fn enabled(listen: &str) -> Self {
+        ServiceSettings {
+            enabled: true,
+            listen: listen.parse().expect("valid socket address"),
+        }
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MiningSettings {
+    pub enabled: bool,           // Whether `node run` mines blocks.
+    pub address: Option<String>, // Receiver of the block rewards.
+    pub block_time_secs: u64,    // Least time between two blocks we mine.
+}
+
+impl Default for MiningSettings {
+    fn default() -> Self {
+        MiningSettings {
+            enabled: false,
+            address: None,
+            block_time_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub keep_blocks: Option<u64>, // Block bodies kept when pruning; all of them if unset.
+    pub snapshot_interval: Option<u64>, // Blocks between state snapshots; none if unset.
+}
+
+impl NodeConfig {
+    /// Reads a TOML config file.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let data = fs::read_to_string(path)?;
+        toml::from_str(&data).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let data = toml::to_string_pretty(self).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn spec_path(&self) -> PathBuf {
+        self.data_dir.join("chain_spec.json")
+    }
+
+    pub fn blocks_path(&self) -> PathBuf {
+        self.data_dir.join("blocks.dat")
+    }
+
+    pub fn mempool_path(&self) -> PathBuf {
+        self.data_dir.join("mempool.json")
+    }
+
+    pub fn wallet_path(&self) -> PathBuf {
+        self.data_dir.join("wallet.json")
+    }
+
+    pub fn address_book_path(&self) -> PathBuf {
+        self.data_dir.join("peers.json")
+    }
+
+    pub fn ban_list_path(&self) -> PathBuf {
+        self.data_dir.join("bans.json")
+    }
+
+    pub fn node_key_path(&self) -> PathBuf {
+        self.data_dir.join("node.key")
+    }
+
+    pub fn snapshot_dir(&self) -> PathBuf {
+        self.data_dir.join("snapshots")
+    }
+
+    /// The chain spec of the data directory, or the default one before
+    /// `init` has written it.
+    pub fn load_spec(&self) -> Result<ChainSpec, ConfigError> {
+        let path = self.spec_path();
+        if !path.exists() {
+            return Ok(ChainSpec::default());
+        }
+        ChainSpec::load(&path).map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Opens the chain in the data directory with the configured pruning
+    /// and snapshots, writing the genesis block on first use.
+    pub fn open_chain(&self) -> Result<Blockchain, ConfigError> {
+        let store =
+            FileBlockStore::open(self.blocks_path()).map_err(|e| ConfigError::Io(e.to_string()))?;
+        let mut blockchain = Blockchain::open_with_spec(self.load_spec()?, store)
+            .map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        if let Some(keep_blocks) = self.storage.keep_blocks {
+            blockchain.set_pruning(PruningMode::Pruned { keep_blocks });
+        }
+        if let Some(interval) = self.storage.snapshot_interval {
+            blockchain.enable_snapshots(self.snapshot_dir(), interval);
+        }
+        Ok(blockchain)
+    }
+
+    /// The network identity kept in the data directory, generated on
+    /// first use so the peer ID survives restarts.
+    pub fn load_node_key(&self) -> Result<Keypair, ConfigError> {
+        let path = self.node_key_path();
+        match fs::read(&path) {
+            Ok(bytes) => Keypair::from_protobuf_encoding(&bytes)
+                .map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let keypair = Keypair::generate_ed25519();
+                let bytes = keypair
+                    .to_protobuf_encoding()
+                    .map_err(|e| ConfigError::Invalid(e.to_string()))?;
+                fs::create_dir_all(&self.data_dir)?;
+                fs::write(&path, bytes)?;
+                info!("generated network key {}", path.display());
+                Ok(keypair)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Network settings for [`crate::p2p::Node::start`], with peers
+    /// remembered in the data directory.
+    pub fn network_config(&self, keypair: Keypair) -> Result<NetworkConfig, ConfigError> {
+        Ok(NetworkConfig {
+            keypair,
+            listen_addr: parse_multiaddr(&self.network.listen)?,
+            peers: parse_multiaddrs(&self.network.peers)?,
+            bootstrap_peers: parse_multiaddrs(&self.network.bootstrap_peers)?,
+            kademlia: self.network.kademlia,
+            mdns: self.network.mdns,
+            address_book: Some(self.address_book_path()),
+            ban_list: Some(self.ban_list_path()),
+            connection_limits: ConnectionLimits {
+                max_inbound: self.network.max_inbound,
+                max_outbound: self.network.max_outbound,
+            },
+            ..NetworkConfig::default()
+        })
+    }
+}
+
+fn parse_multiaddr(addr: &str) -> Result<Multiaddr, ConfigError> {
+    addr.parse()
+        .map_err(|e| ConfigError::Invalid(format!("bad multiaddr {}: {}", addr, e)))
+}
+
+fn parse_multiaddrs(addrs: &[String]) -> Result<Vec<Multiaddr>, ConfigError> {
+    addrs.iter().map(|addr| parse_multiaddr(addr)).collect()
+}
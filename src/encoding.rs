@@ -0,0 +1,156 @@
+//! Deterministic binary encoding used for hashing, storage and networking.
+//!
+//! Integers are fixed width big-endian, byte strings and strings carry a u32
+//! length prefix and every top level item starts with a version byte. There
+//! is exactly one encoding per value, so decoding rejects anything that
+//! doesn't round-trip byte for byte: unknown versions, truncated input,
+//! invalid UTF-8 and trailing bytes.
+
+/// Version byte written in front of encoded transactions, headers and blocks.
+/// Version 2 added the header's difficulty and version 3 its state root;
+/// older layouts are rejected.
+pub const ENCODING_VERSION: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnsupportedVersion(u8),
+    InvalidUtf8,
+    TrailingBytes(usize),
+    Invalid(String),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "Unexpected end of input"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported encoding version {}", version)
+            }
+            DecodeError::InvalidUtf8 => write!(f, "Invalid UTF-8 in string"),
+            DecodeError::TrailingBytes(count) => write!(f, "{} trailing bytes", count),
+            DecodeError::Invalid(ref err) => write!(f, "Invalid encoding: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub trait Encode {
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError>;
+
+    /// Decodes a value that must span all of `bytes`.
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let value = Self::decode_from(&mut reader)?;
+        reader.finish()?;
+        Ok(value)
+    }
+}
+
+pub fn put_u8(out: &mut Vec<u8>, value: u8) {
+    out.push(value);
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u128(out: &mut Vec<u8>, value: u128) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_bytes(out: &mut Vec<u8>, value: &[u8]) {
+    put_u32(out, value.len() as u32);
+    out.extend_from_slice(value);
+}
+
+pub fn put_str(out: &mut Vec<u8>, value: &str) {
+    put_bytes(out, value.as_bytes());
+}
+
+/// Cursor over an encoded byte slice.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.remaining() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let slice = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(slice)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_u128(&mut self) -> Result<u128, DecodeError> {
+        Ok(u128::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = self.read_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    pub fn read_string(&mut self) -> Result<String, DecodeError> {
+        String::from_utf8(self.read_bytes()?).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    /// Reads a version byte and checks it is one we understand.
+    pub fn read_version(&mut self) -> Result<u8, DecodeError> {
+        match self.read_u8()? {
+            ENCODING_VERSION => Ok(ENCODING_VERSION),
+            version => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Fails if anything is left unread.
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        match self.remaining() {
+            0 => Ok(()),
+            count => Err(DecodeError::TrailingBytes(count)),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! In-process event bus carrying chain and mempool changes to whoever is
+//! listening, such as WebSocket subscribers.
+
+use crate::blockchain::Block;
+use crate::mempool::MempoolEvent;
+use tokio::sync::broadcast;
+
+/// Events a bus holds on to for subscribers that fall behind.
+pub const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// Something that happened to the chain or the mempool.
+#[derive(Clone)]
+pub enum NodeEvent {
+    BlockConnected(Block), // Appended to the active chain.
+    Mempool(MempoolEvent), // A change to the mempool.
+    Reorganized {
+        fork_height: u64,         // Last block both branches share.
+        disconnected: Vec<Block>, // Blocks of the old branch, oldest first.
+        connected: Vec<Block>,    // Blocks of the new branch, oldest first.
+    },
+}
+
+/// Broadcast channel every subscriber gets its own copy of each event from.
+///
+/// Publishing never blocks: a subscriber that falls more than the capacity
+/// behind misses the oldest events instead of holding up the chain.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<NodeEvent>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        EventBus { sender }
+    }
+
+    /// Hands `event` to every current subscriber.
+    pub fn publish(&self, event: NodeEvent) {
+        // Failing just means nobody is listening.
+        let _ = self.sender.send(event);
+    }
+
+    /// Receives every event published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_CAPACITY)
+    }
+}
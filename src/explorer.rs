@@ -0,0 +1,366 @@
+//! Read-only REST API for block explorers.
+//!
+//! Everything is a GET answered with JSON, hashes and signatures hex
+//! encoded:
+//!
+//! - `/blocks?page=&limit=` lists blocks, newest first.
+//! - `/blocks/{height or hash}` is a block with its transactions.
+//! - `/transactions/{id}` is a transaction with its confirmations.
+//! - `/addresses/{address}?page=&limit=` has the balance and history.
+//! - `/mempool?page=&limit=` lists waiting transactions, best paying first.
+//!
+//! Lookups go through the chain indexes, so none of them scan the chain.
+
+use crate::blockchain::{Blockchain, BlockchainError};
+use crate::gossip::SharedChain;
+use crate::mempool::Mempool;
+use crate::rpc::{BlockView, TransactionView};
+use crate::transaction::Transaction;
+use crate::ws::HeadView;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tiny_http::{Header, Method, Response, Server};
+
+/// Items per page unless a `limit` is given.
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+/// Largest `limit` accepted.
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// One page of a longer list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub page: usize,  // Counted from zero.
+    pub limit: usize, // Items per page.
+    pub total: usize, // Items across all pages.
+    pub items: Vec<T>,
+}
+
+/// A block with how deep it is buried.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockDetail {
+    #[serde(flatten)]
+    pub block: BlockView,
+    pub confirmations: u64,
+}
+
+/// A transaction with how deep it is buried; zero while in the mempool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionDetail {
+    #[serde(flatten)]
+    pub transaction: TransactionView,
+    pub confirmations: u64,
+}
+
+/// Account of an address and a page of its mined transactions, newest
+/// first, plus whatever of it is still in the mempool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressDetail {
+    pub address: String,
+    pub balance: u64,
+    pub nonce: u64,
+    pub transactions: Page<TransactionDetail>,
+    pub pending: Vec<TransactionView>,
+}
+
+/// A mempool transaction; queued ones wait for an earlier nonce.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MempoolEntry {
+    #[serde(flatten)]
+    pub transaction: TransactionView,
+    pub queued: bool,
+}
+
+// Failed request, answered with its status and a JSON error message.
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<BlockchainError> for ApiError {
+    fn from(err: BlockchainError) -> Self {
+        let status = match err {
+            BlockchainError::BlockNotFound | BlockchainError::TransactionNotFound => 404,
+            // The data existed but this node no longer keeps it.
+            BlockchainError::Pruned(_) => 410,
+            _ => 500,
+        };
+        ApiError::new(status, err.to_string())
+    }
+}
+
+/// HTTP server answering explorer requests on a background thread.
+pub struct ExplorerServer {
+    server: Arc<Server>,
+    addr: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ExplorerServer {
+    /// Starts serving `chain` on `addr`, which must be a loopback address;
+    /// put a reverse proxy in front to expose it. Port 0 picks a free port;
+    /// see `local_addr`.
+    pub fn start(addr: SocketAddr, chain: SharedChain) -> io::Result<Self> {
+        if !addr.ip().is_loopback() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "refusing to serve the explorer API on non-loopback address {}",
+                    addr
+                ),
+            ));
+        }
+        let server = Server::http(addr).map_err(io::Error::other)?;
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| io::Error::other("explorer API is not listening on TCP"))?;
+        let server = Arc::new(server);
+        let thread = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || serve(&server, &chain))
+        };
+        info!("serving the explorer API on http://{}", addr);
+        Ok(ExplorerServer {
+            server,
+            addr,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops accepting requests and waits for the server thread to exit.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ExplorerServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn serve(server: &Server, chain: &SharedChain) {
+    for request in server.incoming_requests() {
+        let (status, body) = if *request.method() != Method::Get {
+            (405, json!({ "error": "only GET is supported" }))
+        } else {
+            match route(chain, request.url()) {
+                Ok(body) => (200, body),
+                Err(err) => (err.status, json!({ "error": err.message })),
+            }
+        };
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+        if let Err(e) = request.respond(response) {
+            warn!("failed to send explorer response: {}", e);
+        }
+    }
+}
+
+fn route(chain: &SharedChain, url: &str) -> Result<Value, ApiError> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let (page, limit) = pagination(query)?;
+    let blockchain = chain.blockchain.lock().unwrap();
+    let mempool = chain.mempool.lock().unwrap();
+    let body = match segments.as_slice() {
+        ["blocks"] => to_value(paginate(
+            blockchain.get_chain().iter().rev(),
+            page,
+            limit,
+            HeadView::new,
+        )),
+        ["blocks", id] => to_value(block_detail(&blockchain, id)?),
+        ["transactions", id] => to_value(transaction_detail(&blockchain, &mempool, id)?),
+        ["addresses", address] => {
+            to_value(address_detail(&blockchain, &mempool, address, page, limit)?)
+        }
+        ["mempool"] => to_value(list_mempool(&blockchain, &mempool, page, limit)),
+        _ => return Err(ApiError::new(404, format!("no such resource {}", path))),
+    };
+    Ok(body)
+}
+
+// `page` and `limit` from the query string, with their defaults.
+fn pagination(query: &str) -> Result<(usize, usize), ApiError> {
+    let mut page = 0;
+    let mut limit = DEFAULT_PAGE_SIZE;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let parse = |value: &str| {
+            value
+                .parse::<usize>()
+                .map_err(|_| ApiError::new(400, format!("invalid {}: {}", key, value)))
+        };
+        match key {
+            "page" => page = parse(value)?,
+            "limit" => limit = parse(value)?,
+            _ => {}
+        }
+    }
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(ApiError::new(
+            400,
+            format!("limit must be between 1 and {}", MAX_PAGE_SIZE),
+        ));
+    }
+    Ok((page, limit))
+}
+
+// Page `page` of `items`, each turned into what's shown by `view`.
+fn paginate<I: ExactSizeIterator, T>(
+    items: I,
+    page: usize,
+    limit: usize,
+    view: impl FnMut(I::Item) -> T,
+) -> Page<T> {
+    Page {
+        page,
+        limit,
+        total: items.len(),
+        items: items
+            .skip(page.saturating_mul(limit))
+            .take(limit)
+            .map(view)
+            .collect(),
+    }
+}
+
+// The block at a height, or with a hash when `id` isn't a number.
+fn block_detail(blockchain: &Blockchain, id: &str) -> Result<BlockDetail, ApiError> {
+    let block = match id.parse::<usize>() {
+        Ok(height) => blockchain.get_block(height)?,
+        Err(_) => blockchain.get_block_by_hash(id)?,
+    };
+    Ok(BlockDetail {
+        block: BlockView::new(block),
+        confirmations: confirmations(blockchain, block.get_index() as u64),
+    })
+}
+
+// A mined transaction, or failing that, one in the mempool.
+fn transaction_detail(
+    blockchain: &Blockchain,
+    mempool: &Mempool,
+    id: &str,
+) -> Result<TransactionDetail, ApiError> {
+    match blockchain.get_transaction_by_id(id) {
+        Ok((transaction, location)) => Ok(TransactionDetail {
+            transaction: TransactionView::new(transaction, Some(location)),
+            confirmations: confirmations(blockchain, location.height),
+        }),
+        Err(BlockchainError::TransactionNotFound) => {
+            let pending = mempool
+                .iter()
+                .find(|transaction| transaction.id() == id)
+                .ok_or(BlockchainError::TransactionNotFound)?;
+            Ok(TransactionDetail {
+                transaction: TransactionView::new(pending, None),
+                confirmations: 0,
+            })
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn address_detail(
+    blockchain: &Blockchain,
+    mempool: &Mempool,
+    address: &str,
+    page: usize,
+    limit: usize,
+) -> Result<AddressDetail, ApiError> {
+    let history = blockchain.get_address_history(address)?;
+    let transactions = paginate(
+        history.into_iter().rev(),
+        page,
+        limit,
+        |(transaction, location)| TransactionDetail {
+            transaction: TransactionView::new(transaction, Some(location)),
+            confirmations: confirmations(blockchain, location.height),
+        },
+    );
+    let mut pending: Vec<&Transaction> = mempool
+        .iter()
+        .filter(|transaction| {
+            transaction.get_sender() == address || transaction.get_receiver() == address
+        })
+        .collect();
+    pending.sort_by_key(|transaction| (transaction.get_sender(), transaction.get_nonce()));
+    Ok(AddressDetail {
+        address: address.to_string(),
+        balance: blockchain.get_balance(address),
+        nonce: blockchain.get_nonce(address),
+        transactions,
+        pending: pending
+            .into_iter()
+            .map(|transaction| TransactionView::new(transaction, None))
+            .collect(),
+    })
+}
+
+fn list_mempool(
+    blockchain: &Blockchain,
+    mempool: &Mempool,
+    page: usize,
+    limit: usize,
+) -> Page<MempoolEntry> {
+    let queued: HashSet<String> = mempool
+        .queued(blockchain.get_state())
+        .iter()
+        .map(Transaction::id)
+        .collect();
+    let mut transactions: Vec<&Transaction> = mempool.iter().collect();
+    transactions.sort_by(|a, b| {
+        b.fee_rate()
+            .cmp(&a.fee_rate())
+            .then_with(|| (a.get_sender(), a.get_nonce()).cmp(&(b.get_sender(), b.get_nonce())))
+    });
+    paginate(transactions.into_iter(), page, limit, |transaction| {
+        MempoolEntry {
+            transaction: TransactionView::new(transaction, None),
+            queued: queued.contains(&transaction.id()),
+        }
+    })
+}
+
+// Blocks at or on top of `height`, counting its own.
+fn confirmations(blockchain: &Blockchain, height: u64) -> u64 {
+    (blockchain.get_chain_length() as u64).saturating_sub(height)
+}
+
+fn to_value<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).expect("explorer views serialize to JSON")
+}
@@ -0,0 +1,201 @@
+use crate::blockchain::{Block, Blockchain};
+use crate::encoding::{Decode, DecodeError, Encode};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::info;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+// Layout: magic, format version, flags, start height, block count, then one
+// `[length: u32 BE][encoded block]` record per block. With the compressed
+// flag set everything behind the fixed header is gzipped.
+const MAGIC: &[u8; 4] = b"MFBC";
+const FORMAT_VERSION: u8 = 1;
+const FLAG_COMPRESSED: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainFileError {
+    Io(String),
+    Format(String),
+    Decode(DecodeError),
+    GenesisMismatch,
+    Gap { expected: u64, found: u64 },
+    BlockRejected(u64),
+    Pruned(u64),
+}
+
+impl std::fmt::Display for ChainFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainFileError::Io(ref err) => write!(f, "Chain file IO error: {}", err),
+            ChainFileError::Format(ref err) => write!(f, "Chain file format error: {}", err),
+            ChainFileError::Decode(ref err) => write!(f, "Chain file decode error: {}", err),
+            ChainFileError::GenesisMismatch => write!(f, "Chain file is for a different chain"),
+            ChainFileError::Gap { expected, found } => write!(
+                f,
+                "Chain file starts at height {}, chain needs height {} first",
+                found, expected
+            ),
+            ChainFileError::BlockRejected(height) => {
+                write!(f, "Block {} was rejected by the chain", height)
+            }
+            ChainFileError::Pruned(height) => {
+                write!(f, "Block {} has been pruned and can't be exported", height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChainFileError {}
+
+impl From<std::io::Error> for ChainFileError {
+    fn from(e: std::io::Error) -> Self {
+        ChainFileError::Io(e.to_string())
+    }
+}
+
+/// Import state handed to the progress callback after every block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportProgress {
+    pub height: u64,   // Height of the block just processed.
+    pub imported: u64, // Blocks added to the chain so far.
+    pub skipped: u64,  // Blocks already on the chain, e.g. from an earlier run.
+    pub total: u64,    // Blocks in the file.
+}
+
+/// Writes the blocks at `range` heights to `path`. Returns how many were written.
+pub fn export_blocks(
+    blockchain: &Blockchain,
+    path: &Path,
+    range: Range<usize>,
+    compress: bool,
+) -> Result<usize, ChainFileError> {
+    let end = range.end.min(blockchain.get_chain_length());
+    let blocks = (range.start..end)
+        .map(|height| {
+            blockchain
+                .get_block(height)
+                .map_err(|_| ChainFileError::Pruned(height as u64))
+        })
+        .collect::<Result<Vec<&Block>, _>>()?;
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&[FORMAT_VERSION, if compress { FLAG_COMPRESSED } else { 0 }])?;
+    file.write_all(&(range.start as u64).to_be_bytes())?;
+    file.write_all(&(blocks.len() as u64).to_be_bytes())?;
+
+    if compress {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        write_records(&mut encoder, &blocks)?;
+        encoder.finish()?.flush()?;
+    } else {
+        write_records(&mut file, &blocks)?;
+        file.flush()?;
+    }
+
+    info!("exported {} blocks to {}", blocks.len(), path.display());
+    Ok(blocks.len())
+}
+
+fn write_records(out: &mut impl Write, blocks: &[&Block]) -> Result<(), ChainFileError> {
+    for block in blocks {
+        let bytes = block.encode();
+        out.write_all(&(bytes.len() as u32).to_be_bytes())?;
+        out.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+/// Adds the blocks in `path` to `blockchain` through [`Blockchain::add_block`].
+///
+/// Blocks the chain already has are skipped, so an interrupted import can
+/// simply be started again. Returns the final progress.
+pub fn import_blocks(
+    blockchain: &mut Blockchain,
+    path: &Path,
+    mut on_progress: impl FnMut(&ImportProgress),
+) -> Result<ImportProgress, ChainFileError> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 22];
+    file.read_exact(&mut header)
+        .map_err(|_| ChainFileError::Format("file too short".into()))?;
+    if &header[0..4] != MAGIC {
+        return Err(ChainFileError::Format("not a chain file".into()));
+    }
+    if header[4] != FORMAT_VERSION {
+        return Err(ChainFileError::Format(format!(
+            "unsupported format version {}",
+            header[4]
+        )));
+    }
+    let compressed = header[5] & FLAG_COMPRESSED != 0;
+    let start = u64::from_be_bytes(header[6..14].try_into().unwrap());
+    let total = u64::from_be_bytes(header[14..22].try_into().unwrap());
+
+    let mut records: Box<dyn Read> = if compressed {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+
+    let mut progress = ImportProgress {
+        height: start,
+        imported: 0,
+        skipped: 0,
+        total,
+    };
+    for offset in 0..total {
+        let block = read_record(&mut records)?;
+        let height = start + offset;
+        progress.height = height;
+
+        let chain_length = blockchain.get_chain_length() as u64;
+        if height < chain_length {
+            // Already on the chain, which must agree with the file.
+            let existing = &blockchain.get_chain()[height as usize];
+            if existing.get_hash() != block.get_hash() {
+                return Err(if height == 0 {
+                    ChainFileError::GenesisMismatch
+                } else {
+                    ChainFileError::BlockRejected(height)
+                });
+            }
+            progress.skipped += 1;
+        } else if height > chain_length {
+            return Err(ChainFileError::Gap {
+                expected: chain_length,
+                found: height,
+            });
+        } else if blockchain.add_block(block) {
+            progress.imported += 1;
+        } else {
+            return Err(ChainFileError::BlockRejected(height));
+        }
+
+        on_progress(&progress);
+    }
+
+    info!(
+        "imported {} blocks from {}, {} already known",
+        progress.imported,
+        path.display(),
+        progress.skipped
+    );
+    Ok(progress)
+}
+
+fn read_record(records: &mut impl Read) -> Result<Block, ChainFileError> {
+    let mut len = [0u8; 4];
+    records
+        .read_exact(&mut len)
+        .map_err(|_| ChainFileError::Format("truncated chain file".into()))?;
+    let mut bytes = vec![0u8; u32::from_be_bytes(len) as usize];
+    records
+        .read_exact(&mut bytes)
+        .map_err(|_| ChainFileError::Format("truncated chain file".into()))?;
+    Block::decode(&bytes).map_err(ChainFileError::Decode)
+}
@@ -0,0 +1,291 @@
+use crate::blockchain::{Block, BlockBody, Blockchain, BlockchainError};
+use crate::compact_block::{CompactBlock, PartialBlock, Reconstruction};
+use crate::encoding::Decode;
+use crate::mempool::{Mempool, MempoolError};
+use crate::transaction::Transaction;
+use log::info;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Gossipsub topic new blocks are announced on, as their binary encoding.
+pub const BLOCKS_TOPIC: &str = "blocks";
+/// Gossipsub topic new blocks are announced on as [`CompactBlock`]s.
+pub const COMPACT_BLOCKS_TOPIC: &str = "compact-blocks";
+/// Gossipsub topic new transactions are announced on, as their binary encoding.
+pub const TRANSACTIONS_TOPIC: &str = "transactions";
+/// Largest gossip message accepted off the wire, whatever its topic.
+pub const MAX_GOSSIP_MESSAGE_SIZE: usize = 2 * 1024 * 1024;
+/// Largest transaction accepted on [`TRANSACTIONS_TOPIC`].
+pub const MAX_TRANSACTION_MESSAGE_SIZE: usize = 64 * 1024;
+
+// How many block hashes and transaction IDs are remembered as seen.
+const SEEN_CAPACITY: usize = 10_000;
+// Compact blocks waiting for missing transactions at a time.
+const MAX_PENDING_COMPACT_BLOCKS: usize = 32;
+
+/// Chain and mempool a node validates gossip against and feeds it into.
+#[derive(Clone)]
+pub struct SharedChain {
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+}
+
+impl SharedChain {
+    pub fn new(blockchain: Blockchain, mempool: Mempool) -> Self {
+        SharedChain {
+            blockchain: Arc::new(Mutex::new(blockchain)),
+            mempool: Arc::new(Mutex::new(mempool)),
+        }
+    }
+}
+
+/// What happens to a gossiped message after validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GossipVerdict {
+    Accept,         // Valid and new, relay it.
+    Ignore,         // Already known or of no use right now, drop it quietly.
+    Reject(String), // Invalid, drop it and hold it against the sender.
+}
+
+/// What happens to a gossiped compact block after a first look.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactVerdict {
+    Done(GossipVerdict), // Rebuilt from the mempool, or not worth rebuilding.
+    Missing { hash: String, indexes: Vec<u32> }, // Transactions to ask the sender for.
+}
+
+/// Insertion ordered set that forgets its oldest entries once full.
+pub struct SeenCache {
+    capacity: usize,
+    entries: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl SeenCache {
+    pub fn new(capacity: usize) -> Self {
+        SeenCache {
+            capacity,
+            entries: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Records `id`, returning false if it was already there.
+    pub fn insert(&mut self, id: &str) -> bool {
+        if self.entries.contains(id) {
+            return false;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(id.to_string());
+        self.order.push_back(id.to_string());
+        true
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.entries.contains(id)
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        if !self.entries.remove(id) {
+            return false;
+        }
+        self.order.retain(|entry| entry != id);
+        true
+    }
+}
+
+/// Validates gossiped blocks and transactions and feeds the good ones into
+/// the chain and mempool. Each valid block and each transaction ID is only
+/// looked at once, whoever sends it. Block hashes are only remembered once
+/// the block checks out, as a bad body under a good header must not keep the
+/// real block out, and transaction IDs once the transaction was accepted or
+/// refused for good.
+pub struct GossipHandler {
+    chain: SharedChain,
+    seen_blocks: SeenCache,
+    seen_transactions: SeenCache,
+    pending: HashMap<String, PartialBlock>, // Compact blocks waiting for transactions.
+}
+
+impl GossipHandler {
+    pub fn new(chain: SharedChain) -> Self {
+        GossipHandler {
+            chain,
+            seen_blocks: SeenCache::new(SEEN_CAPACITY),
+            seen_transactions: SeenCache::new(SEEN_CAPACITY),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn chain(&self) -> &SharedChain {
+        &self.chain
+    }
+
+    /// Handles a block announced on [`BLOCKS_TOPIC`].
+    ///
+    /// Proof of work, limits and signatures are checked before anything
+    /// else. A valid block that doesn't extend our tip is ignored rather than
+    /// relayed; catching up on a branch is left to sync.
+    pub fn handle_block(&mut self, data: &[u8]) -> GossipVerdict {
+        let block = match Block::decode(data) {
+            Ok(block) => block,
+            Err(e) => return GossipVerdict::Reject(format!("undecodable block: {}", e)),
+        };
+        if self.seen_blocks.contains(block.get_hash()) {
+            return GossipVerdict::Ignore;
+        }
+        self.import_block(block)
+    }
+
+    /// Handles a block announced on [`COMPACT_BLOCKS_TOPIC`].
+    ///
+    /// Only blocks extending our tip with valid proof of work are rebuilt.
+    /// Transactions missing from the mempool have to be fetched from the
+    /// sender and handed to [`GossipHandler::fill_compact_block`].
+    pub fn handle_compact_block(&mut self, data: &[u8]) -> CompactVerdict {
+        let compact = match CompactBlock::decode(data) {
+            Ok(compact) => compact,
+            Err(e) => {
+                let reason = format!("undecodable compact block: {}", e);
+                return CompactVerdict::Done(GossipVerdict::Reject(reason));
+            }
+        };
+        let hash = compact.get_hash();
+        if self.seen_blocks.contains(&hash) || self.pending.contains_key(&hash) {
+            return CompactVerdict::Done(GossipVerdict::Ignore);
+        }
+
+        let reconstruction = {
+            let blockchain = self.chain.blockchain.lock().unwrap();
+            let tip = blockchain.get_chain().last().expect("chain has a tip");
+            if compact.get_header().get_previous_hash() != tip.get_hash() {
+                return CompactVerdict::Done(GossipVerdict::Ignore);
+            }
+            if let Err(e) =
+                blockchain.check_header_chain(tip.get_header(), &[compact.get_header().clone()])
+            {
+                return CompactVerdict::Done(GossipVerdict::Reject(e.to_string()));
+            }
+            compact.reconstruct(&self.chain.mempool.lock().unwrap())
+        };
+        match reconstruction {
+            Reconstruction::Complete(block) => CompactVerdict::Done(self.import_block(block)),
+            Reconstruction::Incomplete(partial) => {
+                if self.pending.len() >= MAX_PENDING_COMPACT_BLOCKS {
+                    return CompactVerdict::Done(GossipVerdict::Ignore);
+                }
+                let indexes = partial.get_missing();
+                self.pending.insert(hash.clone(), partial);
+                CompactVerdict::Missing { hash, indexes }
+            }
+        }
+    }
+
+    /// Completes a compact block with the transactions the sender returned.
+    /// Returns `None` if they don't make up the block, in which case the full
+    /// body has to be fetched and passed to
+    /// [`GossipHandler::complete_compact_block`].
+    pub fn fill_compact_block(
+        &mut self,
+        hash: &str,
+        transactions: Vec<Transaction>,
+    ) -> Option<GossipVerdict> {
+        let block = self.pending.get(hash)?.fill(transactions).ok()?;
+        self.pending.remove(hash);
+        Some(self.import_block(block))
+    }
+
+    /// Completes a compact block with its full body.
+    pub fn complete_compact_block(&mut self, hash: &str, body: BlockBody) -> GossipVerdict {
+        match self.pending.remove(hash) {
+            Some(partial) => self.import_block(Block::new(partial.get_header().clone(), body)),
+            None => GossipVerdict::Ignore,
+        }
+    }
+
+    /// Gives up on a compact block, so it's looked at again if someone else
+    /// announces it.
+    pub fn abandon_compact_block(&mut self, hash: &str) {
+        self.pending.remove(hash);
+    }
+
+    // Validates a block, remembering its hash if it's valid, and connects it
+    // if it extends our tip.
+    fn import_block(&mut self, block: Block) -> GossipVerdict {
+        let mut blockchain = self.chain.blockchain.lock().unwrap();
+        if blockchain
+            .get_index()
+            .get_block_height(block.get_hash())
+            .is_some()
+        {
+            return GossipVerdict::Ignore;
+        }
+        if let Err(e) = blockchain.check_block(&block) {
+            return GossipVerdict::Reject(e.to_string());
+        }
+        self.seen_blocks.insert(block.get_hash());
+        let tip_hash = blockchain.get_chain().last().map(|b| b.get_hash());
+        if tip_hash != Some(block.get_previous_hash()) {
+            return GossipVerdict::Ignore;
+        }
+
+        match blockchain.try_add_block(block.clone()) {
+            Ok(()) => {
+                info!("accepted gossiped block {}", block.get_hash());
+                self.chain
+                    .mempool
+                    .lock()
+                    .unwrap()
+                    .on_block_added(&block, blockchain.get_state());
+                GossipVerdict::Accept
+            }
+            Err(BlockchainError::Storage(_)) => GossipVerdict::Ignore,
+            Err(e) => GossipVerdict::Reject(e.to_string()),
+        }
+    }
+
+    /// Handles a transaction announced on [`TRANSACTIONS_TOPIC`].
+    pub fn handle_transaction(&mut self, data: &[u8]) -> GossipVerdict {
+        if data.len() > MAX_TRANSACTION_MESSAGE_SIZE {
+            return GossipVerdict::Reject(format!("transaction of {} bytes", data.len()));
+        }
+        let transaction = match Transaction::decode(data) {
+            Ok(transaction) => transaction,
+            Err(e) => return GossipVerdict::Reject(format!("undecodable transaction: {}", e)),
+        };
+        let id = transaction.id();
+        if self.seen_transactions.contains(&id) {
+            return GossipVerdict::Ignore;
+        }
+
+        let blockchain = self.chain.blockchain.lock().unwrap();
+        if blockchain
+            .get_index()
+            .get_transaction_location(&id)
+            .is_some()
+        {
+            self.seen_transactions.insert(&id);
+            return GossipVerdict::Ignore;
+        }
+        let mut mempool = self.chain.mempool.lock().unwrap();
+        let verdict = match mempool.add_transaction(transaction, blockchain.get_state()) {
+            Ok(_) => GossipVerdict::Accept,
+            Err(e @ (MempoolError::InvalidSignature | MempoolError::InvalidTransaction(_))) => {
+                GossipVerdict::Reject(e.to_string())
+            }
+            // Valid but not for us anymore, e.g. a nonce we've moved past.
+            Err(MempoolError::NonceTooLow { .. } | MempoolError::AlreadyKnown) => {
+                GossipVerdict::Ignore
+            }
+            // Refused for now, e.g. a full pool or funds still to arrive;
+            // it's looked at again when it's gossiped again.
+            Err(_) => return GossipVerdict::Ignore,
+        };
+        self.seen_transactions.insert(&id);
+        verdict
+    }
+}
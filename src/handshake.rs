@@ -0,0 +1,184 @@
+use crate::blockchain::Blockchain;
+use crate::encoding::{self, Decode, DecodeError, Encode, Reader};
+use crate::sync::{read_message, write_message};
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::StreamProtocol;
+use std::io;
+use std::time::Duration;
+
+/// Protocol the handshake runs on.
+pub const HANDSHAKE_PROTOCOL: &str = "/my-first-blockchain/handshake/1.0.0";
+/// Version of the node protocol. Peers have to agree on it to talk.
+pub const NODE_PROTOCOL_VERSION: u32 = 1;
+
+// A status is well under a kilobyte.
+const MAX_STATUS_SIZE: usize = 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a node tells a peer about itself right after connecting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub protocol_version: u32, // Version of the node protocol spoken.
+    pub chain_id: u64,         // Chain the node is on.
+    pub genesis_hash: String,  // Hash of the chain's first block.
+    pub best_height: u64,      // Height of the node's tip.
+    pub best_hash: String,     // Hash of the node's tip.
+    pub cumulative_work: u128, // Total work behind the node's tip.
+}
+
+/// Why a peer can't be talked to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    ProtocolVersion { ours: u32, theirs: u32 },
+    ChainId { ours: u64, theirs: u64 },
+    Genesis { ours: String, theirs: String },
+    Failed(String), // No usable status was received.
+}
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeError::ProtocolVersion { ours, theirs } => write!(
+                f,
+                "Peer speaks protocol version {}, we speak {}",
+                theirs, ours
+            ),
+            HandshakeError::ChainId { ours, theirs } => {
+                write!(f, "Peer is on chain {}, we are on chain {}", theirs, ours)
+            }
+            HandshakeError::Genesis { ours, theirs } => {
+                write!(f, "Peer has genesis block {}, ours is {}", theirs, ours)
+            }
+            HandshakeError::Failed(ref err) => write!(f, "Handshake failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl Status {
+    /// Our own status, as of the current tip of `blockchain`.
+    pub fn local(blockchain: &Blockchain) -> Self {
+        let chain = blockchain.get_chain();
+        Status {
+            protocol_version: NODE_PROTOCOL_VERSION,
+            chain_id: blockchain.get_spec().chain_id,
+            genesis_hash: chain[0].get_hash().to_string(),
+            best_height: chain.len() as u64 - 1,
+            best_hash: chain[chain.len() - 1].get_hash().to_string(),
+            cumulative_work: blockchain.get_cumulative_work(),
+        }
+    }
+
+    /// Checks that a peer announcing `theirs` is on the same network as us.
+    pub fn check_compatible(&self, theirs: &Status) -> Result<(), HandshakeError> {
+        if theirs.protocol_version != self.protocol_version {
+            return Err(HandshakeError::ProtocolVersion {
+                ours: self.protocol_version,
+                theirs: theirs.protocol_version,
+            });
+        }
+        if theirs.chain_id != self.chain_id {
+            return Err(HandshakeError::ChainId {
+                ours: self.chain_id,
+                theirs: theirs.chain_id,
+            });
+        }
+        if theirs.genesis_hash != self.genesis_hash {
+            return Err(HandshakeError::Genesis {
+                ours: self.genesis_hash.clone(),
+                theirs: theirs.genesis_hash.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Encode for Status {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encoding::put_u8(out, encoding::ENCODING_VERSION);
+        encoding::put_u32(out, self.protocol_version);
+        encoding::put_u64(out, self.chain_id);
+        encoding::put_str(out, &self.genesis_hash);
+        encoding::put_u64(out, self.best_height);
+        encoding::put_str(out, &self.best_hash);
+        encoding::put_u128(out, self.cumulative_work);
+    }
+}
+
+impl Decode for Status {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        Ok(Status {
+            protocol_version: reader.read_u32()?,
+            chain_id: reader.read_u64()?,
+            genesis_hash: reader.read_string()?,
+            best_height: reader.read_u64()?,
+            best_hash: reader.read_string()?,
+            cumulative_work: reader.read_u128()?,
+        })
+    }
+}
+
+/// Exchanges [`Status`]es: each side sends its own as the request and gets
+/// the peer's back as the response.
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeCodec;
+
+#[async_trait]
+impl request_response::Codec for HandshakeCodec {
+    type Protocol = StreamProtocol;
+    type Request = Status;
+    type Response = Status;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Status>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_STATUS_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Status>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_STATUS_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: Status,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: Status,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &response).await
+    }
+}
+
+/// The request-response behaviour the handshake runs on.
+pub fn behaviour() -> request_response::Behaviour<HandshakeCodec> {
+    request_response::Behaviour::new(
+        [(
+            StreamProtocol::new(HANDSHAKE_PROTOCOL),
+            ProtocolSupport::Full,
+        )],
+        request_response::Config::default().with_request_timeout(HANDSHAKE_TIMEOUT),
+    )
+}
@@ -0,0 +1,132 @@
+use crate::blockchain::Block;
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Where a transaction sits in the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxLocation {
+    pub block_hash: String,
+    pub height: u64,
+    pub position: usize,
+}
+
+/// Lookup tables over the blocks of the active chain.
+///
+/// Blocks are connected in height order and disconnected in reverse order
+/// during a reorg, so every table only ever reflects the active chain.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChainIndex {
+    tip: Option<String>, // Hash of the last connected block.
+    transactions: HashMap<String, TxLocation>,
+    addresses: HashMap<String, Vec<TxLocation>>,
+    block_heights: HashMap<String, u64>,
+}
+
+impl ChainIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the index for a whole chain.
+    pub fn from_blocks(blocks: &[Block]) -> Self {
+        let mut index = Self::new();
+        for block in blocks {
+            index.connect_block(block);
+        }
+        index
+    }
+
+    /// Hash of the block the index is up to date with.
+    pub fn tip(&self) -> Option<&str> {
+        self.tip.as_deref()
+    }
+
+    pub fn connect_block(&mut self, block: &Block) {
+        let block_hash = block.get_hash().to_string();
+        let height = block.get_index() as u64;
+
+        for (position, transaction) in block.get_data_raw().iter().enumerate() {
+            let location = TxLocation {
+                block_hash: block_hash.clone(),
+                height,
+                position,
+            };
+            for address in involved_addresses(transaction) {
+                self.addresses
+                    .entry(address.to_string())
+                    .or_default()
+                    .push(location.clone());
+            }
+            self.transactions.insert(transaction.id(), location);
+        }
+
+        self.block_heights.insert(block_hash.clone(), height);
+        self.tip = Some(block_hash);
+    }
+
+    /// Undoes `connect_block` for the current tip.
+    pub fn disconnect_block(&mut self, block: &Block) {
+        let block_hash = block.get_hash();
+
+        for transaction in block.get_data_raw() {
+            let key = transaction.id();
+            if self
+                .transactions
+                .get(&key)
+                .is_some_and(|location| location.block_hash == block_hash)
+            {
+                self.transactions.remove(&key);
+            }
+            for address in involved_addresses(transaction) {
+                if let Some(history) = self.addresses.get_mut(address) {
+                    history.retain(|location| location.block_hash != block_hash);
+                    if history.is_empty() {
+                        self.addresses.remove(address);
+                    }
+                }
+            }
+        }
+
+        self.block_heights.remove(block_hash);
+        self.tip = Some(block.get_previous_hash().to_string());
+    }
+
+    pub fn get_transaction_location(&self, transaction_id: &str) -> Option<&TxLocation> {
+        self.transactions.get(transaction_id)
+    }
+
+    /// Locations of every transaction sending to or from `address`, oldest first.
+    pub fn get_address_history(&self, address: &str) -> &[TxLocation] {
+        self.addresses.get(address).map_or(&[], |h| h.as_slice())
+    }
+
+    pub fn get_block_height(&self, block_hash: &str) -> Option<u64> {
+        self.block_heights.get(block_hash).copied()
+    }
+
+    /// Writes the index to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let data = serde_json::to_vec(self)?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Reads an index written by [`ChainIndex::save`], None if it's missing or
+    /// unreadable so the caller can rebuild it.
+    pub fn load(path: &Path) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+}
+
+fn involved_addresses(transaction: &Transaction) -> Vec<&str> {
+    let mut addresses = vec![transaction.get_receiver()];
+    if !transaction.is_coinbase() && transaction.get_sender() != transaction.get_receiver() {
+        addresses.push(transaction.get_sender());
+    }
+    addresses
+}
@@ -1,6 +1,29 @@
 pub mod account;
+pub mod address_book;
 pub mod blockchain;
+pub mod chain_spec;
+pub mod compact_block;
+pub mod config;
+pub mod encoding;
+pub mod events;
+pub mod explorer;
+pub mod export;
+pub mod gossip;
+pub mod handshake;
+pub mod index;
+pub mod limits;
+pub mod mempool;
 pub mod network_behaviour;
 pub mod p2p;
+pub mod reputation;
+pub mod rpc;
+pub mod rpc_client;
+pub mod simulation;
+pub mod snapshot;
+pub mod state;
+pub mod storage;
+pub mod sync;
 pub mod transaction;
 pub mod utils;
+pub mod wallet;
+pub mod ws;
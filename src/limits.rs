@@ -0,0 +1,188 @@
+use libp2p::PeerId;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Tokens refilled at a steady rate up to a burst size. Each message takes
+/// one; a peer that runs dry has to wait for the next.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,   // Tokens held at most, the largest burst allowed.
+    per_second: f64, // Tokens added every second.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(capacity: u32, per_second: f64, now: Instant) -> Self {
+        TokenBucket {
+            capacity: capacity as f64,
+            per_second,
+            tokens: capacity as f64,
+            updated: now,
+        }
+    }
+
+    /// Takes a token as of `now`, returning false if there was none left.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = self.updated.max(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Kinds of gossip that are rate limited separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Transaction,
+    Block, // Full and compact block announcements alike.
+}
+
+/// How much gossip a single peer may send us.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub transactions_per_second: f64, // Steady rate of new transactions.
+    pub transaction_burst: u32,       // Transactions accepted at once.
+    pub blocks_per_second: f64,       // Steady rate of block announcements.
+    pub block_burst: u32,             // Block announcements accepted at once.
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            transactions_per_second: 50.0,
+            transaction_burst: 200,
+            blocks_per_second: 2.0,
+            block_burst: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PeerBuckets {
+    transactions: TokenBucket,
+    blocks: TokenBucket,
+}
+
+/// Per-peer token buckets for each [`MessageKind`].
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    peers: HashMap<PeerId, PeerBuckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Whether `peer` may send another message of `kind` as of `now`. A
+    /// peer we haven't heard from before starts with full buckets.
+    pub fn allow(&mut self, peer: PeerId, kind: MessageKind, now: Instant) -> bool {
+        let config = &self.config;
+        let buckets = self.peers.entry(peer).or_insert_with(|| PeerBuckets {
+            transactions: TokenBucket::new(
+                config.transaction_burst,
+                config.transactions_per_second,
+                now,
+            ),
+            blocks: TokenBucket::new(config.block_burst, config.blocks_per_second, now),
+        });
+        match kind {
+            MessageKind::Transaction => buckets.transactions.try_take(now),
+            MessageKind::Block => buckets.blocks.try_take(now),
+        }
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+}
+
+/// How many peers we keep connections to.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits {
+    pub max_inbound: usize,  // Peers that connected to us.
+    pub max_outbound: usize, // Peers we connected to.
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            max_inbound: 32,
+            max_outbound: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Connection {
+    inbound: bool,  // Whether the peer dialed us.
+    since: Instant, // When the first connection was established.
+    useful: u64,    // Gossip messages of the peer we accepted.
+}
+
+/// Connected peers, which way they connected and how useful they've been,
+/// to pick whom to drop when there are too many.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionTracker {
+    peers: HashMap<PeerId, Connection>,
+}
+
+impl ConnectionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, peer: PeerId, inbound: bool, now: Instant) {
+        self.peers.entry(peer).or_insert(Connection {
+            inbound,
+            since: now,
+            useful: 0,
+        });
+    }
+
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    /// Credits `peer` with a message that turned out valid and new.
+    pub fn mark_useful(&mut self, peer: &PeerId) {
+        if let Some(connection) = self.peers.get_mut(peer) {
+            connection.useful += 1;
+        }
+    }
+
+    /// Connected peers that dialed us, or that we dialed.
+    pub fn count(&self, inbound: bool) -> usize {
+        self.peers
+            .values()
+            .filter(|connection| connection.inbound == inbound)
+            .count()
+    }
+
+    /// The inbound peer other than `except` that is least worth keeping:
+    /// the lowest `score`, then the fewest useful messages, then the most
+    /// recently connected.
+    pub fn least_useful_inbound(
+        &self,
+        except: &PeerId,
+        score: impl Fn(&PeerId) -> i64,
+    ) -> Option<PeerId> {
+        self.peers
+            .iter()
+            .filter(|(peer, connection)| connection.inbound && *peer != except)
+            .min_by_key(|(peer, connection)| {
+                (score(peer), connection.useful, Reverse(connection.since))
+            })
+            .map(|(peer, _)| *peer)
+    }
+}
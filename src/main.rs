@@ -1,64 +1,658 @@
-use my_first_blockchain::transaction;
-use my_first_blockchain::utils::{public_key_to_address, sign_transaction};
-use my_first_blockchain::{blockchain::Blockchain, utils::generate_key_pair};
-use std::sync::{Arc, Mutex};
-use transaction::Transaction;
+//! Command line interface of a full node.
+//!
+//! Every command reads the node config, `config.toml` unless `--config`
+//! says otherwise, and works on its data directory. Commands that open the
+//! chain directly (`mine`, `chain`, `tx`, and `wallet` with `--offline`)
+//! must not run while a node is running on the same data directory; the
+//! other `wallet` commands talk to a running node over JSON-RPC.
+
+use clap::{Args, Parser, Subcommand};
+use log::{debug, info, warn};
+use my_first_blockchain::blockchain::{Block, Blockchain, BlockchainError};
+use my_first_blockchain::chain_spec::ChainSpec;
+use my_first_blockchain::config::NodeConfig;
+use my_first_blockchain::encoding::Decode;
+use my_first_blockchain::events::{EventBus, NodeEvent};
+use my_first_blockchain::explorer::{ExplorerServer, TransactionDetail};
+use my_first_blockchain::export::{export_blocks, import_blocks};
+use my_first_blockchain::gossip::SharedChain;
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::p2p::{NetworkEvent, Node};
+use my_first_blockchain::rpc::{ChainInfo, RpcServer, TransactionView};
+use my_first_blockchain::rpc_client::RpcClient;
+use my_first_blockchain::transaction::Transaction;
+use my_first_blockchain::wallet::Wallet;
+use my_first_blockchain::ws::WsServer;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+type CliResult<T = ()> = Result<T, Box<dyn Error>>;
+
+const DEFAULT_CONFIG: &str = "config.toml";
+
+#[derive(Parser)]
+#[command(version, about = "Full node of my_first_blockchain")]
+struct Cli {
+    /// Node config file [default: config.toml, if it exists]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Data directory, overriding the one of the config file
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Writes the config, the chain spec and the genesis block
+    Init {
+        /// Chain spec to start the chain from [default: built-in spec]
+        #[arg(long)]
+        spec: Option<PathBuf>,
+    },
+    /// Runs the node
+    #[command(subcommand)]
+    Node(NodeCommand),
+    /// Mines blocks from the pending transactions
+    Mine {
+        /// Receiver of the block rewards [default: mining.address]
+        #[arg(long)]
+        address: Option<String>,
+        /// Blocks to mine
+        #[arg(long, default_value_t = 1)]
+        blocks: u64,
+    },
+    /// Manages the keys of the wallet
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+    /// Inspects and moves the chain in the data directory
+    #[command(subcommand)]
+    Chain(ChainCommand),
+    /// Inspects transactions
+    #[command(subcommand)]
+    Tx(TxCommand),
+}
+
+#[derive(Subcommand)]
+enum NodeCommand {
+    /// Connects to the network and serves the APIs until interrupted
+    Run(RunArgs),
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Multiaddr to listen on
+    #[arg(long)]
+    listen: Option<String>,
+    /// Multiaddr of a peer to dial, in addition to the configured ones
+    #[arg(long = "peer")]
+    peers: Vec<String>,
+    /// Address to serve JSON-RPC on
+    #[arg(long)]
+    rpc: Option<SocketAddr>,
+    /// Address to serve WebSocket subscriptions on
+    #[arg(long)]
+    ws: Option<SocketAddr>,
+    /// Address to serve the explorer API on
+    #[arg(long)]
+    explorer: Option<SocketAddr>,
+    /// Mines blocks paying their rewards to this address
+    #[arg(long)]
+    mine: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum WalletCommand {
+    /// Creates a new key and prints its address
+    New,
+    /// Prints the addresses of the wallet
+    List,
+    /// Prints the balance of an address, or of every wallet address
+    Balance {
+        address: Option<String>,
+        /// Reads the data directory instead of asking the node
+        #[arg(long)]
+        offline: bool,
+    },
+    /// Signs a transfer and hands it to the node
+    Send {
+        /// Wallet address to send from
+        #[arg(long)]
+        from: String,
+        /// Address to send to
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long, default_value_t = 1)]
+        fee: u64,
+        /// Nonce to use [default: the next free one]
+        #[arg(long)]
+        nonce: Option<u64>,
+        /// Adds the transfer to the mempool file instead of the node
+        #[arg(long)]
+        offline: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ChainCommand {
+    /// Prints a summary of the chain as JSON
+    Info,
+    /// Checks every block of the chain
+    Validate,
+    /// Writes blocks to a chain file
+    Export {
+        path: PathBuf,
+        /// First height to write
+        #[arg(long, default_value_t = 0)]
+        from: usize,
+        /// Height to stop before [default: the tip]
+        #[arg(long)]
+        to: Option<usize>,
+        /// Gzips the blocks
+        #[arg(long)]
+        compress: bool,
+    },
+    /// Adds the blocks of a chain file to the chain
+    Import { path: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum TxCommand {
+    /// Prints a transaction as JSON, given its ID or hex encoding
+    Inspect { transaction: String },
+}
 
 fn main() {
-    // Create a new blockchain
-    let mut blockchain1 = Blockchain::new();
-
-    // Generate some key pairs for the transactions
-    let (prikey1, pubkey1) = generate_key_pair();
-    let (_, pubkey2) = generate_key_pair();
-
-    // Create some transactions
-    let transaction1 = vec![Transaction::new(
-        public_key_to_address(&pubkey1),
-        public_key_to_address(&pubkey2),
-        10,
-        0,
-        sign_transaction(
-            prikey1,
-            public_key_to_address(&pubkey1),
-            public_key_to_address(&pubkey2),
-            10,
-            0,
-        ),
-    )];
-
-    println!("Difficulity is: {}", blockchain1.get_difficulty());
-
-    // Mine a block with this transaction
-
-    let arc_blockchain = Arc::new(Mutex::new(blockchain1.clone()));
-    let transactions = Arc::new(transaction1.clone());
-    blockchain1.mine_block(transactions, Arc::clone(&arc_blockchain));
-
-    let mut blockchain = arc_blockchain.lock().unwrap();
-
-    // Display the blockchain
-    blockchain.print_chain();
-
-    // Check if the blockchain is valid
-    if blockchain.is_chain_valid() {
-        println!("The blockchain is valid.");
+    let filters = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+    pretty_env_logger::formatted_builder()
+        .parse_filters(&filters)
+        .init();
+
+    let cli = Cli::parse();
+    if let Err(e) = run(cli) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> CliResult {
+    let config_path = cli
+        .config
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG));
+    if let Command::Init { spec } = &cli.command {
+        return init(&config_path, cli.data_dir, spec.as_deref());
+    }
+
+    let mut config = if config_path.exists() {
+        NodeConfig::load(&config_path)?
+    } else if cli.config.is_some() {
+        return Err(format!("config file {} not found", config_path.display()).into());
     } else {
-        println!("The blockchain is not valid.");
+        NodeConfig::default()
+    };
+    if let Some(data_dir) = cli.data_dir {
+        config.data_dir = data_dir;
+    }
+
+    match cli.command {
+        Command::Init { .. } => unreachable!("handled above"),
+        Command::Node(NodeCommand::Run(args)) => {
+            apply_run_args(&mut config, args);
+            tokio::runtime::Runtime::new()?.block_on(run_node(config))
+        }
+        Command::Mine { address, blocks } => {
+            let address = address
+                .or_else(|| config.mining.address.clone())
+                .ok_or("no reward address, pass --address or set mining.address")?;
+            mine(&config, &address, blocks)
+        }
+        Command::Wallet(command) => wallet(&config, command),
+        Command::Chain(command) => chain(&config, command),
+        Command::Tx(TxCommand::Inspect { transaction }) => inspect(&config, &transaction),
     }
+}
 
-    // Verify the transaction's signature
-    let transaction = &blockchain.get_chain()[1].get_data_raw()[0];
-    if transaction.verify_signature() {
-        println!("The transaction's signature is valid.");
+// Writes the config unless it exists, then the chain spec and the genesis
+// block unless the data directory already holds a chain.
+fn init(config_path: &Path, data_dir: Option<PathBuf>, spec: Option<&Path>) -> CliResult {
+    let mut config = if config_path.exists() {
+        NodeConfig::load(config_path)?
     } else {
-        println!("The transaction's signature is not valid.");
+        NodeConfig::default()
+    };
+    if let Some(data_dir) = data_dir {
+        config.data_dir = data_dir;
+    }
+    if !config_path.exists() {
+        config.save(config_path)?;
+        println!("wrote {}", config_path.display());
     }
 
-    // Check the transaction's inclusion in the Merkle tree
-    if blockchain.check_transaction_validity(&transaction1[0]).unwrap_or(false) {
-        println!("The transaction is included in the Merkle tree.");
+    let spec_path = config.spec_path();
+    if spec_path.exists() {
+        // Running init again is fine, as long as it's for the same chain.
+        if let Some(path) = spec {
+            if ChainSpec::load(path)? != config.load_spec()? {
+                return Err(format!(
+                    "{} already holds a chain with a different spec",
+                    config.data_dir.display()
+                )
+                .into());
+            }
+        }
     } else {
-        println!("The transaction is not included in the Merkle tree.");
+        let spec = match spec {
+            Some(path) => ChainSpec::load(path)?,
+            None => ChainSpec::default(),
+        };
+        std::fs::create_dir_all(&config.data_dir)?;
+        spec.save(&spec_path)?;
+    }
+
+    let blockchain = config.open_chain()?;
+    println!(
+        "chain {} in {}, genesis {}, height {}",
+        blockchain.get_spec().chain_id,
+        config.data_dir.display(),
+        blockchain.get_chain()[0].get_hash(),
+        blockchain.get_chain_length() - 1
+    );
+    Ok(())
+}
+
+fn apply_run_args(config: &mut NodeConfig, args: RunArgs) {
+    if let Some(listen) = args.listen {
+        config.network.listen = listen;
+    }
+    config.network.peers.extend(args.peers);
+    for (service, addr) in [
+        (&mut config.rpc, args.rpc),
+        (&mut config.ws, args.ws),
+        (&mut config.explorer, args.explorer),
+    ] {
+        if let Some(addr) = addr {
+            service.enabled = true;
+            service.listen = addr;
+        }
     }
+    if let Some(address) = args.mine {
+        config.mining.enabled = true;
+        config.mining.address = Some(address);
+    }
+}
+
+async fn run_node(config: NodeConfig) -> CliResult {
+    let bus = EventBus::default();
+    let mut blockchain = config.open_chain()?;
+    blockchain.set_event_bus(bus.clone());
+    let mut mempool = Mempool::load(
+        &config.mempool_path(),
+        MempoolConfig::default(),
+        blockchain.get_state(),
+    )?;
+    mempool.set_event_bus(bus.clone());
+    info!(
+        "opened chain {} at height {}",
+        blockchain.get_spec().chain_id,
+        blockchain.get_chain_length() - 1
+    );
+    let chain = SharedChain::new(blockchain, mempool);
+    // Subscribed before anything can change the chain, so no block goes
+    // unannounced.
+    let mut events = bus.subscribe();
+
+    let keypair = config.load_node_key()?;
+    let mut node = Node::start(config.network_config(keypair)?, chain.clone()).await?;
+    info!("peer ID {}", node.peer_id());
+
+    let _rpc = match config.rpc.enabled {
+        true => Some(RpcServer::start(config.rpc.listen, chain.clone())?),
+        false => None,
+    };
+    let _ws = match config.ws.enabled {
+        true => Some(WsServer::start(config.ws.listen, bus.clone())?),
+        false => None,
+    };
+    let _explorer = match config.explorer.enabled {
+        true => Some(ExplorerServer::start(
+            config.explorer.listen,
+            chain.clone(),
+        )?),
+        false => None,
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (mined_tx, mut mined) = mpsc::unbounded_channel();
+    let miner = match config.mining.enabled {
+        true => {
+            let address = config
+                .mining
+                .address
+                .clone()
+                .ok_or("mining is enabled but mining.address is not set")?;
+            Some(spawn_miner(
+                &config,
+                chain.clone(),
+                address,
+                Arc::clone(&stop),
+                mined_tx,
+            ))
+        }
+        false => None,
+    };
+
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            event = node.next_event() => match event {
+                Some(event) => log_network_event(event),
+                None => break,
+            },
+            // Only our own blocks are announced; gossip relays those of
+            // peers, and synced blocks are old news.
+            Some(block) = mined.recv() => {
+                if let Err(e) = node.publish_block(&block).await {
+                    debug!("block not announced: {}", e);
+                }
+            }
+            event = events.recv() => match event {
+                Ok(NodeEvent::BlockConnected(block)) => {
+                    info!("block {} at height {}", block.get_hash(), block.get_index());
+                }
+                // Transactions from peers come through here too; gossip drops
+                // the copies its peers already have.
+                Ok(NodeEvent::Mempool(change)) => {
+                    if let Some(transaction) = change.admitted() {
+                        if let Err(e) = node.publish_transaction(transaction).await {
+                            debug!("transaction not announced: {}", e);
+                        }
+                    }
+                }
+                Ok(NodeEvent::Reorganized { fork_height, disconnected, connected }) => info!(
+                    "reorganized at height {}: {} blocks out, {} in",
+                    fork_height,
+                    disconnected.len(),
+                    connected.len()
+                ),
+                Err(RecvError::Lagged(missed)) => warn!("missed {} chain events", missed),
+                Err(RecvError::Closed) => break,
+            },
+            _ = &mut shutdown => {
+                info!("shutting down");
+                break;
+            }
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    if let Some(miner) = miner {
+        let _ = miner.join();
+    }
+    node.shutdown().await;
+    chain.mempool.lock().unwrap().save(&config.mempool_path())?;
+    Ok(())
+}
+
+fn log_network_event(event: NetworkEvent) {
+    match event {
+        NetworkEvent::PeerConnected(peer) => info!("connected to {}", peer),
+        NetworkEvent::PeerDisconnected(peer) => info!("disconnected from {}", peer),
+        NetworkEvent::PeerIncompatible { peer, reason } => {
+            warn!("peer {} is incompatible: {}", peer, reason)
+        }
+        NetworkEvent::PeerBanned(peer) => warn!("banned {}", peer),
+        event => debug!("{:?}", event),
+    }
+}
+
+// Mines on a thread of its own, at most one block per configured block
+// time, until `stop` is set. The chain is only locked to prepare the block
+// and to add it, never during the nonce search. Blocks that make it into
+// the chain are sent to `mined` for announcing.
+fn spawn_miner(
+    config: &NodeConfig,
+    chain: SharedChain,
+    address: String,
+    stop: Arc<AtomicBool>,
+    mined: UnboundedSender<Block>,
+) -> JoinHandle<()> {
+    let block_time = Duration::from_secs(config.mining.block_time_secs);
+    info!("mining for {}", address);
+    thread::spawn(move || {
+        let mut last_block: Option<Instant> = None;
+        while !stop.load(Ordering::Relaxed) {
+            if last_block.is_some_and(|started| started.elapsed() < block_time) {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            last_block = Some(Instant::now());
+            let prepared = {
+                let blockchain = chain.blockchain.lock().unwrap();
+                let mempool = chain.mempool.lock().unwrap();
+                let transactions = blockchain.block_template(&mempool, &address);
+                blockchain.prepare_next_block(transactions, now())
+            };
+            let mut block = match prepared {
+                Ok(block) => block,
+                Err(e) => {
+                    warn!("failed to mine a block: {}", e);
+                    continue;
+                }
+            };
+            block.mine();
+            let mut blockchain = chain.blockchain.lock().unwrap();
+            match blockchain.try_add_block(block.clone()) {
+                Ok(()) => {
+                    chain
+                        .mempool
+                        .lock()
+                        .unwrap()
+                        .on_block_added(&block, blockchain.get_state());
+                    let _ = mined.send(block);
+                }
+                // Most likely a peer's block arrived first.
+                Err(e) => debug!("mined block dropped: {}", e),
+            }
+        }
+    })
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+fn mine(config: &NodeConfig, address: &str, blocks: u64) -> CliResult {
+    let mut blockchain = config.open_chain()?;
+    let mut mempool = load_mempool(config, &blockchain)?;
+    for _ in 0..blocks {
+        let transactions = blockchain.block_template(&mempool, address);
+        let block = blockchain.mine_next_block(transactions, now())?;
+        blockchain.try_add_block(block.clone())?;
+        mempool.on_block_added(&block, blockchain.get_state());
+        println!(
+            "mined block {} at height {} with {} transactions",
+            block.get_hash(),
+            block.get_index(),
+            block.get_data_raw().len()
+        );
+    }
+    mempool.save(&config.mempool_path())?;
+    Ok(())
+}
+
+fn load_mempool(config: &NodeConfig, blockchain: &Blockchain) -> CliResult<Mempool> {
+    Ok(Mempool::load(
+        &config.mempool_path(),
+        MempoolConfig::default(),
+        blockchain.get_state(),
+    )?)
+}
+
+fn wallet(config: &NodeConfig, command: WalletCommand) -> CliResult {
+    let path = config.wallet_path();
+    let mut wallet = Wallet::load(&path)?;
+    match command {
+        WalletCommand::New => {
+            let address = wallet.generate_key();
+            wallet.save(&path)?;
+            println!("{}", address);
+        }
+        WalletCommand::List => {
+            for address in wallet.get_addresses() {
+                println!("{}", address);
+            }
+        }
+        WalletCommand::Balance { address, offline } => {
+            let addresses = match address {
+                Some(address) => vec![address],
+                None => wallet
+                    .get_addresses()
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            };
+            if offline {
+                let blockchain = config.open_chain()?;
+                for address in addresses {
+                    println!("{}: {}", address, blockchain.get_balance(&address));
+                }
+            } else {
+                let client = RpcClient::new(config.rpc.listen);
+                for address in addresses {
+                    println!("{}: {}", address, client.get_balance(&address)?);
+                }
+            }
+        }
+        WalletCommand::Send {
+            from,
+            to,
+            amount,
+            fee,
+            nonce,
+            offline,
+        } => {
+            if !wallet.contains(&from) {
+                return Err(format!("no key for {} in {}", from, path.display()).into());
+            }
+            if offline {
+                let blockchain = config.open_chain()?;
+                let mut mempool = load_mempool(config, &blockchain)?;
+                let nonce = nonce.unwrap_or_else(|| {
+                    // Goes after whatever of ours is already waiting.
+                    let mut nonce = blockchain.get_nonce(&from);
+                    while mempool.get(&from, nonce).is_some() {
+                        nonce += 1;
+                    }
+                    nonce
+                });
+                let transaction = wallet.sign_transfer(&from, &to, amount, fee, nonce)?;
+                mempool.add_transaction(transaction.clone(), blockchain.get_state())?;
+                mempool.save(&config.mempool_path())?;
+                println!("{}", transaction.id());
+            } else {
+                let client = RpcClient::new(config.rpc.listen);
+                let nonce = match nonce {
+                    Some(nonce) => nonce,
+                    None => client.get_nonce(&from)?,
+                };
+                let transaction = wallet.sign_transfer(&from, &to, amount, fee, nonce)?;
+                println!("{}", client.send_raw_transaction(&transaction)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn chain(config: &NodeConfig, command: ChainCommand) -> CliResult {
+    let mut blockchain = config.open_chain()?;
+    match command {
+        ChainCommand::Info => {
+            let mempool = load_mempool(config, &blockchain)?;
+            let info = ChainInfo::new(&blockchain, mempool.len());
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        }
+        ChainCommand::Validate => {
+            blockchain
+                .validate_chain()
+                .map_err(|e| format!("the chain is invalid: {}", e))?;
+            println!("{} blocks valid", blockchain.get_chain_length());
+        }
+        ChainCommand::Export {
+            path,
+            from,
+            to,
+            compress,
+        } => {
+            let to = to.unwrap_or_else(|| blockchain.get_chain_length());
+            let written = export_blocks(&blockchain, &path, from..to, compress)?;
+            println!("exported {} blocks to {}", written, path.display());
+        }
+        ChainCommand::Import { path } => {
+            let progress = import_blocks(&mut blockchain, &path, |progress| {
+                if progress.height % 1000 == 0 {
+                    info!("imported up to height {}", progress.height);
+                }
+            })?;
+            println!(
+                "imported {} blocks, skipped {}, height {}",
+                progress.imported,
+                progress.skipped,
+                blockchain.get_chain_length() - 1
+            );
+        }
+    }
+    Ok(())
+}
+
+// Accepts a hex encoded transaction or the ID of one that is mined or
+// pending, and prints it with where it is and whether its signature holds.
+fn inspect(config: &NodeConfig, input: &str) -> CliResult {
+    let blockchain = config.open_chain()?;
+    let mempool = load_mempool(config, &blockchain)?;
+    let decoded = hex::decode(input)
+        .ok()
+        .and_then(|bytes| Transaction::decode(&bytes).ok());
+    let id = decoded
+        .as_ref()
+        .map(Transaction::id)
+        .unwrap_or_else(|| input.to_string());
+
+    let (transaction, location) = match blockchain.get_transaction_by_id(&id) {
+        Ok((transaction, location)) => (transaction.clone(), Some(location.clone())),
+        Err(BlockchainError::TransactionNotFound) => {
+            let transaction = decoded
+                .or_else(|| {
+                    mempool
+                        .iter()
+                        .find(|transaction| transaction.id() == id)
+                        .cloned()
+                })
+                .ok_or_else(|| format!("transaction {} not found", id))?;
+            (transaction, None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let detail = TransactionDetail {
+        transaction: TransactionView::new(&transaction, location.as_ref()),
+        confirmations: location.map_or(0, |location| {
+            (blockchain.get_chain_length() as u64).saturating_sub(location.height)
+        }),
+    };
+
+    let mut value = serde_json::to_value(detail)?;
+    value["signatureValid"] = transaction.verify_signature().into();
+    println!("{}", serde_json::to_string_pretty(&value)?);
+    Ok(())
 }
@@ -0,0 +1,576 @@
+use crate::blockchain::Block;
+use crate::chain_spec::ChainSpec;
+use crate::encoding::Encode;
+use crate::events::{EventBus, NodeEvent};
+use crate::state::WorldState;
+use crate::transaction::Transaction;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Version written into mempool dump files.
+pub const MEMPOOL_FILE_VERSION: u32 = 1;
+
+/// Limits applied to the pool of pending transactions.
+#[derive(Debug, Clone)]
+pub struct MempoolConfig {
+    pub max_transactions: usize, // Entries kept before the cheapest ones get evicted.
+    pub replace_bump_percent: u64, // Fee increase required to replace a pending transaction.
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        MempoolConfig {
+            max_transactions: 4096,
+            replace_bump_percent: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    InvalidTransaction(String),
+    InvalidSignature,
+    NonceTooLow {
+        expected: u64,
+        got: u64,
+    },
+    InsufficientBalance {
+        balance: u64,
+        required: u64,
+    },
+    AlreadyKnown,
+    ReplacementUnderpriced {
+        existing_fee: u64,
+        required_fee: u64,
+    },
+    MempoolFull,
+    Persistence(String),
+}
+
+impl std::fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MempoolError::InvalidTransaction(ref err) => write!(f, "Invalid transaction: {}", err),
+            MempoolError::InvalidSignature => write!(f, "Invalid signature"),
+            MempoolError::NonceTooLow { expected, got } => {
+                write!(
+                    f,
+                    "Nonce too low: expected at least {}, got {}",
+                    expected, got
+                )
+            }
+            MempoolError::InsufficientBalance { balance, required } => write!(
+                f,
+                "Insufficient balance: have {}, need {}",
+                balance, required
+            ),
+            MempoolError::AlreadyKnown => write!(f, "Transaction already known"),
+            MempoolError::ReplacementUnderpriced {
+                existing_fee,
+                required_fee,
+            } => write!(
+                f,
+                "Replacement underpriced: existing fee {}, need at least {}",
+                existing_fee, required_fee
+            ),
+            MempoolError::MempoolFull => write!(f, "Mempool is full"),
+            MempoolError::Persistence(ref err) => write!(f, "Mempool persistence error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// Where an admitted transaction ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddOutcome {
+    Pending, // Next in line for its sender, can go into a block.
+    Queued,  // Waiting for an earlier nonce of the same sender.
+}
+
+/// Changes to the pool, published on the event bus for whoever relays or
+/// displays them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolEvent {
+    Added(Transaction),
+    Evicted(Transaction),
+    Removed(Transaction),
+    Replaced { old: Transaction, new: Transaction },
+}
+
+impl MempoolEvent {
+    /// The transaction this change admitted to the pool, if any.
+    pub fn admitted(&self) -> Option<&Transaction> {
+        match self {
+            MempoolEvent::Added(transaction)
+            | MempoolEvent::Replaced {
+                new: transaction, ..
+            } => Some(transaction),
+            MempoolEvent::Evicted(_) | MempoolEvent::Removed(_) => None,
+        }
+    }
+}
+
+// On-disk layout of a mempool dump.
+#[derive(Serialize, Deserialize)]
+struct MempoolFile {
+    version: u32,
+    transactions: Vec<Transaction>,
+}
+
+/// Pool of transactions waiting to be mined.
+///
+/// Transactions are kept per sender ordered by nonce. Those whose nonce
+/// directly follows the sender's account nonce are pending, the rest are
+/// queued until the gap is filled.
+pub struct Mempool {
+    config: MempoolConfig,
+    transactions: HashMap<String, BTreeMap<u64, Transaction>>,
+    bus: Option<EventBus>, // Where changes to the pool are announced, if anywhere.
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Mempool {
+            config,
+            transactions: HashMap::new(),
+            bus: None,
+        }
+    }
+
+    /// Announces changes to the pool from now on to `bus`.
+    pub fn set_event_bus(&mut self, bus: EventBus) {
+        self.bus = Some(bus);
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.values().map(|txs| txs.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn contains(&self, transaction: &Transaction) -> bool {
+        self.get(transaction.get_sender(), transaction.get_nonce()) == Some(transaction)
+    }
+
+    pub fn get(&self, sender: &str, nonce: u64) -> Option<&Transaction> {
+        self.transactions.get(sender)?.get(&nonce)
+    }
+
+    /// Every transaction in the pool, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions.values().flat_map(|txs| txs.values())
+    }
+
+    /// Validates a transaction against `state` and admits it to the pool.
+    pub fn add_transaction(
+        &mut self,
+        transaction: Transaction,
+        state: &WorldState,
+    ) -> Result<AddOutcome, MempoolError> {
+        self.validate(&transaction, state)?;
+
+        // A replacement takes the slot of the transaction it replaces.
+        let replaced = self.remove(transaction.get_sender(), transaction.get_nonce());
+        if replaced.is_none() && self.len() >= self.config.max_transactions {
+            self.evict_for(&transaction)?;
+        }
+
+        let outcome = if self.is_ready(&transaction, state) {
+            AddOutcome::Pending
+        } else {
+            AddOutcome::Queued
+        };
+
+        self.transactions
+            .entry(transaction.get_sender().to_string())
+            .or_default()
+            .insert(transaction.get_nonce(), transaction.clone());
+        match replaced {
+            Some(old) => {
+                info!(
+                    "replaced transaction {}:{} in the mempool",
+                    old.get_sender(),
+                    old.get_nonce()
+                );
+                let sender = transaction.get_sender().to_string();
+                self.emit(MempoolEvent::Replaced {
+                    old,
+                    new: transaction,
+                });
+                // A pricier replacement may leave later nonces unaffordable.
+                self.prune_sender(&sender, state);
+            }
+            None => self.emit(MempoolEvent::Added(transaction)),
+        }
+        Ok(outcome)
+    }
+
+    fn validate(&self, transaction: &Transaction, state: &WorldState) -> Result<(), MempoolError> {
+        if transaction.is_coinbase() {
+            return Err(MempoolError::InvalidTransaction(
+                "coinbase transactions can't be relayed".into(),
+            ));
+        }
+        if !transaction.is_valid() {
+            return Err(MempoolError::InvalidTransaction(
+                "malformed transaction".into(),
+            ));
+        }
+        if !transaction.verify_signature() {
+            return Err(MempoolError::InvalidSignature);
+        }
+
+        let sender = transaction.get_sender();
+        let account_nonce = state.get_nonce(sender);
+        if transaction.get_nonce() < account_nonce {
+            return Err(MempoolError::NonceTooLow {
+                expected: account_nonce,
+                got: transaction.get_nonce(),
+            });
+        }
+        if let Some(existing) = self.get(sender, transaction.get_nonce()) {
+            if existing == transaction {
+                return Err(MempoolError::AlreadyKnown);
+            }
+            self.check_replacement(existing, transaction)?;
+        }
+
+        // Earlier transactions of the same sender spend first.
+        let balance = state.get_balance(sender);
+        let required = self
+            .transactions
+            .get(sender)
+            .map(|txs| {
+                txs.range(..transaction.get_nonce())
+                    .map(|(_, tx)| tx.total_cost())
+                    .sum::<u64>()
+            })
+            .unwrap_or(0)
+            .saturating_add(transaction.total_cost());
+        if required > balance {
+            return Err(MempoolError::InsufficientBalance { balance, required });
+        }
+
+        Ok(())
+    }
+
+    // A replacement has to pay at least `replace_bump_percent` more fee than
+    // the transaction it replaces, and strictly more than it.
+    fn check_replacement(
+        &self,
+        existing: &Transaction,
+        replacement: &Transaction,
+    ) -> Result<(), MempoolError> {
+        let existing_fee = existing.get_fee();
+        let bump = existing_fee.saturating_mul(self.config.replace_bump_percent) / 100;
+        let required_fee = existing_fee.saturating_add(bump.max(1));
+        if replacement.get_fee() < required_fee {
+            return Err(MempoolError::ReplacementUnderpriced {
+                existing_fee,
+                required_fee,
+            });
+        }
+        Ok(())
+    }
+
+    // Every nonce between the account nonce and this one is already pooled
+    fn is_ready(&self, transaction: &Transaction, state: &WorldState) -> bool {
+        let sender = transaction.get_sender();
+        (state.get_nonce(sender)..transaction.get_nonce())
+            .all(|nonce| self.get(sender, nonce).is_some())
+    }
+
+    // Makes room for `incoming` by dropping the cheapest evictable entry.
+    // Only the highest nonce of each sender is a candidate so eviction never
+    // leaves a gap behind.
+    fn evict_for(&mut self, incoming: &Transaction) -> Result<(), MempoolError> {
+        let cheapest = self
+            .transactions
+            .values()
+            .filter_map(|txs| txs.values().next_back())
+            .min_by_key(|tx| tx.fee_rate())
+            .cloned();
+
+        match cheapest {
+            Some(victim) if victim.fee_rate() < incoming.fee_rate() => {
+                info!(
+                    "evicting transaction {}:{} from the mempool",
+                    victim.get_sender(),
+                    victim.get_nonce()
+                );
+                self.remove(victim.get_sender(), victim.get_nonce());
+                self.emit(MempoolEvent::Evicted(victim));
+                Ok(())
+            }
+            _ => Err(MempoolError::MempoolFull),
+        }
+    }
+
+    fn remove(&mut self, sender: &str, nonce: u64) -> Option<Transaction> {
+        let txs = self.transactions.get_mut(sender)?;
+        let removed = txs.remove(&nonce);
+        if txs.is_empty() {
+            self.transactions.remove(sender);
+        }
+        removed
+    }
+
+    /// Transactions that can be mined on top of `state`, per sender in nonce order.
+    pub fn pending(&self, state: &WorldState) -> Vec<Transaction> {
+        self.ready_by_sender(state)
+            .into_values()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Transactions waiting for an earlier nonce of their sender.
+    pub fn queued(&self, state: &WorldState) -> Vec<Transaction> {
+        let ready = self.ready_by_sender(state);
+        self.transactions
+            .iter()
+            .flat_map(|(sender, txs)| {
+                let skip = ready.get(sender.as_str()).map_or(0, |r| r.len());
+                txs.values().skip(skip)
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn ready_by_sender(&self, state: &WorldState) -> HashMap<&str, Vec<&Transaction>> {
+        let mut ready = HashMap::new();
+        for (sender, txs) in &self.transactions {
+            let mut expected = state.get_nonce(sender);
+            let run: Vec<&Transaction> = txs
+                .range(expected..)
+                .take_while(|(nonce, _)| {
+                    let next = **nonce == expected;
+                    expected += 1;
+                    next
+                })
+                .map(|(_, tx)| tx)
+                .collect();
+            if !run.is_empty() {
+                ready.insert(sender.as_str(), run);
+            }
+        }
+        ready
+    }
+
+    /// Picks up to `max` pending transactions, highest fee rate first while
+    /// keeping each sender's transactions in nonce order.
+    pub fn select_transactions(&self, state: &WorldState, max: usize) -> Vec<Transaction> {
+        let ready = self.ready_by_sender(state);
+        let mut heap: BinaryHeap<(u64, &str, usize)> = ready
+            .iter()
+            .map(|(sender, txs)| (txs[0].fee_rate(), *sender, 0))
+            .collect();
+
+        let mut selected = Vec::new();
+        while selected.len() < max {
+            let Some((_, sender, index)) = heap.pop() else {
+                break;
+            };
+            let txs = &ready[sender];
+            selected.push(txs[index].clone());
+            if let Some(next) = txs.get(index + 1) {
+                heap.push((next.fee_rate(), sender, index + 1));
+            }
+        }
+        selected
+    }
+
+    /// Like [`Mempool::select_transactions`] but stops at the block size,
+    /// transaction count and gas limits of `spec`. `reserved_bytes` is the
+    /// size of the block without any transactions.
+    pub fn select_block_transactions(
+        &self,
+        state: &WorldState,
+        spec: &ChainSpec,
+        reserved_bytes: usize,
+    ) -> Vec<Transaction> {
+        let ready = self.ready_by_sender(state);
+        let mut heap: BinaryHeap<(u64, &str, usize)> = ready
+            .iter()
+            .map(|(sender, txs)| (txs[0].fee_rate(), *sender, 0))
+            .collect();
+
+        let mut selected = Vec::new();
+        let mut size = reserved_bytes;
+        let mut gas = 0u64;
+        while selected.len() < spec.max_block_transactions {
+            let Some((_, sender, index)) = heap.pop() else {
+                break;
+            };
+            let transaction = ready[sender][index];
+            let tx_size = transaction.encode().len();
+            let tx_gas = spec.transaction_gas(transaction);
+            // Skipping one transaction also skips the sender's later nonces.
+            if size + tx_size > spec.max_block_size || gas + tx_gas > spec.max_block_gas {
+                continue;
+            }
+            size += tx_size;
+            gas += tx_gas;
+            selected.push(transaction.clone());
+            if let Some(next) = ready[sender].get(index + 1) {
+                heap.push((next.fee_rate(), sender, index + 1));
+            }
+        }
+        selected
+    }
+
+    /// Drops transactions included in `block` and any that conflict with the
+    /// resulting `state`.
+    pub fn on_block_added(&mut self, block: &Block, state: &WorldState) {
+        for transaction in block.get_data_raw() {
+            if let Some(removed) = self.remove(transaction.get_sender(), transaction.get_nonce()) {
+                self.emit(MempoolEvent::Removed(removed));
+            }
+        }
+        self.prune(state);
+    }
+
+    /// Returns transactions of `disconnected` blocks to the pool, then removes
+    /// everything included in `connected` blocks.
+    pub fn on_reorg(&mut self, disconnected: &[Block], connected: &[Block], state: &WorldState) {
+        for block in connected {
+            for transaction in block.get_data_raw() {
+                if let Some(removed) =
+                    self.remove(transaction.get_sender(), transaction.get_nonce())
+                {
+                    self.emit(MempoolEvent::Removed(removed));
+                }
+            }
+        }
+        for block in disconnected {
+            for transaction in block.get_data_raw() {
+                if transaction.is_coinbase() {
+                    continue;
+                }
+                // Anything no longer valid on the new branch is simply dropped.
+                let _ = self.add_transaction(transaction.clone(), state);
+            }
+        }
+        self.prune(state);
+    }
+
+    /// Removes transactions whose nonce is already used or that the sender
+    /// can no longer afford.
+    pub fn prune(&mut self, state: &WorldState) {
+        let senders: Vec<String> = self.transactions.keys().cloned().collect();
+        for sender in senders {
+            self.prune_sender(&sender, state);
+        }
+    }
+
+    fn prune_sender(&mut self, sender: &str, state: &WorldState) {
+        let Some(txs) = self.transactions.get_mut(sender) else {
+            return;
+        };
+        let account_nonce = state.get_nonce(sender);
+        let mut budget = state.get_balance(sender);
+        let mut dropped = Vec::new();
+        txs.retain(|nonce, tx| {
+            let keep = *nonce >= account_nonce && tx.total_cost() <= budget;
+            if keep {
+                budget -= tx.total_cost();
+            } else {
+                dropped.push(tx.clone());
+            }
+            keep
+        });
+        if txs.is_empty() {
+            self.transactions.remove(sender);
+        }
+        for transaction in dropped {
+            self.emit(MempoolEvent::Removed(transaction));
+        }
+    }
+
+    fn emit(&self, event: MempoolEvent) {
+        if let Some(bus) = &self.bus {
+            bus.publish(NodeEvent::Mempool(event));
+        }
+    }
+
+    /// Writes every pooled transaction to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<(), MempoolError> {
+        let mut transactions: Vec<Transaction> = self
+            .transactions
+            .values()
+            .flat_map(|txs| txs.values())
+            .cloned()
+            .collect();
+        transactions
+            .sort_by(|a, b| (a.get_sender(), a.get_nonce()).cmp(&(b.get_sender(), b.get_nonce())));
+        let file = MempoolFile {
+            version: MEMPOOL_FILE_VERSION,
+            transactions,
+        };
+        let data =
+            serde_json::to_vec(&file).map_err(|e| MempoolError::Persistence(e.to_string()))?;
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data).map_err(|e| MempoolError::Persistence(e.to_string()))?;
+        fs::rename(&tmp_path, path).map_err(|e| MempoolError::Persistence(e.to_string()))
+    }
+
+    /// Loads a dump written by [`Mempool::save`], revalidating every
+    /// transaction against `state`. Transactions that became invalid while the
+    /// node was down are dropped. A missing file yields an empty mempool.
+    pub fn load(
+        path: &Path,
+        config: MempoolConfig,
+        state: &WorldState,
+    ) -> Result<Mempool, MempoolError> {
+        let mut mempool = Mempool::new(config);
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(mempool),
+            Err(e) => return Err(MempoolError::Persistence(e.to_string())),
+        };
+
+        // A dump we can't understand must not keep the node from starting.
+        let file: MempoolFile = match serde_json::from_slice::<serde_json::Value>(&data) {
+            Ok(value) => match value.get("version").and_then(|v| v.as_u64()) {
+                Some(version) if version == MEMPOOL_FILE_VERSION as u64 => {
+                    serde_json::from_value(value)
+                        .map_err(|e| MempoolError::Persistence(e.to_string()))?
+                }
+                version => {
+                    warn!("ignoring mempool file with version {:?}", version);
+                    return Ok(mempool);
+                }
+            },
+            Err(e) => {
+                warn!("ignoring unreadable mempool file: {}", e);
+                return Ok(mempool);
+            }
+        };
+
+        let total = file.transactions.len();
+        for transaction in file.transactions {
+            if let Err(e) = mempool.add_transaction(transaction, state) {
+                warn!("dropping persisted transaction: {}", e);
+            }
+        }
+        info!(
+            "restored {} of {} mempool transactions",
+            mempool.len(),
+            total
+        );
+        Ok(mempool)
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(MempoolConfig::default())
+    }
+}
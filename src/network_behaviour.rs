@@ -1 +1,100 @@
+use crate::gossip::MAX_GOSSIP_MESSAGE_SIZE;
+use crate::handshake::{self, HandshakeCodec};
+use crate::sync::{self, SyncCodec, SyncConfig};
+use libp2p::allow_block_list::{self, BlockedPeers};
+use libp2p::gossipsub::{self, MessageAuthenticity, MessageId};
+use libp2p::identity::Keypair;
+use libp2p::kad::{self, store::MemoryStore};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::{identify, mdns, ping, request_response, StreamProtocol};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
 
+/// Protocol version nodes advertise through identify.
+pub const PROTOCOL_VERSION: &str = "/my-first-blockchain/1.0.0";
+/// Protocol of our Kademlia DHT, kept apart from other networks' DHTs.
+pub const KAD_PROTOCOL: &str = "/my-first-blockchain/kad/1.0.0";
+
+/// Everything a node speaks on a connection.
+///
+/// Identify tells us who a peer is and where it listens, the handshake
+/// whether it's on our chain and how far along, ping keeps idle
+/// connections alive and measures latency, gossipsub floods blocks and
+/// transactions through the network and sync serves headers and bodies to
+/// nodes catching up. Kademlia and, on local networks, mDNS find peers to
+/// connect to. Banned peers are refused any connection at all.
+#[derive(NetworkBehaviour)]
+pub struct NodeBehaviour {
+    pub identify: identify::Behaviour,
+    pub handshake: request_response::Behaviour<HandshakeCodec>,
+    pub ping: ping::Behaviour,
+    pub gossipsub: gossipsub::Behaviour,
+    pub sync: request_response::Behaviour<SyncCodec>,
+    pub kademlia: Toggle<kad::Behaviour<MemoryStore>>,
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    pub blocked: allow_block_list::Behaviour<BlockedPeers>,
+}
+
+impl NodeBehaviour {
+    pub fn new(
+        keypair: &Keypair,
+        sync_config: &SyncConfig,
+        enable_kademlia: bool,
+        enable_mdns: bool,
+    ) -> Result<Self, String> {
+        let identify = identify::Behaviour::new(
+            identify::Config::new(PROTOCOL_VERSION.to_string(), keypair.public())
+                .with_agent_version(format!("my-first-blockchain/{}", env!("CARGO_PKG_VERSION"))),
+        );
+
+        // Messages are identified by their content so the same block or
+        // transaction published by two nodes is only relayed once. Nothing is
+        // relayed until the node has validated it, and nothing larger than a
+        // full block is read at all.
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .heartbeat_interval(Duration::from_millis(500))
+            .heartbeat_initial_delay(Duration::from_millis(100))
+            .message_id_fn(|message| MessageId::from(Sha256::digest(&message.data).to_vec()))
+            .max_transmit_size(MAX_GOSSIP_MESSAGE_SIZE)
+            .validate_messages()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let gossipsub = gossipsub::Behaviour::new(
+            MessageAuthenticity::Signed(keypair.clone()),
+            gossipsub_config,
+        )?;
+
+        // Always answer DHT queries; without a confirmed external address
+        // Kademlia would otherwise stay a client and never be found.
+        let peer_id = keypair.public().to_peer_id();
+        let kademlia = enable_kademlia.then(|| {
+            let mut kademlia = kad::Behaviour::with_config(
+                peer_id,
+                MemoryStore::new(peer_id),
+                kad::Config::new(StreamProtocol::new(KAD_PROTOCOL)),
+            );
+            kademlia.set_mode(Some(kad::Mode::Server));
+            kademlia
+        });
+        let mdns = if enable_mdns {
+            Some(
+                mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)
+                    .map_err(|e| e.to_string())?,
+            )
+        } else {
+            None
+        };
+
+        Ok(NodeBehaviour {
+            identify,
+            handshake: handshake::behaviour(),
+            ping: ping::Behaviour::new(ping::Config::new()),
+            gossipsub,
+            sync: sync::behaviour(sync_config),
+            kademlia: kademlia.into(),
+            mdns: mdns.into(),
+            blocked: allow_block_list::Behaviour::default(),
+        })
+    }
+}
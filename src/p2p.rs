@@ -1 +1,1043 @@
+use crate::address_book::AddressBook;
+use crate::blockchain::Block;
+use crate::compact_block::CompactBlock;
+use crate::encoding::{Decode, Encode};
+use crate::gossip::{
+    CompactVerdict, GossipHandler, GossipVerdict, SharedChain, BLOCKS_TOPIC, COMPACT_BLOCKS_TOPIC,
+    TRANSACTIONS_TOPIC,
+};
+use crate::handshake::{HandshakeError, Status};
+use crate::limits::{
+    ConnectionLimits, ConnectionTracker, MessageKind, RateLimitConfig, RateLimiter,
+};
+use crate::network_behaviour::{NodeBehaviour, NodeBehaviourEvent, KAD_PROTOCOL};
+use crate::reputation::{BanList, Misbehaviour, Reputation, ReputationConfig};
+use crate::sync::{self, SyncConfig, SyncEvent, SyncRequest, SyncResponse, Synchronizer};
+use crate::transaction::Transaction;
+use futures::StreamExt;
+use libp2p::gossipsub::{self, IdentTopic, MessageAcceptance, MessageId};
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::OutboundRequestId;
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{
+    identify, kad, mdns, noise, ping, request_response, tcp, yamux, Multiaddr, PeerId, Swarm,
+    SwarmBuilder,
+};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 
+/// How a node joins the network.
+#[derive(Clone)]
+pub struct NetworkConfig {
+    pub keypair: Keypair,       // Identity of the node, its peer ID derives from it.
+    pub listen_addr: Multiaddr, // Where to accept connections.
+    pub peers: Vec<Multiaddr>,  // Peers dialed right after starting.
+    pub idle_connection_timeout: Duration, // How long a silent connection stays open.
+    pub sync: SyncConfig,       // How blocks are downloaded from peers.
+    pub bootstrap_peers: Vec<Multiaddr>, // DHT entry points, each ending in /p2p/<peer id>.
+    pub kademlia: bool,         // Whether to find peers through the DHT.
+    pub mdns: bool,             // Whether to look for peers on the local network.
+    pub address_book: Option<PathBuf>, // Where known peers are remembered across restarts.
+    pub reputation: ReputationConfig, // When misbehaving peers get banned.
+    pub ban_list: Option<PathBuf>, // Where bans are remembered across restarts.
+    pub rate_limits: RateLimitConfig, // How much gossip each peer may send.
+    pub connection_limits: ConnectionLimits, // How many peers we stay connected to.
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            keypair: Keypair::generate_ed25519(),
+            listen_addr: "/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr"),
+            peers: Vec::new(),
+            idle_connection_timeout: Duration::from_secs(60),
+            sync: SyncConfig::default(),
+            bootstrap_peers: Vec::new(),
+            kademlia: true,
+            mdns: false,
+            address_book: None,
+            reputation: ReputationConfig::default(),
+            ban_list: None,
+            rate_limits: RateLimitConfig::default(),
+            connection_limits: ConnectionLimits::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkError {
+    Transport(String),
+    Behaviour(String),
+    Publish(String),
+    Stopped,
+}
+
+impl std::fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkError::Transport(ref err) => write!(f, "Transport error: {}", err),
+            NetworkError::Behaviour(ref err) => write!(f, "Network behaviour error: {}", err),
+            NetworkError::Publish(ref err) => write!(f, "Publish failed: {}", err),
+            NetworkError::Stopped => write!(f, "The network task has stopped"),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+/// Something that happened on the network, reported in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    Listening(Multiaddr),
+    PeerConnected(PeerId),
+    PeerDisconnected(PeerId),
+    HandshakeCompleted {
+        peer: PeerId,
+        status: Status,
+    },
+    PeerIncompatible {
+        peer: PeerId,
+        reason: String,
+    },
+    PeerDiscovered {
+        peer: PeerId,
+        address: Multiaddr,
+    },
+    PeerIdentified {
+        peer: PeerId,
+        protocol_version: String,
+        agent_version: String,
+    },
+    Pinged {
+        peer: PeerId,
+        rtt: Duration,
+    },
+    Subscribed {
+        peer: PeerId,
+        topic: String,
+    },
+    Message {
+        source: PeerId, // Peer that relayed the message to us.
+        topic: String,
+        data: Vec<u8>,
+    },
+    BlockAccepted {
+        source: PeerId,
+        hash: String,
+    },
+    CompactBlockIncomplete {
+        source: PeerId,
+        hash: String,
+        missing: usize, // Transactions we had to ask the sender for.
+    },
+    TransactionAccepted {
+        source: PeerId,
+        id: String,
+    },
+    GossipRejected {
+        source: PeerId,
+        topic: String,
+        reason: String,
+    },
+    RateLimited {
+        source: PeerId,
+        topic: String,
+    },
+    Sync(SyncEvent),
+    PeerEvicted(PeerId), // Disconnected to make room for another peer.
+    PeerBanned(PeerId),
+    PeerUnbanned(PeerId),
+}
+
+enum Command {
+    Dial(Multiaddr, oneshot::Sender<Result<(), NetworkError>>),
+    Subscribe(String, oneshot::Sender<Result<(), NetworkError>>),
+    Publish(String, Vec<u8>, oneshot::Sender<Result<(), NetworkError>>),
+    ListenAddrs(oneshot::Sender<Vec<Multiaddr>>),
+    ConnectedPeers(oneshot::Sender<Vec<PeerId>>),
+    Sync,
+    Ban(PeerId, Duration),
+    Unban(PeerId),
+    BannedPeers(oneshot::Sender<Vec<(PeerId, u64)>>),
+    Shutdown,
+}
+
+/// Handle to a running node. The swarm itself is driven on a tokio task and
+/// talked to through channels, so the handle can be used from anywhere.
+pub struct Node {
+    peer_id: PeerId,
+    commands: mpsc::UnboundedSender<Command>,
+    events: mpsc::UnboundedReceiver<NetworkEvent>,
+    task: JoinHandle<()>,
+}
+
+impl Node {
+    /// Starts listening on `config.listen_addr` and dials `config.peers`,
+    /// the bootstrap peers and every peer in the address book. Gossiped
+    /// blocks and transactions are validated against and fed into `chain`.
+    /// Every new peer has to complete a handshake showing it's on the same
+    /// chain, and is asked for blocks we're missing if it claims more work
+    /// than we have. Peers on the ban list are refused until their ban runs
+    /// out. Gossip beyond a peer's rate limit is dropped and held against
+    /// it, and the least useful peers are evicted once all connection slots
+    /// are taken.
+    /// Returns once the listener is up, so [`Node::listen_addrs`] is never
+    /// empty.
+    pub async fn start(config: NetworkConfig, chain: SharedChain) -> Result<Node, NetworkError> {
+        let mut swarm = SwarmBuilder::with_existing_identity(config.keypair)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default().nodelay(true),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .map_err(|e| NetworkError::Transport(e.to_string()))?
+            .with_behaviour(|keypair| {
+                NodeBehaviour::new(keypair, &config.sync, config.kademlia, config.mdns)
+                    .map_err(Into::into)
+            })
+            .map_err(|e| NetworkError::Behaviour(e.to_string()))?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(config.idle_connection_timeout))
+            .build();
+
+        for topic in [BLOCKS_TOPIC, COMPACT_BLOCKS_TOPIC, TRANSACTIONS_TOPIC] {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(&IdentTopic::new(topic))
+                .map_err(|e| NetworkError::Behaviour(e.to_string()))?;
+        }
+        swarm
+            .listen_on(config.listen_addr)
+            .map_err(|e| NetworkError::Transport(e.to_string()))?;
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let listen_addr = loop {
+            match swarm.select_next_some().await {
+                SwarmEvent::NewListenAddr { address, .. } => break address,
+                SwarmEvent::ListenerError { error, .. } => {
+                    return Err(NetworkError::Transport(error.to_string()))
+                }
+                _ => {}
+            }
+        };
+        info!("listening on {}", listen_addr);
+        let _ = events_tx.send(NetworkEvent::Listening(listen_addr.clone()));
+
+        let ban_list = config
+            .ban_list
+            .as_deref()
+            .map(BanList::load)
+            .unwrap_or_default();
+        for (peer, _) in ban_list.get_bans() {
+            swarm.behaviour_mut().blocked.block_peer(peer);
+        }
+
+        for peer in config.peers {
+            if let Err(e) = swarm.dial(peer.clone()) {
+                warn!("Failed to dial {}: {}", peer, e);
+            }
+        }
+        let address_book = config
+            .address_book
+            .as_deref()
+            .map(AddressBook::load)
+            .unwrap_or_default();
+        let mut known_peers = address_book.get_peers();
+        for addr in config.bootstrap_peers {
+            match addr.iter().last() {
+                Some(Protocol::P2p(peer)) => known_peers.push((peer, vec![addr])),
+                _ => warn!("Ignoring bootstrap peer {} without a /p2p/ peer ID", addr),
+            }
+        }
+        known_peers.retain(|(peer, _)| !ban_list.is_banned(peer));
+        for (peer, addresses) in &known_peers {
+            for addr in addresses {
+                add_to_dht(&mut swarm, peer, addr.clone());
+            }
+            let dial = DialOpts::peer_id(*peer)
+                .addresses(addresses.clone())
+                .build();
+            if let Err(e) = swarm.dial(dial) {
+                warn!("Failed to dial {}: {}", peer, e);
+            }
+        }
+        if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+            // Fills our routing table and connects us to the peers found.
+            if !known_peers.is_empty() {
+                let _ = kademlia.bootstrap();
+            }
+        }
+
+        let peer_id = *swarm.local_peer_id();
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let driver = Driver {
+            swarm,
+            commands: commands_rx,
+            events: events_tx,
+            listen_addrs: vec![listen_addr],
+            sync: Synchronizer::new(config.sync, chain.clone()),
+            gossip: GossipHandler::new(chain),
+            address_book,
+            address_book_path: config.address_book,
+            reputation: Reputation::new(config.reputation, ban_list),
+            ban_list_path: config.ban_list,
+            statuses: HashMap::new(),
+            compact_requests: HashMap::new(),
+            rate_limiter: RateLimiter::new(config.rate_limits),
+            connections: ConnectionTracker::new(),
+            connection_limits: config.connection_limits,
+        };
+        let task = tokio::spawn(driver.run());
+
+        Ok(Node {
+            peer_id,
+            commands,
+            events,
+            task,
+        })
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Addresses the node accepts connections on.
+    pub async fn listen_addrs(&self) -> Vec<Multiaddr> {
+        self.request(Command::ListenAddrs).await.unwrap_or_default()
+    }
+
+    pub async fn connected_peers(&self) -> Vec<PeerId> {
+        self.request(Command::ConnectedPeers)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Starts connecting to `addr`. The connection is reported as a
+    /// [`NetworkEvent::PeerConnected`] once established.
+    pub async fn dial(&self, addr: Multiaddr) -> Result<(), NetworkError> {
+        self.request(|reply| Command::Dial(addr, reply)).await?
+    }
+
+    pub async fn subscribe(&self, topic: &str) -> Result<(), NetworkError> {
+        let topic = topic.to_string();
+        self.request(|reply| Command::Subscribe(topic, reply))
+            .await?
+    }
+
+    /// Broadcasts `data` to every peer subscribed to `topic`.
+    pub async fn publish(&self, topic: &str, data: Vec<u8>) -> Result<(), NetworkError> {
+        let topic = topic.to_string();
+        self.request(|reply| Command::Publish(topic, data, reply))
+            .await?
+    }
+
+    /// Announces a block, normally one we just mined, to the network. It
+    /// goes out in compact form, leaving out the transactions peers already
+    /// have in their mempools.
+    pub async fn publish_block(&self, block: &Block) -> Result<(), NetworkError> {
+        self.publish(
+            COMPACT_BLOCKS_TOPIC,
+            CompactBlock::from_block(block).encode(),
+        )
+        .await
+    }
+
+    /// Announces a transaction, normally one we just admitted to our mempool.
+    pub async fn publish_transaction(&self, transaction: &Transaction) -> Result<(), NetworkError> {
+        self.publish(TRANSACTIONS_TOPIC, transaction.encode()).await
+    }
+
+    /// Asks connected peers for blocks we're missing. Happens by itself
+    /// whenever a peer connects; the outcome is reported as a
+    /// [`NetworkEvent::Sync`] if anything was downloaded.
+    pub async fn sync(&self) -> Result<(), NetworkError> {
+        self.commands
+            .send(Command::Sync)
+            .map_err(|_| NetworkError::Stopped)
+    }
+
+    /// Disconnects `peer` and refuses it for `duration`.
+    pub async fn ban_peer(&self, peer: PeerId, duration: Duration) -> Result<(), NetworkError> {
+        self.commands
+            .send(Command::Ban(peer, duration))
+            .map_err(|_| NetworkError::Stopped)
+    }
+
+    pub async fn unban_peer(&self, peer: PeerId) -> Result<(), NetworkError> {
+        self.commands
+            .send(Command::Unban(peer))
+            .map_err(|_| NetworkError::Stopped)
+    }
+
+    /// Banned peers with the Unix time their ban ends at.
+    pub async fn banned_peers(&self) -> Vec<(PeerId, u64)> {
+        self.request(Command::BannedPeers).await.unwrap_or_default()
+    }
+
+    /// Waits for the next network event, None once the node has stopped.
+    pub async fn next_event(&mut self) -> Option<NetworkEvent> {
+        self.events.recv().await
+    }
+
+    /// Closes every connection and waits for the network task to finish.
+    pub async fn shutdown(self) {
+        let _ = self.commands.send(Command::Shutdown);
+        let _ = self.task.await;
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> Result<T, NetworkError> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .map_err(|_| NetworkError::Stopped)?;
+        response.await.map_err(|_| NetworkError::Stopped)
+    }
+}
+
+// Owns the swarm and runs on its own task until shut down.
+struct Driver {
+    swarm: Swarm<NodeBehaviour>,
+    commands: mpsc::UnboundedReceiver<Command>,
+    events: mpsc::UnboundedSender<NetworkEvent>,
+    listen_addrs: Vec<Multiaddr>,
+    gossip: GossipHandler,
+    sync: Synchronizer,
+    address_book: AddressBook,
+    address_book_path: Option<PathBuf>,
+    reputation: Reputation,
+    ban_list_path: Option<PathBuf>,
+    statuses: HashMap<PeerId, Status>, // Peers that completed the handshake.
+    compact_requests: HashMap<OutboundRequestId, CompactRequest>,
+    rate_limiter: RateLimiter,
+    connections: ConnectionTracker,
+    connection_limits: ConnectionLimits,
+}
+
+// A compact block whose validation waits for data from the peer that sent it.
+struct CompactRequest {
+    source: PeerId,
+    message_id: MessageId,
+    hash: String,
+    data: Vec<u8>, // The compact block as gossiped.
+}
+
+impl Driver {
+    async fn run(mut self) {
+        let mut ban_expiry = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                event = self.swarm.select_next_some() => self.handle_swarm_event(event),
+                command = self.commands.recv() => match command {
+                    Some(Command::Shutdown) | None => break,
+                    Some(command) => self.handle_command(command),
+                },
+                _ = ban_expiry.tick() => self.expire_bans(),
+            }
+            if let Some(event) = self.sync.poll(&mut self.swarm.behaviour_mut().sync) {
+                self.emit(NetworkEvent::Sync(event));
+            }
+            for (peer, misbehaviour) in self.sync.drain_penalties() {
+                self.penalize(peer, misbehaviour);
+            }
+        }
+        self.save_address_book();
+        self.save_ban_list();
+        info!("network task for {} stopped", self.swarm.local_peer_id());
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Dial(addr, reply) => {
+                let result = self
+                    .swarm
+                    .dial(addr)
+                    .map_err(|e| NetworkError::Transport(e.to_string()));
+                let _ = reply.send(result);
+            }
+            Command::Subscribe(topic, reply) => {
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .subscribe(&IdentTopic::new(topic))
+                    .map(|_| ())
+                    .map_err(|e| NetworkError::Behaviour(e.to_string()));
+                let _ = reply.send(result);
+            }
+            Command::Publish(topic, data, reply) => {
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .publish(IdentTopic::new(topic), data)
+                    .map(|_| ())
+                    .map_err(|e| NetworkError::Publish(e.to_string()));
+                let _ = reply.send(result);
+            }
+            Command::ListenAddrs(reply) => {
+                let _ = reply.send(self.listen_addrs.clone());
+            }
+            Command::ConnectedPeers(reply) => {
+                let _ = reply.send(self.swarm.connected_peers().copied().collect());
+            }
+            Command::Sync => self.sync.start(),
+            Command::Ban(peer, duration) => self.ban(peer, duration),
+            Command::Unban(peer) => {
+                if self.reputation.unban(&peer) {
+                    self.lift_ban(peer);
+                }
+            }
+            Command::BannedPeers(reply) => {
+                let _ = reply.send(self.reputation.get_ban_list().get_bans());
+            }
+            Command::Shutdown => {}
+        }
+    }
+
+    fn handle_swarm_event(&mut self, event: SwarmEvent<NodeBehaviourEvent>) {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                self.listen_addrs.push(address.clone());
+                self.emit(NetworkEvent::Listening(address));
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } if num_established.get() == 1 => {
+                info!("connected to {}", peer_id);
+                if endpoint.is_dialer() {
+                    self.remember(peer_id, vec![endpoint.get_remote_address().clone()]);
+                }
+                if !self.admit(peer_id, endpoint.is_listener()) {
+                    return;
+                }
+                // Both sides send their status, so each learns the other's
+                // from whichever arrives first.
+                let status = self.local_status();
+                self.swarm
+                    .behaviour_mut()
+                    .handshake
+                    .send_request(&peer_id, status);
+                self.emit(NetworkEvent::PeerConnected(peer_id));
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                info!("disconnected from {}", peer_id);
+                self.statuses.remove(&peer_id);
+                self.sync.remove_peer(&peer_id);
+                self.connections.remove(&peer_id);
+                self.rate_limiter.remove_peer(&peer_id);
+                self.emit(NetworkEvent::PeerDisconnected(peer_id));
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                warn!("Failed to connect to {:?}: {}", peer_id, error);
+            }
+            SwarmEvent::Behaviour(event) => self.handle_behaviour_event(event),
+            _ => {}
+        }
+    }
+
+    fn handle_behaviour_event(&mut self, event: NodeBehaviourEvent) {
+        match event {
+            NodeBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. }) => {
+                if info.protocols.iter().any(|p| p.as_ref() == KAD_PROTOCOL) {
+                    for addr in &info.listen_addrs {
+                        add_to_dht(&mut self.swarm, &peer_id, addr.clone());
+                    }
+                }
+                self.remember(peer_id, info.listen_addrs.clone());
+                self.emit(NetworkEvent::PeerIdentified {
+                    peer: peer_id,
+                    protocol_version: info.protocol_version,
+                    agent_version: info.agent_version,
+                });
+            }
+            NodeBehaviourEvent::Ping(ping::Event {
+                peer,
+                result: Ok(rtt),
+                ..
+            }) => {
+                self.emit(NetworkEvent::Pinged { peer, rtt });
+            }
+            NodeBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed { peer_id, topic }) => {
+                self.emit(NetworkEvent::Subscribed {
+                    peer: peer_id,
+                    topic: topic.into_string(),
+                });
+            }
+            NodeBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            }) => self.handle_gossip(propagation_source, message_id, message),
+            NodeBehaviourEvent::Handshake(event) => self.handle_handshake_event(event),
+            NodeBehaviourEvent::Sync(event) => self.handle_sync_event(event),
+            NodeBehaviourEvent::Mdns(mdns::Event::Discovered(found)) => {
+                for (peer, address) in found {
+                    add_to_dht(&mut self.swarm, &peer, address.clone());
+                    self.discovered(peer, vec![address]);
+                }
+            }
+            NodeBehaviourEvent::Kademlia(kad::Event::RoutingUpdated {
+                peer,
+                is_new_peer: true,
+                addresses,
+                ..
+            }) => self.discovered(peer, addresses.iter().cloned().collect()),
+            _ => {}
+        }
+    }
+
+    // Connects to a peer mDNS or the DHT told us about, unless we already are.
+    fn discovered(&mut self, peer: PeerId, addresses: Vec<Multiaddr>) {
+        let Some(address) = addresses.first().cloned() else {
+            return;
+        };
+        if self.reputation.is_banned(&peer) {
+            return;
+        }
+        info!("discovered {} at {}", peer, address);
+        self.emit(NetworkEvent::PeerDiscovered { peer, address });
+        let outbound_full = self.connections.count(false) >= self.connection_limits.max_outbound;
+        if !self.swarm.is_connected(&peer) && !outbound_full {
+            let dial = DialOpts::peer_id(peer).addresses(addresses).build();
+            if let Err(e) = self.swarm.dial(dial) {
+                warn!("Failed to dial {}: {}", peer, e);
+            }
+        }
+    }
+
+    // Keeps a new peer within the connection limits. Once inbound slots are
+    // full the least useful other inbound peer makes room; a peer we dialed
+    // beyond our outbound slots is dropped again. Returns whether the new
+    // peer stays.
+    fn admit(&mut self, peer: PeerId, inbound: bool) -> bool {
+        self.connections.add(peer, inbound, Instant::now());
+        if inbound {
+            if self.connections.count(true) <= self.connection_limits.max_inbound {
+                return true;
+            }
+            let reputation = &self.reputation;
+            let evicted = self
+                .connections
+                .least_useful_inbound(&peer, |p| reputation.get_score(p))
+                .unwrap_or(peer);
+            self.evict(evicted);
+            evicted != peer
+        } else {
+            if self.connections.count(false) <= self.connection_limits.max_outbound {
+                return true;
+            }
+            self.evict(peer);
+            false
+        }
+    }
+
+    fn evict(&mut self, peer: PeerId) {
+        info!("evicting {} to stay within the connection limits", peer);
+        self.connections.remove(&peer);
+        let _ = self.swarm.disconnect_peer_id(peer);
+        self.emit(NetworkEvent::PeerEvicted(peer));
+    }
+
+    // Notes where a peer listens, saving the address book if that's news.
+    fn remember(&mut self, peer: PeerId, addresses: Vec<Multiaddr>) {
+        let mut changed = false;
+        for addr in addresses {
+            changed |= self.address_book.add_address(peer, addr);
+        }
+        if changed {
+            self.save_address_book();
+        }
+    }
+
+    fn save_address_book(&self) {
+        if let Some(path) = &self.address_book_path {
+            if let Err(e) = self.address_book.save(path) {
+                warn!("Failed to save the address book: {}", e);
+            }
+        }
+    }
+
+    // Holds `misbehaviour` against `peer`, banning it once it has done
+    // too much.
+    fn penalize(&mut self, peer: PeerId, misbehaviour: Misbehaviour) {
+        if self.reputation.report(peer, misbehaviour) {
+            self.block(peer);
+        }
+    }
+
+    fn ban(&mut self, peer: PeerId, duration: Duration) {
+        self.reputation.ban(peer, duration);
+        self.block(peer);
+    }
+
+    // Closes every connection to a newly banned peer and refuses new ones.
+    fn block(&mut self, peer: PeerId) {
+        self.swarm.behaviour_mut().blocked.block_peer(peer);
+        self.sync.remove_peer(&peer);
+        self.save_ban_list();
+        self.emit(NetworkEvent::PeerBanned(peer));
+    }
+
+    fn expire_bans(&mut self) {
+        for peer in self.reputation.expire_bans() {
+            self.lift_ban(peer);
+        }
+    }
+
+    fn lift_ban(&mut self, peer: PeerId) {
+        info!("ban of {} lifted", peer);
+        self.swarm.behaviour_mut().blocked.unblock_peer(peer);
+        self.save_ban_list();
+        self.emit(NetworkEvent::PeerUnbanned(peer));
+    }
+
+    fn save_ban_list(&self) {
+        if let Some(path) = &self.ban_list_path {
+            if let Err(e) = self.reputation.get_ban_list().save(path) {
+                warn!("Failed to save the ban list: {}", e);
+            }
+        }
+    }
+
+    fn handle_handshake_event(&mut self, event: request_response::Event<Status, Status>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                let status = self.local_status();
+                // Fails only if the peer has gone away in the meantime.
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .handshake
+                    .send_response(channel, status);
+                self.on_status(peer, request);
+            }
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { response, .. },
+            } => self.on_status(peer, response),
+            // A peer that hung up in the meantime isn't incompatible.
+            request_response::Event::OutboundFailure { peer, error, .. }
+                if self.swarm.is_connected(&peer) && !self.statuses.contains_key(&peer) =>
+            {
+                self.incompatible(peer, HandshakeError::Failed(error.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    // Checks the status a peer sent us, syncing from it if it's ahead.
+    fn on_status(&mut self, peer: PeerId, status: Status) {
+        let ours = self.local_status();
+        if let Err(e) = ours.check_compatible(&status) {
+            self.incompatible(peer, e);
+            return;
+        }
+        self.sync.add_peer(peer, status.cumulative_work);
+        if status.cumulative_work > ours.cumulative_work {
+            self.sync.start();
+        }
+        if self.statuses.insert(peer, status.clone()).is_none() {
+            info!(
+                "{} is at height {} with work {}",
+                peer, status.best_height, status.cumulative_work
+            );
+            self.emit(NetworkEvent::HandshakeCompleted { peer, status });
+        }
+    }
+
+    // Drops a peer on another network and forgets where to find it.
+    fn incompatible(&mut self, peer: PeerId, error: HandshakeError) {
+        warn!("Disconnecting incompatible peer {}: {}", peer, error);
+        self.statuses.remove(&peer);
+        if let Some(kademlia) = self.swarm.behaviour_mut().kademlia.as_mut() {
+            kademlia.remove_peer(&peer);
+        }
+        if self.address_book.remove_peer(&peer) {
+            self.save_address_book();
+        }
+        let _ = self.swarm.disconnect_peer_id(peer);
+        self.emit(NetworkEvent::PeerIncompatible {
+            peer,
+            reason: error.to_string(),
+        });
+    }
+
+    fn local_status(&self) -> Status {
+        Status::local(&self.gossip.chain().blockchain.lock().unwrap())
+    }
+
+    fn handle_sync_event(
+        &mut self,
+        event: request_response::Event<sync::SyncRequest, sync::SyncResponse>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+                ..
+            } => {
+                let response = sync::respond(self.gossip.chain(), request);
+                // Fails only if the peer has gone away in the meantime.
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .sync
+                    .send_response(channel, response);
+            }
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+                ..
+            } => match self.compact_requests.remove(&request_id) {
+                Some(request) => self.on_compact_response(request, response),
+                None => self.sync.on_response(request_id, response),
+            },
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            } => match self.compact_requests.remove(&request_id) {
+                Some(request) => {
+                    warn!(
+                        "Couldn't complete compact block {} from {}: {}",
+                        request.hash, request.source, error
+                    );
+                    if matches!(error, request_response::OutboundFailure::Timeout) {
+                        self.penalize(request.source, Misbehaviour::Timeout);
+                    }
+                    self.gossip.abandon_compact_block(&request.hash);
+                    self.conclude_compact_block(request, GossipVerdict::Ignore);
+                }
+                None => self.sync.on_failure(request_id, &error),
+            },
+            _ => {}
+        }
+    }
+
+    // Gossipsub holds on to every message until it has been validated here,
+    // so only accepted messages are relayed further.
+    fn handle_gossip(
+        &mut self,
+        source: PeerId,
+        message_id: gossipsub::MessageId,
+        message: gossipsub::Message,
+    ) {
+        let topic = message.topic.into_string();
+        let kind = match topic.as_str() {
+            TRANSACTIONS_TOPIC => Some(MessageKind::Transaction),
+            BLOCKS_TOPIC | COMPACT_BLOCKS_TOPIC => Some(MessageKind::Block),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            if !self.rate_limiter.allow(source, kind, Instant::now()) {
+                self.report_gossip(
+                    source,
+                    &message_id,
+                    topic.clone(),
+                    message.data,
+                    GossipVerdict::Ignore,
+                );
+                self.penalize(source, Misbehaviour::Flooding);
+                self.emit(NetworkEvent::RateLimited { source, topic });
+                return;
+            }
+        }
+        let verdict = match topic.as_str() {
+            BLOCKS_TOPIC => self.gossip.handle_block(&message.data),
+            COMPACT_BLOCKS_TOPIC => match self.gossip.handle_compact_block(&message.data) {
+                CompactVerdict::Done(verdict) => verdict,
+                CompactVerdict::Missing { hash, indexes } => {
+                    info!(
+                        "asking {} for {} transactions of block {}",
+                        source,
+                        indexes.len(),
+                        hash
+                    );
+                    self.emit(NetworkEvent::CompactBlockIncomplete {
+                        source,
+                        hash: hash.clone(),
+                        missing: indexes.len(),
+                    });
+                    let request = SyncRequest::Transactions {
+                        block: hash.clone(),
+                        indexes,
+                    };
+                    self.request_for_compact_block(
+                        CompactRequest {
+                            source,
+                            message_id,
+                            hash,
+                            data: message.data,
+                        },
+                        request,
+                    );
+                    return;
+                }
+            },
+            TRANSACTIONS_TOPIC => self.gossip.handle_transaction(&message.data),
+            _ => GossipVerdict::Accept,
+        };
+        self.report_gossip(source, &message_id, topic, message.data, verdict);
+    }
+
+    // Asks the sender of a compact block for what's needed to complete it,
+    // holding the message back until the answer is in.
+    fn request_for_compact_block(&mut self, compact: CompactRequest, request: SyncRequest) {
+        let id = self
+            .swarm
+            .behaviour_mut()
+            .sync
+            .send_request(&compact.source, request);
+        self.compact_requests.insert(id, compact);
+    }
+
+    fn on_compact_response(&mut self, request: CompactRequest, response: SyncResponse) {
+        let verdict = match response {
+            SyncResponse::Transactions(transactions) => {
+                match self.gossip.fill_compact_block(&request.hash, transactions) {
+                    Some(verdict) => verdict,
+                    None => {
+                        // Fall back to the full block.
+                        warn!(
+                            "{} didn't complete block {}, fetching all of it",
+                            request.source, request.hash
+                        );
+                        let body = SyncRequest::Bodies(vec![request.hash.clone()]);
+                        self.request_for_compact_block(request, body);
+                        return;
+                    }
+                }
+            }
+            SyncResponse::Bodies(bodies) if bodies.len() == 1 => {
+                let body = bodies.into_iter().next().expect("one body");
+                self.gossip.complete_compact_block(&request.hash, body)
+            }
+            _ => {
+                warn!(
+                    "{} couldn't provide block {} it announced",
+                    request.source, request.hash
+                );
+                self.penalize(request.source, Misbehaviour::UnrequestedData);
+                self.gossip.abandon_compact_block(&request.hash);
+                GossipVerdict::Ignore
+            }
+        };
+        self.conclude_compact_block(request, verdict);
+    }
+
+    fn conclude_compact_block(&mut self, request: CompactRequest, verdict: GossipVerdict) {
+        self.report_gossip(
+            request.source,
+            &request.message_id,
+            COMPACT_BLOCKS_TOPIC.to_string(),
+            request.data,
+            verdict,
+        );
+    }
+
+    // Tells gossipsub whether to relay a message and reports the outcome.
+    fn report_gossip(
+        &mut self,
+        source: PeerId,
+        message_id: &MessageId,
+        topic: String,
+        data: Vec<u8>,
+        verdict: GossipVerdict,
+    ) {
+        let acceptance = match verdict {
+            GossipVerdict::Accept => MessageAcceptance::Accept,
+            GossipVerdict::Ignore => MessageAcceptance::Ignore,
+            GossipVerdict::Reject(_) => MessageAcceptance::Reject,
+        };
+        if let Err(e) = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(message_id, &source, acceptance)
+        {
+            warn!("Failed to relay gossip from {}: {}", source, e);
+        }
+
+        if verdict == GossipVerdict::Accept {
+            self.connections.mark_useful(&source);
+        }
+        let event = match (verdict, topic.as_str()) {
+            (GossipVerdict::Accept, BLOCKS_TOPIC) => NetworkEvent::BlockAccepted {
+                source,
+                hash: Block::decode(&data)
+                    .map(|block| block.get_hash().to_string())
+                    .unwrap_or_default(),
+            },
+            (GossipVerdict::Accept, COMPACT_BLOCKS_TOPIC) => NetworkEvent::BlockAccepted {
+                source,
+                hash: CompactBlock::decode(&data)
+                    .map(|compact| compact.get_hash())
+                    .unwrap_or_default(),
+            },
+            (GossipVerdict::Accept, TRANSACTIONS_TOPIC) => NetworkEvent::TransactionAccepted {
+                source,
+                id: Transaction::decode(&data)
+                    .map(|transaction| transaction.id())
+                    .unwrap_or_default(),
+            },
+            (GossipVerdict::Accept, _) => NetworkEvent::Message {
+                source,
+                topic,
+                data,
+            },
+            (GossipVerdict::Ignore, _) => return,
+            (GossipVerdict::Reject(reason), _) => {
+                warn!("Rejected gossip from {} on {}: {}", source, topic, reason);
+                let misbehaviour = if topic == TRANSACTIONS_TOPIC {
+                    Misbehaviour::InvalidTransaction
+                } else {
+                    Misbehaviour::InvalidBlock
+                };
+                self.penalize(source, misbehaviour);
+                NetworkEvent::GossipRejected {
+                    source,
+                    topic,
+                    reason,
+                }
+            }
+        };
+        self.emit(event);
+    }
+
+    // Nobody listening for events is fine, they are dropped then.
+    fn emit(&self, event: NetworkEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+// Tells the DHT where `peer` listens, if the DHT is enabled.
+fn add_to_dht(swarm: &mut Swarm<NodeBehaviour>, peer: &PeerId, addr: Multiaddr) {
+    if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+        kademlia.add_address(peer, addr);
+    }
+}
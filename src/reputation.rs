@@ -0,0 +1,267 @@
+use libp2p::PeerId;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Bumped whenever the layout of the ban list file changes.
+const BAN_LIST_VERSION: u32 = 1;
+
+/// Things a peer can do wrong, each costing it some reputation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehaviour {
+    InvalidBlock,       // Bad proof of work, signatures or linkage.
+    InvalidTransaction, // Undecodable or badly signed transaction.
+    UnrequestedData,    // More or other data than was asked for.
+    Timeout,            // Didn't answer a request in time.
+    Flooding,           // Sent gossip faster than its rate limit.
+}
+
+impl Misbehaviour {
+    pub fn penalty(&self) -> i64 {
+        match self {
+            Misbehaviour::InvalidBlock => 50,
+            Misbehaviour::InvalidTransaction => 20,
+            Misbehaviour::UnrequestedData => 20,
+            Misbehaviour::Timeout => 5,
+            Misbehaviour::Flooding => 10,
+        }
+    }
+}
+
+impl std::fmt::Display for Misbehaviour {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Misbehaviour::InvalidBlock => write!(f, "invalid block"),
+            Misbehaviour::InvalidTransaction => write!(f, "invalid transaction"),
+            Misbehaviour::UnrequestedData => write!(f, "unrequested data"),
+            Misbehaviour::Timeout => write!(f, "timeout"),
+            Misbehaviour::Flooding => write!(f, "flooding"),
+        }
+    }
+}
+
+/// When peers get banned and for how long.
+#[derive(Debug, Clone)]
+pub struct ReputationConfig {
+    pub ban_threshold: i64,       // Peers whose score drops to this are banned.
+    pub ban_duration: Duration,   // How long an automatic ban lasts.
+    pub recovery_per_minute: i64, // Points a peer wins back every minute.
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        ReputationConfig {
+            ban_threshold: -100,
+            ban_duration: Duration::from_secs(24 * 60 * 60),
+            recovery_per_minute: 5,
+        }
+    }
+}
+
+/// Peers that aren't allowed to connect, and until when.
+#[derive(Debug, Clone, Default)]
+pub struct BanList {
+    bans: HashMap<PeerId, u64>, // Unix time each ban ends at.
+}
+
+#[derive(Serialize, Deserialize)]
+struct BanListFile {
+    version: u32,
+    bans: Vec<BanEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BanEntry {
+    peer_id: String,
+    until: u64,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ban(&mut self, peer: PeerId, duration: Duration) {
+        self.bans.insert(peer, now() + duration.as_secs());
+    }
+
+    pub fn unban(&mut self, peer: &PeerId) -> bool {
+        self.bans.remove(peer).is_some()
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.bans.get(peer).is_some_and(|until| *until > now())
+    }
+
+    /// Banned peers with the Unix time their ban ends at.
+    pub fn get_bans(&self) -> Vec<(PeerId, u64)> {
+        let mut bans: Vec<(PeerId, u64)> = self
+            .bans
+            .iter()
+            .map(|(peer, until)| (*peer, *until))
+            .collect();
+        bans.sort();
+        bans
+    }
+
+    /// Lifts every ban that has run out, returning the peers concerned.
+    pub fn expire(&mut self) -> Vec<PeerId> {
+        let now = now();
+        let expired: Vec<PeerId> = self
+            .bans
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in &expired {
+            self.bans.remove(peer);
+        }
+        expired
+    }
+
+    /// Writes the ban list to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let file = BanListFile {
+            version: BAN_LIST_VERSION,
+            bans: self
+                .get_bans()
+                .into_iter()
+                .map(|(peer, until)| BanEntry {
+                    peer_id: peer.to_string(),
+                    until,
+                })
+                .collect(),
+        };
+        let data = serde_json::to_vec(&file)?;
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Reads a ban list written by [`BanList::save`], dropping bans that ran
+    /// out in the meantime. A missing or unreadable file yields no bans.
+    pub fn load(path: &Path) -> Self {
+        let mut list = BanList::new();
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("ignoring unreadable ban list: {}", e);
+                }
+                return list;
+            }
+        };
+        let file: BanListFile = match serde_json::from_slice(&data) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("ignoring unreadable ban list: {}", e);
+                return list;
+            }
+        };
+        if file.version != BAN_LIST_VERSION {
+            warn!("ignoring ban list with version {}", file.version);
+            return list;
+        }
+
+        for entry in file.bans {
+            if let Ok(peer) = entry.peer_id.parse::<PeerId>() {
+                list.bans.insert(peer, entry.until);
+            }
+        }
+        list.expire();
+        list
+    }
+}
+
+// Score of a peer as of `updated`; it creeps back towards zero from there.
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    value: i64,
+    updated: Instant,
+}
+
+/// Per-peer reputation driven by validation outcomes.
+///
+/// Every peer starts at zero and loses points for each misbehaviour,
+/// slowly winning them back over time. Dropping to the threshold gets it
+/// banned for a while, after which it starts from zero again.
+pub struct Reputation {
+    config: ReputationConfig,
+    scores: HashMap<PeerId, Score>,
+    bans: BanList,
+}
+
+impl Reputation {
+    pub fn new(config: ReputationConfig, bans: BanList) -> Self {
+        Reputation {
+            config,
+            scores: HashMap::new(),
+            bans,
+        }
+    }
+
+    pub fn get_score(&self, peer: &PeerId) -> i64 {
+        self.scores
+            .get(peer)
+            .map(|score| self.recovered(score))
+            .unwrap_or(0)
+    }
+
+    /// Lowers `peer`'s score for `misbehaviour`, returning true if that got
+    /// it banned.
+    pub fn report(&mut self, peer: PeerId, misbehaviour: Misbehaviour) -> bool {
+        let value = self.get_score(&peer) - misbehaviour.penalty();
+        warn!("{} from {}, score now {}", misbehaviour, peer, value);
+        if value > self.config.ban_threshold {
+            self.scores.insert(
+                peer,
+                Score {
+                    value,
+                    updated: Instant::now(),
+                },
+            );
+            return false;
+        }
+        self.ban(peer, self.config.ban_duration);
+        true
+    }
+
+    pub fn ban(&mut self, peer: PeerId, duration: Duration) {
+        info!("banning {} for {}s", peer, duration.as_secs());
+        self.scores.remove(&peer);
+        self.bans.ban(peer, duration);
+    }
+
+    pub fn unban(&mut self, peer: &PeerId) -> bool {
+        self.bans.unban(peer)
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.bans.is_banned(peer)
+    }
+
+    pub fn get_ban_list(&self) -> &BanList {
+        &self.bans
+    }
+
+    /// Lifts every ban that has run out, returning the peers concerned.
+    pub fn expire_bans(&mut self) -> Vec<PeerId> {
+        self.bans.expire()
+    }
+
+    fn recovered(&self, score: &Score) -> i64 {
+        let minutes = score.updated.elapsed().as_secs() as i64 / 60;
+        (score.value + minutes * self.config.recovery_per_minute).min(0)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,487 @@
+//! JSON-RPC 2.0 over HTTP for wallets and tools.
+//!
+//! Requests are POSTed as a single call or a batch, with positional or named
+//! parameters, and answered from the node's chain and mempool. There is no
+//! authentication, so the server only ever listens on a loopback address.
+
+use crate::blockchain::{Block, Blockchain, BlockchainError, TransactionProof};
+use crate::encoding::Decode;
+use crate::gossip::SharedChain;
+use crate::index::TxLocation;
+use crate::transaction::Transaction;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tiny_http::{Header, Method, Response, Server};
+
+/// Invalid JSON was received.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Missing or malformed parameters.
+pub const INVALID_PARAMS: i64 = -32602;
+/// The node failed to answer an otherwise valid request.
+pub const INTERNAL_ERROR: i64 = -32603;
+/// No block or transaction matches the request.
+pub const NOT_FOUND: i64 = -32001;
+/// The requested block body has been pruned.
+pub const PRUNED: i64 = -32002;
+/// A submitted transaction was not admitted to the mempool.
+pub const TRANSACTION_REJECTED: i64 = -32003;
+
+/// Largest request body the server reads.
+pub const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// Error object of a JSON-RPC response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<BlockchainError> for RpcError {
+    fn from(err: BlockchainError) -> Self {
+        let code = match err {
+            BlockchainError::BlockNotFound | BlockchainError::TransactionNotFound => NOT_FOUND,
+            BlockchainError::Pruned(_) => PRUNED,
+            _ => INTERNAL_ERROR,
+        };
+        RpcError::new(code, err.to_string())
+    }
+}
+
+/// A transaction as returned over RPC, with where it was mined if it was.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionView {
+    pub id: String,
+    pub sender: String,
+    pub receiver: String,
+    pub amount: u64,
+    pub fee: u64,
+    pub nonce: u64,
+    pub signature: String, // Hex encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<String>, // None while still in the mempool.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+impl TransactionView {
+    pub fn new(transaction: &Transaction, location: Option<&TxLocation>) -> Self {
+        TransactionView {
+            id: transaction.id(),
+            sender: transaction.get_sender().to_string(),
+            receiver: transaction.get_receiver().to_string(),
+            amount: transaction.get_amount(),
+            fee: transaction.get_fee(),
+            nonce: transaction.get_nonce(),
+            signature: hex::encode(transaction.get_signature()),
+            block_hash: location.map(|location| location.block_hash.clone()),
+            height: location.map(|location| location.height),
+            position: location.map(|location| location.position),
+        }
+    }
+}
+
+/// A block as returned over RPC.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockView {
+    pub hash: String,
+    pub height: u64,
+    pub timestamp: u64,
+    pub previous_hash: String,
+    pub merkle_root: String, // Hex encoded.
+    pub state_root: String,  // Hex encoded.
+    pub difficulty: u32,
+    pub nonce: u64,
+    pub transactions: Vec<TransactionView>,
+}
+
+impl BlockView {
+    pub fn new(block: &Block) -> Self {
+        let header = block.get_header();
+        BlockView {
+            hash: block.get_hash().to_string(),
+            height: block.get_index() as u64,
+            timestamp: block.get_timestamp(),
+            previous_hash: block.get_previous_hash().to_string(),
+            merkle_root: hex::encode(header.get_merkle_root()),
+            state_root: hex::encode(header.get_state_root()),
+            difficulty: header.get_difficulty(),
+            nonce: block.get_nonce(),
+            transactions: block
+                .get_data_raw()
+                .iter()
+                .map(|transaction| TransactionView::new(transaction, None))
+                .collect(),
+        }
+    }
+}
+
+/// Summary of the node's view of the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainInfo {
+    pub chain_id: u64,
+    pub height: u64,
+    pub best_hash: String,
+    pub genesis_hash: String,
+    pub difficulty: usize,
+    pub cumulative_work: String, // Decimal, as it may not fit a JSON number.
+    pub mempool_size: usize,
+    #[serde(default)]
+    pub pruned_height: Option<u64>,
+}
+
+impl ChainInfo {
+    pub fn new(blockchain: &Blockchain, mempool_size: usize) -> Self {
+        let chain = blockchain.get_chain();
+        ChainInfo {
+            chain_id: blockchain.get_spec().chain_id,
+            height: chain.len() as u64 - 1,
+            best_hash: chain.last().unwrap().get_hash().to_string(),
+            genesis_hash: chain[0].get_hash().to_string(),
+            difficulty: blockchain.get_difficulty(),
+            cumulative_work: blockchain.get_cumulative_work().to_string(),
+            mempool_size,
+            pruned_height: blockchain.get_pruned_height(),
+        }
+    }
+}
+
+/// A merkle proof as returned over RPC, with hashes hex encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerkleProofView {
+    pub transaction_id: String,
+    pub block_hash: String,
+    pub height: u64,
+    pub position: usize,
+    pub leaf_count: usize,
+    pub merkle_root: String,
+    pub hashes: Vec<String>,
+}
+
+impl From<TransactionProof> for MerkleProofView {
+    fn from(proof: TransactionProof) -> Self {
+        MerkleProofView {
+            transaction_id: proof.transaction_id,
+            block_hash: proof.block_hash,
+            height: proof.height,
+            position: proof.position,
+            leaf_count: proof.leaf_count,
+            merkle_root: hex::encode(proof.merkle_root),
+            hashes: proof.hashes.iter().map(hex::encode).collect(),
+        }
+    }
+}
+
+impl TryFrom<MerkleProofView> for TransactionProof {
+    type Error = hex::FromHexError;
+
+    fn try_from(view: MerkleProofView) -> Result<Self, Self::Error> {
+        let decode = |hash: &str| {
+            let mut bytes = [0u8; 32];
+            hex::decode_to_slice(hash, &mut bytes).map(|_| bytes)
+        };
+        Ok(TransactionProof {
+            transaction_id: view.transaction_id,
+            block_hash: view.block_hash,
+            height: view.height,
+            position: view.position,
+            leaf_count: view.leaf_count,
+            merkle_root: decode(&view.merkle_root)?,
+            hashes: view
+                .hashes
+                .iter()
+                .map(|hash| decode(hash))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// HTTP server answering JSON-RPC requests on a background thread.
+pub struct RpcServer {
+    server: Arc<Server>,
+    addr: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Starts serving `chain` on `addr`, which must be a loopback address.
+    /// Port 0 picks a free port; see `local_addr`.
+    pub fn start(addr: SocketAddr, chain: SharedChain) -> io::Result<Self> {
+        if !addr.ip().is_loopback() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("refusing to serve RPC on non-loopback address {}", addr),
+            ));
+        }
+        let server = Server::http(addr).map_err(io::Error::other)?;
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| io::Error::other("RPC server is not listening on TCP"))?;
+        let server = Arc::new(server);
+        let thread = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || serve(&server, &chain))
+        };
+        info!("serving JSON-RPC on http://{}", addr);
+        Ok(RpcServer {
+            server,
+            addr,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops accepting requests and waits for the server thread to exit.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn serve(server: &Server, chain: &SharedChain) {
+    for mut request in server.incoming_requests() {
+        if *request.method() != Method::Post {
+            let _ = request.respond(Response::empty(405));
+            continue;
+        }
+        let mut body = Vec::new();
+        let read = request
+            .as_reader()
+            .take(MAX_REQUEST_SIZE as u64 + 1)
+            .read_to_end(&mut body);
+        if read.is_err() || body.len() > MAX_REQUEST_SIZE {
+            let _ = request.respond(Response::empty(413));
+            continue;
+        }
+        let result = match handle(chain, &body) {
+            Some(response) => request.respond(
+                Response::from_string(response.to_string())
+                    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()),
+            ),
+            // Nothing but notifications, so nothing to answer.
+            None => request.respond(Response::empty(204)),
+        };
+        if let Err(e) = result {
+            warn!("failed to send RPC response: {}", e);
+        }
+    }
+}
+
+/// Answers a raw request body, which may hold a single call or a batch.
+/// Returns None when every call was a notification.
+pub fn handle(chain: &SharedChain, body: &[u8]) -> Option<Value> {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(error_response(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+        }
+    };
+    match request {
+        Value::Array(calls) if calls.is_empty() => Some(error_response(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "empty batch"),
+        )),
+        Value::Array(calls) => {
+            let responses: Vec<Value> = calls
+                .into_iter()
+                .filter_map(|call| handle_call(chain, call))
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        call => handle_call(chain, call),
+    }
+}
+
+fn handle_call(chain: &SharedChain, call: Value) -> Option<Value> {
+    let Value::Object(call) = call else {
+        return Some(error_response(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "request must be an object"),
+        ));
+    };
+    // A missing ID makes a notification; an explicit null does not. Malformed
+    // requests are answered either way, as they can't be told apart.
+    let id = call.get("id").cloned();
+    let (method, params) = match parse_call(&call) {
+        Ok(parsed) => parsed,
+        Err(err) => return Some(error_response(id.unwrap_or(Value::Null), err)),
+    };
+    let result = dispatch(chain, method, params);
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(err) => error_response(id, err),
+    })
+}
+
+fn parse_call(call: &Map<String, Value>) -> Result<(&str, &Value), RpcError> {
+    if call.get("jsonrpc") != Some(&json!("2.0")) {
+        return Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""));
+    }
+    let method = call
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_REQUEST, "method must be a string"))?;
+    let params = call.get("params").unwrap_or(&Value::Null);
+    if !matches!(params, Value::Array(_) | Value::Object(_) | Value::Null) {
+        return Err(RpcError::new(
+            INVALID_REQUEST,
+            "params must be an array or an object",
+        ));
+    }
+    Ok((method, params))
+}
+
+pub(crate) fn error_response(id: Value, err: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": err, "id": id })
+}
+
+// Parameter `index`, or `name` when parameters are passed by name.
+pub(crate) fn param<T: DeserializeOwned>(
+    params: &Value,
+    index: usize,
+    name: &str,
+) -> Result<T, RpcError> {
+    let value = match params {
+        Value::Array(values) => values.get(index),
+        Value::Object(values) => values.get(name),
+        _ => None,
+    }
+    .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing parameter {}", name)))?;
+    serde_json::from_value(value.clone())
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid {}: {}", name, e)))
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+}
+
+fn dispatch(chain: &SharedChain, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "getBlockByHeight" => {
+            let height: u64 = param(params, 0, "height")?;
+            let blockchain = chain.blockchain.lock().unwrap();
+            to_value(BlockView::new(blockchain.get_block(height as usize)?))
+        }
+        "getBlockByHash" => {
+            let hash: String = param(params, 0, "hash")?;
+            let blockchain = chain.blockchain.lock().unwrap();
+            to_value(BlockView::new(blockchain.get_block_by_hash(&hash)?))
+        }
+        "getTransaction" => {
+            let id: String = param(params, 0, "id")?;
+            let blockchain = chain.blockchain.lock().unwrap();
+            match blockchain.get_transaction_by_id(&id) {
+                Ok((transaction, location)) => {
+                    to_value(TransactionView::new(transaction, Some(location)))
+                }
+                Err(BlockchainError::TransactionNotFound) => {
+                    let mempool = chain.mempool.lock().unwrap();
+                    let pending = mempool
+                        .iter()
+                        .find(|transaction| transaction.id() == id)
+                        .ok_or(BlockchainError::TransactionNotFound)?;
+                    to_value(TransactionView::new(pending, None))
+                }
+                Err(e) => Err(e.into()),
+            }
+        }
+        "getBalance" => {
+            let address: String = param(params, 0, "address")?;
+            to_value(chain.blockchain.lock().unwrap().get_balance(&address))
+        }
+        "getNonce" => {
+            let address: String = param(params, 0, "address")?;
+            to_value(chain.blockchain.lock().unwrap().get_nonce(&address))
+        }
+        "sendRawTransaction" => {
+            let data: String = param(params, 0, "data")?;
+            let bytes = hex::decode(data.trim_start_matches("0x"))
+                .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid data: {}", e)))?;
+            let transaction = Transaction::decode(&bytes)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid data: {}", e)))?;
+            let id = transaction.id();
+            let blockchain = chain.blockchain.lock().unwrap();
+            if blockchain
+                .get_index()
+                .get_transaction_location(&id)
+                .is_some()
+            {
+                return Err(RpcError::new(
+                    TRANSACTION_REJECTED,
+                    "Transaction already mined",
+                ));
+            }
+            let mut mempool = chain.mempool.lock().unwrap();
+            mempool
+                .add_transaction(transaction, blockchain.get_state())
+                .map_err(|e| RpcError::new(TRANSACTION_REJECTED, e.to_string()))?;
+            to_value(id)
+        }
+        "getChainInfo" => {
+            let blockchain = chain.blockchain.lock().unwrap();
+            let mempool_size = chain.mempool.lock().unwrap().len();
+            to_value(ChainInfo::new(&blockchain, mempool_size))
+        }
+        "getMerkleProof" => {
+            let id: String = param(params, 0, "id")?;
+            let proof = chain.blockchain.lock().unwrap().get_merkle_proof(&id)?;
+            to_value(MerkleProofView::from(proof))
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {}", method),
+        )),
+    }
+}
@@ -0,0 +1,156 @@
+use crate::blockchain::TransactionProof;
+use crate::encoding::Encode;
+use crate::rpc::{BlockView, ChainInfo, MerkleProofView, RpcError, TransactionView};
+use crate::transaction::Transaction;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// How long a call may take before it is given up on.
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcClientError {
+    Io(String),
+    Http(u16),
+    Rpc(RpcError),
+    InvalidResponse(String),
+}
+
+impl std::fmt::Display for RpcClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcClientError::Io(ref err) => write!(f, "I/O error: {}", err),
+            RpcClientError::Http(status) => write!(f, "HTTP status {}", status),
+            RpcClientError::Rpc(ref err) => write!(f, "{}", err),
+            RpcClientError::InvalidResponse(ref err) => write!(f, "Invalid response: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RpcClientError {}
+
+impl From<std::io::Error> for RpcClientError {
+    fn from(err: std::io::Error) -> Self {
+        RpcClientError::Io(err.to_string())
+    }
+}
+
+/// Typed client for a node's JSON-RPC server.
+pub struct RpcClient {
+    addr: SocketAddr,
+    next_id: AtomicU64,
+}
+
+impl RpcClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        RpcClient {
+            addr,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Calls `method` with `params` and decodes its result.
+    pub fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, RpcClientError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": id });
+        let mut response = self.post(&request)?;
+        if response.get("id") != Some(&json!(id)) {
+            return Err(RpcClientError::InvalidResponse(format!(
+                "expected a response to call {}",
+                id
+            )));
+        }
+        if let Some(error) = response.get_mut("error") {
+            let error = serde_json::from_value(error.take())
+                .map_err(|e| RpcClientError::InvalidResponse(e.to_string()))?;
+            return Err(RpcClientError::Rpc(error));
+        }
+        let result = response
+            .get_mut("result")
+            .map(Value::take)
+            .ok_or_else(|| RpcClientError::InvalidResponse("no result".into()))?;
+        serde_json::from_value(result).map_err(|e| RpcClientError::InvalidResponse(e.to_string()))
+    }
+
+    /// Posts a raw request body and returns the parsed response body.
+    pub fn post(&self, request: &Value) -> Result<Value, RpcClientError> {
+        let body = request.to_string();
+        let mut stream = TcpStream::connect_timeout(&self.addr, CALL_TIMEOUT)?;
+        stream.set_read_timeout(Some(CALL_TIMEOUT))?;
+        stream.set_write_timeout(Some(CALL_TIMEOUT))?;
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.addr,
+            body.len(),
+            body
+        )?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        let invalid = |message: &str| RpcClientError::InvalidResponse(message.to_string());
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| invalid("no end of headers"))?;
+        let head = std::str::from_utf8(&response[..split]).map_err(|_| invalid("bad headers"))?;
+        let status: u16 = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| invalid("no status"))?;
+        if status != 200 {
+            return Err(RpcClientError::Http(status));
+        }
+        serde_json::from_slice(&response[split + 4..])
+            .map_err(|e| RpcClientError::InvalidResponse(e.to_string()))
+    }
+
+    pub fn get_block_by_height(&self, height: u64) -> Result<BlockView, RpcClientError> {
+        self.call("getBlockByHeight", json!([height]))
+    }
+
+    pub fn get_block_by_hash(&self, hash: &str) -> Result<BlockView, RpcClientError> {
+        self.call("getBlockByHash", json!([hash]))
+    }
+
+    pub fn get_transaction(&self, id: &str) -> Result<TransactionView, RpcClientError> {
+        self.call("getTransaction", json!([id]))
+    }
+
+    pub fn get_balance(&self, address: &str) -> Result<u64, RpcClientError> {
+        self.call("getBalance", json!([address]))
+    }
+
+    pub fn get_nonce(&self, address: &str) -> Result<u64, RpcClientError> {
+        self.call("getNonce", json!([address]))
+    }
+
+    /// Submits a signed transaction, returning its ID.
+    pub fn send_raw_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<String, RpcClientError> {
+        self.call(
+            "sendRawTransaction",
+            json!([hex::encode(transaction.encode())]),
+        )
+    }
+
+    pub fn get_chain_info(&self) -> Result<ChainInfo, RpcClientError> {
+        self.call("getChainInfo", json!([]))
+    }
+
+    pub fn get_merkle_proof(&self, id: &str) -> Result<TransactionProof, RpcClientError> {
+        let view: MerkleProofView = self.call("getMerkleProof", json!([id]))?;
+        TransactionProof::try_from(view).map_err(|e| RpcClientError::InvalidResponse(e.to_string()))
+    }
+}
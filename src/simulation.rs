@@ -0,0 +1,475 @@
+//! Deterministic in-process network simulation.
+//!
+//! Runs many [`Blockchain`] nodes in one process on a virtual clock. Blocks
+//! travel over a simulated message bus with random latency, message loss
+//! and partitions, all drawn from a seeded generator, so a scenario with the
+//! same seed plays out the same way every time. Nodes follow the longest
+//! chain, fetch the parents of blocks they can't place from the sender and
+//! reorganize when a longer branch shows up.
+
+use crate::blockchain::{Block, Blockchain};
+use crate::chain_spec::ChainSpec;
+use crate::mempool::Mempool;
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// Small deterministic random number generator (SplitMix64).
+#[derive(Debug, Clone)]
+pub struct SimRng {
+    state: u64,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        SimRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`, or 0 if `bound` is 0.
+    pub fn next_below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        self.next_u64() % bound
+    }
+
+    /// A number in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Shape of a simulated network.
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub nodes: usize,          // Nodes in the network, all connected to each other.
+    pub seed: u64,             // Seeds latency, message loss and anything tests draw.
+    pub spec: ChainSpec,       // Chain every node starts from.
+    pub min_latency: Duration, // Fastest a message is delivered.
+    pub max_latency: Duration, // Slowest a message is delivered.
+    pub loss_rate: f64,        // Share of messages silently dropped.
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            nodes: 4,
+            seed: 0,
+            spec: ChainSpec {
+                difficulty: 1,
+                ..ChainSpec::default()
+            },
+            min_latency: Duration::from_millis(50),
+            max_latency: Duration::from_millis(200),
+            loss_rate: 0.0,
+        }
+    }
+}
+
+/// Something that happened in a simulation, recorded in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimEvent {
+    Mined {
+        time: Duration,
+        node: usize,
+        height: u64,
+        hash: String,
+    },
+    Connected {
+        time: Duration,
+        node: usize,
+        height: u64,
+        hash: String,
+    },
+    Reorganized {
+        time: Duration,
+        node: usize,
+        fork_height: u64,
+        disconnected: usize, // Blocks of the old branch given up.
+        tip_height: u64,
+    },
+    Dropped {
+        time: Duration,
+        from: usize,
+        to: usize,
+    },
+}
+
+#[derive(Clone)]
+enum Message {
+    Block(Block),
+    GetBlock(String), // Asks for a block the sender announced a descendant of.
+}
+
+enum Action {
+    Deliver {
+        from: usize,
+        to: usize,
+        message: Message,
+    },
+    Mine(usize),
+}
+
+struct SimNode {
+    blockchain: Blockchain,
+    miner: String,                       // Address its coinbases pay.
+    side_blocks: HashMap<String, Block>, // Blocks off the active chain, by hash.
+}
+
+/// A network of nodes driven by a virtual clock.
+///
+/// Nothing happens until the simulation is stepped: each step runs the
+/// earliest scheduled message delivery or mining, moving the clock forward
+/// to it.
+pub struct Simulation {
+    config: SimConfig,
+    clock: Duration,
+    rng: SimRng,
+    nodes: Vec<SimNode>,
+    queue: BTreeMap<(Duration, u64), Action>, // Keyed by time, then scheduling order.
+    scheduled: u64,
+    groups: Option<Vec<usize>>, // Partition group of every node while partitioned.
+    trace: Vec<SimEvent>,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Self {
+        let nodes = (0..config.nodes)
+            .map(|i| SimNode {
+                blockchain: Blockchain::with_spec(config.spec.clone()),
+                miner: format!("miner-{}", i),
+                side_blocks: HashMap::new(),
+            })
+            .collect();
+        Simulation {
+            rng: SimRng::new(config.seed),
+            config,
+            clock: Duration::ZERO,
+            nodes,
+            queue: BTreeMap::new(),
+            scheduled: 0,
+            groups: None,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn get_time(&self) -> Duration {
+        self.clock
+    }
+
+    pub fn get_node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn get_blockchain(&self, node: usize) -> &Blockchain {
+        &self.nodes[node].blockchain
+    }
+
+    /// Hash of every node's tip, by node.
+    pub fn get_tips(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let chain = node.blockchain.get_chain();
+                chain[chain.len() - 1].get_hash().to_string()
+            })
+            .collect()
+    }
+
+    /// Whether every node has the same tip.
+    pub fn is_converged(&self) -> bool {
+        let tips = self.get_tips();
+        tips.windows(2).all(|pair| pair[0] == pair[1])
+    }
+
+    pub fn get_trace(&self) -> &[SimEvent] {
+        &self.trace
+    }
+
+    /// The simulation's generator, for tests drawing their own choices
+    /// from the seed.
+    pub fn rng(&mut self) -> &mut SimRng {
+        &mut self.rng
+    }
+
+    /// Has `node` mine a block on its tip right now and announce it.
+    pub fn mine(&mut self, node: usize) -> Block {
+        let block = {
+            let sim_node = &self.nodes[node];
+            let blockchain = &sim_node.blockchain;
+            let coinbase = blockchain.block_template(&Mempool::default(), &sim_node.miner);
+            let timestamp = self.config.spec.genesis_timestamp + self.clock.as_secs();
+            blockchain
+                .mine_next_block(coinbase, timestamp)
+                .expect("a coinbase-only block is within the limits")
+        };
+        self.trace.push(SimEvent::Mined {
+            time: self.clock,
+            node,
+            height: block.get_index() as u64,
+            hash: block.get_hash().to_string(),
+        });
+        self.receive_block(node, None, block.clone());
+        block
+    }
+
+    /// Changes the share of messages dropped from now on.
+    pub fn set_loss_rate(&mut self, loss_rate: f64) {
+        self.config.loss_rate = loss_rate;
+    }
+
+    /// Has `node` mine a block once `delay` has passed.
+    pub fn schedule_mining(&mut self, node: usize, delay: Duration) {
+        self.schedule(delay, Action::Mine(node));
+    }
+
+    /// Splits the network: messages only get through between nodes of the
+    /// same group, including messages already on their way. Nodes in no
+    /// group are cut off from everyone.
+    pub fn partition(&mut self, groups: &[Vec<usize>]) {
+        let mut assignment: Vec<usize> = (0..self.nodes.len())
+            .map(|node| groups.len() + node)
+            .collect();
+        for (group, members) in groups.iter().enumerate() {
+            for node in members {
+                assignment[*node] = group;
+            }
+        }
+        self.groups = Some(assignment);
+    }
+
+    /// Reconnects every node. As on a fresh connection, each node announces
+    /// its tip to all others.
+    pub fn heal(&mut self) {
+        self.groups = None;
+        for node in 0..self.nodes.len() {
+            let chain = self.nodes[node].blockchain.get_chain();
+            let tip = chain[chain.len() - 1].clone();
+            self.broadcast(node, None, Message::Block(tip));
+        }
+    }
+
+    /// Runs the next scheduled action, returning false if there was none.
+    pub fn step(&mut self) -> bool {
+        let Some(((time, _), action)) = self.queue.pop_first() else {
+            return false;
+        };
+        self.clock = time;
+        match action {
+            Action::Deliver { from, to, message } => {
+                if self.is_cut_off(from, to) {
+                    self.dropped(from, to);
+                } else {
+                    self.deliver(from, to, message);
+                }
+            }
+            Action::Mine(node) => {
+                self.mine(node);
+            }
+        }
+        true
+    }
+
+    /// Runs until nothing is scheduled anymore.
+    pub fn run_until_idle(&mut self) {
+        while self.step() {}
+    }
+
+    /// Runs everything scheduled within the next `duration` and moves the
+    /// clock to its end.
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.clock + duration;
+        while self
+            .queue
+            .first_key_value()
+            .is_some_and(|((time, _), _)| *time <= end)
+        {
+            self.step();
+        }
+        self.clock = end;
+    }
+
+    fn schedule(&mut self, delay: Duration, action: Action) {
+        self.scheduled += 1;
+        self.queue
+            .insert((self.clock + delay, self.scheduled), action);
+    }
+
+    fn is_cut_off(&self, from: usize, to: usize) -> bool {
+        self.groups
+            .as_ref()
+            .is_some_and(|groups| groups[from] != groups[to])
+    }
+
+    fn dropped(&mut self, from: usize, to: usize) {
+        self.trace.push(SimEvent::Dropped {
+            time: self.clock,
+            from,
+            to,
+        });
+    }
+
+    // Puts a message on the bus, where it's lost or delayed at random.
+    fn send(&mut self, from: usize, to: usize, message: Message) {
+        if self.is_cut_off(from, to) || self.rng.next_f64() < self.config.loss_rate {
+            self.dropped(from, to);
+            return;
+        }
+        let spread = self
+            .config
+            .max_latency
+            .saturating_sub(self.config.min_latency)
+            .as_millis() as u64;
+        let latency =
+            self.config.min_latency + Duration::from_millis(self.rng.next_below(spread + 1));
+        self.schedule(latency, Action::Deliver { from, to, message });
+    }
+
+    fn broadcast(&mut self, from: usize, except: Option<usize>, message: Message) {
+        for to in 0..self.nodes.len() {
+            if to != from && Some(to) != except {
+                self.send(from, to, message.clone());
+            }
+        }
+    }
+
+    fn deliver(&mut self, from: usize, to: usize, message: Message) {
+        match message {
+            Message::Block(block) => self.receive_block(to, Some(from), block),
+            Message::GetBlock(hash) => {
+                let node = &self.nodes[to];
+                let blockchain = &node.blockchain;
+                let block = blockchain
+                    .get_index()
+                    .get_block_height(&hash)
+                    .map(|height| blockchain.get_chain()[height as usize].clone())
+                    .or_else(|| node.side_blocks.get(&hash).cloned());
+                if let Some(block) = block {
+                    self.send(to, from, Message::Block(block));
+                }
+            }
+        }
+    }
+
+    // Places a block a node mined or was sent, switching to the branch it's
+    // on if that's now the longest, and relays whatever got connected.
+    fn receive_block(&mut self, node: usize, from: Option<usize>, block: Block) {
+        let hash = block.get_hash().to_string();
+        {
+            let sim_node = &mut self.nodes[node];
+            if sim_node
+                .blockchain
+                .get_index()
+                .get_block_height(&hash)
+                .is_some()
+                || sim_node.side_blocks.contains_key(&hash)
+            {
+                return;
+            }
+            sim_node.side_blocks.insert(hash.clone(), block);
+        }
+
+        let (fork_height, branch) = match self.branch_to(node, &hash) {
+            Ok(found) => found,
+            Err(missing) => {
+                if let Some(from) = from {
+                    self.send(node, from, Message::GetBlock(missing));
+                }
+                return;
+            }
+        };
+        let chain_length = self.nodes[node].blockchain.get_chain_length();
+        if fork_height as usize + 1 + branch.len() <= chain_length {
+            return;
+        }
+
+        let sim_node = &mut self.nodes[node];
+        for block in &branch {
+            sim_node.side_blocks.remove(block.get_hash());
+        }
+        if fork_height as usize + 1 == chain_length {
+            for block in &branch {
+                if let Err(e) = sim_node.blockchain.try_add_block(block.clone()) {
+                    warn!("node {} refused block {}: {}", node, block.get_hash(), e);
+                    return;
+                }
+            }
+        } else {
+            match sim_node
+                .blockchain
+                .reorganize(fork_height as usize, branch.clone())
+            {
+                Ok(disconnected) => {
+                    self.trace.push(SimEvent::Reorganized {
+                        time: self.clock,
+                        node,
+                        fork_height,
+                        disconnected: disconnected.len(),
+                        tip_height: fork_height + branch.len() as u64,
+                    });
+                    for block in disconnected {
+                        sim_node
+                            .side_blocks
+                            .insert(block.get_hash().to_string(), block);
+                    }
+                }
+                Err(e) => {
+                    warn!("node {} couldn't switch branches: {}", node, e);
+                    return;
+                }
+            }
+        }
+
+        for block in branch {
+            self.trace.push(SimEvent::Connected {
+                time: self.clock,
+                node,
+                height: block.get_index() as u64,
+                hash: block.get_hash().to_string(),
+            });
+            self.broadcast(node, from, Message::Block(block));
+        }
+    }
+
+    // The side blocks leading from the active chain to `hash` and on to the
+    // furthest descendant known, with the height they fork off at. Fails
+    // with the first ancestor the node doesn't have.
+    fn branch_to(&self, node: usize, hash: &str) -> Result<(u64, Vec<Block>), String> {
+        let sim_node = &self.nodes[node];
+        let mut branch = Vec::new();
+        let mut current = hash.to_string();
+        let fork_height = loop {
+            if let Some(height) = sim_node.blockchain.get_index().get_block_height(&current) {
+                break height;
+            }
+            match sim_node.side_blocks.get(&current) {
+                Some(block) => {
+                    current = block.get_previous_hash().to_string();
+                    branch.push(block.clone());
+                }
+                None => return Err(current),
+            }
+        };
+        branch.reverse();
+
+        // Children are picked by lowest hash so the outcome is the same
+        // whatever order they were stored in.
+        while let Some(child) = sim_node
+            .side_blocks
+            .values()
+            .filter(|block| block.get_previous_hash() == branch[branch.len() - 1].get_hash())
+            .min_by(|a, b| a.get_hash().cmp(b.get_hash()))
+        {
+            branch.push(child.clone());
+        }
+        Ok((fork_height, branch))
+    }
+}
@@ -0,0 +1,119 @@
+use crate::blockchain::BlockHeader;
+use crate::encoding::{self, Decode, DecodeError, Encode, Reader};
+use crate::state::WorldState;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    Io(String),
+    Decode(DecodeError),
+    Mismatch(String),
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(ref err) => write!(f, "Snapshot IO error: {}", err),
+            SnapshotError::Decode(ref err) => write!(f, "Snapshot decode error: {}", err),
+            SnapshotError::Mismatch(ref err) => write!(f, "Snapshot doesn't match: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e.to_string())
+    }
+}
+
+/// The world state right after the block at `height` was applied.
+///
+/// A snapshot is only trusted once [`StateSnapshot::verify`] has matched it
+/// against the header of that block, whose state root commits to the state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateSnapshot {
+    height: u64,
+    block_hash: String,
+    state: WorldState,
+}
+
+impl StateSnapshot {
+    pub fn new(height: u64, block_hash: String, state: WorldState) -> Self {
+        StateSnapshot {
+            height,
+            block_hash,
+            state,
+        }
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn get_block_hash(&self) -> &str {
+        &self.block_hash
+    }
+
+    pub fn get_state(&self) -> &WorldState {
+        &self.state
+    }
+
+    pub fn into_state(self) -> WorldState {
+        self.state
+    }
+
+    /// Checks that `header` is the block the snapshot was taken at and that
+    /// its state root commits to the snapshot's accounts.
+    pub fn verify(&self, header: &BlockHeader) -> Result<(), SnapshotError> {
+        if header.get_index() != self.height as u128 {
+            return Err(SnapshotError::Mismatch(format!(
+                "snapshot is at height {}, header at {}",
+                self.height,
+                header.get_index()
+            )));
+        }
+        if header.calculate_hash() != self.block_hash {
+            return Err(SnapshotError::Mismatch("block hash differs".into()));
+        }
+        if *header.get_state_root() != self.state.state_root() {
+            return Err(SnapshotError::Mismatch("state root differs".into()));
+        }
+        Ok(())
+    }
+
+    /// Writes the snapshot to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, self.encode())?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SnapshotError> {
+        let data = fs::read(path)?;
+        StateSnapshot::decode(&data).map_err(SnapshotError::Decode)
+    }
+}
+
+impl Encode for StateSnapshot {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encoding::put_u8(out, encoding::ENCODING_VERSION);
+        encoding::put_u64(out, self.height);
+        encoding::put_str(out, &self.block_hash);
+        self.state.encode_to(out);
+    }
+}
+
+impl Decode for StateSnapshot {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        Ok(StateSnapshot {
+            height: reader.read_u64()?,
+            block_hash: reader.read_string()?,
+            state: WorldState::decode_from(reader)?,
+        })
+    }
+}
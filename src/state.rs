@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::account::Account;
+use crate::blockchain::Block;
+use crate::encoding::{self, Decode, DecodeError, Encode, Reader};
+use crate::transaction::Transaction;
+
+/// Balances and nonces of every account touched by the chain.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorldState {
+    accounts: HashMap<String, Account>,
+}
+
+impl WorldState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rebuilds the state by replaying every transaction of the given blocks.
+    pub fn from_blocks(blocks: &[Block]) -> Result<Self, String> {
+        let mut state = Self::new();
+        for block in blocks {
+            state.apply_block(block)?;
+        }
+        Ok(state)
+    }
+
+    /// Applies all transactions of a block in order.
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), String> {
+        self.apply_transactions(block.get_data_raw())
+    }
+
+    /// Applies `transactions` in order, stopping at the first that fails.
+    pub fn apply_transactions(&mut self, transactions: &[Transaction]) -> Result<(), String> {
+        for transaction in transactions {
+            transaction.execute(self)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_account(&self, address: &str) -> Option<&Account> {
+        self.accounts.get(address)
+    }
+
+    /// Returns the account for `address`, creating an empty one if needed.
+    pub fn get_account_mut(&mut self, address: &str) -> &mut Account {
+        self.accounts
+            .entry(address.to_string())
+            .or_insert_with(|| Account::new(address.to_string()))
+    }
+
+    pub fn get_balance(&self, address: &str) -> u64 {
+        self.get_account(address).map_or(0, |a| a.get_balance())
+    }
+
+    pub fn get_nonce(&self, address: &str) -> u64 {
+        self.get_account(address).map_or(0, |a| a.get_nonce())
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    /// Hash committing to every account, independent of insertion order.
+    pub fn state_root(&self) -> [u8; 32] {
+        Sha256::digest(self.encode()).into()
+    }
+}
+
+// Accounts are written sorted by address so equal states encode identically.
+impl Encode for WorldState {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let mut accounts: Vec<&Account> = self.accounts.values().collect();
+        accounts.sort_by(|a, b| a.get_address().cmp(b.get_address()));
+
+        encoding::put_u8(out, encoding::ENCODING_VERSION);
+        encoding::put_u32(out, accounts.len() as u32);
+        for account in accounts {
+            account.encode_to(out);
+        }
+    }
+}
+
+impl Decode for WorldState {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        let count = reader.read_u32()? as usize;
+        if count > reader.remaining() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut state = WorldState::new();
+        let mut previous: Option<String> = None;
+        for _ in 0..count {
+            let account = Account::decode_from(reader)?;
+            // Anything but strictly ascending addresses isn't the canonical encoding.
+            if previous.as_deref() >= Some(account.get_address()) {
+                return Err(DecodeError::Invalid("accounts out of order".into()));
+            }
+            previous = Some(account.get_address().to_string());
+            state
+                .accounts
+                .insert(account.get_address().to_string(), account);
+        }
+        Ok(state)
+    }
+}
@@ -0,0 +1,372 @@
+use crate::blockchain::{Block, BlockBody, BlockHeader};
+use crate::encoding::{Decode, Encode, Reader};
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Each record is `[payload length: u32 LE][checksum: 4 bytes][payload]`.
+const RECORD_HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    Io(String),
+    Corrupted(String),
+    Encoding(String),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Io(ref err) => write!(f, "Storage IO error: {}", err),
+            StorageError::Corrupted(ref err) => write!(f, "Storage corrupted: {}", err),
+            StorageError::Encoding(ref err) => write!(f, "Storage encoding error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError::Io(e.to_string())
+    }
+}
+
+/// Persistent home of the blocks making up the chain.
+pub trait BlockStore: Send {
+    /// Returns every stored block ordered by height.
+    fn load_blocks(&mut self) -> Result<Vec<Block>, StorageError>;
+
+    /// Reads the block stored at `height`.
+    fn read_block(&mut self, height: usize) -> Result<Option<Block>, StorageError>;
+
+    /// Reads only the header of the block stored at `height`.
+    fn read_header(&mut self, height: usize) -> Result<Option<BlockHeader>, StorageError> {
+        Ok(self
+            .read_block(height)?
+            .map(|block| block.get_header().clone()))
+    }
+
+    /// Returns the headers of every stored block ordered by height.
+    fn load_headers(&mut self) -> Result<Vec<BlockHeader>, StorageError> {
+        let mut headers = Vec::with_capacity(self.len());
+        for height in 0..self.len() {
+            if let Some(header) = self.read_header(height)? {
+                headers.push(header);
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Durably appends a block on top of the stored ones.
+    fn append_block(&mut self, block: &Block) -> Result<(), StorageError>;
+
+    /// Drops every block at or above height `len`, used when the chain reorgs.
+    fn truncate(&mut self, len: usize) -> Result<(), StorageError>;
+
+    /// Replaces every block below height `below` by its header alone.
+    fn prune_bodies(&mut self, below: usize) -> Result<(), StorageError>;
+
+    /// Number of blocks stored.
+    fn len(&self) -> usize;
+
+    /// File the chain index is persisted to alongside the blocks, if any.
+    fn index_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// File holding the state snapshot a bootstrapped chain starts from, if any.
+    fn snapshot_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Keeps blocks in memory only, mostly useful for tests.
+#[derive(Default)]
+pub struct MemoryBlockStore {
+    blocks: Vec<Block>,
+}
+
+impl MemoryBlockStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BlockStore for MemoryBlockStore {
+    fn load_blocks(&mut self) -> Result<Vec<Block>, StorageError> {
+        Ok(self.blocks.clone())
+    }
+
+    fn read_block(&mut self, height: usize) -> Result<Option<Block>, StorageError> {
+        Ok(self.blocks.get(height).cloned())
+    }
+
+    fn append_block(&mut self, block: &Block) -> Result<(), StorageError> {
+        self.blocks.push(block.clone());
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), StorageError> {
+        self.blocks.truncate(len);
+        Ok(())
+    }
+
+    fn prune_bodies(&mut self, below: usize) -> Result<(), StorageError> {
+        for block in self.blocks.iter_mut().take(below) {
+            *block = Block::new(block.get_header().clone(), BlockBody::default());
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.blocks.len()
+    }
+}
+
+/// Append-only block file with an in-memory index of record offsets.
+///
+/// Every record carries a checksum of its payload. When the node crashes
+/// half way through a write, the torn record at the end fails its checksum
+/// on the next start and the file is truncated back to the last good block.
+/// Damage anywhere else is reported as [`StorageError::Corrupted`].
+pub struct FileBlockStore {
+    path: PathBuf,
+    file: File,
+    offsets: Vec<u64>, // Offset of every record, indexed by block height.
+    end: u64,          // Offset right behind the last good record.
+}
+
+impl FileBlockStore {
+    /// Opens the block file at `path`, creating it and its directory if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let mut store = FileBlockStore {
+            path,
+            file,
+            offsets: Vec::new(),
+            end: 0,
+        };
+        store.rebuild_index()?;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_payload(&mut self, height: usize) -> Result<Option<Vec<u8>>, StorageError> {
+        let Some(&offset) = self.offsets.get(height) else {
+            return Ok(None);
+        };
+        match self.read_record(offset, self.end)? {
+            Record::Intact(payload) => Ok(Some(payload)),
+            _ => Err(StorageError::Corrupted(format!(
+                "block {} unreadable",
+                height
+            ))),
+        }
+    }
+
+    // Walks the file and records where each intact record starts. Only the
+    // last record can be torn by a crash, so it alone is dropped; a damaged
+    // record with more data behind it means the file is corrupted.
+    fn rebuild_index(&mut self) -> Result<(), StorageError> {
+        let file_len = self.file.metadata()?.len();
+        let mut offset = 0;
+        while offset < file_len {
+            match self.read_record(offset, file_len)? {
+                Record::Intact(payload) => {
+                    self.offsets.push(offset);
+                    offset += (RECORD_HEADER_LEN + payload.len()) as u64;
+                }
+                Record::Damaged { end } if end < file_len => {
+                    return Err(StorageError::Corrupted(format!(
+                        "block {} at offset {} of {} fails its checksum",
+                        self.offsets.len(),
+                        offset,
+                        self.path.display()
+                    )));
+                }
+                Record::Damaged { .. } | Record::Torn => {
+                    warn!(
+                        "truncating torn block file {} at offset {}",
+                        self.path.display(),
+                        offset
+                    );
+                    self.file.set_len(offset)?;
+                    self.file.sync_all()?;
+                    break;
+                }
+            }
+        }
+        self.end = offset;
+        Ok(())
+    }
+
+    // Reads the record at `offset` of a file whose data ends at `file_len`.
+    // The length is checked against what's left of the file before anything
+    // is allocated for the payload.
+    fn read_record(&mut self, offset: u64, file_len: u64) -> Result<Record, StorageError> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        if read_full(&mut self.file, &mut header)? < RECORD_HEADER_LEN {
+            return Ok(Record::Torn);
+        }
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let end = offset + RECORD_HEADER_LEN as u64 + len;
+        if end > file_len {
+            return Ok(Record::Torn);
+        }
+        let mut payload = vec![0u8; len as usize];
+        if read_full(&mut self.file, &mut payload)? < payload.len() {
+            return Ok(Record::Torn);
+        }
+        if checksum(&payload) != header[4..8] {
+            return Ok(Record::Damaged { end });
+        }
+        Ok(Record::Intact(payload))
+    }
+}
+
+// What was found at the offset of a record.
+enum Record {
+    Intact(Vec<u8>),
+    Damaged { end: u64 }, // Fails its checksum; `end` is where it stops.
+    Torn,                 // Runs past the end of the file.
+}
+
+impl BlockStore for FileBlockStore {
+    fn load_blocks(&mut self) -> Result<Vec<Block>, StorageError> {
+        let mut blocks = Vec::with_capacity(self.offsets.len());
+        for height in 0..self.offsets.len() {
+            if let Some(block) = self.read_block(height)? {
+                blocks.push(block);
+            }
+        }
+        Ok(blocks)
+    }
+
+    fn read_block(&mut self, height: usize) -> Result<Option<Block>, StorageError> {
+        match self.read_payload(height)? {
+            Some(payload) => decode_block(&payload).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // The header is a prefix of the encoded block, so the body is never decoded
+    fn read_header(&mut self, height: usize) -> Result<Option<BlockHeader>, StorageError> {
+        match self.read_payload(height)? {
+            Some(payload) => BlockHeader::decode_from(&mut Reader::new(&payload))
+                .map(Some)
+                .map_err(|e| StorageError::Encoding(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn append_block(&mut self, block: &Block) -> Result<(), StorageError> {
+        let record = encode_record(&block.encode());
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+
+        self.offsets.push(self.end);
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), StorageError> {
+        if let Some(&offset) = self.offsets.get(len) {
+            self.file.set_len(offset)?;
+            self.file.sync_all()?;
+            self.offsets.truncate(len);
+            self.end = offset;
+        }
+        Ok(())
+    }
+
+    // Writes a copy of the file with header-only records below `below` and
+    // swaps it in, so a crash leaves either the old or the new file
+    fn prune_bodies(&mut self, below: usize) -> Result<(), StorageError> {
+        let tmp_path = self.path.with_extension("prune");
+        let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+        for height in 0..self.offsets.len() {
+            let mut payload = self
+                .read_payload(height)?
+                .ok_or_else(|| StorageError::Corrupted(format!("block {} unreadable", height)))?;
+            if height < below {
+                let header = BlockHeader::decode_from(&mut Reader::new(&payload))
+                    .map_err(|e| StorageError::Encoding(e.to_string()))?;
+                payload = Block::new(header, BlockBody::default()).encode();
+            }
+            tmp.write_all(&encode_record(&payload))?;
+        }
+        tmp.into_inner()
+            .map_err(|e| StorageError::Io(e.to_string()))?
+            .sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.offsets.clear();
+        self.rebuild_index()
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn index_path(&self) -> Option<PathBuf> {
+        Some(self.path.with_extension("idx"))
+    }
+
+    fn snapshot_path(&self) -> Option<PathBuf> {
+        Some(self.path.with_extension("snapshot"))
+    }
+}
+
+fn decode_block(payload: &[u8]) -> Result<Block, StorageError> {
+    Block::decode(payload).map_err(|e| StorageError::Encoding(e.to_string()))
+}
+
+fn encode_record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&checksum(payload));
+    record.extend_from_slice(payload);
+    record
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let digest = Sha256::digest(payload);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+// Like `read_exact` but reports how much was read instead of failing at EOF.
+fn read_full(file: &mut File, buf: &mut [u8]) -> Result<usize, StorageError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
@@ -0,0 +1,866 @@
+use crate::blockchain::{Block, BlockBody, BlockHeader};
+use crate::encoding::{self, Decode, DecodeError, Encode, Reader};
+use crate::gossip::SharedChain;
+use crate::reputation::Misbehaviour;
+use crate::transaction::Transaction;
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport};
+use libp2p::{PeerId, StreamProtocol};
+use log::{info, warn};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io;
+use std::time::Duration;
+
+/// Protocol headers and block bodies are requested over.
+pub const SYNC_PROTOCOL: &str = "/my-first-blockchain/sync/1.0.0";
+
+// Caps on what a single request is answered with, whatever was asked for.
+const MAX_HEADERS_PER_RESPONSE: u32 = 2000;
+const MAX_BODIES_PER_RESPONSE: usize = 128;
+// Largest sync message read off the wire.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+// How far headers may run ahead of the connected blocks.
+const MAX_HEADERS_AHEAD: usize = 10_000;
+// Failed requests after which a peer isn't asked for anything anymore.
+const MAX_PEER_FAILURES: u32 = 3;
+
+/// Tuning of the initial block download.
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    pub headers_per_request: u32,  // Headers asked for at a time.
+    pub bodies_per_request: usize, // Bodies asked for at a time.
+    pub requests_per_peer: usize,  // Body requests one peer may have outstanding.
+    pub request_timeout: Duration, // How long a peer gets to answer.
+    pub max_attempts: u32,         // Tries per batch of bodies before giving up.
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            headers_per_request: 512,
+            bodies_per_request: 16,
+            requests_per_peer: 2,
+            request_timeout: Duration::from_secs(10),
+            max_attempts: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncRequest {
+    Headers { start: u64, max: u32 }, // Up to `max` headers from height `start` on.
+    Bodies(Vec<String>),              // Bodies of the blocks with these hashes.
+    Transactions { block: String, indexes: Vec<u32> }, // Some transactions of a block.
+}
+
+/// Answer to a [`SyncRequest`]. Bodies are returned in the order asked for,
+/// stopping at the first block the peer doesn't have the body of.
+/// Transactions are all or nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncResponse {
+    Headers(Vec<BlockHeader>),
+    Bodies(Vec<BlockBody>),
+    Transactions(Vec<Transaction>),
+}
+
+/// Outcome of a round of syncing, reported once it's over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncEvent {
+    Completed { height: u64, imported: u64 }, // Caught up, with our new tip height.
+    Failed(String),
+}
+
+/// Frames sync messages as a u32 big-endian length followed by their
+/// binary encoding.
+#[derive(Debug, Clone, Default)]
+pub struct SyncCodec;
+
+#[async_trait]
+impl request_response::Codec for SyncCodec {
+    type Protocol = StreamProtocol;
+    type Request = SyncRequest;
+    type Response = SyncResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<SyncRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_MESSAGE_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<SyncResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_MESSAGE_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: SyncRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: SyncResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &response).await
+    }
+}
+
+/// Reads a length-prefixed message of at most `max_size` bytes.
+pub(crate) async fn read_message<T, M>(io: &mut T, max_size: usize) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: Decode,
+{
+    let mut len = [0u8; 4];
+    io.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too large",
+        ));
+    }
+    let mut bytes = vec![0u8; len];
+    io.read_exact(&mut bytes).await?;
+    M::decode(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `message` as a u32 big-endian length followed by its encoding.
+pub(crate) async fn write_message<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Encode,
+{
+    let bytes = message.encode();
+    io.write_all(&(bytes.len() as u32).to_be_bytes()).await?;
+    io.write_all(&bytes).await
+}
+
+/// The request-response behaviour sync runs on.
+pub fn behaviour(config: &SyncConfig) -> request_response::Behaviour<SyncCodec> {
+    request_response::Behaviour::new(
+        [(StreamProtocol::new(SYNC_PROTOCOL), ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(config.request_timeout),
+    )
+}
+
+/// Answers a peer's sync request from our chain.
+pub fn respond(chain: &SharedChain, request: SyncRequest) -> SyncResponse {
+    let blockchain = chain.blockchain.lock().unwrap();
+    match request {
+        SyncRequest::Headers { start, max } => SyncResponse::Headers(
+            blockchain.get_headers(start as usize, max.min(MAX_HEADERS_PER_RESPONSE) as usize),
+        ),
+        SyncRequest::Bodies(hashes) => SyncResponse::Bodies(
+            hashes
+                .iter()
+                .take(MAX_BODIES_PER_RESPONSE)
+                .map_while(|hash| blockchain.get_block_body(hash).ok().cloned())
+                .collect(),
+        ),
+        SyncRequest::Transactions { block, indexes } => {
+            let transactions = blockchain
+                .get_block_body(&block)
+                .ok()
+                .and_then(|body| {
+                    indexes
+                        .iter()
+                        .map(|index| body.get_transactions().get(*index as usize).cloned())
+                        .collect::<Option<Vec<Transaction>>>()
+                })
+                .unwrap_or_default();
+            SyncResponse::Transactions(transactions)
+        }
+    }
+}
+
+// Consecutive heights whose bodies are fetched with one request.
+#[derive(Debug, Clone)]
+struct Batch {
+    start: u64,
+    count: u64,
+    attempts: u32,
+    avoid: Option<PeerId>, // Peer that failed to deliver it last time.
+}
+
+enum InFlight {
+    Headers(PeerId),
+    Bodies(PeerId, Batch),
+}
+
+#[derive(Default)]
+struct PeerState {
+    requests: usize, // Requests waiting for an answer.
+    failures: u32,   // Requests that failed or were answered with bad data.
+    work: u128,      // Cumulative work the peer claimed in its handshake.
+}
+
+/// Headers-first initial block download.
+///
+/// Headers are fetched from one peer at a time, preferring the one that
+/// advertised the most work, and checked for proof of work and linkage
+/// before anything else. The bodies for them are then
+/// fetched in batches from every peer at once and connected in order. A
+/// failed or timed out request is retried with another peer where possible,
+/// and peers that keep failing are left out. A round ends once no peer has
+/// further headers for us.
+///
+/// Headers that don't follow on from our tip are asked for again from ever
+/// further back until they meet our chain. Blocks of a branch forking off
+/// below the tip are collected until they outweigh ours above the fork
+/// point, and the chain is then reorganized onto them.
+pub struct Synchronizer {
+    config: SyncConfig,
+    chain: SharedChain,
+    peers: HashMap<PeerId, PeerState>,
+    active: bool,
+    exhausted: HashSet<PeerId>, // Peers with no further headers this round.
+    header_peer: Option<PeerId>, // Peer that gave us a full batch of headers last.
+    headers: VecDeque<BlockHeader>, // Validated headers of blocks not connected yet.
+    first_height: u64,          // Height of the first entry in `headers`.
+    fork: Option<u64>,          // Last height `headers` share with our chain, if below the tip.
+    fork_search: Option<u64>,   // Height headers are asked from while looking for a fork point.
+    queue: VecDeque<Batch>,     // Bodies still to be requested.
+    in_flight: HashMap<OutboundRequestId, InFlight>,
+    ready: BTreeMap<u64, Block>, // Downloaded blocks waiting for their parent.
+    imported: u64,
+    outcome: Option<SyncEvent>,
+    penalties: Vec<(PeerId, Misbehaviour)>, // Misbehaviour not yet reported.
+}
+
+impl Synchronizer {
+    pub fn new(config: SyncConfig, chain: SharedChain) -> Self {
+        Synchronizer {
+            config,
+            chain,
+            peers: HashMap::new(),
+            active: false,
+            exhausted: HashSet::new(),
+            header_peer: None,
+            headers: VecDeque::new(),
+            first_height: 0,
+            fork: None,
+            fork_search: None,
+            queue: VecDeque::new(),
+            in_flight: HashMap::new(),
+            ready: BTreeMap::new(),
+            imported: 0,
+            outcome: None,
+            penalties: Vec::new(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Adds a peer that claims `work` behind its tip to sync from.
+    pub fn add_peer(&mut self, peer: PeerId, work: u128) {
+        self.peers.entry(peer).or_default().work = work;
+        self.exhausted.remove(&peer);
+    }
+
+    /// Forgets `peer`, handing whatever it was asked for to other peers.
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        let lost: Vec<OutboundRequestId> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| match request {
+                InFlight::Headers(p) | InFlight::Bodies(p, _) => p == peer,
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in lost {
+            self.fail(id, "peer disconnected", None);
+        }
+    }
+
+    /// Starts a round of syncing with every connected peer, unless one is
+    /// already running.
+    pub fn start(&mut self) {
+        if self.active {
+            return;
+        }
+        self.active = true;
+        self.exhausted.clear();
+        self.imported = 0;
+        self.fork_search = None;
+        self.first_height = self.chain.blockchain.lock().unwrap().get_chain_length() as u64;
+    }
+
+    /// Sends whatever requests there's room for and reports the round's
+    /// outcome once it's over. Rounds that find nothing new end quietly.
+    pub fn poll(
+        &mut self,
+        behaviour: &mut request_response::Behaviour<SyncCodec>,
+    ) -> Option<SyncEvent> {
+        if let Some(outcome) = self.outcome.take() {
+            return Some(outcome);
+        }
+        if !self.active {
+            return None;
+        }
+        self.request_headers(behaviour);
+        self.request_bodies(behaviour);
+        if !self.in_flight.is_empty() {
+            return None;
+        }
+
+        if !self.queue.is_empty() || !self.headers.is_empty() {
+            self.abort("no peers left to sync from".into());
+            return self.outcome.take();
+        }
+        self.active = false;
+        if self.imported == 0 {
+            return None;
+        }
+        let height = self.chain.blockchain.lock().unwrap().get_chain_length() as u64 - 1;
+        info!("synced {} blocks, tip at height {}", self.imported, height);
+        Some(SyncEvent::Completed {
+            height,
+            imported: self.imported,
+        })
+    }
+
+    pub fn on_response(&mut self, id: OutboundRequestId, response: SyncResponse) {
+        let Some(request) = self.in_flight.remove(&id) else {
+            return;
+        };
+        match (request, response) {
+            (InFlight::Headers(peer), SyncResponse::Headers(headers)) => {
+                self.finish_request(&peer);
+                self.on_headers(peer, headers);
+            }
+            (InFlight::Bodies(peer, batch), SyncResponse::Bodies(bodies)) => {
+                self.finish_request(&peer);
+                self.on_bodies(peer, batch, bodies);
+            }
+            (request, _) => {
+                self.in_flight.insert(id, request);
+                self.fail(
+                    id,
+                    "answered with the wrong kind of response",
+                    Some(Misbehaviour::UnrequestedData),
+                );
+            }
+        }
+    }
+
+    /// Handles a request that failed or timed out.
+    pub fn on_failure(&mut self, id: OutboundRequestId, error: &request_response::OutboundFailure) {
+        let penalty = match error {
+            request_response::OutboundFailure::Timeout => Some(Misbehaviour::Timeout),
+            _ => None,
+        };
+        self.fail(id, &error.to_string(), penalty);
+    }
+
+    /// Takes the misbehaviour seen from peers since the last call, for the
+    /// node to hold against their reputation.
+    pub fn drain_penalties(&mut self) -> Vec<(PeerId, Misbehaviour)> {
+        std::mem::take(&mut self.penalties)
+    }
+
+    fn fail(&mut self, id: OutboundRequestId, reason: &str, penalty: Option<Misbehaviour>) {
+        let Some(request) = self.in_flight.remove(&id) else {
+            return;
+        };
+        match request {
+            InFlight::Headers(peer) => {
+                warn!("Header request to {} failed: {}", peer, reason);
+                self.finish_request(&peer);
+                self.strike(&peer, penalty);
+                self.exhausted.insert(peer);
+            }
+            InFlight::Bodies(peer, batch) => {
+                warn!("Body request to {} failed: {}", peer, reason);
+                self.finish_request(&peer);
+                self.strike(&peer, penalty);
+                self.retry(batch, peer);
+            }
+        }
+    }
+
+    fn request_headers(&mut self, behaviour: &mut request_response::Behaviour<SyncCodec>) {
+        if self.headers.len() >= MAX_HEADERS_AHEAD
+            || self
+                .in_flight
+                .values()
+                .any(|request| matches!(request, InFlight::Headers(_)))
+        {
+            return;
+        }
+        let candidates: Vec<PeerId> = self
+            .peers
+            .iter()
+            .filter(|(peer, state)| {
+                state.failures < MAX_PEER_FAILURES && !self.exhausted.contains(peer)
+            })
+            .map(|(peer, _)| *peer)
+            .collect();
+        let peer = match self.header_peer.filter(|peer| candidates.contains(peer)) {
+            Some(peer) => peer,
+            None => match candidates.into_iter().min_by_key(|peer| {
+                let state = &self.peers[peer];
+                (state.failures, Reverse(state.work))
+            }) {
+                Some(peer) => peer,
+                None => return,
+            },
+        };
+
+        let start = if self.headers.is_empty() {
+            self.fork_search
+                .unwrap_or_else(|| self.chain.blockchain.lock().unwrap().get_chain_length() as u64)
+        } else {
+            self.first_height + self.headers.len() as u64
+        };
+        let request = SyncRequest::Headers {
+            start,
+            max: self.config.headers_per_request,
+        };
+        let id = behaviour.send_request(&peer, request);
+        self.in_flight.insert(id, InFlight::Headers(peer));
+        self.peers.entry(peer).or_default().requests += 1;
+    }
+
+    fn request_bodies(&mut self, behaviour: &mut request_response::Behaviour<SyncCodec>) {
+        while let Some(batch) = self.queue.pop_front() {
+            // Least busy peer, passing over the one that failed this batch
+            // last time unless there's nobody else.
+            let peer = self
+                .peers
+                .iter()
+                .filter(|(_, state)| {
+                    state.failures < MAX_PEER_FAILURES
+                        && state.requests < self.config.requests_per_peer
+                })
+                .min_by_key(|(peer, state)| (Some(**peer) == batch.avoid, state.requests))
+                .map(|(peer, _)| *peer);
+            let Some(peer) = peer else {
+                self.queue.push_front(batch);
+                return;
+            };
+
+            let hashes = (batch.start..batch.start + batch.count)
+                .map(|height| self.headers[(height - self.first_height) as usize].calculate_hash())
+                .collect();
+            let id = behaviour.send_request(&peer, SyncRequest::Bodies(hashes));
+            self.in_flight.insert(id, InFlight::Bodies(peer, batch));
+            self.peers.entry(peer).or_default().requests += 1;
+        }
+    }
+
+    fn on_headers(&mut self, peer: PeerId, headers: Vec<BlockHeader>) {
+        if headers.len() as u32 > self.config.headers_per_request {
+            warn!("{} sent more headers than asked for", peer);
+            self.strike(&peer, Some(Misbehaviour::UnrequestedData));
+            self.exhausted.insert(peer);
+            return;
+        }
+        let full = headers.len() as u32 == self.config.headers_per_request;
+        let blockchain = self.chain.blockchain.lock().unwrap();
+        let chain_length = blockchain.get_chain_length() as u64;
+        let next = headers.last().map(|header| header.get_index() as u64 + 1);
+        let mut headers = headers;
+        if self.headers.is_empty() {
+            // Headers we have are shared history, or came in through gossip
+            // since we asked.
+            headers.retain(|header| {
+                blockchain
+                    .get_index()
+                    .get_block_height(&header.calculate_hash())
+                    .is_none()
+            });
+        }
+        if headers.is_empty() {
+            drop(blockchain);
+            if self.headers.is_empty() {
+                self.fork_search = next.filter(|next| full && *next < chain_length);
+            }
+            if !full {
+                self.exhausted.insert(peer);
+            }
+            return;
+        }
+
+        let parent = match self.headers.back() {
+            Some(header) if header.calculate_hash() == headers[0].get_previous_hash() => {
+                header.clone()
+            }
+            Some(_) => {
+                // A peer on another branch isn't misbehaving, it just can't
+                // help with this one.
+                drop(blockchain);
+                warn!(
+                    "Headers from {} don't extend the branch we're fetching",
+                    peer
+                );
+                self.exhausted.insert(peer);
+                return;
+            }
+            None => {
+                let parent = blockchain
+                    .get_index()
+                    .get_block_height(headers[0].get_previous_hash())
+                    .and_then(|height| blockchain.get_header(height as usize))
+                    .cloned();
+                let Some(parent) = parent else {
+                    // The peer's branch forks off further back; look twice
+                    // as far down next time.
+                    drop(blockchain);
+                    let asked = self.fork_search.unwrap_or(chain_length);
+                    if asked <= 1 {
+                        warn!("Headers from {} don't meet our chain", peer);
+                        self.strike(&peer, None);
+                        self.exhausted.insert(peer);
+                        return;
+                    }
+                    let back = chain_length.saturating_sub(asked).max(1) * 2;
+                    self.fork_search = Some(chain_length.saturating_sub(back).max(1));
+                    return;
+                };
+                let fork = parent.get_index() as u64;
+                self.fork = Some(fork).filter(|fork| fork + 1 < chain_length);
+                self.fork_search = None;
+                self.first_height = fork + 1;
+                parent
+            }
+        };
+        let checked = blockchain.check_header_chain(&parent, &headers);
+        drop(blockchain);
+        if let Err(e) = checked {
+            warn!("Invalid headers from {}: {}", peer, e);
+            self.strike(&peer, Some(Misbehaviour::InvalidBlock));
+            self.exhausted.insert(peer);
+            if self.headers.is_empty() {
+                self.fork = None;
+            }
+            return;
+        }
+
+        let mut start = self.first_height + self.headers.len() as u64;
+        let end = start + headers.len() as u64;
+        while start < end {
+            let count = (self.config.bodies_per_request as u64).min(end - start);
+            self.queue.push_back(Batch {
+                start,
+                count,
+                attempts: 0,
+                avoid: None,
+            });
+            start += count;
+        }
+        self.headers.extend(headers);
+        if full {
+            self.header_peer = Some(peer);
+        } else {
+            self.exhausted.insert(peer);
+        }
+    }
+
+    fn on_bodies(&mut self, peer: PeerId, batch: Batch, bodies: Vec<BlockBody>) {
+        if bodies.len() as u64 > batch.count {
+            warn!("{} sent more bodies than asked for", peer);
+            self.penalties.push((peer, Misbehaviour::UnrequestedData));
+        }
+        let mut delivered = 0;
+        let mut penalty = None;
+        {
+            let blockchain = self.chain.blockchain.lock().unwrap();
+            for body in bodies.into_iter().take(batch.count as usize) {
+                let height = batch.start + delivered;
+                let header = self.headers[(height - self.first_height) as usize].clone();
+                let block = Block::new(header, body);
+                if let Err(e) = blockchain.check_block(&block) {
+                    warn!("Invalid body for block {} from {}: {}", height, peer, e);
+                    penalty = Some(Misbehaviour::InvalidBlock);
+                    break;
+                }
+                self.ready.insert(height, block);
+                delivered += 1;
+            }
+        }
+
+        // Running out of bodies isn't held against a peer; pruned nodes
+        // simply don't have them.
+        if delivered < batch.count {
+            self.strike(&peer, penalty);
+            self.retry(
+                Batch {
+                    start: batch.start + delivered,
+                    count: batch.count - delivered,
+                    ..batch
+                },
+                peer,
+            );
+        }
+        self.connect_ready();
+    }
+
+    // Connects downloaded blocks for as long as the next one is there. Those
+    // of a branch are held back until there are enough of them to outweigh
+    // our blocks above the fork point.
+    fn connect_ready(&mut self) {
+        let mut blockchain = self.chain.blockchain.lock().unwrap();
+        if let Some(fork) = self.fork {
+            let count = (self.first_height..)
+                .take_while(|height| self.ready.contains_key(height))
+                .count();
+            let work = self
+                .headers
+                .iter()
+                .take(count)
+                .fold(0u128, |work, header| work.saturating_add(header.get_work()));
+            if work <= blockchain.get_work_after(fork as usize) {
+                return;
+            }
+            let mut blocks = Vec::with_capacity(count);
+            for _ in 0..count {
+                blocks.extend(self.ready.remove(&self.first_height));
+                self.headers.pop_front();
+                self.first_height += 1;
+            }
+            match blockchain.reorganize(fork as usize, blocks.clone()) {
+                Ok(disconnected) => {
+                    self.chain.mempool.lock().unwrap().on_reorg(
+                        &disconnected,
+                        &blocks,
+                        blockchain.get_state(),
+                    );
+                    self.fork = None;
+                    self.imported += count as u64;
+                }
+                Err(e) => {
+                    let reason =
+                        format!("branch forking after block {} doesn't connect: {}", fork, e);
+                    drop(blockchain);
+                    self.abort(reason);
+                    return;
+                }
+            }
+        }
+        while let Some(block) = self.ready.remove(&self.first_height) {
+            self.headers.pop_front();
+            self.first_height += 1;
+            if blockchain
+                .get_index()
+                .get_block_height(block.get_hash())
+                .is_some()
+            {
+                continue;
+            }
+            if let Err(e) = blockchain.try_add_block(block.clone()) {
+                let reason = format!("block {} doesn't connect: {}", block.get_index(), e);
+                drop(blockchain);
+                self.abort(reason);
+                return;
+            }
+            self.chain
+                .mempool
+                .lock()
+                .unwrap()
+                .on_block_added(&block, blockchain.get_state());
+            self.imported += 1;
+        }
+    }
+
+    fn retry(&mut self, batch: Batch, failed_peer: PeerId) {
+        if batch.attempts + 1 >= self.config.max_attempts {
+            self.abort(format!(
+                "no peer delivered block {} after {} attempts",
+                batch.start, self.config.max_attempts
+            ));
+            return;
+        }
+        self.queue.push_front(Batch {
+            attempts: batch.attempts + 1,
+            avoid: Some(failed_peer),
+            ..batch
+        });
+    }
+
+    fn finish_request(&mut self, peer: &PeerId) {
+        if let Some(state) = self.peers.get_mut(peer) {
+            state.requests = state.requests.saturating_sub(1);
+        }
+    }
+
+    fn strike(&mut self, peer: &PeerId, penalty: Option<Misbehaviour>) {
+        if let Some(misbehaviour) = penalty {
+            self.penalties.push((*peer, misbehaviour));
+        }
+        if let Some(state) = self.peers.get_mut(peer) {
+            state.failures += 1;
+            if state.failures == MAX_PEER_FAILURES {
+                warn!("Not syncing from {} anymore", peer);
+            }
+        }
+    }
+
+    // Drops everything fetched so far. Answers to requests still out are
+    // ignored when they come in.
+    fn abort(&mut self, reason: String) {
+        warn!("Sync failed: {}", reason);
+        self.active = false;
+        self.header_peer = None;
+        self.headers.clear();
+        self.fork = None;
+        self.fork_search = None;
+        self.queue.clear();
+        self.ready.clear();
+        self.in_flight.clear();
+        for state in self.peers.values_mut() {
+            state.requests = 0;
+        }
+        self.outcome = Some(SyncEvent::Failed(reason));
+    }
+}
+
+impl Encode for SyncRequest {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encoding::put_u8(out, encoding::ENCODING_VERSION);
+        match self {
+            SyncRequest::Headers { start, max } => {
+                encoding::put_u8(out, 0);
+                encoding::put_u64(out, *start);
+                encoding::put_u32(out, *max);
+            }
+            SyncRequest::Bodies(hashes) => {
+                encoding::put_u8(out, 1);
+                encoding::put_u32(out, hashes.len() as u32);
+                for hash in hashes {
+                    encoding::put_str(out, hash);
+                }
+            }
+            SyncRequest::Transactions { block, indexes } => {
+                encoding::put_u8(out, 2);
+                encoding::put_str(out, block);
+                encoding::put_u32(out, indexes.len() as u32);
+                for index in indexes {
+                    encoding::put_u32(out, *index);
+                }
+            }
+        }
+    }
+}
+
+impl Decode for SyncRequest {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        match reader.read_u8()? {
+            0 => Ok(SyncRequest::Headers {
+                start: reader.read_u64()?,
+                max: reader.read_u32()?,
+            }),
+            1 => {
+                let count = read_count(reader)?;
+                let mut hashes = Vec::with_capacity(count);
+                for _ in 0..count {
+                    hashes.push(reader.read_string()?);
+                }
+                Ok(SyncRequest::Bodies(hashes))
+            }
+            2 => {
+                let block = reader.read_string()?;
+                let count = read_count(reader)?;
+                let mut indexes = Vec::with_capacity(count);
+                for _ in 0..count {
+                    indexes.push(reader.read_u32()?);
+                }
+                Ok(SyncRequest::Transactions { block, indexes })
+            }
+            tag => Err(DecodeError::Invalid(format!(
+                "unknown sync request {}",
+                tag
+            ))),
+        }
+    }
+}
+
+impl Encode for SyncResponse {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encoding::put_u8(out, encoding::ENCODING_VERSION);
+        match self {
+            SyncResponse::Headers(headers) => {
+                encoding::put_u8(out, 0);
+                encoding::put_u32(out, headers.len() as u32);
+                headers.iter().for_each(|header| header.encode_to(out));
+            }
+            SyncResponse::Bodies(bodies) => {
+                encoding::put_u8(out, 1);
+                encoding::put_u32(out, bodies.len() as u32);
+                bodies.iter().for_each(|body| body.encode_to(out));
+            }
+            SyncResponse::Transactions(transactions) => {
+                encoding::put_u8(out, 2);
+                encoding::put_u32(out, transactions.len() as u32);
+                transactions.iter().for_each(|tx| tx.encode_to(out));
+            }
+        }
+    }
+}
+
+impl Decode for SyncResponse {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        match reader.read_u8()? {
+            0 => {
+                let count = read_count(reader)?;
+                let mut headers = Vec::with_capacity(count);
+                for _ in 0..count {
+                    headers.push(BlockHeader::decode_from(reader)?);
+                }
+                Ok(SyncResponse::Headers(headers))
+            }
+            1 => {
+                let count = read_count(reader)?;
+                let mut bodies = Vec::with_capacity(count);
+                for _ in 0..count {
+                    bodies.push(BlockBody::decode_from(reader)?);
+                }
+                Ok(SyncResponse::Bodies(bodies))
+            }
+            2 => {
+                let count = read_count(reader)?;
+                let mut transactions = Vec::with_capacity(count);
+                for _ in 0..count {
+                    transactions.push(Transaction::decode_from(reader)?);
+                }
+                Ok(SyncResponse::Transactions(transactions))
+            }
+            tag => Err(DecodeError::Invalid(format!(
+                "unknown sync response {}",
+                tag
+            ))),
+        }
+    }
+}
+
+// Every item takes at least a byte, so a count larger than the remaining
+// input can't be honest.
+fn read_count(reader: &mut Reader) -> Result<usize, DecodeError> {
+    let count = reader.read_u32()? as usize;
+    if count > reader.remaining() {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    Ok(count)
+}
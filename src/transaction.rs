@@ -1,14 +1,22 @@
-use secp256k1::Message;
+use secp256k1::{ecdsa::Signature, Message};
 use serde::{Deserialize, Serialize};
 use sha3::{Digest, Keccak256};
 
+use crate::encoding::{self, Decode, DecodeError, Encode, Reader};
+use crate::state::WorldState;
 use crate::utils::{public_key_to_address, recover_public_key};
 
-#[derive(Serialize, Deserialize)]
+// Signature carried by minting transactions, which credit the receiver
+// without debiting the sender.
+pub const COINBASE_SIGNATURE: &[u8] = b"coinbase";
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
 pub struct Transaction {
     sender: String,
     receiver: String,
     amount: u64,
+    #[serde(default)]
+    fee: u64,
     nonce: u64,
     signature: Vec<u8>,
 }
@@ -18,6 +26,7 @@ impl Clone for Transaction {
             sender: self.sender.clone(),
             receiver: self.receiver.clone(),
             amount: self.amount,
+            fee: self.fee,
             nonce: self.nonce,
             signature: self.signature.clone(),
         }
@@ -31,11 +40,24 @@ impl Transaction {
         amount: u64,
         nonce: u64,
         signature: Vec<u8>,
+    ) -> Self {
+        Self::new_with_fee(sender, receiver, amount, 0, nonce, signature)
+    }
+
+    // Create a new transaction paying a fee to the miner
+    pub fn new_with_fee(
+        sender: String,
+        receiver: String,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+        signature: Vec<u8>,
     ) -> Self {
         Transaction {
             sender,
             receiver,
             amount,
+            fee,
             nonce,
             signature,
         }
@@ -43,16 +65,31 @@ impl Transaction {
 
     // Method to verify the transaction's signature
     pub fn verify_signature(&self) -> bool {
-        let message = Message::from_digest_slice(&self.calculate_hash())
-            .expect("Failed to convert message hash");
-        let public_key = recover_public_key(&message, &self.signature).unwrap();
-
-        let address = public_key_to_address(&public_key);
-        if address != self.sender && address != self.receiver && self.amount == 0 {
+        if !self.has_canonical_signature() {
             return false;
         }
+        let message = Message::from_digest_slice(&self.signing_hash())
+            .expect("Failed to convert message hash");
+        let public_key = match recover_public_key(&message, &self.signature) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
 
-        true
+        public_key_to_address(&public_key) == self.sender
+    }
+
+    // Only the low-S form of a signature is accepted, otherwise anyone could
+    // flip S and produce a second valid signature with a different ID
+    fn has_canonical_signature(&self) -> bool {
+        let Some(compact) = self.signature.get(..64) else {
+            return false;
+        };
+        let Ok(signature) = Signature::from_compact(compact) else {
+            return false;
+        };
+        let mut normalized = signature;
+        normalized.normalize_s();
+        normalized == signature
     }
 
     // Serialize the transaction into a JSON string
@@ -65,37 +102,77 @@ impl Transaction {
         serde_json::from_str(transaction_data).unwrap()
     }
 
-    // Calculate the hash of the transaction
-    fn calculate_hash(&self) -> [u8; 32] {
-        let serialized_transaction = self.serialize();
-        Keccak256::digest(serialized_transaction.as_bytes()).into()
+    // Calculate the hash of the canonical binary encoding. This is what
+    // merkle trees commit to and what the transaction ID is made of.
+    pub fn calculate_hash(&self) -> [u8; 32] {
+        Keccak256::digest(self.encode()).into()
+    }
+
+    // Hash of the canonical encoding without the signature, which is what
+    // the sender signs
+    pub fn signing_hash(&self) -> [u8; 32] {
+        let mut unsigned = Vec::new();
+        self.encode_unsigned(&mut unsigned);
+        Keccak256::digest(&unsigned).into()
+    }
+
+    fn encode_unsigned(&self, out: &mut Vec<u8>) {
+        encoding::put_u8(out, encoding::ENCODING_VERSION);
+        encoding::put_str(out, &self.sender);
+        encoding::put_str(out, &self.receiver);
+        encoding::put_u64(out, self.amount);
+        encoding::put_u64(out, self.fee);
+        encoding::put_u64(out, self.nonce);
+    }
+
+    // Hex encoded hash identifying the transaction
+    pub fn id(&self) -> String {
+        hex::encode(self.calculate_hash())
     }
 
     // Check if the transaction is valid
-    fn is_valid(&self) -> bool {
-        // Implement validity checks here
-        true
+    pub fn is_valid(&self) -> bool {
+        !self.sender.is_empty()
+            && !self.receiver.is_empty()
+            && (self.is_coinbase() || self.signature.len() == 65)
     }
 
-    // Apply the transaction (this would be part of a larger blockchain logic)
-    fn execute(&mut self) {
-        // Update blockchain state based on the transaction
+    // Minting transactions credit the receiver out of thin air
+    pub fn is_coinbase(&self) -> bool {
+        self.signature == COINBASE_SIGNATURE
     }
 
-    // Print transaction details
-    pub fn print_details(&self) {
-        println!("{:?}", self.to_string());
+    // Amount plus fee, i.e. what the sender is debited
+    pub fn total_cost(&self) -> u64 {
+        self.amount.saturating_add(self.fee)
     }
 
-    fn to_string(&self) -> String {
-        format!(
-            "Sender: {}\nReceiver: {}\nAmount: {}\nNonce: {}\nSignature: {:?}",
-            self.sender, self.receiver, self.amount, self.nonce, self.signature
-        )
+    // Fee paid per 1000 bytes of encoded transaction
+    pub fn fee_rate(&self) -> u64 {
+        let size = self.encode().len() as u64;
+        self.fee.saturating_mul(1000) / size
     }
 
-    fn to_json(&self) -> String {
-        serde_json::to_string(self).unwrap()
+    // Apply the transaction to the account state. Fails, leaving `state`
+    // partly updated, if the sender can't pay or a balance would overflow.
+    pub fn execute(&self, state: &mut WorldState) -> Result<(), String> {
+        if !self.is_coinbase() {
+            let total_cost = self
+                .amount
+                .checked_add(self.fee)
+                .ok_or_else(|| format!("cost of transaction {} overflows", self.id()))?;
+            let sender = state.get_account_mut(&self.sender);
+            sender.debit(total_cost)?;
+            if sender.get_nonce() <= self.nonce {
+                sender.set_nonce(self.nonce.saturating_add(1));
+            }
+        }
+        state.get_account_mut(&self.receiver).credit(self.amount)
+    }
+
+    // Print transaction details
+    pub fn print_details(&self) {
+        println!("{:?}", self.to_string());
     }
 
     pub fn get_sender(&self) -> &str {
@@ -110,6 +187,10 @@ impl Transaction {
         self.amount
     }
 
+    pub fn get_fee(&self) -> u64 {
+        self.fee
+    }
+
     pub fn get_nonce(&self) -> u64 {
         self.nonce
     }
@@ -119,12 +200,43 @@ impl Transaction {
     }
 }
 
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.encode_unsigned(out);
+        encoding::put_bytes(out, &self.signature);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        Ok(Transaction {
+            sender: reader.read_string()?,
+            receiver: reader.read_string()?,
+            amount: reader.read_u64()?,
+            fee: reader.read_u64()?,
+            nonce: reader.read_u64()?,
+            signature: reader.read_bytes()?,
+        })
+    }
+}
+
+impl std::fmt::Display for Transaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Sender: {}\nReceiver: {}\nAmount: {}\nFee: {}\nNonce: {}\nSignature: {:?}",
+            self.sender, self.receiver, self.amount, self.fee, self.nonce, self.signature
+        )
+    }
+}
+
 impl std::fmt::Debug for Transaction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Transaction {{ sender: {}, receiver: {}, amount: {}, nonce: {}, signature: {:?} }}",
-            self.sender, self.receiver, self.amount, self.nonce, self.signature
+            "Transaction {{ sender: {}, receiver: {}, amount: {}, fee: {}, nonce: {}, signature: {:?} }}",
+            self.sender, self.receiver, self.amount, self.fee, self.nonce, self.signature
         )
     }
 }
@@ -1,3 +1,4 @@
+use crate::transaction::Transaction;
 use secp256k1::{
     ecdsa::RecoverableSignature, ecdsa::RecoveryId, rand, Message, PublicKey, Secp256k1, SecretKey,
 };
@@ -10,8 +11,18 @@ pub fn sign_transaction(
     amount: u64,
     nonce: u64,
 ) -> Vec<u8> {
-    let message = format!("{}{}{}{}", sender, receiver, amount, nonce);
-    let message_hash = Keccak256::digest(message.as_bytes());
+    sign_transaction_with_fee(key, sender, receiver, amount, 0, nonce)
+}
+
+pub fn sign_transaction_with_fee(
+    key: SecretKey,
+    sender: String,
+    receiver: String,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+) -> Vec<u8> {
+    let message_hash = transaction_message_hash(&sender, &receiver, amount, fee, nonce);
     let secp = Secp256k1::new();
 
     let message_hash =
@@ -25,6 +36,26 @@ pub fn sign_transaction(
     signature
 }
 
+// Hash of the message a sender signs: the canonical encoding of the
+// transaction without its signature.
+pub fn transaction_message_hash(
+    sender: &str,
+    receiver: &str,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+) -> [u8; 32] {
+    let unsigned = Transaction::new_with_fee(
+        sender.to_string(),
+        receiver.to_string(),
+        amount,
+        fee,
+        nonce,
+        Vec::new(),
+    );
+    unsigned.signing_hash()
+}
+
 pub fn public_key_to_address(public_key: &secp256k1::PublicKey) -> String {
     let serialized_public_key = public_key.serialize_uncompressed();
     let mut hasher = Keccak256::new();
@@ -43,11 +74,13 @@ pub fn recover_public_key(
     sig: &[u8],
 ) -> Result<secp256k1::PublicKey, secp256k1::Error> {
     let secp = Secp256k1::new();
-    // let message = Message::from_digest_slice(msg)?;
+    if sig.len() != 65 {
+        return Err(secp256k1::Error::InvalidSignature);
+    }
     let recovery_id_value = sig[64] as i32 - 27;
     let recovery_id = RecoveryId::from_i32(recovery_id_value)?;
     let signature = RecoverableSignature::from_compact(&sig[0..64], recovery_id)?;
-    secp.recover_ecdsa(&msg, &signature)
+    secp.recover_ecdsa(msg, &signature)
 }
 
 pub fn generate_key_pair() -> (SecretKey, PublicKey) {
@@ -0,0 +1,185 @@
+//! Keys of the accounts a node operator sends from.
+//!
+//! The wallet is a JSON file of hex encoded secret keys. It isn't
+//! encrypted, so it's written readable by its owner only and should be
+//! kept that way.
+
+use crate::transaction::Transaction;
+use crate::utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const WALLET_FILE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletError {
+    Io(String),
+    Format(String),
+    UnknownAddress(String),
+}
+
+impl std::fmt::Display for WalletError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalletError::Io(ref err) => write!(f, "Wallet IO error: {}", err),
+            WalletError::Format(ref err) => write!(f, "Wallet format error: {}", err),
+            WalletError::UnknownAddress(ref address) => {
+                write!(f, "No key for address {} in the wallet", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+impl From<std::io::Error> for WalletError {
+    fn from(e: std::io::Error) -> Self {
+        WalletError::Io(e.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WalletFile {
+    version: u32,
+    keys: Vec<WalletKey>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WalletKey {
+    address: String,
+    secret_key: String, // Hex encoded.
+}
+
+/// Secret keys by the address they control.
+#[derive(Default)]
+pub struct Wallet {
+    keys: BTreeMap<String, SecretKey>,
+}
+
+impl Wallet {
+    pub fn new() -> Self {
+        Wallet::default()
+    }
+
+    /// Reads a wallet written by [`Wallet::save`]. A missing file yields an
+    /// empty wallet; one that doesn't parse is an error, as its keys would
+    /// otherwise be overwritten by the next save.
+    pub fn load(path: &Path) -> Result<Self, WalletError> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Wallet::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let file: WalletFile =
+            serde_json::from_slice(&data).map_err(|e| WalletError::Format(e.to_string()))?;
+        if file.version != WALLET_FILE_VERSION {
+            return Err(WalletError::Format(format!(
+                "unsupported wallet version {}",
+                file.version
+            )));
+        }
+
+        let mut wallet = Wallet::new();
+        for key in file.keys {
+            let bytes =
+                hex::decode(&key.secret_key).map_err(|e| WalletError::Format(e.to_string()))?;
+            let secret_key =
+                SecretKey::from_slice(&bytes).map_err(|e| WalletError::Format(e.to_string()))?;
+            let address = wallet.add_key(secret_key);
+            if address != key.address {
+                return Err(WalletError::Format(format!(
+                    "key stored for {} belongs to {}",
+                    key.address, address
+                )));
+            }
+        }
+        Ok(wallet)
+    }
+
+    /// Writes the wallet to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<(), WalletError> {
+        let file = WalletFile {
+            version: WALLET_FILE_VERSION,
+            keys: self
+                .keys
+                .iter()
+                .map(|(address, secret_key)| WalletKey {
+                    address: address.clone(),
+                    secret_key: hex::encode(secret_key.secret_bytes()),
+                })
+                .collect(),
+        };
+        let data =
+            serde_json::to_vec_pretty(&file).map_err(|e| WalletError::Format(e.to_string()))?;
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Creates a new key and returns its address.
+    pub fn generate_key(&mut self) -> String {
+        let (secret_key, _) = generate_key_pair();
+        self.add_key(secret_key)
+    }
+
+    /// Adds an existing key and returns its address.
+    pub fn add_key(&mut self, secret_key: SecretKey) -> String {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+        let address = public_key_to_address(&public_key);
+        self.keys.insert(address.clone(), secret_key);
+        address
+    }
+
+    /// Addresses of the wallet, sorted.
+    pub fn get_addresses(&self) -> Vec<&str> {
+        self.keys.keys().map(String::as_str).collect()
+    }
+
+    pub fn contains(&self, address: &str) -> bool {
+        self.keys.contains_key(address)
+    }
+
+    /// A transfer from `sender`, signed with its key from the wallet.
+    pub fn sign_transfer(
+        &self,
+        sender: &str,
+        receiver: &str,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+    ) -> Result<Transaction, WalletError> {
+        let secret_key = self
+            .keys
+            .get(sender)
+            .ok_or_else(|| WalletError::UnknownAddress(sender.to_string()))?;
+        let signature = sign_transaction_with_fee(
+            *secret_key,
+            sender.to_string(),
+            receiver.to_string(),
+            amount,
+            fee,
+            nonce,
+        );
+        Ok(Transaction::new_with_fee(
+            sender.to_string(),
+            receiver.to_string(),
+            amount,
+            fee,
+            nonce,
+            signature,
+        ))
+    }
+}
@@ -0,0 +1,393 @@
+//! WebSocket subscriptions to chain and mempool events.
+//!
+//! Clients send JSON-RPC `subscribe` requests naming what they want to hear
+//! about: `newHeads`, `pendingTransactions`, `reorgs` or
+//! `addressTransactions` with a list of addresses. Each returns a
+//! subscription ID, and matching events are then pushed as `subscription`
+//! notifications carrying that ID until the client calls `unsubscribe`.
+
+use crate::blockchain::Block;
+use crate::events::{EventBus, NodeEvent};
+use crate::index::TxLocation;
+use crate::rpc::{
+    error_response, param, RpcError, TransactionView, INVALID_PARAMS, INVALID_REQUEST,
+    METHOD_NOT_FOUND, PARSE_ERROR,
+};
+use crate::transaction::Transaction;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::sync::broadcast::error::TryRecvError;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{Message, WebSocket};
+
+/// Connections served at a time; further ones are closed right away.
+pub const MAX_WS_CONNECTIONS: usize = 64;
+/// Largest message a client may send.
+pub const MAX_WS_MESSAGE_SIZE: usize = 64 * 1024;
+
+// How often a connection checks for new events while the client is quiet.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// How long the opening handshake and any single write may take.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A new tip as pushed to `newHeads` subscribers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeadView {
+    pub hash: String,
+    pub height: u64,
+    pub previous_hash: String,
+    pub timestamp: u64,
+    pub difficulty: u32,
+    pub transaction_count: usize,
+}
+
+impl HeadView {
+    pub fn new(block: &Block) -> Self {
+        HeadView {
+            hash: block.get_hash().to_string(),
+            height: block.get_index() as u64,
+            previous_hash: block.get_previous_hash().to_string(),
+            timestamp: block.get_timestamp(),
+            difficulty: block.get_header().get_difficulty(),
+            transaction_count: block.get_data_raw().len(),
+        }
+    }
+}
+
+/// A reorganization as pushed to `reorgs` subscribers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReorgView {
+    pub fork_height: u64,
+    pub disconnected: Vec<String>, // Hashes of the abandoned blocks, oldest first.
+    pub connected: Vec<String>,    // Hashes of the new branch, oldest first.
+    pub tip_height: u64,
+}
+
+// What a subscription listens for.
+enum Subscription {
+    NewHeads,
+    PendingTransactions,
+    Reorgs,
+    AddressTransactions(HashSet<String>),
+}
+
+impl Subscription {
+    fn parse(params: &Value) -> Result<Self, RpcError> {
+        let kind: String = param(params, 0, "kind")?;
+        match kind.as_str() {
+            "newHeads" => Ok(Subscription::NewHeads),
+            "pendingTransactions" => Ok(Subscription::PendingTransactions),
+            "reorgs" => Ok(Subscription::Reorgs),
+            "addressTransactions" => {
+                let addresses: HashSet<String> = param(params, 1, "addresses")?;
+                if addresses.is_empty() {
+                    return Err(RpcError::new(INVALID_PARAMS, "no addresses given"));
+                }
+                Ok(Subscription::AddressTransactions(addresses))
+            }
+            _ => Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("unknown subscription {}", kind),
+            )),
+        }
+    }
+
+    // Payloads `event` produces for this subscription, in order.
+    fn payloads(&self, event: &NodeEvent) -> Vec<Value> {
+        let blocks: &[Block] = match event {
+            NodeEvent::BlockConnected(block) => std::slice::from_ref(block),
+            NodeEvent::Reorganized { connected, .. } => connected,
+            NodeEvent::Mempool(_) => &[],
+        };
+        match (self, event) {
+            (Subscription::NewHeads, _) => blocks
+                .iter()
+                .map(|block| json!(HeadView::new(block)))
+                .collect(),
+            (Subscription::PendingTransactions, NodeEvent::Mempool(change)) => change
+                .admitted()
+                .map(|transaction| json!(TransactionView::new(transaction, None)))
+                .into_iter()
+                .collect(),
+            (
+                Subscription::Reorgs,
+                NodeEvent::Reorganized {
+                    fork_height,
+                    disconnected,
+                    connected,
+                },
+            ) => {
+                let hashes = |blocks: &[Block]| {
+                    blocks
+                        .iter()
+                        .map(|block| block.get_hash().to_string())
+                        .collect()
+                };
+                vec![json!(ReorgView {
+                    fork_height: *fork_height,
+                    disconnected: hashes(disconnected),
+                    connected: hashes(connected),
+                    tip_height: fork_height + connected.len() as u64,
+                })]
+            }
+            (Subscription::AddressTransactions(addresses), event) => {
+                let involved = |transaction: &Transaction| {
+                    addresses.contains(transaction.get_sender())
+                        || addresses.contains(transaction.get_receiver())
+                };
+                if let NodeEvent::Mempool(change) = event {
+                    return change
+                        .admitted()
+                        .filter(|transaction| involved(transaction))
+                        .map(|transaction| json!(TransactionView::new(transaction, None)))
+                        .into_iter()
+                        .collect();
+                }
+                blocks
+                    .iter()
+                    .flat_map(|block| {
+                        block
+                            .get_data_raw()
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, transaction)| involved(transaction))
+                            .map(|(position, transaction)| {
+                                let location = TxLocation {
+                                    block_hash: block.get_hash().to_string(),
+                                    height: block.get_index() as u64,
+                                    position,
+                                };
+                                json!(TransactionView::new(transaction, Some(&location)))
+                            })
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Subscriptions of a single connection.
+#[derive(Default)]
+struct Subscriptions {
+    active: BTreeMap<u64, Subscription>,
+    next_id: u64,
+}
+
+impl Subscriptions {
+    // Answers a request from the client.
+    fn handle(&mut self, text: &str) -> Value {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                return error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))
+            }
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let params = request.get("params").unwrap_or(&Value::Null);
+        let result = match request.get("method").and_then(Value::as_str) {
+            Some("subscribe") => Subscription::parse(params).map(|subscription| {
+                self.next_id += 1;
+                self.active.insert(self.next_id, subscription);
+                json!(self.next_id.to_string())
+            }),
+            Some("unsubscribe") => param::<String>(params, 0, "subscription").map(|subscription| {
+                let removed = subscription
+                    .parse()
+                    .ok()
+                    .and_then(|id: u64| self.active.remove(&id));
+                json!(removed.is_some())
+            }),
+            Some(method) => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            )),
+            None => Err(RpcError::new(INVALID_REQUEST, "method must be a string")),
+        };
+        match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(err) => error_response(id, err),
+        }
+    }
+
+    // Notifications `event` produces across all subscriptions.
+    fn notifications(&self, event: &NodeEvent) -> Vec<Value> {
+        self.active
+            .iter()
+            .flat_map(|(id, subscription)| {
+                subscription.payloads(event).into_iter().map(move |result| {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": "subscription",
+                        "params": { "subscription": id.to_string(), "result": result },
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// WebSocket server pushing events from an [`EventBus`] to subscribers,
+/// with a thread per connection.
+pub struct WsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WsServer {
+    /// Starts serving events from `bus` on `addr`, which must be a loopback
+    /// address. Port 0 picks a free port; see `local_addr`.
+    pub fn start(addr: SocketAddr, bus: EventBus) -> io::Result<Self> {
+        if !addr.ip().is_loopback() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "refusing to serve WebSockets on non-loopback address {}",
+                    addr
+                ),
+            ));
+        }
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || accept_loop(listener, bus, stop))
+        };
+        info!("serving WebSocket subscriptions on ws://{}", addr);
+        Ok(WsServer {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops accepting connections and closes the open ones.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            // Wake the accept loop up so it sees the flag.
+            let _ = TcpStream::connect(self.addr);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WsServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn accept_loop(listener: TcpListener, bus: EventBus, stop: Arc<AtomicBool>) {
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("failed to accept a WebSocket connection: {}", e);
+                continue;
+            }
+        };
+        if open.load(Ordering::Relaxed) >= MAX_WS_CONNECTIONS {
+            debug!("too many WebSocket connections, refusing one");
+            continue;
+        }
+        open.fetch_add(1, Ordering::Relaxed);
+        // Subscribe before the handshake so nothing published meanwhile is lost.
+        let events = bus.subscribe();
+        let (stop, open) = (Arc::clone(&stop), Arc::clone(&open));
+        thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, events, &stop) {
+                debug!("WebSocket connection closed: {}", e);
+            }
+            open.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    mut events: tokio::sync::broadcast::Receiver<NodeEvent>,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_WS_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_WS_MESSAGE_SIZE),
+        ..WebSocketConfig::default()
+    };
+    let mut socket = tungstenite::accept_with_config(stream, Some(config))
+        .map_err(|e| io::Error::other(format!("handshake failed: {}", e)))?;
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let mut subscriptions = Subscriptions::default();
+    while !stop.load(Ordering::Relaxed) {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let response = subscriptions.handle(&text);
+                send(&mut socket, &response)?;
+            }
+            // Pings are answered and closes acknowledged by tungstenite.
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(io_error(e)),
+        }
+        loop {
+            match events.try_recv() {
+                Ok(event) => {
+                    for notification in subscriptions.notifications(&event) {
+                        send(&mut socket, &notification)?;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Lagged(missed)) => {
+                    warn!("WebSocket subscriber fell behind, {} events missed", missed)
+                }
+                Err(TryRecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+    socket.close(None).map_err(io_error)?;
+    socket.flush().map_err(io_error)
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: &Value) -> io::Result<()> {
+    socket
+        .send(Message::Text(message.to_string()))
+        .map_err(io_error)
+}
+
+fn io_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        err => io::Error::other(err.to_string()),
+    }
+}
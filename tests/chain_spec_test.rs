@@ -0,0 +1,214 @@
+use my_first_blockchain::blockchain::{Blockchain, BlockchainError};
+use my_first_blockchain::chain_spec::ChainSpec;
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use my_first_blockchain::{
+        mempool::Mempool,
+        storage::FileBlockStore,
+        transaction::Transaction,
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+
+    use super::*;
+
+    fn mint(receiver: &str, amount: u64) -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            receiver.to_string(),
+            amount,
+            0,
+            "coinbase".into(),
+        )
+    }
+
+    fn mine(blockchain: &mut Blockchain, data: Vec<Transaction>) -> (bool, Blockchain) {
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        let mined = blockchain.mine_block(Arc::new(data), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        (mined, blockchain.clone())
+    }
+
+    #[test]
+    fn test_mining_refuses_blocks_over_the_limits() {
+        let mut blockchain = Blockchain::with_spec(ChainSpec {
+            max_block_transactions: 2,
+            ..ChainSpec::default()
+        });
+        let (mined, result) = mine(
+            &mut blockchain,
+            vec![mint("a", 1), mint("b", 1), mint("c", 1)],
+        );
+        assert!(!mined);
+        assert_eq!(result.get_chain_length(), 1);
+
+        let mut blockchain = Blockchain::with_spec(ChainSpec {
+            max_block_gas: 21_000,
+            ..ChainSpec::default()
+        });
+        let (mined, _) = mine(&mut blockchain, vec![mint("a", 1)]);
+        assert!(!mined);
+    }
+
+    #[test]
+    fn test_stored_blocks_over_the_limits_are_rejected() {
+        let path = std::env::temp_dir().join(format!("limits-{}.dat", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut blockchain = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        let (mined, _) = mine(&mut blockchain, vec![mint("a", 1)]);
+        assert!(mined);
+
+        let strict = ChainSpec {
+            max_block_gas: 21_000,
+            ..ChainSpec::default()
+        };
+        let reopened = Blockchain::open_with_spec(strict, FileBlockStore::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("idx"));
+        assert!(matches!(reopened, Err(BlockchainError::BlockInvalid(_))));
+    }
+
+    #[test]
+    fn test_stored_blocks_overpaying_their_coinbase_are_rejected() {
+        let path = std::env::temp_dir().join(format!("reward-{}.dat", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let generous = ChainSpec {
+            block_reward: 1_000,
+            ..ChainSpec::default()
+        };
+        let mut blockchain =
+            Blockchain::open_with_spec(generous, FileBlockStore::open(&path).unwrap()).unwrap();
+        let (mined, _) = mine(&mut blockchain, vec![mint("a", 1_000)]);
+        assert!(mined);
+
+        let reopened = Blockchain::open(FileBlockStore::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("idx"));
+        assert!(matches!(reopened, Err(BlockchainError::BlockInvalid(_))));
+    }
+
+    #[test]
+    fn test_blocks_pay_their_reward_in_one_leading_coinbase() {
+        let mut blockchain = Blockchain::with_spec(ChainSpec {
+            difficulty: 1,
+            ..ChainSpec::default()
+        });
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let block = blockchain
+            .mine_next_block(vec![mint(&sender, 50)], 1)
+            .unwrap();
+        blockchain.try_add_block(block).unwrap();
+
+        let signature =
+            sign_transaction_with_fee(prikey, sender.clone(), "receiver".to_string(), 10, 2, 0);
+        let transfer =
+            Transaction::new_with_fee(sender, "receiver".to_string(), 10, 2, 0, signature);
+        let reward = |amount| {
+            Transaction::new(
+                "coinbase".to_string(),
+                "miner".to_string(),
+                amount,
+                2,
+                "coinbase".into(),
+            )
+        };
+        for invalid in [
+            vec![transfer.clone()],
+            vec![transfer.clone(), reward(50)],
+            vec![reward(50), reward(1), transfer.clone()],
+            vec![reward(53), transfer.clone()],
+        ] {
+            let block = blockchain.mine_next_block(invalid, 2).unwrap();
+            assert!(matches!(
+                blockchain.check_block(&block),
+                Err(BlockchainError::BlockInvalid(_))
+            ));
+            assert!(blockchain.try_add_block(block).is_err());
+        }
+
+        // The fees go to the miner on top of the reward.
+        let block = blockchain
+            .mine_next_block(vec![reward(52), transfer], 2)
+            .unwrap();
+        blockchain.try_add_block(block).unwrap();
+        assert_eq!(blockchain.get_balance("miner"), 52);
+    }
+
+    #[test]
+    fn test_block_template_respects_limits() {
+        let spec = ChainSpec {
+            max_block_transactions: 3,
+            block_reward: 100,
+            ..ChainSpec::default()
+        };
+        let mut blockchain = Blockchain::with_spec(spec);
+        let mut keys = Vec::new();
+        for _ in 0..5 {
+            let (prikey, pubkey) = generate_key_pair();
+            let address = public_key_to_address(&pubkey);
+            let (mined, funded) = mine(&mut blockchain, vec![mint(&address, 100)]);
+            assert!(mined);
+            blockchain = funded;
+            keys.push((prikey, address));
+        }
+
+        let mut mempool = Mempool::default();
+        for (fee, (prikey, address)) in keys.iter().enumerate() {
+            let fee = fee as u64 + 1;
+            let signature = sign_transaction_with_fee(
+                *prikey,
+                address.clone(),
+                "receiver".to_string(),
+                10,
+                fee,
+                0,
+            );
+            let tx = Transaction::new_with_fee(
+                address.clone(),
+                "receiver".to_string(),
+                10,
+                fee,
+                0,
+                signature,
+            );
+            mempool.add_transaction(tx, blockchain.get_state()).unwrap();
+        }
+
+        // One slot goes to the coinbase, which also collects the fees.
+        let template = blockchain.block_template(&mempool, "miner");
+        let fees: Vec<u64> = template[1..].iter().map(|tx| tx.get_fee()).collect();
+        assert_eq!(fees, vec![5, 4]);
+        assert!(template[0].is_coinbase());
+        assert_eq!(template[0].get_amount(), 100 + 5 + 4);
+
+        let mut blockchain = blockchain;
+        let (mined, blockchain) = mine(&mut blockchain, template);
+        assert!(mined);
+        assert_eq!(blockchain.get_balance("miner"), 109);
+    }
+
+    #[test]
+    fn test_spec_file_round_trip_with_defaults() {
+        let path = std::env::temp_dir().join(format!("spec-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"difficulty": 2, "max_block_size": 4096}"#).unwrap();
+
+        let spec = ChainSpec::load(&path).unwrap();
+        assert_eq!(spec.difficulty, 2);
+        assert_eq!(spec.max_block_size, 4096);
+        assert_eq!(
+            spec.max_block_transactions,
+            ChainSpec::default().max_block_transactions
+        );
+
+        spec.save(&path).unwrap();
+        let reloaded = ChainSpec::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded, spec);
+    }
+}
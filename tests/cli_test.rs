@@ -0,0 +1,270 @@
+use my_first_blockchain::rpc::ChainInfo;
+use my_first_blockchain::rpc_client::RpcClient;
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::net::{SocketAddr, TcpListener};
+    use std::path::{Path, PathBuf};
+    use std::process::{Child, Command, Output, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use serde_json::Value;
+
+    use super::*;
+
+    // A fresh directory holding a cheap to mine chain spec.
+    fn workspace(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("spec.json"),
+            r#"{ "chain_id": 7, "difficulty": 1 }"#,
+        )
+        .unwrap();
+        dir
+    }
+
+    fn command(dir: &Path, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_my_first_blockchain"));
+        command.current_dir(dir).env("RUST_LOG", "warn").args(args);
+        command
+    }
+
+    fn run(dir: &Path, args: &[&str]) -> Output {
+        command(dir, args).output().unwrap()
+    }
+
+    // Stdout of a command that has to succeed.
+    fn ok(dir: &Path, args: &[&str]) -> String {
+        let output = run(dir, args);
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn chain_info(dir: &Path) -> ChainInfo {
+        serde_json::from_str(&ok(dir, &["chain", "info"])).unwrap()
+    }
+
+    fn balance(dir: &Path, address: &str) -> u64 {
+        let line = ok(dir, &["wallet", "balance", address, "--offline"]);
+        line.trim().rsplit(' ').next().unwrap().parse().unwrap()
+    }
+
+    fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    struct Running(Child);
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    #[test]
+    fn test_init_writes_config_spec_and_genesis() {
+        let dir = workspace("init");
+        ok(
+            &dir,
+            &["init", "--spec", "spec.json", "--data-dir", "chain"],
+        );
+        let config = fs::read_to_string(dir.join("config.toml")).unwrap();
+        assert!(config.contains("data_dir = \"chain\""));
+        assert!(dir.join("chain/blocks.dat").exists());
+
+        let info = chain_info(&dir);
+        assert_eq!((info.chain_id, info.height, info.difficulty), (7, 0, 1));
+        assert_eq!(info.best_hash, info.genesis_hash);
+        assert!(ok(&dir, &["chain", "validate"]).contains("1 blocks valid"));
+
+        // Again for the same chain is fine, for another one it isn't.
+        ok(&dir, &["init"]);
+        ok(&dir, &["init", "--spec", "spec.json"]);
+        fs::write(dir.join("other.json"), r#"{ "chain_id": 8 }"#).unwrap();
+        let output = run(&dir, &["init", "--spec", "other.json"]);
+        assert!(!output.status.success());
+        assert_eq!(chain_info(&dir).chain_id, 7);
+
+        let output = run(&dir, &["--config", "missing.toml", "chain", "info"]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("missing.toml"));
+    }
+
+    #[test]
+    fn test_mine_send_and_inspect_offline() {
+        let dir = workspace("wallet");
+        ok(&dir, &["init", "--spec", "spec.json"]);
+        let address = ok(&dir, &["wallet", "new"]).trim().to_string();
+        let other = ok(&dir, &["wallet", "new"]).trim().to_string();
+        let mut listed: Vec<String> = ok(&dir, &["wallet", "list"])
+            .lines()
+            .map(String::from)
+            .collect();
+        listed.sort();
+        let mut expected = vec![address.clone(), other.clone()];
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        ok(&dir, &["mine", "--address", &address, "--blocks", "2"]);
+        assert_eq!(balance(&dir, &address), 100);
+
+        let send = |receiver: &str| {
+            ok(
+                &dir,
+                &[
+                    "wallet",
+                    "send",
+                    "--offline",
+                    "--from",
+                    &address,
+                    "--to",
+                    receiver,
+                    "--amount",
+                    "10",
+                    "--fee",
+                    "2",
+                ],
+            )
+            .trim()
+            .to_string()
+        };
+        // The second transfer goes after the pending first one.
+        let first = send(&other);
+        let second = send("bob");
+        assert_eq!(chain_info(&dir).mempool_size, 2);
+        let pending: Value = serde_json::from_str(&ok(&dir, &["tx", "inspect", &second])).unwrap();
+        assert_eq!(pending["nonce"], 1);
+        assert_eq!(pending["confirmations"], 0);
+        assert_eq!(pending["signatureValid"], true);
+
+        ok(&dir, &["mine", "--address", &other]);
+        assert_eq!(balance(&dir, &address), 100 - 2 * 12);
+        assert_eq!(balance(&dir, &other), 10 + 50 + 2 * 2);
+        assert_eq!(balance(&dir, "bob"), 10);
+        let info = chain_info(&dir);
+        assert_eq!((info.height, info.mempool_size), (3, 0));
+        let mined: Value = serde_json::from_str(&ok(&dir, &["tx", "inspect", &first])).unwrap();
+        assert_eq!(mined["height"], 3);
+        assert_eq!(mined["blockHash"], info.best_hash.as_str());
+        assert_eq!(mined["confirmations"], 1);
+
+        assert!(!run(&dir, &["tx", "inspect", "beef"]).status.success());
+        let output = run(
+            &dir,
+            &[
+                "wallet",
+                "send",
+                "--offline",
+                "--from",
+                "0xnobody",
+                "--to",
+                "bob",
+                "--amount",
+                "1",
+            ],
+        );
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn test_export_validate_and_import() {
+        let dir = workspace("export");
+        ok(
+            &dir,
+            &["init", "--spec", "spec.json", "--data-dir", "source"],
+        );
+        ok(&dir, &["mine", "--address", "miner", "--blocks", "3"]);
+        ok(&dir, &["chain", "validate"]);
+        ok(&dir, &["chain", "export", "blocks.chain", "--compress"]);
+
+        let source = chain_info(&dir);
+        let copy = ["--data-dir", "copy"];
+        ok(&dir, &["init", "--spec", "spec.json", copy[0], copy[1]]);
+        let imported = ok(&dir, &["chain", "import", "blocks.chain", copy[0], copy[1]]);
+        assert!(imported.contains("imported 3 blocks, skipped 1"));
+        let info: ChainInfo =
+            serde_json::from_str(&ok(&dir, &["chain", "info", copy[0], copy[1]])).unwrap();
+        assert_eq!(info.best_hash, source.best_hash);
+        ok(&dir, &["chain", "validate", copy[0], copy[1]]);
+    }
+
+    #[test]
+    fn test_node_run_serves_rpc_and_mines() {
+        let dir = workspace("node");
+        ok(&dir, &["init", "--spec", "spec.json"]);
+        let address = ok(&dir, &["wallet", "new"]).trim().to_string();
+        ok(&dir, &["mine", "--address", &address]);
+
+        // Flags take precedence over the config file.
+        let rpc = free_addr();
+        let config = fs::read_to_string(dir.join("config.toml"))
+            .unwrap()
+            .replace("block_time_secs = 10", "block_time_secs = 0")
+            .replace("enabled = true", "enabled = false");
+        fs::write(dir.join("config.toml"), config).unwrap();
+        let child = command(
+            &dir,
+            &[
+                "node",
+                "run",
+                "--listen",
+                "/ip4/127.0.0.1/tcp/0",
+                "--rpc",
+                &rpc.to_string(),
+                "--mine",
+                "miner",
+            ],
+        )
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+        let _node = Running(child);
+
+        let client = RpcClient::new(rpc);
+        let started = Instant::now();
+        while client.get_chain_info().is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(30),
+                "RPC never came up"
+            );
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        // Wallet commands go through the node's RPC once it's configured.
+        let config = fs::read_to_string(dir.join("config.toml"))
+            .unwrap()
+            .replace("127.0.0.1:8545", &rpc.to_string());
+        fs::write(dir.join("config.toml"), config).unwrap();
+        let id = ok(
+            &dir,
+            &[
+                "wallet", "send", "--from", &address, "--to", "carol", "--amount", "7",
+            ],
+        )
+        .trim()
+        .to_string();
+        while client.get_balance("carol").unwrap() != 7 {
+            assert!(started.elapsed() < Duration::from_secs(30), "never mined");
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(client.get_transaction(&id).unwrap().height.is_some());
+        assert!(client.get_balance("miner").unwrap() >= 50);
+        let output = ok(&dir, &["wallet", "balance", "carol"]);
+        assert_eq!(output.trim(), "carol: 7");
+    }
+}
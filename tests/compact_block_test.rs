@@ -0,0 +1,306 @@
+use my_first_blockchain::blockchain::{Block, Blockchain};
+use my_first_blockchain::compact_block::{CompactBlock, CompactBlockError, Reconstruction};
+use my_first_blockchain::encoding::{Decode, Encode};
+use my_first_blockchain::gossip::{
+    CompactVerdict, GossipHandler, GossipVerdict, SharedChain, COMPACT_BLOCKS_TOPIC,
+};
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::p2p::{NetworkConfig, NetworkEvent, Node};
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        transaction::Transaction,
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+    use secp256k1::SecretKey;
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+
+    fn mine(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let mut blockchain = blockchain.clone();
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(Arc::new(transactions), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.get_chain().last().unwrap().clone()
+    }
+
+    fn coinbase(amount: u64) -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            "miner".to_string(),
+            amount,
+            0,
+            "coinbase".into(),
+        )
+    }
+
+    fn funded_chain(address: &str) -> Blockchain {
+        let spec = ChainSpec {
+            difficulty: 2,
+            block_reward: 1_000,
+            ..ChainSpec::default()
+        };
+        let blockchain = Blockchain::with_spec(spec);
+        let mint = Transaction::new(
+            "coinbase".to_string(),
+            address.to_string(),
+            1_000,
+            0,
+            "coinbase".into(),
+        );
+        let block = mine(&blockchain, vec![mint]);
+        let mut blockchain = blockchain;
+        assert!(blockchain.add_block(block));
+        blockchain
+    }
+
+    // `count` transfers from `sender`, with nonces from `first_nonce` on.
+    fn transfers(key: SecretKey, sender: &str, first_nonce: u64, count: u64) -> Vec<Transaction> {
+        (first_nonce..first_nonce + count)
+            .map(|nonce| {
+                let receiver = format!("receiver-{}", nonce);
+                Transaction::new_with_fee(
+                    sender.to_string(),
+                    receiver.clone(),
+                    1,
+                    1,
+                    nonce,
+                    sign_transaction_with_fee(key, sender.to_string(), receiver, 1, 1, nonce),
+                )
+            })
+            .collect()
+    }
+
+    fn mempool_with(blockchain: &Blockchain, transactions: &[Transaction]) -> Mempool {
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        for transaction in transactions {
+            mempool
+                .add_transaction(transaction.clone(), blockchain.get_state())
+                .unwrap();
+        }
+        mempool
+    }
+
+    fn loopback_config() -> NetworkConfig {
+        NetworkConfig {
+            listen_addr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            kademlia: false,
+            ..NetworkConfig::default()
+        }
+    }
+
+    // Waits up to ten seconds for an event matching `predicate`.
+    async fn wait_for(node: &mut Node, predicate: impl Fn(&NetworkEvent) -> bool) -> NetworkEvent {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let event = node.next_event().await.expect("node stopped");
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for a network event")
+    }
+
+    #[test]
+    fn test_compact_block_saves_most_of_the_bytes() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender);
+        let transactions = transfers(key, &sender, 0, 50);
+        let mut body = vec![coinbase(10)];
+        body.extend(transactions.iter().cloned());
+        let block = mine(&base, body);
+
+        let compact = CompactBlock::from_block(&block);
+        assert_eq!(CompactBlock::decode(&compact.encode()).unwrap(), compact);
+        let full_size = block.encode().len();
+        let compact_size = compact.encode().len();
+        // Six bytes per transaction instead of the whole signed transaction.
+        let per_transaction = transactions[0].encode().len() - 6;
+        assert!(full_size - compact_size >= 50 * per_transaction);
+        assert!(compact_size * 10 < full_size);
+
+        match compact.reconstruct(&mempool_with(&base, &transactions)) {
+            Reconstruction::Complete(rebuilt) => {
+                assert_eq!(rebuilt.get_hash(), block.get_hash());
+                assert_eq!(rebuilt.encode(), block.encode());
+            }
+            Reconstruction::Incomplete(_) => panic!("every transaction was in the mempool"),
+        }
+    }
+
+    #[test]
+    fn test_missing_transactions_are_filled_in_and_checked() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender);
+        let transactions = transfers(key, &sender, 0, 5);
+        let mut body = vec![coinbase(10)];
+        body.extend(transactions.iter().cloned());
+        let block = mine(&base, body);
+
+        // The mempool only has the first three.
+        let compact = CompactBlock::from_block(&block);
+        let partial = match compact.reconstruct(&mempool_with(&base, &transactions[..3])) {
+            Reconstruction::Incomplete(partial) => partial,
+            Reconstruction::Complete(_) => panic!("two transactions were missing"),
+        };
+        assert_eq!(partial.get_missing(), vec![4, 5]);
+
+        assert_eq!(
+            partial.fill(transactions[3..4].to_vec()).err(),
+            Some(CompactBlockError::TransactionCount {
+                expected: 2,
+                got: 1,
+            })
+        );
+        let swapped = vec![transactions[4].clone(), transactions[3].clone()];
+        assert_eq!(
+            partial.fill(swapped).err(),
+            Some(CompactBlockError::MerkleRootMismatch)
+        );
+        let rebuilt = partial.fill(transactions[3..].to_vec()).unwrap();
+        assert_eq!(rebuilt.get_hash(), block.get_hash());
+    }
+
+    #[test]
+    fn test_gossip_falls_back_to_the_full_block() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender);
+        let transactions = transfers(key, &sender, 0, 3);
+        let mut body = vec![coinbase(10)];
+        body.extend(transactions.iter().cloned());
+        let block = mine(&base, body);
+
+        let chain = SharedChain::new(base.clone(), Mempool::new(MempoolConfig::default()));
+        let mut handler = GossipHandler::new(chain.clone());
+        let data = CompactBlock::from_block(&block).encode();
+        let hash = block.get_hash().to_string();
+        assert_eq!(
+            handler.handle_compact_block(&data),
+            CompactVerdict::Missing {
+                hash: hash.clone(),
+                indexes: vec![1, 2, 3],
+            }
+        );
+        assert_eq!(
+            handler.handle_compact_block(&data),
+            CompactVerdict::Done(GossipVerdict::Ignore)
+        );
+
+        // A sender returning the wrong transactions doesn't complete it...
+        let wrong = transfers(key, &sender, 10, 3);
+        assert_eq!(handler.fill_compact_block(&hash, wrong), None);
+        // ...but the full body does.
+        let (_, full_body) = block.clone().into_parts();
+        assert_eq!(
+            handler.complete_compact_block(&hash, full_body),
+            GossipVerdict::Accept
+        );
+        assert_eq!(
+            chain.blockchain.lock().unwrap().get_chain()[2].get_hash(),
+            block.get_hash()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_compact_blocks_only_fetch_what_peers_lack() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender);
+        let transactions = transfers(key, &sender, 0, 10);
+
+        // Both have every transaction in their mempool.
+        let a_chain = SharedChain::new(base.clone(), mempool_with(&base, &transactions));
+        let b_chain = SharedChain::new(base.clone(), mempool_with(&base, &transactions));
+        let mut a = Node::start(loopback_config(), a_chain.clone())
+            .await
+            .unwrap();
+        let a_id = a.peer_id();
+        let mut b = Node::start(
+            NetworkConfig {
+                peers: a.listen_addrs().await,
+                ..loopback_config()
+            },
+            b_chain.clone(),
+        )
+        .await
+        .unwrap();
+        let b_id = b.peer_id();
+        wait_for(&mut a, |e| {
+            matches!(e, NetworkEvent::Subscribed { peer, topic } if *peer == b_id && topic == COMPACT_BLOCKS_TOPIC)
+        })
+        .await;
+        // Relaying needs the mesh, which forms on the next heartbeats.
+        sleep(Duration::from_millis(1500)).await;
+
+        let mut body = vec![coinbase(10)];
+        body.extend(transactions[..5].iter().cloned());
+        let first = mine(&base, body);
+        assert!(a_chain.blockchain.lock().unwrap().add_block(first.clone()));
+        a.publish_block(&first).await.unwrap();
+        let event = wait_for(&mut b, |e| {
+            matches!(
+                e,
+                NetworkEvent::BlockAccepted { .. } | NetworkEvent::CompactBlockIncomplete { .. }
+            )
+        })
+        .await;
+        assert_eq!(
+            event,
+            NetworkEvent::BlockAccepted {
+                source: a_id,
+                hash: first.get_hash().to_string(),
+            }
+        );
+
+        // A transaction B never heard of is fetched from A.
+        let extra = transfers(key, &sender, 10, 1);
+        let mut body = vec![coinbase(20)];
+        body.extend(transactions[5..].iter().cloned());
+        body.extend(extra);
+        let second = {
+            let mut blockchain = a_chain.blockchain.lock().unwrap();
+            let block = mine(&blockchain, body);
+            assert!(blockchain.add_block(block.clone()));
+            block
+        };
+        a.publish_block(&second).await.unwrap();
+        let event = wait_for(&mut b, |e| {
+            matches!(e, NetworkEvent::CompactBlockIncomplete { .. })
+        })
+        .await;
+        assert_eq!(
+            event,
+            NetworkEvent::CompactBlockIncomplete {
+                source: a_id,
+                hash: second.get_hash().to_string(),
+                missing: 1,
+            }
+        );
+        wait_for(
+            &mut b,
+            |e| matches!(e, NetworkEvent::BlockAccepted { hash, .. } if hash == second.get_hash()),
+        )
+        .await;
+        {
+            let blockchain = b_chain.blockchain.lock().unwrap();
+            assert_eq!(blockchain.get_chain_length(), 4);
+            assert_eq!(blockchain.get_balance("receiver-10"), 1);
+        }
+        assert!(b_chain.mempool.lock().unwrap().is_empty());
+
+        a.shutdown().await;
+        b.shutdown().await;
+    }
+}
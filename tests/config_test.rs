@@ -0,0 +1,96 @@
+use my_first_blockchain::config::{ConfigError, NodeConfig};
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    use my_first_blockchain::blockchain::PruningMode;
+
+    use super::*;
+
+    fn config_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.toml", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_missing_settings_take_their_defaults() {
+        let path = config_path("config-partial");
+        fs::write(
+            &path,
+            r#"
+data_dir = "/var/lib/node"
+
+[network]
+peers = ["/ip4/10.0.0.1/tcp/30333"]
+
+[explorer]
+listen = "127.0.0.1:9090"
+
+[storage]
+keep_blocks = 100
+"#,
+        )
+        .unwrap();
+        let config = NodeConfig::load(&path).unwrap();
+        let defaults = NodeConfig::default();
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/node"));
+        assert_eq!(config.network.listen, defaults.network.listen);
+        assert_eq!(config.network.peers.len(), 1);
+        // A section that's written out is enabled unless it says otherwise.
+        assert!(config.explorer.enabled);
+        assert_eq!(config.explorer.listen.port(), 9090);
+        assert_eq!(config.rpc, defaults.rpc);
+        assert_eq!(config.mining, defaults.mining);
+        assert_eq!(config.storage.keep_blocks, Some(100));
+        assert_eq!(
+            config.blocks_path(),
+            PathBuf::from("/var/lib/node/blocks.dat")
+        );
+
+        config.save(&path).unwrap();
+        assert_eq!(NodeConfig::load(&path).unwrap(), config);
+        fs::write(&path, "data_dir = 3").unwrap();
+        assert!(matches!(
+            NodeConfig::load(&path),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_data_dir_holds_chain_and_network_key() {
+        let dir = std::env::temp_dir().join(format!("config-data-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut config = NodeConfig {
+            data_dir: dir.clone(),
+            ..NodeConfig::default()
+        };
+        config.storage.keep_blocks = Some(5);
+
+        let blockchain = config.open_chain().unwrap();
+        assert_eq!(blockchain.get_chain_length(), 1);
+        assert_eq!(blockchain.get_spec(), &config.load_spec().unwrap());
+        assert!(config.blocks_path().exists());
+        assert_eq!(
+            blockchain.get_pruning(),
+            PruningMode::Pruned { keep_blocks: 5 }
+        );
+
+        let key = config.load_node_key().unwrap();
+        let again = config.load_node_key().unwrap();
+        assert_eq!(key.public(), again.public());
+        let network = config.network_config(key).unwrap();
+        assert_eq!(network.address_book, Some(config.address_book_path()));
+        assert_eq!(
+            network.connection_limits.max_inbound,
+            config.network.max_inbound
+        );
+
+        config.network.peers.push("not a multiaddr".to_string());
+        assert!(matches!(
+            config.network_config(again),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
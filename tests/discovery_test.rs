@@ -0,0 +1,173 @@
+use my_first_blockchain::address_book::AddressBook;
+use my_first_blockchain::blockchain::Blockchain;
+use my_first_blockchain::gossip::SharedChain;
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::p2p::{NetworkConfig, NetworkEvent, Node};
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use libp2p::multiaddr::Protocol;
+    use libp2p::{Multiaddr, PeerId};
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn empty_chain() -> SharedChain {
+        SharedChain::new(Blockchain::new(), Mempool::new(MempoolConfig::default()))
+    }
+
+    fn loopback_config() -> NetworkConfig {
+        NetworkConfig {
+            listen_addr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            ..NetworkConfig::default()
+        }
+    }
+
+    // Waits up to ten seconds for an event matching `predicate`.
+    async fn wait_for(node: &mut Node, predicate: impl Fn(&NetworkEvent) -> bool) -> NetworkEvent {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let event = node.next_event().await.expect("node stopped");
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for a network event")
+    }
+
+    async fn dialable_addr(node: &Node) -> Multiaddr {
+        node.listen_addrs().await[0]
+            .clone()
+            .with(Protocol::P2p(node.peer_id()))
+    }
+
+    #[test]
+    fn test_address_book_keeps_recent_addresses_across_saves() {
+        let peer = PeerId::random();
+        let mut book = AddressBook::new();
+        let first: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        assert!(book.add_address(peer, first.clone().with(Protocol::P2p(peer))));
+        assert!(!book.add_address(peer, first.clone()));
+        for port in 0..10 {
+            let addr: Multiaddr = format!("/ip4/10.0.0.2/tcp/{}", port).parse().unwrap();
+            book.add_address(peer, addr);
+        }
+        let addresses = book.get_addresses(&peer);
+        assert_eq!(addresses.len(), 8);
+        assert_eq!(addresses[0].to_string(), "/ip4/10.0.0.2/tcp/9");
+        assert!(!addresses.contains(&first));
+
+        let path = std::env::temp_dir().join(format!("peers-{}.json", std::process::id()));
+        book.save(&path).unwrap();
+        let loaded = AddressBook::load(&path);
+        assert_eq!(loaded.get_peers(), book.get_peers());
+
+        std::fs::write(&path, b"not an address book").unwrap();
+        assert!(AddressBook::load(&path).is_empty());
+        std::fs::remove_file(&path).unwrap();
+        assert!(AddressBook::load(&path).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kademlia_finds_peers_through_bootstrap_node() {
+        let mut hub = Node::start(loopback_config(), empty_chain()).await.unwrap();
+        let bootstrap = vec![dialable_addr(&hub).await];
+
+        let first = Node::start(
+            NetworkConfig {
+                bootstrap_peers: bootstrap.clone(),
+                ..loopback_config()
+            },
+            empty_chain(),
+        )
+        .await
+        .unwrap();
+        let first_id = first.peer_id();
+        wait_for(
+            &mut hub,
+            |e| matches!(e, NetworkEvent::PeerDiscovered { peer, .. } if *peer == first_id),
+        )
+        .await;
+
+        // Only told about the hub, the second node still finds the first.
+        let mut second = Node::start(
+            NetworkConfig {
+                bootstrap_peers: bootstrap,
+                ..loopback_config()
+            },
+            empty_chain(),
+        )
+        .await
+        .unwrap();
+        wait_for(&mut second, |e| *e == NetworkEvent::PeerConnected(first_id)).await;
+
+        for node in [hub, first, second] {
+            node.shutdown().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_node_reconnects_to_known_peers_after_restart() {
+        let path = std::env::temp_dir().join(format!("known-peers-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut peer = Node::start(loopback_config(), empty_chain()).await.unwrap();
+        let peer_id = peer.peer_id();
+
+        let node = Node::start(
+            NetworkConfig {
+                peers: peer.listen_addrs().await,
+                address_book: Some(path.clone()),
+                ..loopback_config()
+            },
+            empty_chain(),
+        )
+        .await
+        .unwrap();
+        let node_id = node.peer_id();
+        wait_for(&mut peer, |e| *e == NetworkEvent::PeerConnected(node_id)).await;
+        node.shutdown().await;
+        assert_eq!(AddressBook::load(&path).get_peers()[0].0, peer_id);
+
+        // Nothing configured but the address book.
+        let mut restarted = Node::start(
+            NetworkConfig {
+                address_book: Some(path.clone()),
+                ..loopback_config()
+            },
+            empty_chain(),
+        )
+        .await
+        .unwrap();
+        wait_for(&mut restarted, |e| {
+            *e == NetworkEvent::PeerConnected(peer_id)
+        })
+        .await;
+        std::fs::remove_file(&path).unwrap();
+
+        restarted.shutdown().await;
+        peer.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_mdns_discovers_peers_on_the_local_network() {
+        let config = || NetworkConfig {
+            listen_addr: "/ip4/0.0.0.0/tcp/0".parse().unwrap(),
+            mdns: true,
+            ..NetworkConfig::default()
+        };
+        let first = Node::start(config(), empty_chain()).await.unwrap();
+        let mut second = Node::start(config(), empty_chain()).await.unwrap();
+        // Neither knows the other's address, so connecting means mDNS
+        // found it, whichever side dialed.
+        let first_id = first.peer_id();
+        wait_for(&mut second, |e| *e == NetworkEvent::PeerConnected(first_id)).await;
+
+        first.shutdown().await;
+        second.shutdown().await;
+    }
+}
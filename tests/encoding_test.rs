@@ -0,0 +1,162 @@
+use my_first_blockchain::blockchain::{Block, BlockBody, BlockHeader, Blockchain};
+use my_first_blockchain::encoding::{Decode, DecodeError, Encode, ENCODING_VERSION};
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use sha3::{Digest, Keccak256};
+
+    use my_first_blockchain::{
+        transaction::Transaction,
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+
+    use super::*;
+
+    fn sample_transaction() -> Transaction {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        Transaction::new_with_fee(
+            sender.clone(),
+            "receiver".to_string(),
+            42,
+            3,
+            0,
+            sign_transaction_with_fee(prikey, sender, "receiver".to_string(), 42, 3, 0),
+        )
+    }
+
+    fn sample_block() -> Block {
+        let mut blockchain = Blockchain::new();
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        let transaction = sample_transaction();
+        // Funds the sender of the transfer.
+        let mint = Transaction::new(
+            "me".into(),
+            transaction.get_sender().to_string(),
+            50,
+            0,
+            "coinbase".into(),
+        );
+        let data = vec![mint, transaction];
+        blockchain.mine_block(Arc::new(data), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.get_chain()[1].clone()
+    }
+
+    #[test]
+    fn test_transaction_round_trip() {
+        let transaction = sample_transaction();
+        let bytes = transaction.encode();
+
+        assert_eq!(bytes[0], ENCODING_VERSION);
+        let decoded = Transaction::decode(&bytes).unwrap();
+        assert_eq!(decoded, transaction);
+        assert_eq!(decoded.encode(), bytes);
+        assert!(decoded.verify_signature());
+    }
+
+    #[test]
+    fn test_signature_covers_the_encoding_without_it() {
+        let transaction = sample_transaction();
+        let bytes = transaction.encode();
+        let unsigned = &bytes[..bytes.len() - 4 - transaction.get_signature().len()];
+
+        let expected: [u8; 32] = Keccak256::digest(unsigned).into();
+        assert_eq!(transaction.signing_hash(), expected);
+    }
+
+    #[test]
+    fn test_block_round_trip_keeps_hash() {
+        let block = sample_block();
+        let bytes = block.encode();
+
+        let decoded = Block::decode(&bytes).unwrap();
+        assert_eq!(decoded.encode(), bytes);
+        assert_eq!(decoded.get_hash(), block.get_hash());
+        assert_eq!(decoded.get_data_raw(), block.get_data_raw());
+        assert_eq!(decoded.get_nonce(), block.get_nonce());
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_fixed_width_big_endian_layout() {
+        let transaction = Transaction::new("a".into(), "b".into(), 1, 2, vec![9]);
+        assert_eq!(
+            transaction.encode(),
+            vec![
+                ENCODING_VERSION,
+                0, 0, 0, 1, b'a',
+                0, 0, 0, 1, b'b',
+                0, 0, 0, 0, 0, 0, 0, 1,
+                0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 2,
+                0, 0, 0, 1, 9,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_canonical_encodings_are_rejected() {
+        let bytes = sample_transaction().encode();
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Transaction::decode(&trailing),
+            Err(DecodeError::TrailingBytes(1))
+        );
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[0] = ENCODING_VERSION + 1;
+        assert_eq!(
+            Transaction::decode(&wrong_version),
+            Err(DecodeError::UnsupportedVersion(ENCODING_VERSION + 1))
+        );
+        // Headers of the first layout had neither difficulty nor state root.
+        let mut first_layout = sample_block().encode();
+        first_layout[0] = 1;
+        assert_eq!(
+            Block::decode(&first_layout).err(),
+            Some(DecodeError::UnsupportedVersion(1))
+        );
+
+        assert_eq!(
+            Transaction::decode(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+
+        let invalid_utf8 = Transaction::new("a".into(), "b".into(), 1, 2, vec![]).encode();
+        let mut invalid_utf8 = invalid_utf8;
+        invalid_utf8[5] = 0xff;
+        assert_eq!(
+            Transaction::decode(&invalid_utf8),
+            Err(DecodeError::InvalidUtf8)
+        );
+
+        let mut block_bytes = sample_block().encode();
+        block_bytes.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(
+            Block::decode(&block_bytes).err(),
+            Some(DecodeError::TrailingBytes(3))
+        );
+    }
+
+    #[test]
+    fn test_header_alone_determines_block_hash() {
+        let block = sample_block();
+        let header_bytes = block.get_header().encode();
+
+        let header = BlockHeader::decode(&header_bytes).unwrap();
+        assert_eq!(&header, block.get_header());
+        assert_eq!(header.calculate_hash(), block.get_hash());
+        assert_eq!(header.get_difficulty(), 4);
+
+        let (header, body) = block.clone().into_parts();
+        assert_eq!(body.get_transactions().len(), 2);
+        let headers_only = Block::new(header, BlockBody::default());
+        assert_eq!(headers_only.get_hash(), block.get_hash());
+    }
+}
@@ -0,0 +1,325 @@
+use my_first_blockchain::blockchain::{Block, Blockchain, PruningMode};
+use my_first_blockchain::explorer::{
+    AddressDetail, BlockDetail, ExplorerServer, MempoolEntry, Page, TransactionDetail,
+    MAX_PAGE_SIZE,
+};
+use my_first_blockchain::gossip::SharedChain;
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::ws::HeadView;
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::{Arc, Mutex};
+
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        transaction::Transaction,
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+    use secp256k1::SecretKey;
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn mine(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let mut blockchain = blockchain.clone();
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(Arc::new(transactions), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.get_chain().last().unwrap().clone()
+    }
+
+    fn funded_chain(address: &str) -> Blockchain {
+        let spec = ChainSpec {
+            difficulty: 2,
+            block_reward: 1_000,
+            ..ChainSpec::default()
+        };
+        let blockchain = Blockchain::with_spec(spec);
+        let mint = Transaction::new(
+            "coinbase".to_string(),
+            address.to_string(),
+            1_000,
+            0,
+            "coinbase".into(),
+        );
+        let block = mine(&blockchain, vec![mint]);
+        let mut blockchain = blockchain;
+        assert!(blockchain.add_block(block));
+        blockchain
+    }
+
+    // A transfer of 10 to `receiver` paying `fee`.
+    fn transfer(key: SecretKey, sender: &str, receiver: &str, fee: u64, nonce: u64) -> Transaction {
+        Transaction::new_with_fee(
+            sender.to_string(),
+            receiver.to_string(),
+            10,
+            fee,
+            nonce,
+            sign_transaction_with_fee(
+                key,
+                sender.to_string(),
+                receiver.to_string(),
+                10,
+                fee,
+                nonce,
+            ),
+        )
+    }
+
+    // Reward paid to "miner" for the block at `height`.
+    fn coinbase(height: u64) -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            "miner".to_string(),
+            1,
+            height,
+            "coinbase".into(),
+        )
+    }
+
+    // A funded chain with four blocks of a coinbase and one transfer each on
+    // top, nonces 0 to 3, alternating between two receivers.
+    fn busy_chain() -> (Blockchain, SecretKey, String, Vec<Transaction>) {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let mut blockchain = funded_chain(&sender);
+        let mut mined = Vec::new();
+        for nonce in 0..4 {
+            let receiver = ["alice", "bob"][nonce as usize % 2];
+            let transaction = transfer(key, &sender, receiver, 1, nonce);
+            let height = blockchain.get_chain_length() as u64;
+            let block = mine(&blockchain, vec![coinbase(height), transaction.clone()]);
+            assert!(blockchain.add_block(block));
+            mined.push(transaction);
+        }
+        (blockchain, key, sender, mined)
+    }
+
+    fn serve(blockchain: Blockchain) -> (ExplorerServer, SharedChain) {
+        let chain = SharedChain::new(blockchain, Mempool::new(MempoolConfig::default()));
+        let server = ExplorerServer::start("127.0.0.1:0".parse().unwrap(), chain.clone()).unwrap();
+        (server, chain)
+    }
+
+    // Sends `method` for `path` and returns the status and JSON body.
+    fn request(addr: SocketAddr, method: &str, path: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            method, path, addr
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn get<T: DeserializeOwned>(addr: SocketAddr, path: &str) -> T {
+        let (status, body) = request(addr, "GET", path);
+        assert_eq!(status, 200, "GET {} failed: {}", path, body);
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn status(addr: SocketAddr, path: &str) -> u16 {
+        request(addr, "GET", path).0
+    }
+
+    #[test]
+    fn test_blocks_are_paginated_newest_first() {
+        let (blockchain, _, _, _) = busy_chain();
+        let hashes: Vec<String> = blockchain
+            .get_chain()
+            .iter()
+            .map(|block| block.get_hash().to_string())
+            .collect();
+        let (server, _) = serve(blockchain);
+        let addr = server.local_addr();
+
+        let first: Page<HeadView> = get(addr, "/blocks?limit=4");
+        assert_eq!((first.page, first.limit, first.total), (0, 4, 6));
+        let heights: Vec<u64> = first.items.iter().map(|head| head.height).collect();
+        assert_eq!(heights, vec![5, 4, 3, 2]);
+        assert_eq!(first.items[0].hash, hashes[5]);
+        assert_eq!(first.items[0].previous_hash, hashes[4]);
+        let second: Page<HeadView> = get(addr, "/blocks?page=1&limit=4");
+        let heights: Vec<u64> = second.items.iter().map(|head| head.height).collect();
+        assert_eq!(heights, vec![1, 0]);
+        let past: Page<HeadView> = get(addr, "/blocks?page=9");
+        assert!(past.items.is_empty());
+
+        assert_eq!(status(addr, "/blocks?limit=0"), 400);
+        assert_eq!(
+            status(addr, &format!("/blocks?limit={}", MAX_PAGE_SIZE + 1)),
+            400
+        );
+        assert_eq!(status(addr, "/blocks?page=first"), 400);
+        assert_eq!(status(addr, "/nothing"), 404);
+        assert_eq!(request(addr, "POST", "/blocks").0, 405);
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_block_detail_by_height_or_hash() {
+        let (blockchain, _, _, mined) = busy_chain();
+        let (server, _) = serve(blockchain);
+        let addr = server.local_addr();
+
+        let block: BlockDetail = get(addr, "/blocks/3");
+        assert_eq!(block.block.height, 3);
+        assert_eq!(block.confirmations, 3);
+        assert_eq!(block.block.transactions.len(), 2);
+        assert_eq!(block.block.transactions[1].id, mined[1].id());
+        assert_eq!(block.block.merkle_root.len(), 64);
+        let by_hash: BlockDetail = get(addr, &format!("/blocks/{}", block.block.hash));
+        assert_eq!(by_hash, block);
+        let tip: BlockDetail = get(addr, "/blocks/5");
+        assert_eq!(tip.confirmations, 1);
+
+        assert_eq!(status(addr, "/blocks/6"), 404);
+        assert_eq!(status(addr, "/blocks/abcdef"), 404);
+    }
+
+    #[test]
+    fn test_transactions_show_their_confirmations() {
+        let (blockchain, key, sender, mined) = busy_chain();
+        let (server, chain) = serve(blockchain);
+        let addr = server.local_addr();
+
+        let detail: TransactionDetail = get(addr, &format!("/transactions/{}", mined[0].id()));
+        assert_eq!(detail.transaction.height, Some(2));
+        assert_eq!(detail.transaction.position, Some(1));
+        assert_eq!(detail.transaction.receiver, "alice");
+        assert_eq!(detail.confirmations, 4);
+
+        let pending = transfer(key, &sender, "carol", 1, 4);
+        {
+            let blockchain = chain.blockchain.lock().unwrap();
+            let mut mempool = chain.mempool.lock().unwrap();
+            mempool
+                .add_transaction(pending.clone(), blockchain.get_state())
+                .unwrap();
+        }
+        let detail: TransactionDetail = get(addr, &format!("/transactions/{}", pending.id()));
+        assert_eq!(detail.confirmations, 0);
+        assert_eq!(detail.transaction.block_hash, None);
+        assert_eq!(status(addr, "/transactions/beef"), 404);
+    }
+
+    #[test]
+    fn test_address_pages_hold_balance_and_history() {
+        let (blockchain, key, sender, mined) = busy_chain();
+        let (server, chain) = serve(blockchain);
+        let addr = server.local_addr();
+
+        let alice: AddressDetail = get(addr, "/addresses/alice");
+        assert_eq!((alice.balance, alice.nonce), (20, 0));
+        assert_eq!(alice.transactions.total, 2);
+        let ids: Vec<&str> = alice
+            .transactions
+            .items
+            .iter()
+            .map(|detail| detail.transaction.id.as_str())
+            .collect();
+        assert_eq!(ids, vec![mined[2].id(), mined[0].id()]);
+
+        {
+            let blockchain = chain.blockchain.lock().unwrap();
+            let mut mempool = chain.mempool.lock().unwrap();
+            mempool
+                .add_transaction(
+                    transfer(key, &sender, "alice", 1, 4),
+                    blockchain.get_state(),
+                )
+                .unwrap();
+        }
+        let page: AddressDetail = get(addr, &format!("/addresses/{}?page=1&limit=2", sender));
+        assert_eq!(page.balance, 1_000 - 4 * 11);
+        assert_eq!(page.nonce, 4);
+        assert_eq!(page.transactions.total, 5);
+        let heights: Vec<Option<u64>> = page
+            .transactions
+            .items
+            .iter()
+            .map(|detail| detail.transaction.height)
+            .collect();
+        assert_eq!(heights, vec![Some(3), Some(2)]);
+        assert_eq!(page.pending.len(), 1);
+        assert_eq!(page.pending[0].nonce, 4);
+
+        let nobody: AddressDetail = get(addr, "/addresses/nobody");
+        assert_eq!((nobody.balance, nobody.transactions.total), (0, 0));
+        assert!(nobody.pending.is_empty());
+    }
+
+    #[test]
+    fn test_mempool_listing_puts_the_best_paying_first() {
+        let (blockchain, key, sender, _) = busy_chain();
+        let (server, chain) = serve(blockchain);
+        let addr = server.local_addr();
+
+        let (other_key, other_pubkey) = generate_key_pair();
+        let other = public_key_to_address(&other_pubkey);
+        {
+            let mut blockchain = chain.blockchain.lock().unwrap();
+            let mint = Transaction::new(
+                "coinbase".to_string(),
+                other.clone(),
+                1_000,
+                1,
+                "coinbase".into(),
+            );
+            let block = mine(&blockchain, vec![mint]);
+            assert!(blockchain.add_block(block));
+            let mut mempool = chain.mempool.lock().unwrap();
+            for transaction in [
+                transfer(key, &sender, "alice", 1, 4),
+                transfer(other_key, &other, "bob", 5, 0),
+                // Waits for nonce 1 of `other`.
+                transfer(other_key, &other, "bob", 9, 2),
+            ] {
+                mempool
+                    .add_transaction(transaction, blockchain.get_state())
+                    .unwrap();
+            }
+        }
+
+        let page: Page<MempoolEntry> = get(addr, "/mempool");
+        assert_eq!(page.total, 3);
+        let listed: Vec<(u64, bool)> = page
+            .items
+            .iter()
+            .map(|entry| (entry.transaction.fee, entry.queued))
+            .collect();
+        assert_eq!(listed, vec![(9, true), (5, false), (1, false)]);
+        let last: Page<MempoolEntry> = get(addr, "/mempool?page=2&limit=1");
+        assert_eq!(last.items[0].transaction.fee, 1);
+    }
+
+    #[test]
+    fn test_pruned_history_is_gone_not_missing() {
+        let (mut blockchain, _, _, mined) = busy_chain();
+        blockchain.set_pruning(PruningMode::Pruned { keep_blocks: 2 });
+        let (server, _) = serve(blockchain);
+        let addr = server.local_addr();
+
+        assert_eq!(status(addr, "/blocks/2"), 410);
+        assert_eq!(
+            status(addr, &format!("/transactions/{}", mined[0].id())),
+            410
+        );
+        let recent: BlockDetail = get(addr, "/blocks/5");
+        assert_eq!(recent.block.transactions.len(), 2);
+        // Headers are kept, so listing still covers every block.
+        let all: Page<HeadView> = get(addr, "/blocks");
+        assert_eq!(all.total, 6);
+    }
+}
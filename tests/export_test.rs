@@ -0,0 +1,142 @@
+use my_first_blockchain::blockchain::Blockchain;
+use my_first_blockchain::export::{export_blocks, import_blocks, ChainFileError};
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use my_first_blockchain::{chain_spec::ChainSpec, transaction::Transaction};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.chain", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn mine(blockchain: Blockchain, amount: u64) -> Blockchain {
+        let mut blockchain = blockchain;
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        let data = vec![Transaction::new(
+            "coinbase".to_string(),
+            "miner".to_string(),
+            amount,
+            0,
+            "coinbase".into(),
+        )];
+        blockchain.mine_block(Arc::new(data), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.clone()
+    }
+
+    fn source_chain() -> Blockchain {
+        mine(mine(mine(Blockchain::new(), 10), 20), 30)
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let source = source_chain();
+
+        for compress in [false, true] {
+            let path = temp_path(&format!("round-trip-{}", compress));
+            let written = export_blocks(&source, &path, 0..usize::MAX, compress).unwrap();
+            assert_eq!(written, 4);
+
+            let mut target = Blockchain::new();
+            let mut reported = Vec::new();
+            let progress = import_blocks(&mut target, &path, |p| reported.push(*p)).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(progress.imported, 3);
+            assert_eq!(progress.skipped, 1); // The shared genesis block.
+            assert_eq!(reported.len(), 4);
+            assert_eq!(reported.last().unwrap().height, 3);
+            assert_eq!(target.get_chain_length(), 4);
+            assert_eq!(
+                target.get_chain()[3].get_hash(),
+                source.get_chain()[3].get_hash()
+            );
+            assert_eq!(target.get_balance("miner"), 60);
+        }
+    }
+
+    #[test]
+    fn test_interrupted_import_resumes() {
+        let source = source_chain();
+        let partial = temp_path("partial");
+        let full = temp_path("full");
+        export_blocks(&source, &partial, 0..2, true).unwrap();
+        export_blocks(&source, &full, 0..4, true).unwrap();
+
+        let mut target = Blockchain::new();
+        import_blocks(&mut target, &partial, |_| {}).unwrap();
+        assert_eq!(target.get_chain_length(), 2);
+
+        let progress = import_blocks(&mut target, &full, |_| {}).unwrap();
+        std::fs::remove_file(&partial).unwrap();
+        std::fs::remove_file(&full).unwrap();
+
+        assert_eq!(progress.skipped, 2);
+        assert_eq!(progress.imported, 2);
+        assert_eq!(target.get_chain_length(), 4);
+        assert!(target.is_chain_valid());
+    }
+
+    #[test]
+    fn test_import_rejects_gaps_and_foreign_chains() {
+        let source = source_chain();
+        let tail = temp_path("tail");
+        export_blocks(&source, &tail, 2..4, false).unwrap();
+
+        let mut target = Blockchain::new();
+        assert_eq!(
+            import_blocks(&mut target, &tail, |_| {}),
+            Err(ChainFileError::Gap {
+                expected: 1,
+                found: 2
+            })
+        );
+        std::fs::remove_file(&tail).unwrap();
+
+        let full = temp_path("foreign");
+        export_blocks(&source, &full, 0..4, false).unwrap();
+        let mut foreign = Blockchain::with_spec(ChainSpec {
+            genesis_timestamp: 42,
+            ..ChainSpec::default()
+        });
+        assert_eq!(
+            import_blocks(&mut foreign, &full, |_| {}),
+            Err(ChainFileError::GenesisMismatch)
+        );
+        std::fs::remove_file(&full).unwrap();
+    }
+
+    #[test]
+    fn test_import_revalidates_blocks() {
+        let source = source_chain();
+        let path = temp_path("tampered");
+        export_blocks(&source, &path, 0..4, false).unwrap();
+
+        // Bump the coinbase amount of the last block without re-mining it.
+        let mut bytes = std::fs::read(&path).unwrap();
+        let amount = 30u64.to_be_bytes();
+        let position = bytes
+            .windows(amount.len())
+            .rposition(|window| window == amount)
+            .unwrap();
+        bytes[position + 7] = 99;
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut target = Blockchain::new();
+        let result = import_blocks(&mut target, &path, |_| {});
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result, Err(ChainFileError::BlockRejected(3)));
+        assert_eq!(target.get_chain_length(), 3);
+    }
+}
@@ -0,0 +1,312 @@
+use my_first_blockchain::blockchain::{Block, BlockBody, Blockchain};
+use my_first_blockchain::compact_block::CompactBlock;
+use my_first_blockchain::gossip::{
+    CompactVerdict, GossipHandler, GossipVerdict, SharedChain, BLOCKS_TOPIC, TRANSACTIONS_TOPIC,
+};
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::p2p::{NetworkConfig, NetworkEvent, Node};
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        encoding::Encode,
+        transaction::Transaction,
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+    use secp256k1::SecretKey;
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+
+    fn mine(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Blockchain {
+        let mut blockchain = blockchain.clone();
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(Arc::new(transactions), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.clone()
+    }
+
+    fn funded_chain(address: &str, amount: u64) -> Blockchain {
+        let coinbase = Transaction::new(
+            "coinbase".to_string(),
+            address.to_string(),
+            amount,
+            0,
+            "coinbase".into(),
+        );
+        let spec = ChainSpec {
+            difficulty: 2,
+            block_reward: amount,
+            ..ChainSpec::default()
+        };
+        mine(&Blockchain::with_spec(spec), vec![coinbase])
+    }
+
+    // Reward paid to "miner" for the block at height 2.
+    fn reward() -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            "miner".to_string(),
+            1,
+            2,
+            "coinbase".into(),
+        )
+    }
+
+    fn signed(key: SecretKey, sender: &str, amount: u64, nonce: u64) -> Transaction {
+        let receiver = "receiver".to_string();
+        Transaction::new_with_fee(
+            sender.to_string(),
+            receiver.clone(),
+            amount,
+            1,
+            nonce,
+            sign_transaction_with_fee(key, sender.to_string(), receiver, amount, 1, nonce),
+        )
+    }
+
+    // Without the DHT the nodes stay connected the way they were dialed.
+    fn loopback_config() -> NetworkConfig {
+        NetworkConfig {
+            listen_addr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            kademlia: false,
+            ..NetworkConfig::default()
+        }
+    }
+
+    // Waits up to ten seconds for an event matching `predicate`.
+    async fn wait_for(node: &mut Node, predicate: impl Fn(&NetworkEvent) -> bool) -> NetworkEvent {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let event = node.next_event().await.expect("node stopped");
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for a network event")
+    }
+
+    // Starts a - b - c on copies of `blockchain` and waits until gossip on
+    // `topic` can flow from a to c.
+    async fn start_line(blockchain: &Blockchain, topic: &str) -> Vec<(Node, SharedChain)> {
+        let shared =
+            || SharedChain::new(blockchain.clone(), Mempool::new(MempoolConfig::default()));
+        let (a_chain, b_chain, c_chain) = (shared(), shared(), shared());
+        let mut a = Node::start(loopback_config(), a_chain.clone())
+            .await
+            .unwrap();
+        let mut b = Node::start(
+            NetworkConfig {
+                peers: a.listen_addrs().await,
+                ..loopback_config()
+            },
+            b_chain.clone(),
+        )
+        .await
+        .unwrap();
+        let c = Node::start(
+            NetworkConfig {
+                peers: b.listen_addrs().await,
+                ..loopback_config()
+            },
+            c_chain.clone(),
+        )
+        .await
+        .unwrap();
+
+        let (b_id, c_id) = (b.peer_id(), c.peer_id());
+        wait_for(&mut a, |e| {
+            matches!(e, NetworkEvent::Subscribed { peer, topic: t } if *peer == b_id && t == topic)
+        })
+        .await;
+        wait_for(&mut b, |e| {
+            matches!(e, NetworkEvent::Subscribed { peer, topic: t } if *peer == c_id && t == topic)
+        })
+        .await;
+        // Relaying needs the mesh, which forms on the next heartbeats.
+        sleep(Duration::from_millis(1500)).await;
+
+        vec![(a, a_chain), (b, b_chain), (c, c_chain)]
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blocks_are_validated_before_relay() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender, 100);
+        let mut nodes = start_line(&base, BLOCKS_TOPIC).await;
+
+        // Valid proof of work, but the transaction isn't signed by `sender`.
+        let forged =
+            Transaction::new_with_fee(sender.clone(), "mallory".to_string(), 50, 1, 0, vec![7; 65]);
+        let timestamp = base.get_chain()[1].get_timestamp() + 1;
+        let bad_block = base
+            .mine_next_block(vec![reward(), forged], timestamp)
+            .unwrap();
+        // Sent in full, as A couldn't serve its transactions for a compact one.
+        nodes[0]
+            .0
+            .publish(BLOCKS_TOPIC, bad_block.encode())
+            .await
+            .unwrap();
+        let rejected = wait_for(&mut nodes[1].0, |e| {
+            matches!(e, NetworkEvent::GossipRejected { .. })
+        })
+        .await;
+        match rejected {
+            NetworkEvent::GossipRejected { source, topic, .. } => {
+                assert_eq!(source, nodes[0].0.peer_id());
+                assert_eq!(topic, BLOCKS_TOPIC);
+            }
+            _ => unreachable!(),
+        }
+
+        // A's own block goes into its chain first, as after mining.
+        let good = signed(prikey, &sender, 30, 0);
+        let good_block = mine(&base, vec![reward(), good]).get_chain()[2].clone();
+        assert!(nodes[0]
+            .1
+            .blockchain
+            .lock()
+            .unwrap()
+            .add_block(good_block.clone()));
+        nodes[0].0.publish_block(&good_block).await.unwrap();
+        let accepted = wait_for(&mut nodes[2].0, |e| {
+            matches!(e, NetworkEvent::BlockAccepted { .. })
+        })
+        .await;
+        assert_eq!(
+            accepted,
+            NetworkEvent::BlockAccepted {
+                source: nodes[1].0.peer_id(),
+                hash: good_block.get_hash().to_string(),
+            }
+        );
+
+        for (_, chain) in &nodes[1..] {
+            let blockchain = chain.blockchain.lock().unwrap();
+            assert_eq!(blockchain.get_chain().len(), 3);
+            assert_eq!(blockchain.get_chain()[2].get_hash(), good_block.get_hash());
+            assert_eq!(blockchain.get_balance("mallory"), 0);
+        }
+        for (node, _) in nodes {
+            node.shutdown().await;
+        }
+    }
+
+    #[test]
+    fn test_bad_bodies_dont_shadow_the_real_block() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender, 100);
+        let good =
+            mine(&base, vec![reward(), signed(prikey, &sender, 30, 0)]).get_chain()[2].clone();
+        // Same header and so the same hash, but not the body it commits to.
+        let forged = Block::new(good.get_header().clone(), BlockBody::new(vec![reward()]));
+        assert_eq!(forged.get_hash(), good.get_hash());
+
+        let chain = SharedChain::new(base.clone(), Mempool::new(MempoolConfig::default()));
+        let mut handler = GossipHandler::new(chain.clone());
+        assert!(matches!(
+            handler.handle_block(&forged.encode()),
+            GossipVerdict::Reject(_)
+        ));
+        let compact = CompactBlock::from_block(&good);
+        let hash = match handler.handle_compact_block(&compact.encode()) {
+            CompactVerdict::Missing { hash, .. } => hash,
+            _ => panic!("the compact block was dropped"),
+        };
+        assert!(matches!(
+            handler.handle_block(&forged.encode()),
+            GossipVerdict::Reject(_)
+        ));
+        assert_eq!(
+            handler.complete_compact_block(&hash, good.get_body().clone()),
+            GossipVerdict::Accept
+        );
+        assert_eq!(handler.handle_block(&good.encode()), GossipVerdict::Ignore);
+        assert_eq!(chain.blockchain.lock().unwrap().get_chain_length(), 3);
+
+        // And the same through full blocks alone.
+        let mut handler = GossipHandler::new(SharedChain::new(
+            base,
+            Mempool::new(MempoolConfig::default()),
+        ));
+        assert!(matches!(
+            handler.handle_block(&forged.encode()),
+            GossipVerdict::Reject(_)
+        ));
+        assert_eq!(handler.handle_block(&good.encode()), GossipVerdict::Accept);
+    }
+
+    #[test]
+    fn test_transactions_refused_for_now_are_looked_at_again() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain("someone", 100);
+        let chain = SharedChain::new(base.clone(), Mempool::new(MempoolConfig::default()));
+        let mut handler = GossipHandler::new(chain.clone());
+        let transfer = signed(prikey, &sender, 30, 0).encode();
+
+        // Nothing to pay with yet.
+        assert_eq!(handler.handle_transaction(&transfer), GossipVerdict::Ignore);
+
+        let funding = Transaction::new(
+            "coinbase".to_string(),
+            sender.clone(),
+            100,
+            2,
+            "coinbase".into(),
+        );
+        *chain.blockchain.lock().unwrap() = mine(&base, vec![funding]);
+        assert_eq!(handler.handle_transaction(&transfer), GossipVerdict::Accept);
+        assert_eq!(handler.handle_transaction(&transfer), GossipVerdict::Ignore);
+        assert_eq!(chain.mempool.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transactions_reach_remote_mempools() {
+        let (prikey, pubkey) = generate_key_pair();
+        let (other_key, _) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender, 100);
+        let mut nodes = start_line(&base, TRANSACTIONS_TOPIC).await;
+
+        let forged = signed(other_key, &sender, 10, 0);
+        nodes[0].0.publish_transaction(&forged).await.unwrap();
+        let rejected = wait_for(&mut nodes[1].0, |e| {
+            matches!(e, NetworkEvent::GossipRejected { .. })
+        })
+        .await;
+        assert!(
+            matches!(rejected, NetworkEvent::GossipRejected { topic, .. } if topic == TRANSACTIONS_TOPIC)
+        );
+
+        let transaction = signed(prikey, &sender, 10, 0);
+        nodes[0].0.publish_transaction(&transaction).await.unwrap();
+        let b_id = nodes[1].0.peer_id();
+        wait_for(&mut nodes[2].0, |e| {
+            *e == NetworkEvent::TransactionAccepted {
+                source: b_id,
+                id: transaction.id(),
+            }
+        })
+        .await;
+
+        for (_, chain) in &nodes[1..] {
+            let mempool = chain.mempool.lock().unwrap();
+            assert!(mempool.contains(&transaction));
+            assert!(!mempool.contains(&forged));
+        }
+        for (node, _) in nodes {
+            node.shutdown().await;
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use my_first_blockchain::blockchain::Blockchain;
+use my_first_blockchain::chain_spec::ChainSpec;
+use my_first_blockchain::encoding::{Decode, Encode};
+use my_first_blockchain::gossip::SharedChain;
+use my_first_blockchain::handshake::{HandshakeError, Status, NODE_PROTOCOL_VERSION};
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::p2p::{NetworkConfig, NetworkEvent, Node};
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use my_first_blockchain::transaction::Transaction;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn fast_spec() -> ChainSpec {
+        ChainSpec {
+            difficulty: 2,
+            ..ChainSpec::default()
+        }
+    }
+
+    fn chain_of(length: u64, spec: ChainSpec) -> Blockchain {
+        let mut blockchain = Blockchain::with_spec(spec);
+        for amount in 1..length {
+            let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+            let coinbase = Transaction::new(
+                "coinbase".to_string(),
+                "alice".to_string(),
+                amount,
+                0,
+                "coinbase".into(),
+            );
+            blockchain.mine_block(Arc::new(vec![coinbase]), Arc::clone(&arc_blockchain));
+            let mined = arc_blockchain.lock().unwrap().clone();
+            blockchain = mined;
+        }
+        blockchain
+    }
+
+    fn shared(blockchain: Blockchain) -> SharedChain {
+        SharedChain::new(blockchain, Mempool::new(MempoolConfig::default()))
+    }
+
+    fn loopback_config() -> NetworkConfig {
+        NetworkConfig {
+            listen_addr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            kademlia: false,
+            ..NetworkConfig::default()
+        }
+    }
+
+    // Waits up to ten seconds for an event matching `predicate`.
+    async fn wait_for(node: &mut Node, predicate: impl Fn(&NetworkEvent) -> bool) -> NetworkEvent {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let event = node.next_event().await.expect("node stopped");
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for a network event")
+    }
+
+    #[test]
+    fn test_status_describes_tip_and_rejects_other_chains() {
+        let blockchain = chain_of(4, fast_spec());
+        let status = Status::local(&blockchain);
+        assert_eq!(status.protocol_version, NODE_PROTOCOL_VERSION);
+        assert_eq!(status.best_height, 3);
+        assert_eq!(
+            status.best_hash,
+            blockchain.get_chain().last().unwrap().get_hash()
+        );
+        assert_eq!(status.cumulative_work, 4 * 16 * 16);
+        assert_eq!(Status::decode(&status.encode()).unwrap(), status);
+
+        // Same chain, different tip.
+        let genesis = Status::local(&Blockchain::with_spec(fast_spec()));
+        assert_eq!(genesis.check_compatible(&status), Ok(()));
+
+        let other_id = Status::local(&Blockchain::with_spec(ChainSpec {
+            chain_id: 2,
+            ..fast_spec()
+        }));
+        assert_eq!(
+            status.check_compatible(&other_id),
+            Err(HandshakeError::ChainId { ours: 1, theirs: 2 })
+        );
+        let other_genesis = Status::local(&Blockchain::with_spec(ChainSpec {
+            genesis_timestamp: 1,
+            ..fast_spec()
+        }));
+        assert!(matches!(
+            status.check_compatible(&other_genesis),
+            Err(HandshakeError::Genesis { .. })
+        ));
+        let newer = Status {
+            protocol_version: NODE_PROTOCOL_VERSION + 1,
+            ..status.clone()
+        };
+        assert!(matches!(
+            status.check_compatible(&newer),
+            Err(HandshakeError::ProtocolVersion { .. })
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_nodes_on_the_same_chain_exchange_their_tips() {
+        let source = chain_of(6, fast_spec());
+        let expected = Status::local(&source);
+        let ahead = Node::start(loopback_config(), shared(source))
+            .await
+            .unwrap();
+        let ahead_id = ahead.peer_id();
+
+        let fresh = shared(Blockchain::with_spec(fast_spec()));
+        let mut node = Node::start(
+            NetworkConfig {
+                peers: ahead.listen_addrs().await,
+                ..loopback_config()
+            },
+            fresh.clone(),
+        )
+        .await
+        .unwrap();
+        let event = wait_for(&mut node, |e| {
+            matches!(e, NetworkEvent::HandshakeCompleted { .. })
+        })
+        .await;
+        assert_eq!(
+            event,
+            NetworkEvent::HandshakeCompleted {
+                peer: ahead_id,
+                status: expected,
+            }
+        );
+        // It claimed more work than we have, so we sync from it.
+        wait_for(&mut node, |e| matches!(e, NetworkEvent::Sync(_))).await;
+        assert_eq!(fresh.blockchain.lock().unwrap().get_chain_length(), 6);
+
+        node.shutdown().await;
+        ahead.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_nodes_on_different_chains_disconnect() {
+        for spec in [
+            ChainSpec {
+                chain_id: 7,
+                ..fast_spec()
+            },
+            ChainSpec {
+                genesis_timestamp: 1,
+                ..fast_spec()
+            },
+        ] {
+            let mut other = Node::start(loopback_config(), shared(Blockchain::with_spec(spec)))
+                .await
+                .unwrap();
+            let other_id = other.peer_id();
+            let mut node = Node::start(
+                NetworkConfig {
+                    peers: other.listen_addrs().await,
+                    ..loopback_config()
+                },
+                shared(Blockchain::with_spec(fast_spec())),
+            )
+            .await
+            .unwrap();
+            let node_id = node.peer_id();
+
+            wait_for(
+                &mut node,
+                |e| matches!(e, NetworkEvent::PeerIncompatible { peer, .. } if *peer == other_id),
+            )
+            .await;
+            wait_for(&mut other, |e| {
+                *e == NetworkEvent::PeerDisconnected(node_id)
+            })
+            .await;
+            wait_for(&mut node, |e| {
+                *e == NetworkEvent::PeerDisconnected(other_id)
+            })
+            .await;
+
+            node.shutdown().await;
+            other.shutdown().await;
+        }
+    }
+}
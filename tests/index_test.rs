@@ -0,0 +1,178 @@
+use my_first_blockchain::blockchain::{Blockchain, BlockchainError};
+use my_first_blockchain::index::ChainIndex;
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use my_first_blockchain::utils::{public_key_to_address, sign_transaction};
+    use my_first_blockchain::{storage::FileBlockStore, transaction::Transaction};
+    use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    use super::*;
+
+    fn mint(receiver: &str, amount: u64) -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            receiver.to_string(),
+            amount,
+            0,
+            "coinbase".into(),
+        )
+    }
+
+    // The key behind alice(), so her transfers carry a valid signature.
+    fn alice_key() -> SecretKey {
+        SecretKey::from_slice(&[1; 32]).unwrap()
+    }
+
+    fn alice() -> String {
+        public_key_to_address(&PublicKey::from_secret_key(&Secp256k1::new(), &alice_key()))
+    }
+
+    fn transfer(receiver: &str, amount: u64, nonce: u64) -> Transaction {
+        let signature = sign_transaction(alice_key(), alice(), receiver.to_string(), amount, nonce);
+        Transaction::new(alice(), receiver.to_string(), amount, nonce, signature)
+    }
+
+    fn mine(blockchain: Blockchain, data: Vec<Transaction>) -> Blockchain {
+        let mut blockchain = blockchain;
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(Arc::new(data), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.clone()
+    }
+
+    #[test]
+    fn test_transaction_and_address_lookups() {
+        let minted = mint(&alice(), 50);
+        let sent = transfer("bob", 20, 0);
+        let blockchain = mine(Blockchain::new(), vec![minted.clone()]);
+        let blockchain = mine(blockchain, vec![mint("miner", 50), sent.clone()]);
+
+        let (found, location) = blockchain.get_transaction_by_id(&sent.id()).unwrap();
+        assert_eq!(found, &sent);
+        assert_eq!(location.height, 2);
+        assert_eq!(location.position, 1);
+        assert_eq!(location.block_hash, blockchain.get_chain()[2].get_hash());
+
+        let history: Vec<&Transaction> = blockchain
+            .get_address_history(&alice())
+            .unwrap()
+            .into_iter()
+            .map(|(tx, _)| tx)
+            .collect();
+        assert_eq!(history, vec![&minted, &sent]);
+        assert_eq!(blockchain.get_address_history("bob").unwrap().len(), 1);
+
+        let tip_hash = blockchain.get_chain()[2].get_hash();
+        assert_eq!(blockchain.get_index().get_block_height(tip_hash), Some(2));
+        assert_eq!(
+            blockchain.get_block_by_hash(tip_hash).unwrap().get_index(),
+            2
+        );
+        assert_eq!(
+            blockchain.get_transaction_by_id("00").err(),
+            Some(BlockchainError::TransactionNotFound)
+        );
+    }
+
+    #[test]
+    fn test_index_follows_reorg() {
+        let base = mine(Blockchain::new(), vec![mint(&alice(), 50)]);
+        let stale_tx = transfer("bob", 20, 0);
+        let mut active = mine(base.clone(), vec![mint("miner", 50), stale_tx.clone()]);
+        let stale_hash = active.get_chain()[2].get_hash().to_string();
+
+        let new_tx = transfer("carol", 30, 0);
+        let fork = mine(
+            mine(base, vec![mint("dave", 50), new_tx.clone()]),
+            vec![mint("carol", 1)],
+        );
+        let branch = fork.get_chain()[2..].to_vec();
+
+        let disconnected = active.reorganize(1, branch).unwrap();
+        assert_eq!(disconnected.len(), 1);
+        assert_eq!(disconnected[0].get_hash(), stale_hash);
+
+        assert_eq!(
+            active.get_transaction_by_id(&stale_tx.id()).err(),
+            Some(BlockchainError::TransactionNotFound)
+        );
+        assert!(active.get_address_history("bob").unwrap().is_empty());
+        assert_eq!(
+            active.get_block_by_hash(&stale_hash).err(),
+            Some(BlockchainError::BlockNotFound)
+        );
+        assert_eq!(
+            active.get_transaction_by_id(&new_tx.id()).unwrap().1.height,
+            2
+        );
+        assert_eq!(active.get_address_history("carol").unwrap().len(), 2);
+        assert_eq!(active.get_balance("bob"), 0);
+        assert_eq!(active.get_balance("carol"), 31);
+        assert_eq!(active.get_chain_length(), 4);
+    }
+
+    #[test]
+    fn test_reorg_rejects_shorter_branch() {
+        let base = mine(Blockchain::new(), vec![mint(&alice(), 50)]);
+        let mut active = mine(base.clone(), vec![mint("bob", 1)]);
+        let fork = mine(base, vec![mint("carol", 1)]);
+
+        assert!(active
+            .reorganize(1, fork.get_chain()[2..].to_vec())
+            .is_err());
+        assert_eq!(active.get_balance("bob"), 1);
+    }
+
+    #[test]
+    fn test_index_is_persisted_next_to_the_block_file() {
+        let path = std::env::temp_dir().join(format!("index-{}.dat", std::process::id()));
+        let index_path = path.with_extension("idx");
+        let _ = std::fs::remove_file(&path);
+
+        let blockchain = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        let sent = transfer("bob", 20, 0);
+        let blockchain = mine(blockchain, vec![mint(&alice(), 50), sent.clone()]);
+
+        // Saved when the chain was created, not for every block.
+        let index = ChainIndex::load(&index_path).unwrap();
+        assert_eq!(index.tip(), Some(blockchain.get_chain()[0].get_hash()));
+
+        // Reopening indexes the blocks added since and saves the result.
+        let reopened = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        let index = ChainIndex::load(&index_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+        assert!(reopened.get_transaction_by_id(&sent.id()).is_ok());
+        assert_eq!(index.tip(), Some(blockchain.get_chain()[1].get_hash()));
+        assert!(index.get_transaction_location(&sent.id()).is_some());
+    }
+
+    #[test]
+    fn test_blocks_repeating_a_transaction_are_rejected() {
+        let minted = mint(&alice(), 50);
+        let mut blockchain = mine(Blockchain::new(), vec![minted.clone()]);
+        let timestamp = blockchain.get_chain()[1].get_timestamp() + 1;
+
+        // The same coinbase again would leave its ID pointing at two blocks.
+        let repeated = blockchain
+            .mine_next_block(vec![minted.clone()], timestamp)
+            .unwrap();
+        assert!(matches!(
+            blockchain.try_add_block(repeated),
+            Err(BlockchainError::BlockInvalid(_))
+        ));
+        assert_eq!(
+            blockchain
+                .get_transaction_by_id(&minted.id())
+                .unwrap()
+                .1
+                .height,
+            1
+        );
+        assert_eq!(blockchain.get_chain_length(), 2);
+    }
+}
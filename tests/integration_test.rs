@@ -1,4 +1,5 @@
-use my_first_blockchain::blockchain::Blockchain;
+use my_first_blockchain::blockchain::{Blockchain, BlockchainError};
+use my_first_blockchain::state::WorldState;
 
 #[cfg(test)]
 mod tests {
@@ -9,11 +10,13 @@ mod tests {
     };
 
     use my_first_blockchain::{
-        transaction::Transaction,
-        utils::{generate_key_pair, public_key_to_address, recover_public_key, sign_transaction},
+        transaction::{Transaction, COINBASE_SIGNATURE},
+        utils::{
+            generate_key_pair, public_key_to_address, recover_public_key, sign_transaction,
+            sign_transaction_with_fee, transaction_message_hash,
+        },
     };
-    use secp256k1::Message;
-    use sha3::{Digest, Keccak256};
+    use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
 
     use super::*;
 
@@ -25,261 +28,87 @@ mod tests {
 
         assert_eq!(genesis_block.get_index(), 0);
         assert_eq!(
-            genesis_block.get_data_raw().get(0).unwrap().get_sender(),
+            genesis_block.get_data_raw().first().unwrap().get_sender(),
             "me"
         );
         assert_eq!(genesis_block.get_previous_hash(), "0");
         assert_eq!(genesis_block.get_hash(), hash);
         assert_eq!(genesis_block.get_nonce(), 0);
+        blockchain.validate_chain().unwrap();
+    }
+
+    // Reward for the block at `height`, paid to `address`.
+    fn coinbase(address: &str, height: u64) -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            address.to_string(),
+            50,
+            height,
+            COINBASE_SIGNATURE.to_vec(),
+        )
+    }
+
+    fn transfer(key: SecretKey, receiver: &str, amount: u64, nonce: u64) -> Transaction {
+        let sender = address(key);
+        let signature = sign_transaction(key, sender.clone(), receiver.to_string(), amount, nonce);
+        Transaction::new(sender, receiver.to_string(), amount, nonce, signature)
+    }
+
+    fn address(key: SecretKey) -> String {
+        public_key_to_address(&PublicKey::from_secret_key(&Secp256k1::new(), &key))
     }
 
     #[test]
     fn test_is_chain_valid() {
         let mut blockchain = Blockchain::new();
-        let (prikey1, pubkey1) = generate_key_pair();
-        let (prikey2, pubkey2) = generate_key_pair();
-        let (prikey3, pubkey3) = generate_key_pair();
-        let (prikey4, pubkey4) = generate_key_pair();
-        let (prikey5, pubkey5) = generate_key_pair();
-        let (prikey6, pubkey6) = generate_key_pair();
+        let keys: Vec<SecretKey> = (0..3).map(|_| generate_key_pair().0).collect();
+        let [a, b, c] = [keys[0], keys[1], keys[2]];
+        let (address_a, address_b, address_c) = (address(a), address(b), address(c));
 
         let transactions = vec![
+            vec![coinbase(&address_a, 1)],
+            vec![coinbase(&address_b, 2), transfer(a, &address_b, 10, 0)],
             vec![
-                Transaction::new(
-                    "sender1".to_string(),
-                    "receiver1".to_string(),
-                    10,
-                    0,
-                    sign_transaction(
-                        prikey1,
-                        public_key_to_address(&pubkey1),
-                        public_key_to_address(&pubkey2),
-                        10,
-                        0,
-                    ),
-                ),
-                Transaction::new(
-                    "sender2".to_string(),
-                    "receiver2".to_string(),
-                    10,
-                    0,
-                    sign_transaction(
-                        prikey2,
-                        public_key_to_address(&pubkey2),
-                        public_key_to_address(&pubkey3),
-                        10,
-                        0,
-                    ),
-                ),
-                Transaction::new(
-                    "sender3".to_string(),
-                    "receiver3".to_string(),
-                    10,
-                    0,
-                    sign_transaction(
-                        prikey3,
-                        public_key_to_address(&pubkey3),
-                        public_key_to_address(&pubkey1),
-                        10,
-                        0,
-                    ),
-                ),
+                coinbase(&address_c, 3),
+                transfer(a, &address_c, 10, 1),
+                transfer(b, &address_c, 10, 0),
             ],
             vec![
-                Transaction::new(
-                    "sender1".to_string(),
-                    "receiver1".to_string(),
-                    10,
-                    1,
-                    sign_transaction(
-                        prikey1,
-                        public_key_to_address(&pubkey1),
-                        public_key_to_address(&pubkey2),
-                        10,
-                        1,
-                    ),
-                ),
-                Transaction::new(
-                    "sender2".to_string(),
-                    "receiver2".to_string(),
-                    10,
-                    1,
-                    sign_transaction(
-                        prikey2,
-                        public_key_to_address(&pubkey2),
-                        public_key_to_address(&pubkey3),
-                        10,
-                        1,
-                    ),
-                ),
-                Transaction::new(
-                    "sender3".to_string(),
-                    "receiver3".to_string(),
-                    0,
-                    1,
-                    sign_transaction(
-                        prikey3,
-                        public_key_to_address(&pubkey3),
-                        public_key_to_address(&pubkey1),
-                        10,
-                        1,
-                    ),
-                ),
-            ],
-            vec![
-                Transaction::new(
-                    "sender1".to_string(),
-                    "receiver1".to_string(),
-                    10,
-                    2,
-                    sign_transaction(
-                        prikey1,
-                        public_key_to_address(&pubkey1),
-                        public_key_to_address(&pubkey2),
-                        10,
-                        0,
-                    ),
-                ),
-                Transaction::new(
-                    "sender2".to_string(),
-                    "receiver2".to_string(),
-                    10,
-                    2,
-                    sign_transaction(
-                        prikey2,
-                        public_key_to_address(&pubkey2),
-                        public_key_to_address(&pubkey3),
-                        10,
-                        0,
-                    ),
-                ),
-                Transaction::new(
-                    "sender3".to_string(),
-                    "receiver3".to_string(),
-                    10,
-                    2,
-                    sign_transaction(
-                        prikey3,
-                        public_key_to_address(&pubkey3),
-                        public_key_to_address(&pubkey1),
-                        10,
-                        0,
-                    ),
-                ),
-            ],
-            vec![
-                Transaction::new(
-                    "sender4".to_string(),
-                    "receiver4".to_string(),
-                    10,
-                    3,
-                    sign_transaction(
-                        prikey4,
-                        public_key_to_address(&pubkey4),
-                        public_key_to_address(&pubkey5),
-                        10,
-                        3,
-                    ),
-                ),
-                Transaction::new(
-                    "sender5".to_string(),
-                    "receiver5".to_string(),
-                    10,
-                    3,
-                    sign_transaction(
-                        prikey5,
-                        public_key_to_address(&pubkey5),
-                        public_key_to_address(&pubkey6),
-                        10,
-                        3,
-                    ),
-                ),
-                Transaction::new(
-                    "sender6".to_string(),
-                    "receiver6".to_string(),
-                    10,
-                    3,
-                    sign_transaction(
-                        prikey6,
-                        public_key_to_address(&pubkey6),
-                        public_key_to_address(&pubkey4),
-                        10,
-                        3,
-                    ),
-                ),
-            ],
-            vec![
-                Transaction::new(
-                    "sender4".to_string(),
-                    "receiver4".to_string(),
-                    10,
-                    4,
-                    sign_transaction(
-                        prikey4,
-                        public_key_to_address(&pubkey4),
-                        public_key_to_address(&pubkey5),
-                        10,
-                        4,
-                    ),
-                ),
-                Transaction::new(
-                    "sender5".to_string(),
-                    "receiver5".to_string(),
-                    10,
-                    4,
-                    sign_transaction(
-                        prikey5,
-                        public_key_to_address(&pubkey5),
-                        public_key_to_address(&pubkey6),
-                        10,
-                        4,
-                    ),
-                ),
-                Transaction::new(
-                    "sender6".to_string(),
-                    "receiver6".to_string(),
-                    10,
-                    4,
-                    sign_transaction(
-                        prikey6,
-                        public_key_to_address(&pubkey6),
-                        public_key_to_address(&pubkey4),
-                        10,
-                        4,
-                    ),
-                ),
+                coinbase(&address_a, 4),
+                transfer(b, &address_a, 10, 1),
+                transfer(c, &address_a, 10, 0),
+                transfer(c, &address_b, 5, 1),
             ],
+            vec![coinbase(&address_b, 5), transfer(a, &address_c, 10, 2)],
         ];
 
         let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
 
         for data in transactions {
             let transactions = Arc::new(data.clone());
-            blockchain.mine_block(transactions, Arc::clone(&arc_blockchain));
+            assert!(blockchain.mine_block(transactions, Arc::clone(&arc_blockchain)));
+            blockchain = arc_blockchain.lock().unwrap().clone();
         }
         let updated_blockchain = arc_blockchain.lock().unwrap();
-        assert_eq!(updated_blockchain.is_chain_valid(), true);
+        assert_eq!(updated_blockchain.get_chain_length(), 6);
+        assert_eq!(
+            updated_blockchain.get_balance(&address_a),
+            50 - 30 + 50 + 20
+        );
+        assert!(updated_blockchain.is_chain_valid());
     }
 
     #[test]
     fn test_mine_block() {
         let (prikey1, pubkey1) = generate_key_pair();
-        let (prikey2, pubkey2) = generate_key_pair();
+        let (_, pubkey2) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey1);
 
         let mut blockchain = Blockchain::new();
-        let data = vec![Transaction::new(
-            "sender1".to_string(),
-            "receiver1".to_string(),
-            10,
-            0,
-            sign_transaction(
-                prikey1,
-                public_key_to_address(&pubkey1),
-                public_key_to_address(&pubkey2),
-                10,
-                0,
-            ),
-        )];
+        let data = vec![
+            coinbase(&sender, 1),
+            transfer(prikey1, &public_key_to_address(&pubkey2), 10, 0),
+        ];
 
         let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
         let transactions = Arc::new(data.clone());
@@ -287,21 +116,21 @@ mod tests {
 
         let blockchain = arc_blockchain.lock().unwrap();
 
-        assert_eq!(result, true);
+        assert!(result);
         assert_eq!(blockchain.get_chain().len(), 2);
 
         let new_block = &blockchain.get_chain()[1];
         assert_eq!(new_block.get_index(), 1);
         assert_eq!(
-            new_block.get_data_raw()[0].get_signature(),
-            data[0].get_signature()
+            new_block.get_data_raw()[1].get_signature(),
+            data[1].get_signature()
         );
         assert_eq!(
             new_block.get_previous_hash(),
             blockchain.get_chain()[0].get_hash()
         );
         assert!(new_block.get_hash().starts_with(&"0".repeat(0)));
-        assert_eq!(blockchain.is_chain_valid(), true);
+        assert!(blockchain.is_chain_valid());
     }
 
     #[test]
@@ -316,8 +145,7 @@ mod tests {
 
         let signature = sign_transaction(prikey1, sender.clone(), receiver.clone(), amount, nonce);
 
-        let message = format!("{}{}{}{}", sender, receiver, amount, nonce);
-        let message_hash = Keccak256::digest(message.as_bytes());
+        let message_hash = transaction_message_hash(&sender, &receiver, amount, 0, nonce);
         let message_hash =
             Message::from_digest_slice(&message_hash).expect("Failed to convert message hash");
 
@@ -333,15 +161,32 @@ mod tests {
 
         assert_eq!(recovered_pubkey, Ok(pubkey1));
 
-        assert_eq!(transaction.verify_signature(), true);
+        assert!(transaction.verify_signature());
+    }
+
+    #[test]
+    fn test_signature_covers_each_field_separately() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let signature = sign_transaction(key, sender.clone(), "bob".into(), 1, 10);
+        let transaction = Transaction::new(sender.clone(), "bob".into(), 1, 10, signature.clone());
+        assert!(transaction.verify_signature());
+
+        // "1" followed by "10" reads the same as "11" followed by "0".
+        let resplit = Transaction::new(sender.clone(), "bob".into(), 11, 0, signature.clone());
+        assert!(!resplit.verify_signature());
+        let moved = Transaction::new(sender.clone(), "bob1".into(), 1, 0, signature);
+        assert!(!moved.verify_signature());
     }
 
     #[test]
     fn test_merkle_transaction_proof() {
+        let (key, pubkey) = generate_key_pair();
+        let me = public_key_to_address(&pubkey);
         let tx_list = vec![
-            Transaction::new("me".to_string(), "me".to_string(), 10, 0, "coinbase".into()),
-            Transaction::new("you".to_string(), "me".to_string(), 5, 0, "transfer".into()),
-            Transaction::new("me".to_string(), "you".to_string(), 3, 0, "transfer".into()),
+            coinbase(&me, 1),
+            transfer(key, "you", 5, 0),
+            transfer(key, "you", 3, 1),
         ];
 
         let fake_transaction = Transaction::new(
@@ -369,4 +214,134 @@ mod tests {
         assert_eq!(result3, Ok(true));
         assert_ne!(result4, Ok(true))
     }
+
+    // n - s for the secp256k1 group order n, the other valid S for a signature
+    fn flip_s(s: &[u8]) -> Vec<u8> {
+        let n = hex::decode("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141")
+            .unwrap();
+        let mut out = vec![0u8; 32];
+        let mut borrow = 0i16;
+        for i in (0..32).rev() {
+            let mut diff = n[i] as i16 - s[i] as i16 - borrow;
+            borrow = if diff < 0 { 1 } else { 0 };
+            if diff < 0 {
+                diff += 256;
+            }
+            out[i] = diff as u8;
+        }
+        out
+    }
+
+    #[test]
+    fn test_transaction_id_is_stable_and_malleated_signatures_are_rejected() {
+        let (prikey1, pubkey1) = generate_key_pair();
+        let (_, pubkey2) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey1);
+        let receiver = public_key_to_address(&pubkey2);
+        let signature = sign_transaction(prikey1, sender.clone(), receiver.clone(), 5, 0);
+        let transaction =
+            Transaction::new(sender.clone(), receiver.clone(), 5, 0, signature.clone());
+
+        assert_eq!(transaction.id(), transaction.clone().id());
+        assert_eq!(transaction.id().len(), 64);
+        assert!(transaction.verify_signature());
+
+        let mut malleated = signature[..32].to_vec();
+        malleated.extend(flip_s(&signature[32..64]));
+        malleated.push(signature[64] ^ 1);
+        let malleated = Transaction::new(sender, receiver, 5, 0, malleated);
+        assert_ne!(malleated.id(), transaction.id());
+        assert!(!malleated.verify_signature());
+    }
+
+    #[test]
+    fn test_transactions_are_found_by_id() {
+        let (key, pubkey) = generate_key_pair();
+        let transaction = transfer(key, "me", 5, 0);
+        let mut blockchain = Blockchain::new();
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(
+            Arc::new(vec![
+                coinbase(&public_key_to_address(&pubkey), 1),
+                transaction.clone(),
+            ]),
+            Arc::clone(&arc_blockchain),
+        );
+        let mut blockchain = arc_blockchain.lock().unwrap();
+
+        let (found, location) = blockchain.get_transaction_by_id(&transaction.id()).unwrap();
+        assert_eq!(found.id(), transaction.id());
+        assert_eq!(location.height, 1);
+        assert_eq!(location.position, 1);
+        assert_eq!(
+            blockchain.check_transaction_validity(&transaction),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_overdrafts_and_overflows_are_rejected() {
+        let (key, pubkey) = generate_key_pair();
+        let alice = public_key_to_address(&pubkey);
+        let mut state = WorldState::new();
+        state.apply_transactions(&[coinbase(&alice, 1)]).unwrap();
+
+        let overdraft = transfer(key, "bob", 51, 0);
+        assert!(overdraft.execute(&mut state.clone()).is_err());
+        let signature =
+            sign_transaction_with_fee(key, alice.clone(), "bob".to_string(), u64::MAX, 1, 0);
+        let wrapping =
+            Transaction::new_with_fee(alice.clone(), "bob".into(), u64::MAX, 1, 0, signature);
+        assert!(wrapping.execute(&mut state.clone()).is_err());
+        let mint = Transaction::new(
+            "coinbase".into(),
+            alice.clone(),
+            u64::MAX,
+            2,
+            COINBASE_SIGNATURE.to_vec(),
+        );
+        assert!(mint.execute(&mut state.clone()).is_err());
+        assert_eq!(state.get_balance(&alice), 50);
+        assert_eq!(state.get_balance("bob"), 0);
+
+        let blockchain = Blockchain::new();
+        assert!(matches!(
+            blockchain.mine_next_block(vec![coinbase(&alice, 1), overdraft], 0),
+            Err(BlockchainError::BlockInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_unauthorized_transfers_are_rejected_when_adding_blocks() {
+        let (key, pubkey) = generate_key_pair();
+        let alice = public_key_to_address(&pubkey);
+        let mut blockchain = Blockchain::new();
+        let paid = transfer(key, "bob", 10, 0);
+        for transactions in [
+            vec![coinbase(&alice, 1)],
+            vec![coinbase("bob", 2), paid.clone()],
+        ] {
+            let timestamp = blockchain.get_chain().last().unwrap().get_timestamp() + 1;
+            let block = blockchain.mine_next_block(transactions, timestamp).unwrap();
+            blockchain.try_add_block(block).unwrap();
+        }
+
+        let (other, _) = generate_key_pair();
+        let forged_signature = sign_transaction(other, alice.clone(), "mallory".into(), 10, 1);
+        let forged = Transaction::new(alice.clone(), "mallory".into(), 10, 1, forged_signature);
+        let skipped_nonce = transfer(key, "bob", 10, 2);
+        let timestamp = blockchain.get_chain()[2].get_timestamp() + 1;
+        for bad in [forged, paid, skipped_nonce] {
+            let block = blockchain
+                .mine_next_block(vec![coinbase("bob", 3), bad], timestamp)
+                .unwrap();
+            assert!(matches!(
+                blockchain.try_add_block(block.clone()),
+                Err(BlockchainError::BlockInvalid(_))
+            ));
+            assert!(!blockchain.add_block(block));
+        }
+        assert_eq!(blockchain.get_chain_length(), 3);
+        assert_eq!(blockchain.get_balance(&alice), 40);
+    }
 }
@@ -0,0 +1,299 @@
+use my_first_blockchain::blockchain::{Block, Blockchain};
+use my_first_blockchain::gossip::{
+    GossipHandler, GossipVerdict, SharedChain, MAX_TRANSACTION_MESSAGE_SIZE, TRANSACTIONS_TOPIC,
+};
+use my_first_blockchain::limits::{
+    ConnectionLimits, ConnectionTracker, MessageKind, RateLimitConfig, RateLimiter, TokenBucket,
+};
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::p2p::{NetworkConfig, NetworkEvent, Node};
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use libp2p::PeerId;
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        encoding::Encode,
+        transaction::Transaction,
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+    use secp256k1::SecretKey;
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+
+    fn mine(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let mut blockchain = blockchain.clone();
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(Arc::new(transactions), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.get_chain().last().unwrap().clone()
+    }
+
+    fn funded_chain(address: &str) -> Blockchain {
+        let spec = ChainSpec {
+            difficulty: 2,
+            block_reward: 1_000,
+            ..ChainSpec::default()
+        };
+        let blockchain = Blockchain::with_spec(spec);
+        let mint = Transaction::new(
+            "coinbase".to_string(),
+            address.to_string(),
+            1_000,
+            0,
+            "coinbase".into(),
+        );
+        let block = mine(&blockchain, vec![mint]);
+        let mut blockchain = blockchain;
+        assert!(blockchain.add_block(block));
+        blockchain
+    }
+
+    // `count` transfers from `sender`, with nonces from 0 on.
+    fn transfers(key: SecretKey, sender: &str, count: u64) -> Vec<Transaction> {
+        (0..count)
+            .map(|nonce| {
+                let receiver = format!("receiver-{}", nonce);
+                Transaction::new_with_fee(
+                    sender.to_string(),
+                    receiver.clone(),
+                    1,
+                    1,
+                    nonce,
+                    sign_transaction_with_fee(key, sender.to_string(), receiver, 1, 1, nonce),
+                )
+            })
+            .collect()
+    }
+
+    fn shared(blockchain: &Blockchain) -> SharedChain {
+        SharedChain::new(blockchain.clone(), Mempool::new(MempoolConfig::default()))
+    }
+
+    fn loopback_config() -> NetworkConfig {
+        NetworkConfig {
+            listen_addr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            kademlia: false,
+            ..NetworkConfig::default()
+        }
+    }
+
+    // Waits up to ten seconds for an event matching `predicate`.
+    async fn wait_for(node: &mut Node, predicate: impl Fn(&NetworkEvent) -> bool) -> NetworkEvent {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let event = node.next_event().await.expect("node stopped");
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for a network event")
+    }
+
+    #[test]
+    fn test_token_bucket_allows_bursts_then_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(3, 2.0, start);
+        for _ in 0..3 {
+            assert!(bucket.try_take(start));
+        }
+        assert!(!bucket.try_take(start));
+        // Two tokens a second, so one after half a second.
+        assert!(!bucket.try_take(start + Duration::from_millis(400)));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+        // Never more than the burst, however long the wait.
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.try_take(later));
+        }
+        assert!(!bucket.try_take(later));
+
+        let config = RateLimitConfig {
+            transaction_burst: 1,
+            block_burst: 1,
+            ..RateLimitConfig::default()
+        };
+        let mut limiter = RateLimiter::new(config);
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        assert!(limiter.allow(peer, MessageKind::Transaction, start));
+        assert!(!limiter.allow(peer, MessageKind::Transaction, start));
+        // Kinds and peers have buckets of their own.
+        assert!(limiter.allow(peer, MessageKind::Block, start));
+        assert!(limiter.allow(other, MessageKind::Transaction, start));
+        limiter.remove_peer(&peer);
+        assert!(limiter.allow(peer, MessageKind::Transaction, start));
+    }
+
+    #[test]
+    fn test_least_useful_inbound_peer_is_picked_for_eviction() {
+        let start = Instant::now();
+        let (old, useful, new, outbound) = (
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+        );
+        let mut tracker = ConnectionTracker::new();
+        tracker.add(old, true, start);
+        tracker.add(useful, true, start + Duration::from_secs(1));
+        tracker.add(new, true, start + Duration::from_secs(2));
+        tracker.add(outbound, false, start);
+        tracker.mark_useful(&useful);
+        assert_eq!(tracker.count(true), 3);
+        assert_eq!(tracker.count(false), 1);
+
+        // Of the two that never sent anything, the newer one goes first.
+        let neutral = |_: &PeerId| 0;
+        assert_eq!(
+            tracker.least_useful_inbound(&PeerId::random(), neutral),
+            Some(new)
+        );
+        assert_eq!(tracker.least_useful_inbound(&new, neutral), Some(old));
+        // A bad score outweighs usefulness.
+        let score = |peer: &PeerId| if *peer == useful { -50 } else { 0 };
+        assert_eq!(
+            tracker.least_useful_inbound(&PeerId::random(), score),
+            Some(useful)
+        );
+
+        tracker.remove(&old);
+        tracker.remove(&new);
+        assert_eq!(tracker.least_useful_inbound(&useful, neutral), None);
+    }
+
+    #[test]
+    fn test_oversized_transactions_are_rejected_unread() {
+        let mut handler = GossipHandler::new(shared(&Blockchain::new()));
+        let verdict = handler.handle_transaction(&vec![0; MAX_TRANSACTION_MESSAGE_SIZE + 1]);
+        assert!(matches!(verdict, GossipVerdict::Reject(reason) if reason.contains("bytes")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transactions_beyond_the_rate_limit_are_dropped() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender);
+        let limited = shared(&base);
+        let config = NetworkConfig {
+            rate_limits: RateLimitConfig {
+                transactions_per_second: 0.01,
+                transaction_burst: 2,
+                ..RateLimitConfig::default()
+            },
+            ..loopback_config()
+        };
+        let mut node = Node::start(config, limited.clone()).await.unwrap();
+        let node_id = node.peer_id();
+        let mut flooder = Node::start(loopback_config(), shared(&base)).await.unwrap();
+        let flooder_id = flooder.peer_id();
+        flooder
+            .dial(node.listen_addrs().await[0].clone())
+            .await
+            .unwrap();
+        wait_for(&mut flooder, |e| {
+            matches!(e, NetworkEvent::Subscribed { peer, topic } if *peer == node_id && topic == TRANSACTIONS_TOPIC)
+        })
+        .await;
+
+        for transaction in transfers(key, &sender, 5) {
+            flooder
+                .publish(TRANSACTIONS_TOPIC, transaction.encode())
+                .await
+                .unwrap();
+        }
+        let mut accepted = 0;
+        let mut limited_count = 0;
+        while accepted + limited_count < 5 {
+            match wait_for(&mut node, |e| {
+                matches!(
+                    e,
+                    NetworkEvent::TransactionAccepted { .. } | NetworkEvent::RateLimited { .. }
+                )
+            })
+            .await
+            {
+                NetworkEvent::TransactionAccepted { source, .. } => {
+                    assert_eq!(source, flooder_id);
+                    accepted += 1;
+                }
+                event => {
+                    assert_eq!(
+                        event,
+                        NetworkEvent::RateLimited {
+                            source: flooder_id,
+                            topic: TRANSACTIONS_TOPIC.to_string(),
+                        }
+                    );
+                    limited_count += 1;
+                }
+            }
+        }
+        assert_eq!((accepted, limited_count), (2, 3));
+        assert_eq!(limited.mempool.lock().unwrap().len(), 2);
+
+        node.shutdown().await;
+        flooder.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_full_node_evicts_its_least_useful_peer() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let base = funded_chain(&sender);
+        let config = NetworkConfig {
+            connection_limits: ConnectionLimits {
+                max_inbound: 2,
+                ..ConnectionLimits::default()
+            },
+            ..loopback_config()
+        };
+        let mut hub = Node::start(config, shared(&base)).await.unwrap();
+        let hub_id = hub.peer_id();
+        let hub_addr = hub.listen_addrs().await[0].clone();
+
+        let mut useful = Node::start(loopback_config(), shared(&base)).await.unwrap();
+        let useful_id = useful.peer_id();
+        useful.dial(hub_addr.clone()).await.unwrap();
+        wait_for(&mut useful, |e| {
+            matches!(e, NetworkEvent::Subscribed { peer, topic } if *peer == hub_id && topic == TRANSACTIONS_TOPIC)
+        })
+        .await;
+        let idle = Node::start(loopback_config(), shared(&base)).await.unwrap();
+        let idle_id = idle.peer_id();
+        idle.dial(hub_addr.clone()).await.unwrap();
+        wait_for(&mut hub, |e| *e == NetworkEvent::PeerConnected(idle_id)).await;
+
+        let transaction = transfers(key, &sender, 1).remove(0);
+        useful.publish_transaction(&transaction).await.unwrap();
+        wait_for(&mut hub, |e| {
+            matches!(e, NetworkEvent::TransactionAccepted { source, .. } if *source == useful_id)
+        })
+        .await;
+
+        // All slots are taken, so the idle peer makes room.
+        let newcomer = Node::start(loopback_config(), shared(&base)).await.unwrap();
+        let newcomer_id = newcomer.peer_id();
+        newcomer.dial(hub_addr).await.unwrap();
+        wait_for(&mut hub, |e| *e == NetworkEvent::PeerEvicted(idle_id)).await;
+        wait_for(&mut hub, |e| *e == NetworkEvent::PeerDisconnected(idle_id)).await;
+        sleep(Duration::from_millis(200)).await;
+        let mut connected = hub.connected_peers().await;
+        connected.sort();
+        let mut expected = vec![useful_id, newcomer_id];
+        expected.sort();
+        assert_eq!(connected, expected);
+
+        hub.shutdown().await;
+        useful.shutdown().await;
+        idle.shutdown().await;
+        newcomer.shutdown().await;
+    }
+}
@@ -0,0 +1,348 @@
+use my_first_blockchain::blockchain::Blockchain;
+use my_first_blockchain::events::{EventBus, NodeEvent};
+use my_first_blockchain::mempool::{
+    AddOutcome, Mempool, MempoolConfig, MempoolError, MempoolEvent,
+};
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        transaction::Transaction,
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+    use secp256k1::SecretKey;
+
+    use super::*;
+
+    fn funded_chain(address: &str, amount: u64) -> Blockchain {
+        let blockchain = Blockchain::with_spec(ChainSpec {
+            block_reward: amount,
+            ..ChainSpec::default()
+        });
+        mint(blockchain, address, amount)
+    }
+
+    // Mines a block paying `amount` to `address`.
+    fn mint(blockchain: Blockchain, address: &str, amount: u64) -> Blockchain {
+        let mut blockchain = blockchain;
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        let coinbase = vec![Transaction::new(
+            "coinbase".to_string(),
+            address.to_string(),
+            amount,
+            blockchain.get_chain_length() as u64,
+            "coinbase".into(),
+        )];
+        blockchain.mine_block(Arc::new(coinbase), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.clone()
+    }
+
+    // Reward paid to "miner" for the block at `height`.
+    fn reward(height: u64) -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            "miner".to_string(),
+            1,
+            height,
+            "coinbase".into(),
+        )
+    }
+
+    fn signed(key: SecretKey, sender: &str, amount: u64, fee: u64, nonce: u64) -> Transaction {
+        let receiver = "receiver".to_string();
+        Transaction::new_with_fee(
+            sender.to_string(),
+            receiver.clone(),
+            amount,
+            fee,
+            nonce,
+            sign_transaction_with_fee(key, sender.to_string(), receiver, amount, fee, nonce),
+        )
+    }
+
+    #[test]
+    fn test_admission_validates_signature_nonce_and_balance() {
+        let (prikey, pubkey) = generate_key_pair();
+        let (other_key, _) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let blockchain = funded_chain(&sender, 100);
+        let state = blockchain.get_state();
+        let mut mempool = Mempool::default();
+
+        let forged = signed(other_key, &sender, 10, 1, 0);
+        assert_eq!(
+            mempool.add_transaction(forged, state),
+            Err(MempoolError::InvalidSignature)
+        );
+
+        let too_expensive = signed(prikey, &sender, 100, 1, 0);
+        assert!(matches!(
+            mempool.add_transaction(too_expensive, state),
+            Err(MempoolError::InsufficientBalance { .. })
+        ));
+
+        let tx = signed(prikey, &sender, 10, 1, 0);
+        assert_eq!(
+            mempool.add_transaction(tx.clone(), state),
+            Ok(AddOutcome::Pending)
+        );
+        assert_eq!(
+            mempool.add_transaction(tx, state),
+            Err(MempoolError::AlreadyKnown)
+        );
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_future_nonces_are_queued_until_gap_is_filled() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let blockchain = funded_chain(&sender, 100);
+        let state = blockchain.get_state();
+        let mut mempool = Mempool::default();
+
+        assert_eq!(
+            mempool.add_transaction(signed(prikey, &sender, 10, 1, 1), state),
+            Ok(AddOutcome::Queued)
+        );
+        assert!(mempool.pending(state).is_empty());
+        assert_eq!(mempool.queued(state).len(), 1);
+
+        assert_eq!(
+            mempool.add_transaction(signed(prikey, &sender, 10, 1, 0), state),
+            Ok(AddOutcome::Pending)
+        );
+        let pending = mempool.pending(state);
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].get_nonce(), 0);
+        assert_eq!(pending[1].get_nonce(), 1);
+        assert!(mempool.queued(state).is_empty());
+    }
+
+    #[test]
+    fn test_selection_orders_by_fee_rate_and_eviction_drops_cheapest() {
+        let (key1, pubkey1) = generate_key_pair();
+        let (key2, pubkey2) = generate_key_pair();
+        let (key3, pubkey3) = generate_key_pair();
+        let sender1 = public_key_to_address(&pubkey1);
+        let sender2 = public_key_to_address(&pubkey2);
+        let sender3 = public_key_to_address(&pubkey3);
+
+        let blockchain = funded_chain(&sender1, 100);
+        let blockchain = mint(mint(blockchain, &sender2, 100), &sender3, 100);
+        let state = blockchain.get_state();
+
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_transactions: 2,
+            ..MempoolConfig::default()
+        });
+        mempool
+            .add_transaction(signed(key1, &sender1, 10, 5, 0), state)
+            .unwrap();
+        mempool
+            .add_transaction(signed(key2, &sender2, 10, 50, 0), state)
+            .unwrap();
+
+        let selected = mempool.select_transactions(state, 10);
+        assert_eq!(selected[0].get_sender(), sender2);
+        assert_eq!(selected[1].get_sender(), sender1);
+
+        assert_eq!(
+            mempool.add_transaction(signed(key3, &sender3, 10, 1, 0), state),
+            Err(MempoolError::MempoolFull)
+        );
+        mempool
+            .add_transaction(signed(key3, &sender3, 10, 20, 0), state)
+            .unwrap();
+        assert_eq!(mempool.len(), 2);
+        assert!(mempool.get(&sender1, 0).is_none());
+    }
+
+    #[test]
+    fn test_included_and_conflicting_transactions_are_dropped() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let mut blockchain = funded_chain(&sender, 100);
+        let mut mempool = Mempool::default();
+
+        let included = signed(prikey, &sender, 10, 1, 0);
+        let follow_up = signed(prikey, &sender, 10, 1, 1);
+        mempool
+            .add_transaction(included.clone(), blockchain.get_state())
+            .unwrap();
+        mempool
+            .add_transaction(follow_up.clone(), blockchain.get_state())
+            .unwrap();
+
+        // A competing transaction with nonce 1 gets mined elsewhere.
+        let conflicting = signed(prikey, &sender, 20, 1, 1);
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(
+            Arc::new(vec![reward(2), included, conflicting]),
+            Arc::clone(&arc_blockchain),
+        );
+        let blockchain = arc_blockchain.lock().unwrap();
+        let block = blockchain.get_chain().last().unwrap();
+
+        mempool.on_block_added(block, blockchain.get_state());
+        assert!(mempool.is_empty());
+        assert!(!mempool.contains(&follow_up));
+    }
+
+    #[test]
+    fn test_replace_by_fee_requires_bump() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let blockchain = funded_chain(&sender, 1000);
+        let state = blockchain.get_state();
+        let mut mempool = Mempool::new(MempoolConfig {
+            replace_bump_percent: 10,
+            ..MempoolConfig::default()
+        });
+
+        let original = signed(prikey, &sender, 10, 100, 0);
+        mempool.add_transaction(original.clone(), state).unwrap();
+        let bus = EventBus::default();
+        mempool.set_event_bus(bus.clone());
+        let mut events = bus.subscribe();
+
+        // Just below the 10% bump
+        assert_eq!(
+            mempool.add_transaction(signed(prikey, &sender, 10, 109, 0), state),
+            Err(MempoolError::ReplacementUnderpriced {
+                existing_fee: 100,
+                required_fee: 110,
+            })
+        );
+        assert!(mempool.contains(&original));
+
+        // Exactly at the threshold
+        let replacement = signed(prikey, &sender, 10, 110, 0);
+        assert_eq!(
+            mempool.add_transaction(replacement.clone(), state),
+            Ok(AddOutcome::Pending)
+        );
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(&replacement));
+        match events.try_recv() {
+            Ok(NodeEvent::Mempool(event)) => assert_eq!(
+                event,
+                MempoolEvent::Replaced {
+                    old: original,
+                    new: replacement,
+                }
+            ),
+            _ => panic!("replacement wasn't announced"),
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_replace_by_fee_zero_fee_and_full_pool() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let blockchain = funded_chain(&sender, 1000);
+        let state = blockchain.get_state();
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_transactions: 1,
+            replace_bump_percent: 10,
+        });
+
+        mempool
+            .add_transaction(signed(prikey, &sender, 10, 0, 0), state)
+            .unwrap();
+        // Same fee is never a replacement, even when the bump rounds to zero.
+        assert!(matches!(
+            mempool.add_transaction(signed(prikey, &sender, 20, 0, 0), state),
+            Err(MempoolError::ReplacementUnderpriced { .. })
+        ));
+        // A full pool doesn't block replacing an entry already in it.
+        assert_eq!(
+            mempool.add_transaction(signed(prikey, &sender, 10, 1, 0), state),
+            Ok(AddOutcome::Pending)
+        );
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_replacement_is_not_pinned_by_descendants() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let blockchain = funded_chain(&sender, 100);
+        let state = blockchain.get_state();
+        let mut mempool = Mempool::default();
+
+        for nonce in 0..3 {
+            mempool
+                .add_transaction(signed(prikey, &sender, 20, 1, nonce), state)
+                .unwrap();
+        }
+
+        // Replacing the first transaction still works with later nonces
+        // queued behind it; those that are no longer affordable get dropped.
+        let replacement = signed(prikey, &sender, 70, 2, 0);
+        mempool.add_transaction(replacement.clone(), state).unwrap();
+
+        let pending = mempool.pending(state);
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0], replacement);
+        assert_eq!(pending[1].get_nonce(), 1);
+        assert!(mempool.get(&sender, 2).is_none());
+    }
+
+    #[test]
+    fn test_mempool_survives_restart() {
+        let (prikey, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let mut blockchain = funded_chain(&sender, 100);
+        let path = std::env::temp_dir().join(format!("mempool-{}.json", sender));
+
+        let mut mempool = Mempool::default();
+        let first = signed(prikey, &sender, 10, 1, 0);
+        let second = signed(prikey, &sender, 10, 1, 1);
+        let queued = signed(prikey, &sender, 10, 1, 5);
+        for tx in [&first, &second, &queued] {
+            mempool
+                .add_transaction(tx.clone(), blockchain.get_state())
+                .unwrap();
+        }
+        mempool.save(&path).unwrap();
+
+        // The first transaction gets mined while the node is down.
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(
+            Arc::new(vec![reward(2), first.clone()]),
+            Arc::clone(&arc_blockchain),
+        );
+        let blockchain = arc_blockchain.lock().unwrap();
+        let state = blockchain.get_state();
+
+        let restored = Mempool::load(&path, MempoolConfig::default(), state).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.len(), 2);
+        assert!(!restored.contains(&first));
+        assert_eq!(restored.pending(state), vec![second]);
+        assert_eq!(restored.queued(state), vec![queued]);
+    }
+
+    #[test]
+    fn test_mempool_load_ignores_missing_and_unknown_versions() {
+        let state = Blockchain::new().get_state().clone();
+        let path = std::env::temp_dir().join("mempool-unknown-version.json");
+        let _ = std::fs::remove_file(&path);
+
+        let mempool = Mempool::load(&path, MempoolConfig::default(), &state).unwrap();
+        assert!(mempool.is_empty());
+
+        std::fs::write(&path, r#"{"version":999,"transactions":[]}"#).unwrap();
+        let mempool = Mempool::load(&path, MempoolConfig::default(), &state).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(mempool.is_empty());
+    }
+}
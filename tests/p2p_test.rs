@@ -0,0 +1,161 @@
+use my_first_blockchain::blockchain::Blockchain;
+use my_first_blockchain::gossip::SharedChain;
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::network_behaviour::PROTOCOL_VERSION;
+use my_first_blockchain::p2p::{NetworkConfig, NetworkEvent, Node};
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    // Without the DHT the nodes stay connected the way they were dialed.
+    fn loopback_config() -> NetworkConfig {
+        NetworkConfig {
+            listen_addr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            kademlia: false,
+            ..NetworkConfig::default()
+        }
+    }
+
+    fn empty_chain() -> SharedChain {
+        SharedChain::new(Blockchain::new(), Mempool::new(MempoolConfig::default()))
+    }
+
+    // Waits up to ten seconds for an event matching `predicate`.
+    async fn wait_for(node: &mut Node, predicate: impl Fn(&NetworkEvent) -> bool) -> NetworkEvent {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let event = node.next_event().await.expect("node stopped");
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for a network event")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_nodes_connect_and_identify_over_loopback() {
+        let mut first = Node::start(loopback_config(), empty_chain()).await.unwrap();
+        let first_addr = first.listen_addrs().await[0].clone();
+        let mut second = Node::start(
+            NetworkConfig {
+                peers: vec![first_addr],
+                ..loopback_config()
+            },
+            empty_chain(),
+        )
+        .await
+        .unwrap();
+
+        let second_id = second.peer_id();
+        wait_for(&mut first, |e| *e == NetworkEvent::PeerConnected(second_id)).await;
+        let identified = wait_for(&mut second, |e| {
+            matches!(e, NetworkEvent::PeerIdentified { .. })
+        })
+        .await;
+        match identified {
+            NetworkEvent::PeerIdentified {
+                peer,
+                protocol_version,
+                ..
+            } => {
+                assert_eq!(peer, first.peer_id());
+                assert_eq!(protocol_version, PROTOCOL_VERSION);
+            }
+            _ => unreachable!(),
+        }
+        wait_for(&mut second, |e| matches!(e, NetworkEvent::Pinged { .. })).await;
+        assert_eq!(first.connected_peers().await, vec![second_id]);
+
+        second.shutdown().await;
+        wait_for(&mut first, |e| {
+            *e == NetworkEvent::PeerDisconnected(second_id)
+        })
+        .await;
+        first.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_gossip_reaches_nodes_without_a_direct_connection() {
+        // a - b - c, so anything c hears from a went through b.
+        let mut a = Node::start(loopback_config(), empty_chain()).await.unwrap();
+        let mut b = Node::start(
+            NetworkConfig {
+                peers: a.listen_addrs().await,
+                ..loopback_config()
+            },
+            empty_chain(),
+        )
+        .await
+        .unwrap();
+        let mut c = Node::start(
+            NetworkConfig {
+                peers: b.listen_addrs().await,
+                ..loopback_config()
+            },
+            empty_chain(),
+        )
+        .await
+        .unwrap();
+        for node in [&a, &b, &c] {
+            node.subscribe("news").await.unwrap();
+        }
+        let (a_id, b_id, c_id) = (a.peer_id(), b.peer_id(), c.peer_id());
+        wait_for(
+            &mut a,
+            |e| matches!(e, NetworkEvent::Subscribed { peer, .. } if *peer == b_id),
+        )
+        .await;
+        wait_for(
+            &mut b,
+            |e| matches!(e, NetworkEvent::Subscribed { peer, .. } if *peer == c_id),
+        )
+        .await;
+        assert!(!c.connected_peers().await.contains(&a_id));
+
+        // The mesh forms on the next heartbeat, so keep publishing until c
+        // hears about it.
+        let received = timeout(Duration::from_secs(10), async {
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                a.publish("news", format!("hello {}", attempt).into_bytes())
+                    .await
+                    .unwrap();
+                let heard = timeout(Duration::from_millis(500), async {
+                    wait_for(&mut c, |e| matches!(e, NetworkEvent::Message { .. })).await
+                })
+                .await;
+                if let Ok(event) = heard {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("message never reached c");
+
+        match received {
+            NetworkEvent::Message {
+                source,
+                topic,
+                data,
+            } => {
+                assert_eq!(source, b_id);
+                assert_eq!(topic, "news");
+                assert!(data.starts_with(b"hello"));
+            }
+            _ => unreachable!(),
+        }
+
+        for node in [a, b, c] {
+            node.shutdown().await;
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use my_first_blockchain::blockchain::{Blockchain, BlockchainError, PruningMode};
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        storage::{BlockStore, FileBlockStore},
+        transaction::Transaction,
+    };
+
+    use super::*;
+
+    fn coinbase(receiver: &str, amount: u64) -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            receiver.to_string(),
+            amount,
+            0,
+            "coinbase".into(),
+        )
+    }
+
+    fn mine(blockchain: Blockchain, transaction: Transaction) -> Blockchain {
+        let mut blockchain = blockchain;
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(Arc::new(vec![transaction]), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.clone()
+    }
+
+    fn fast_spec() -> ChainSpec {
+        ChainSpec {
+            difficulty: 2,
+            ..ChainSpec::default()
+        }
+    }
+
+    #[test]
+    fn test_archive_mode_keeps_every_body() {
+        let old = coinbase("alice", 1);
+        let mut blockchain = mine(Blockchain::with_spec(fast_spec()), old.clone());
+        for amount in 2..6 {
+            blockchain = mine(blockchain, coinbase("alice", amount));
+        }
+
+        assert_eq!(blockchain.get_pruning(), PruningMode::Archive);
+        assert_eq!(blockchain.get_pruned_height(), None);
+        assert!(blockchain.get_block(0).is_ok());
+        assert!(blockchain.get_transaction_by_id(&old.id()).is_ok());
+    }
+
+    #[test]
+    fn test_pruned_mode_reports_pruned_data() {
+        let old = coinbase("alice", 1);
+        let recent = coinbase("bob", 7);
+        let mut blockchain = Blockchain::with_spec(fast_spec());
+        blockchain.set_pruning(PruningMode::Pruned { keep_blocks: 2 });
+
+        blockchain = mine(blockchain, old.clone());
+        for amount in 2..5 {
+            blockchain = mine(blockchain, coinbase("alice", amount));
+        }
+        blockchain = mine(blockchain, recent.clone());
+
+        // Six blocks with a window of two: bodies below height 4 are gone.
+        assert_eq!(blockchain.get_pruned_height(), Some(3));
+        assert_eq!(
+            blockchain.get_block(1).err(),
+            Some(BlockchainError::Pruned(1))
+        );
+        assert_eq!(
+            blockchain.get_transaction_by_id(&old.id()).err(),
+            Some(BlockchainError::Pruned(1))
+        );
+        assert_eq!(
+            blockchain.get_address_history("alice").err(),
+            Some(BlockchainError::Pruned(1))
+        );
+        assert!(blockchain.get_header(1).is_some());
+        assert!(blockchain.get_transaction_by_id(&recent.id()).is_ok());
+        assert_eq!(blockchain.get_address_history("bob").unwrap().len(), 1);
+
+        // The state is unaffected by dropping the bodies.
+        assert_eq!(blockchain.get_balance("alice"), 10);
+        assert!(blockchain.is_chain_valid());
+    }
+
+    #[test]
+    fn test_pruned_block_file_reopens() {
+        let path = std::env::temp_dir().join(format!("pruned-{}.dat", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = FileBlockStore::open(&path).unwrap();
+        let mut blockchain = Blockchain::open_with_spec(fast_spec(), store).unwrap();
+        blockchain.set_pruning(PruningMode::Pruned { keep_blocks: 2 });
+        for amount in 1..6 {
+            blockchain = mine(blockchain, coinbase("alice", amount));
+        }
+        let tip = blockchain.get_chain()[5].get_hash().to_string();
+        drop(blockchain);
+
+        let mut store = FileBlockStore::open(&path).unwrap();
+        assert_eq!(store.len(), 6);
+        assert!(store
+            .read_block(2)
+            .unwrap()
+            .unwrap()
+            .get_data_raw()
+            .is_empty());
+        assert_eq!(
+            store.read_block(5).unwrap().unwrap().get_data_raw().len(),
+            1
+        );
+
+        let reopened = Blockchain::open_with_spec(fast_spec(), store).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("snapshot")).unwrap();
+        std::fs::remove_file(path.with_extension("idx")).unwrap();
+
+        assert_eq!(reopened.get_chain()[5].get_hash(), tip);
+        assert_eq!(reopened.get_balance("alice"), 15);
+        assert_eq!(reopened.get_pruned_height(), Some(3));
+        assert_eq!(
+            reopened.get_block(2).err(),
+            Some(BlockchainError::Pruned(2))
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use my_first_blockchain::blockchain::Blockchain;
+use my_first_blockchain::gossip::{SharedChain, BLOCKS_TOPIC};
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::p2p::{NetworkConfig, NetworkEvent, Node};
+use my_first_blockchain::reputation::{BanList, Misbehaviour, Reputation, ReputationConfig};
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use libp2p::multiaddr::Protocol;
+    use libp2p::PeerId;
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+
+    fn empty_chain() -> SharedChain {
+        SharedChain::new(Blockchain::new(), Mempool::new(MempoolConfig::default()))
+    }
+
+    fn loopback_config() -> NetworkConfig {
+        NetworkConfig {
+            listen_addr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            kademlia: false,
+            ..NetworkConfig::default()
+        }
+    }
+
+    // Waits up to ten seconds for an event matching `predicate`.
+    async fn wait_for(node: &mut Node, predicate: impl Fn(&NetworkEvent) -> bool) -> NetworkEvent {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let event = node.next_event().await.expect("node stopped");
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for a network event")
+    }
+
+    #[test]
+    fn test_repeated_misbehaviour_gets_peer_banned() {
+        let peer = PeerId::random();
+        let mut reputation = Reputation::new(ReputationConfig::default(), BanList::new());
+        assert_eq!(reputation.get_score(&peer), 0);
+
+        for _ in 0..4 {
+            assert!(!reputation.report(peer, Misbehaviour::InvalidTransaction));
+        }
+        assert_eq!(reputation.get_score(&peer), -80);
+        assert!(!reputation.is_banned(&peer));
+
+        assert!(reputation.report(peer, Misbehaviour::InvalidTransaction));
+        assert!(reputation.is_banned(&peer));
+        assert_eq!(reputation.get_score(&peer), 0);
+
+        // Others aren't affected, and a ban can be lifted by hand.
+        let other = PeerId::random();
+        assert!(!reputation.report(other, Misbehaviour::InvalidBlock));
+        assert!(!reputation.is_banned(&other));
+        assert!(reputation.unban(&peer));
+        assert!(!reputation.is_banned(&peer));
+    }
+
+    #[test]
+    fn test_ban_list_expires_and_survives_saves() {
+        let banned = PeerId::random();
+        let expired = PeerId::random();
+        let mut list = BanList::new();
+        list.ban(banned, Duration::from_secs(3600));
+        list.ban(expired, Duration::ZERO);
+        assert!(list.is_banned(&banned));
+        assert!(!list.is_banned(&expired));
+
+        let path = std::env::temp_dir().join(format!("bans-{}.json", std::process::id()));
+        list.save(&path).unwrap();
+        let loaded = BanList::load(&path);
+        assert!(loaded.is_banned(&banned));
+        assert_eq!(loaded.get_bans().len(), 1);
+
+        assert_eq!(list.expire(), vec![expired]);
+        assert_eq!(list.get_bans(), loaded.get_bans());
+
+        std::fs::write(&path, b"not a ban list").unwrap();
+        assert!(BanList::load(&path).get_bans().is_empty());
+        std::fs::remove_file(&path).unwrap();
+        assert!(BanList::load(&path).get_bans().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_peer_sending_invalid_blocks_stays_banned_after_restart() {
+        let path = std::env::temp_dir().join(format!("ban-list-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = || NetworkConfig {
+            ban_list: Some(path.clone()),
+            ..loopback_config()
+        };
+        let mut honest = Node::start(config(), empty_chain()).await.unwrap();
+        let honest_id = honest.peer_id();
+        let mut attacker = Node::start(loopback_config(), empty_chain()).await.unwrap();
+        let attacker_id = attacker.peer_id();
+        attacker
+            .dial(honest.listen_addrs().await[0].clone())
+            .await
+            .unwrap();
+        wait_for(&mut attacker, |e| {
+            matches!(e, NetworkEvent::Subscribed { peer, topic } if *peer == honest_id && topic == BLOCKS_TOPIC)
+        })
+        .await;
+
+        // Each garbage block costs half of what it takes to get banned.
+        for data in [vec![1, 2, 3], vec![4, 5, 6]] {
+            attacker.publish(BLOCKS_TOPIC, data).await.unwrap();
+        }
+        wait_for(&mut honest, |e| *e == NetworkEvent::PeerBanned(attacker_id)).await;
+        wait_for(&mut attacker, |e| {
+            *e == NetworkEvent::PeerDisconnected(honest_id)
+        })
+        .await;
+        honest.shutdown().await;
+
+        let mut restarted = Node::start(config(), empty_chain()).await.unwrap();
+        assert_eq!(restarted.banned_peers().await[0].0, attacker_id);
+        let attacker_addr = attacker.listen_addrs().await[0]
+            .clone()
+            .with(Protocol::P2p(attacker_id));
+        assert!(restarted.dial(attacker_addr.clone()).await.is_err());
+        let _ = attacker
+            .dial(restarted.listen_addrs().await[0].clone())
+            .await;
+        sleep(Duration::from_millis(500)).await;
+        assert!(restarted.connected_peers().await.is_empty());
+
+        restarted.unban_peer(attacker_id).await.unwrap();
+        wait_for(&mut restarted, |e| {
+            *e == NetworkEvent::PeerUnbanned(attacker_id)
+        })
+        .await;
+        restarted.dial(attacker_addr).await.unwrap();
+        wait_for(&mut restarted, |e| {
+            *e == NetworkEvent::PeerConnected(attacker_id)
+        })
+        .await;
+        std::fs::remove_file(&path).unwrap();
+
+        restarted.shutdown().await;
+        attacker.shutdown().await;
+    }
+}
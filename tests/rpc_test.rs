@@ -0,0 +1,262 @@
+use my_first_blockchain::blockchain::{Block, Blockchain};
+use my_first_blockchain::gossip::SharedChain;
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::rpc::{
+    RpcError, RpcServer, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, NOT_FOUND, PARSE_ERROR,
+    TRANSACTION_REJECTED,
+};
+use my_first_blockchain::rpc_client::{RpcClient, RpcClientError};
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        transaction::Transaction,
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+    use secp256k1::SecretKey;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn mine(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let mut blockchain = blockchain.clone();
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(Arc::new(transactions), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.get_chain().last().unwrap().clone()
+    }
+
+    fn funded_chain(address: &str) -> Blockchain {
+        let spec = ChainSpec {
+            difficulty: 2,
+            block_reward: 1_000,
+            ..ChainSpec::default()
+        };
+        let blockchain = Blockchain::with_spec(spec);
+        let mint = Transaction::new(
+            "coinbase".to_string(),
+            address.to_string(),
+            1_000,
+            0,
+            "coinbase".into(),
+        );
+        let block = mine(&blockchain, vec![mint]);
+        let mut blockchain = blockchain;
+        assert!(blockchain.add_block(block));
+        blockchain
+    }
+
+    // `count` transfers from `sender`, with nonces from `first_nonce` on.
+    fn transfers(key: SecretKey, sender: &str, first_nonce: u64, count: u64) -> Vec<Transaction> {
+        (first_nonce..first_nonce + count)
+            .map(|nonce| {
+                let receiver = format!("receiver-{}", nonce);
+                Transaction::new_with_fee(
+                    sender.to_string(),
+                    receiver.clone(),
+                    10,
+                    1,
+                    nonce,
+                    sign_transaction_with_fee(key, sender.to_string(), receiver, 10, 1, nonce),
+                )
+            })
+            .collect()
+    }
+
+    // A chain with a coinbase and three transfers mined at height 2, served
+    // over RPC.
+    fn serve() -> (RpcServer, SharedChain, SecretKey, String, Vec<Transaction>) {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let mut blockchain = funded_chain(&sender);
+        let mined = transfers(key, &sender, 0, 3);
+        let reward = Transaction::new(
+            "coinbase".to_string(),
+            "miner".to_string(),
+            1,
+            2,
+            "coinbase".into(),
+        );
+        let mut body = vec![reward];
+        body.extend(mined.iter().cloned());
+        let block = mine(&blockchain, body);
+        assert!(blockchain.add_block(block));
+        let chain = SharedChain::new(blockchain, Mempool::new(MempoolConfig::default()));
+        let server = RpcServer::start("127.0.0.1:0".parse().unwrap(), chain.clone()).unwrap();
+        (server, chain, key, sender, mined)
+    }
+
+    fn rpc_error(result: Result<impl std::fmt::Debug, RpcClientError>) -> RpcError {
+        match result {
+            Err(RpcClientError::Rpc(err)) => err,
+            other => panic!("expected an RPC error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_queries_answer_from_the_chain() {
+        let (server, chain, _, sender, mined) = serve();
+        let client = RpcClient::new(server.local_addr());
+
+        let info = client.get_chain_info().unwrap();
+        let blockchain = chain.blockchain.lock().unwrap().clone();
+        assert_eq!(info.height, 2);
+        assert_eq!(info.best_hash, blockchain.get_chain()[2].get_hash());
+        assert_eq!(info.genesis_hash, blockchain.get_chain()[0].get_hash());
+        assert_eq!(info.chain_id, blockchain.get_spec().chain_id);
+        assert_eq!(
+            info.cumulative_work,
+            blockchain.get_cumulative_work().to_string()
+        );
+        assert_eq!(info.mempool_size, 0);
+
+        let block = client.get_block_by_height(2).unwrap();
+        assert_eq!(block.hash, info.best_hash);
+        assert_eq!(block.previous_hash, blockchain.get_chain()[1].get_hash());
+        assert_eq!(block.transactions.len(), 4);
+        assert_eq!(block.transactions[2].id, mined[1].id());
+        assert_eq!(client.get_block_by_hash(&block.hash).unwrap(), block);
+
+        let transaction = client.get_transaction(&mined[2].id()).unwrap();
+        assert_eq!(transaction.sender, sender);
+        assert_eq!(transaction.receiver, "receiver-2");
+        assert_eq!((transaction.amount, transaction.fee), (10, 1));
+        assert_eq!(transaction.block_hash.as_deref(), Some(block.hash.as_str()));
+        assert_eq!(
+            (transaction.height, transaction.position),
+            (Some(2), Some(3))
+        );
+
+        assert_eq!(client.get_balance(&sender).unwrap(), 1_000 - 3 * 11);
+        assert_eq!(client.get_balance("receiver-0").unwrap(), 10);
+        assert_eq!(client.get_nonce(&sender).unwrap(), 3);
+        assert_eq!(client.get_nonce("nobody").unwrap(), 0);
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_merkle_proofs_verify_against_the_block() {
+        let (server, _, _, _, mined) = serve();
+        let client = RpcClient::new(server.local_addr());
+        let block = client.get_block_by_height(2).unwrap();
+        for (position, transaction) in mined.iter().enumerate() {
+            let proof = client.get_merkle_proof(&transaction.id()).unwrap();
+            assert_eq!(proof.position, position + 1);
+            assert_eq!(proof.leaf_count, 4);
+            assert_eq!(hex::encode(proof.merkle_root), block.merkle_root);
+            assert!(proof.verify());
+
+            let mut forged = proof.clone();
+            forged.transaction_id = mined[(position + 1) % 3].id();
+            assert!(!forged.verify());
+        }
+        assert_eq!(rpc_error(client.get_merkle_proof("00")).code, NOT_FOUND);
+    }
+
+    #[test]
+    fn test_raw_transactions_land_in_the_mempool() {
+        let (server, chain, key, sender, mined) = serve();
+        let client = RpcClient::new(server.local_addr());
+
+        let transaction = transfers(key, &sender, 3, 1).remove(0);
+        let id = client.send_raw_transaction(&transaction).unwrap();
+        assert_eq!(id, transaction.id());
+        assert!(chain.mempool.lock().unwrap().contains(&transaction));
+        assert_eq!(client.get_chain_info().unwrap().mempool_size, 1);
+        // Pending transactions are found too, just without a block.
+        let pending = client.get_transaction(&id).unwrap();
+        assert_eq!(pending.nonce, 3);
+        assert_eq!(pending.block_hash, None);
+
+        let err = rpc_error(client.send_raw_transaction(&mined[0]));
+        assert_eq!(err.code, TRANSACTION_REJECTED);
+        let err = rpc_error(client.send_raw_transaction(&transaction));
+        assert_eq!(err.code, TRANSACTION_REJECTED);
+        let err = rpc_error(client.call::<String>("sendRawTransaction", json!(["zz"])));
+        assert_eq!(err.code, INVALID_PARAMS);
+        assert_eq!(chain.mempool.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_bad_requests_get_standard_errors() {
+        let (server, _, _, _, _) = serve();
+        let client = RpcClient::new(server.local_addr());
+
+        let err = rpc_error(client.call::<Value>("getBlockByNumber", json!([1])));
+        assert_eq!(err.code, METHOD_NOT_FOUND);
+        let err = rpc_error(client.call::<Value>("getBlockByHeight", json!(["two"])));
+        assert_eq!(err.code, INVALID_PARAMS);
+        let err = rpc_error(client.call::<Value>("getBalance", json!([])));
+        assert_eq!(err.code, INVALID_PARAMS);
+        assert_eq!(rpc_error(client.get_block_by_height(9)).code, NOT_FOUND);
+        assert_eq!(rpc_error(client.get_block_by_hash("abc")).code, NOT_FOUND);
+        assert_eq!(rpc_error(client.get_transaction("abc")).code, NOT_FOUND);
+        // Parameters may also be passed by name.
+        let balance: u64 = client
+            .call("getBalance", json!({ "address": "receiver-1" }))
+            .unwrap();
+        assert_eq!(balance, 10);
+
+        let response = client.post(&json!({ "method": "getNonce" })).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+        assert_eq!(response["id"], Value::Null);
+        let response = client.post(&json!("getNonce")).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+        let response = client.post(&json!([])).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+
+        // Not JSON at all.
+        let mut raw = std::net::TcpStream::connect(server.local_addr()).unwrap();
+        write!(
+            raw,
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\n{{oops"
+        )
+        .unwrap();
+        let mut response = String::new();
+        raw.read_to_string(&mut response).unwrap();
+        let body: Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["error"]["code"], PARSE_ERROR);
+    }
+
+    #[test]
+    fn test_batches_and_notifications() {
+        let (server, _, _, sender, _) = serve();
+        let client = RpcClient::new(server.local_addr());
+
+        let response = client
+            .post(&json!([
+                { "jsonrpc": "2.0", "method": "getNonce", "params": [sender], "id": 1 },
+                { "jsonrpc": "2.0", "method": "getNonce", "params": [sender] },
+                { "jsonrpc": "2.0", "method": "nope", "id": "two" },
+                { "jsonrpc": "2.0", "method": "getBalance", "params": ["receiver-0"], "id": null },
+            ]))
+            .unwrap();
+        // The notification gets no response; an explicit null ID does.
+        assert_eq!(
+            response,
+            json!([
+                { "jsonrpc": "2.0", "result": 3, "id": 1 },
+                { "jsonrpc": "2.0", "error": { "code": METHOD_NOT_FOUND, "message": "unknown method nope" }, "id": "two" },
+                { "jsonrpc": "2.0", "result": 10, "id": null },
+            ])
+        );
+
+        // Nothing to answer at all.
+        let notifications = json!([{ "jsonrpc": "2.0", "method": "getNonce", "params": [sender] }]);
+        assert_eq!(client.post(&notifications), Err(RpcClientError::Http(204)));
+    }
+
+    #[test]
+    fn test_server_only_listens_on_loopback() {
+        let chain = SharedChain::new(Blockchain::new(), Mempool::new(MempoolConfig::default()));
+        match RpcServer::start("0.0.0.0:0".parse().unwrap(), chain) {
+            Err(err) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+            Ok(_) => panic!("served RPC on a public address"),
+        }
+    }
+}
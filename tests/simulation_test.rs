@@ -0,0 +1,151 @@
+use my_first_blockchain::simulation::{SimConfig, SimEvent, Simulation};
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+
+    // Every node mines at random times over `blocks` seconds.
+    fn mine_randomly(sim: &mut Simulation, blocks: u64) {
+        let nodes = sim.get_node_count() as u64;
+        for second in 0..blocks {
+            let node = sim.rng().next_below(nodes) as usize;
+            let jitter = sim.rng().next_below(1000);
+            sim.schedule_mining(node, Duration::from_millis(second * 1000 + jitter));
+        }
+    }
+
+    fn reorganizations(sim: &Simulation, node: usize) -> Vec<usize> {
+        sim.get_trace()
+            .iter()
+            .filter_map(|event| match event {
+                SimEvent::Reorganized {
+                    node: n,
+                    disconnected,
+                    ..
+                } if *n == node => Some(*disconnected),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_seed_plays_out_the_same() {
+        let run = |seed: u64| {
+            let mut sim = Simulation::new(SimConfig {
+                nodes: 5,
+                seed,
+                loss_rate: 0.1,
+                max_latency: Duration::from_millis(1500),
+                ..SimConfig::default()
+            });
+            mine_randomly(&mut sim, 15);
+            sim.run_until_idle();
+            (sim.get_trace().to_vec(), sim.get_tips())
+        };
+        let (trace, tips) = run(7);
+        assert!(trace.len() > 15);
+        assert_eq!(run(7), (trace.clone(), tips));
+        assert_ne!(run(8).0, trace);
+    }
+
+    #[test]
+    fn test_simultaneous_blocks_fork_and_resolve() {
+        let mut sim = Simulation::new(SimConfig::default());
+        // Both mine before hearing of the other's block.
+        let first = sim.mine(0);
+        let second = sim.mine(3);
+        assert_ne!(first.get_hash(), second.get_hash());
+        sim.run_until_idle();
+        assert!(!sim.is_converged());
+        assert_eq!(sim.get_tips()[0], first.get_hash());
+        assert_eq!(sim.get_tips()[3], second.get_hash());
+
+        // The next block decides; everyone on the other branch switches.
+        let decider = sim.mine(3);
+        sim.run_until_idle();
+        assert!(sim.is_converged());
+        assert_eq!(sim.get_tips()[0], decider.get_hash());
+        assert_eq!(reorganizations(&sim, 0), vec![1]);
+        assert!(reorganizations(&sim, 3).is_empty());
+        for node in 0..4 {
+            let blockchain = sim.get_blockchain(node);
+            assert_eq!(blockchain.get_chain_length(), 3);
+            assert_eq!(blockchain.get_chain()[1].get_hash(), second.get_hash());
+            assert_eq!(blockchain.get_balance("miner-3"), 100);
+            assert_eq!(blockchain.get_balance("miner-0"), 0);
+        }
+    }
+
+    #[test]
+    fn test_partitioned_network_heals_onto_the_longer_branch() {
+        let mut sim = Simulation::new(SimConfig {
+            nodes: 6,
+            seed: 42,
+            ..SimConfig::default()
+        });
+        sim.mine(0);
+        sim.run_until_idle();
+        assert!(sim.is_converged());
+
+        sim.partition(&[vec![0, 1, 2], vec![3, 4, 5]]);
+        for i in 0..4 {
+            sim.schedule_mining([0, 1, 2, 0][i], Duration::from_secs(i as u64 + 1));
+        }
+        for i in 0..2 {
+            sim.schedule_mining([4, 5][i], Duration::from_secs(i as u64 + 1));
+        }
+        sim.run_until_idle();
+        let tips = sim.get_tips();
+        assert!(tips[..3].iter().all(|tip| *tip == tips[0]));
+        assert!(tips[3..].iter().all(|tip| *tip == tips[3]));
+        assert_ne!(tips[0], tips[3]);
+        assert!(sim
+            .get_trace()
+            .iter()
+            .any(|event| matches!(event, SimEvent::Dropped { from: 0, to: 3, .. })));
+
+        sim.heal();
+        sim.run_until_idle();
+        assert!(sim.is_converged());
+        assert_eq!(sim.get_tips()[0], tips[0]);
+        for node in 0..6 {
+            assert_eq!(sim.get_blockchain(node).get_chain_length(), 6);
+            assert!(sim.get_blockchain(node).is_chain_valid());
+        }
+        for node in 3..6 {
+            assert_eq!(reorganizations(&sim, node), vec![2]);
+        }
+    }
+
+    #[test]
+    fn test_nodes_catch_up_after_lossy_periods() {
+        let mut sim = Simulation::new(SimConfig {
+            nodes: 8,
+            seed: 3,
+            loss_rate: 0.4,
+            ..SimConfig::default()
+        });
+        mine_randomly(&mut sim, 20);
+        sim.run_until_idle();
+        assert!(sim
+            .get_trace()
+            .iter()
+            .any(|event| matches!(event, SimEvent::Dropped { .. })));
+
+        // Once the links are good again, a single block brings everyone to
+        // the longest chain, fetching whatever they missed.
+        sim.set_loss_rate(0.0);
+        let longest = (0..8)
+            .max_by_key(|node| sim.get_blockchain(*node).get_chain_length())
+            .unwrap();
+        let length = sim.get_blockchain(longest).get_chain_length();
+        sim.mine(longest);
+        sim.run_until_idle();
+        assert!(sim.is_converged());
+        assert_eq!(sim.get_blockchain(0).get_chain_length(), length + 1);
+        assert!(sim.get_time() >= Duration::from_secs(19));
+    }
+}
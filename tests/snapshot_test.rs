@@ -0,0 +1,141 @@
+use my_first_blockchain::blockchain::Blockchain;
+use my_first_blockchain::snapshot::StateSnapshot;
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        storage::{FileBlockStore, MemoryBlockStore},
+        transaction::Transaction,
+    };
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", name, std::process::id()))
+    }
+
+    fn mine(blockchain: Blockchain, receiver: &str, amount: u64) -> Blockchain {
+        let mut blockchain = blockchain;
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        let data = vec![Transaction::new(
+            "coinbase".to_string(),
+            receiver.to_string(),
+            amount,
+            0,
+            "coinbase".into(),
+        )];
+        blockchain.mine_block(Arc::new(data), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.clone()
+    }
+
+    #[test]
+    fn test_headers_commit_to_state() {
+        let blockchain = mine(mine(Blockchain::new(), "alice", 10), "bob", 5);
+
+        let tip = blockchain.get_header(2).unwrap();
+        assert_eq!(*tip.get_state_root(), blockchain.get_state().state_root());
+        assert_ne!(
+            blockchain.get_header(1).unwrap().get_state_root(),
+            tip.get_state_root()
+        );
+        assert!(blockchain.snapshot().verify(tip).is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_file_round_trip_and_tampering() {
+        let blockchain = mine(Blockchain::new(), "alice", 10);
+        let snapshot = blockchain.snapshot();
+        let path = temp_path("snapshot.bin");
+
+        snapshot.save(&path).unwrap();
+        let loaded = StateSnapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+        assert!(loaded.verify(blockchain.get_header(1).unwrap()).is_ok());
+
+        // A snapshot claiming another balance no longer matches the header.
+        let mut state = snapshot.get_state().clone();
+        state.get_account_mut("alice").credit(1_000).unwrap();
+        let forged = StateSnapshot::new(1, snapshot.get_block_hash().to_string(), state);
+        assert!(forged.verify(blockchain.get_header(1).unwrap()).is_err());
+        assert!(snapshot.verify(blockchain.get_header(0).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_bootstrap_from_snapshot_and_later_blocks() {
+        let source = mine(mine(Blockchain::new(), "alice", 10), "bob", 5);
+        let snapshot = source.snapshot();
+        let headers = source.get_headers(0, 3);
+        let source = mine(mine(source, "alice", 7), "carol", 3);
+
+        let path = temp_path("bootstrap.dat");
+        let _ = std::fs::remove_file(&path);
+        let store = FileBlockStore::open(&path).unwrap();
+        let mut node =
+            Blockchain::bootstrap(ChainSpec::default(), snapshot, headers, store).unwrap();
+        for block in &source.get_chain()[3..] {
+            assert!(node.add_block(block.clone()));
+        }
+        assert_eq!(node.get_state(), source.get_state());
+        assert_eq!(node.get_balance("alice"), 17);
+
+        // The snapshot is kept with the blocks so the node restarts without it.
+        drop(node);
+        let reopened = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("snapshot")).unwrap();
+        std::fs::remove_file(path.with_extension("idx")).unwrap();
+        assert_eq!(reopened.get_chain_length(), 5);
+        assert_eq!(reopened.get_state(), source.get_state());
+    }
+
+    #[test]
+    fn test_bootstrap_rejects_mismatched_snapshot() {
+        let source = mine(mine(Blockchain::new(), "alice", 10), "bob", 5);
+        let snapshot = source.snapshot();
+
+        let short = Blockchain::bootstrap(
+            ChainSpec::default(),
+            snapshot.clone(),
+            source.get_headers(0, 2),
+            MemoryBlockStore::new(),
+        );
+        assert!(short.is_err());
+
+        let mut state = snapshot.get_state().clone();
+        state.get_account_mut("mallory").credit(50).unwrap();
+        let forged = StateSnapshot::new(2, snapshot.get_block_hash().to_string(), state);
+        let result = Blockchain::bootstrap(
+            ChainSpec::default(),
+            forged,
+            source.get_headers(0, 3),
+            MemoryBlockStore::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_periodic_snapshots() {
+        let dir = temp_path("snapshots");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut blockchain = Blockchain::new();
+        blockchain.enable_snapshots(&dir, 2);
+
+        let blockchain = mine(mine(mine(blockchain, "alice", 1), "alice", 2), "alice", 3);
+
+        assert!(!Blockchain::snapshot_file(&dir, 1).exists());
+        assert!(!Blockchain::snapshot_file(&dir, 3).exists());
+        let snapshot = StateSnapshot::load(&Blockchain::snapshot_file(&dir, 2)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(snapshot.verify(blockchain.get_header(2).unwrap()).is_ok());
+        assert_eq!(snapshot.get_state().get_balance("alice"), 3);
+    }
+}
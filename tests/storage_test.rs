@@ -0,0 +1,187 @@
+use my_first_blockchain::blockchain::{Blockchain, BlockchainError};
+use my_first_blockchain::chain_spec::ChainSpec;
+use my_first_blockchain::storage::{BlockStore, FileBlockStore, MemoryBlockStore, StorageError};
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        fs::OpenOptions,
+        io::Write,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use my_first_blockchain::transaction::Transaction;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.dat", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn mine(blockchain: Blockchain, amount: u64) -> Blockchain {
+        let mut blockchain = blockchain;
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        let data = vec![Transaction::new(
+            "coinbase".to_string(),
+            "miner".to_string(),
+            amount,
+            0,
+            "coinbase".into(),
+        )];
+        blockchain.mine_block(Arc::new(data), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.clone()
+    }
+
+    #[test]
+    fn test_blockchain_reloads_from_file_store() {
+        let path = temp_path("reload");
+
+        let blockchain = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        let genesis_hash = blockchain.get_chain()[0].get_hash().to_string();
+        let blockchain = mine(mine(blockchain, 10), 15);
+        assert_eq!(blockchain.get_chain_length(), 3);
+
+        let reopened = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reopened.get_chain_length(), 3);
+        assert_eq!(reopened.get_chain()[0].get_hash(), genesis_hash);
+        assert_eq!(
+            reopened.get_chain()[2].get_hash(),
+            blockchain.get_chain()[2].get_hash()
+        );
+        assert_eq!(reopened.get_balance("miner"), 25);
+        assert!(reopened.is_chain_valid());
+    }
+
+    #[test]
+    fn test_store_of_another_genesis_is_refused() {
+        let path = temp_path("genesis");
+        mine(
+            Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap(),
+            10,
+        );
+
+        let other = ChainSpec {
+            genesis_timestamp: 1,
+            ..ChainSpec::default()
+        };
+        let result = Blockchain::open_with_spec(other, FileBlockStore::open(&path).unwrap());
+        assert!(matches!(result, Err(BlockchainError::BlockInvalid(_))));
+        assert!(Blockchain::open(FileBlockStore::open(&path).unwrap()).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_write_is_truncated_on_open() {
+        let path = temp_path("torn");
+
+        let blockchain = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        mine(blockchain, 10);
+        let good_len = std::fs::metadata(&path).unwrap().len();
+
+        // Simulate a crash half way through writing the next record.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2, 3, 4, b'{', b'"'])
+            .unwrap();
+        drop(file);
+
+        let mut store = FileBlockStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), good_len);
+        assert_eq!(store.load_blocks().unwrap().len(), 2);
+
+        // The store keeps working after recovery.
+        let blockchain = mine(Blockchain::open(store).unwrap(), 5);
+        assert_eq!(blockchain.get_chain_length(), 3);
+        let store = FileBlockStore::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(store.len(), 3);
+    }
+
+    #[test]
+    fn test_corrupted_record_fails_checksum() {
+        let path = temp_path("corrupt");
+
+        let blockchain = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        mine(blockchain, 10);
+
+        // Flip a byte inside the payload of the last block.
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 2;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let store = FileBlockStore::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_damage_before_the_last_record_is_reported() {
+        let path = temp_path("damaged");
+
+        let blockchain = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        mine(mine(blockchain, 10), 15);
+        let good = std::fs::read(&path).unwrap();
+
+        // A length claiming more than the file holds is a torn tail, not
+        // something to allocate for.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4])
+            .unwrap();
+        drop(file);
+        assert_eq!(FileBlockStore::open(&path).unwrap().len(), 3);
+        assert_eq!(std::fs::read(&path).unwrap(), good);
+
+        // A byte flipped in the genesis payload is left alone for the
+        // operator to look at.
+        let mut bytes = good.clone();
+        bytes[10] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        let opened = FileBlockStore::open(&path);
+        let kept = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(opened, Err(StorageError::Corrupted(_))));
+        assert_eq!(kept, bytes);
+    }
+
+    #[test]
+    fn test_memory_store_receives_genesis() {
+        let blockchain = Blockchain::open(MemoryBlockStore::new()).unwrap();
+        assert_eq!(blockchain.get_chain_length(), 1);
+    }
+
+    #[test]
+    fn test_headers_are_read_without_bodies() {
+        let path = temp_path("headers");
+
+        let blockchain = Blockchain::open(FileBlockStore::open(&path).unwrap()).unwrap();
+        let blockchain = mine(blockchain, 10);
+
+        let mut store = FileBlockStore::open(&path).unwrap();
+        let headers = store.load_headers().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(&headers[1], blockchain.get_chain()[1].get_header());
+        assert_eq!(
+            headers[1].get_previous_hash(),
+            blockchain.get_chain()[0].get_hash()
+        );
+        assert_eq!(blockchain.get_headers(1, 10), headers[1..].to_vec());
+        assert_eq!(
+            blockchain
+                .get_block_body(blockchain.get_chain()[1].get_hash())
+                .unwrap()
+                .get_transactions()
+                .len(),
+            1
+        );
+    }
+}
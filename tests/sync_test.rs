@@ -0,0 +1,298 @@
+use my_first_blockchain::blockchain::{Blockchain, PruningMode};
+use my_first_blockchain::gossip::SharedChain;
+use my_first_blockchain::handshake::{self, HandshakeCodec, Status};
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::p2p::{NetworkConfig, NetworkEvent, Node};
+use my_first_blockchain::sync::{self, SyncCodec, SyncConfig, SyncEvent};
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
+    use libp2p::{noise, request_response, tcp, yamux, Multiaddr, SwarmBuilder};
+    use my_first_blockchain::{chain_spec::ChainSpec, transaction::Transaction};
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn fast_spec() -> ChainSpec {
+        ChainSpec {
+            difficulty: 2,
+            ..ChainSpec::default()
+        }
+    }
+
+    fn chain_of(length: u64, pruning: PruningMode) -> Blockchain {
+        let mut blockchain = Blockchain::with_spec(fast_spec());
+        blockchain.set_pruning(pruning);
+        for amount in 1..length {
+            let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+            let coinbase = Transaction::new(
+                "coinbase".to_string(),
+                "alice".to_string(),
+                amount,
+                0,
+                "coinbase".into(),
+            );
+            blockchain.mine_block(Arc::new(vec![coinbase]), Arc::clone(&arc_blockchain));
+            let mined = arc_blockchain.lock().unwrap().clone();
+            blockchain = mined;
+        }
+        blockchain
+    }
+
+    // Mines `count` blocks on top of `blockchain` paying `miner`, so chains
+    // extended for different miners fork.
+    fn extend(blockchain: &mut Blockchain, miner: &str, count: usize) {
+        for _ in 0..count {
+            let transactions = blockchain.block_template(&Mempool::default(), miner);
+            let timestamp = blockchain.get_chain().last().unwrap().get_timestamp() + 1;
+            let block = blockchain.mine_next_block(transactions, timestamp).unwrap();
+            blockchain.try_add_block(block).unwrap();
+        }
+    }
+
+    fn shared(blockchain: Blockchain) -> SharedChain {
+        SharedChain::new(blockchain, Mempool::new(MempoolConfig::default()))
+    }
+
+    // Small batches and a short timeout, so a short chain still takes many
+    // requests and unresponsive peers are noticed quickly.
+    fn config(peers: Vec<Multiaddr>) -> NetworkConfig {
+        NetworkConfig {
+            listen_addr: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            peers,
+            sync: SyncConfig {
+                headers_per_request: 5,
+                bodies_per_request: 2,
+                requests_per_peer: 1,
+                request_timeout: Duration::from_secs(1),
+                ..SyncConfig::default()
+            },
+            ..NetworkConfig::default()
+        }
+    }
+
+    // Waits up to twenty seconds for an event matching `predicate`.
+    async fn wait_for(node: &mut Node, predicate: impl Fn(&NetworkEvent) -> bool) -> NetworkEvent {
+        timeout(Duration::from_secs(20), async {
+            loop {
+                let event = node.next_event().await.expect("node stopped");
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for a network event")
+    }
+
+    #[derive(NetworkBehaviour)]
+    struct SilentBehaviour {
+        handshake: request_response::Behaviour<HandshakeCodec>,
+        sync: request_response::Behaviour<SyncCodec>,
+    }
+
+    // A peer that claims a long chain in its handshake but never answers a
+    // sync request.
+    async fn start_silent_peer(requests: Arc<AtomicUsize>) -> Multiaddr {
+        let mut status = Status::local(&Blockchain::with_spec(fast_spec()));
+        status.best_height = 1000;
+        status.cumulative_work = u128::MAX;
+        let mut swarm = SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .unwrap()
+            .with_behaviour(|_| SilentBehaviour {
+                handshake: handshake::behaviour(),
+                sync: sync::behaviour(&SyncConfig::default()),
+            })
+            .unwrap()
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+        swarm
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        let addr = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                break address;
+            }
+        };
+        tokio::spawn(async move {
+            let mut unanswered = Vec::new();
+            loop {
+                match swarm.select_next_some().await {
+                    SwarmEvent::Behaviour(SilentBehaviourEvent::Handshake(
+                        request_response::Event::Message {
+                            message: request_response::Message::Request { channel, .. },
+                            ..
+                        },
+                    )) => {
+                        let _ = swarm
+                            .behaviour_mut()
+                            .handshake
+                            .send_response(channel, status.clone());
+                    }
+                    SwarmEvent::Behaviour(SilentBehaviourEvent::Sync(
+                        request_response::Event::Message {
+                            message: request_response::Message::Request { channel, .. },
+                            ..
+                        },
+                    )) => {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        unanswered.push(channel);
+                    }
+                    _ => {}
+                }
+            }
+        });
+        addr
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_new_node_downloads_chain_from_several_peers() {
+        let source = chain_of(21, PruningMode::Archive);
+        let a = Node::start(config(vec![]), shared(source.clone()))
+            .await
+            .unwrap();
+        let b = Node::start(config(vec![]), shared(source.clone()))
+            .await
+            .unwrap();
+        let mut peers = a.listen_addrs().await;
+        peers.extend(b.listen_addrs().await);
+
+        let fresh = shared(Blockchain::with_spec(fast_spec()));
+        let mut node = Node::start(config(peers), fresh.clone()).await.unwrap();
+        let event = wait_for(&mut node, |e| matches!(e, NetworkEvent::Sync(_))).await;
+        assert_eq!(
+            event,
+            NetworkEvent::Sync(SyncEvent::Completed {
+                height: 20,
+                imported: 20,
+            })
+        );
+
+        {
+            let blockchain = fresh.blockchain.lock().unwrap();
+            assert_eq!(
+                blockchain.get_chain().last().unwrap().get_hash(),
+                source.get_chain().last().unwrap().get_hash()
+            );
+            assert_eq!(blockchain.get_balance("alice"), source.get_balance("alice"));
+            assert!(blockchain.is_chain_valid());
+        }
+
+        for node in [a, b, node] {
+            node.shutdown().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_moves_on_from_unresponsive_peer() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let silent = start_silent_peer(Arc::clone(&requests)).await;
+        let fresh = shared(Blockchain::with_spec(fast_spec()));
+        let mut node = Node::start(config(vec![silent]), fresh.clone())
+            .await
+            .unwrap();
+        wait_for(&mut node, |e| matches!(e, NetworkEvent::PeerConnected(_))).await;
+
+        // The silent peer is asked first, then a good peer turns up.
+        let source = chain_of(13, PruningMode::Archive);
+        let good = Node::start(config(vec![]), shared(source.clone()))
+            .await
+            .unwrap();
+        node.dial(good.listen_addrs().await[0].clone())
+            .await
+            .unwrap();
+        let event = wait_for(&mut node, |e| matches!(e, NetworkEvent::Sync(_))).await;
+        assert_eq!(
+            event,
+            NetworkEvent::Sync(SyncEvent::Completed {
+                height: 12,
+                imported: 12,
+            })
+        );
+        assert!(requests.load(Ordering::SeqCst) >= 1);
+        assert_eq!(
+            fresh
+                .blockchain
+                .lock()
+                .unwrap()
+                .get_chain()
+                .last()
+                .unwrap()
+                .get_hash(),
+            source.get_chain().last().unwrap().get_hash()
+        );
+
+        node.shutdown().await;
+        good.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_fails_when_no_peer_has_the_bodies() {
+        let source = chain_of(9, PruningMode::Pruned { keep_blocks: 2 });
+        assert!(source.get_pruned_height().is_some());
+
+        let pruned = Node::start(config(vec![]), shared(source)).await.unwrap();
+        let fresh = shared(Blockchain::with_spec(fast_spec()));
+        let mut node = Node::start(config(pruned.listen_addrs().await), fresh.clone())
+            .await
+            .unwrap();
+        let event = wait_for(&mut node, |e| matches!(e, NetworkEvent::Sync(_))).await;
+        assert!(matches!(event, NetworkEvent::Sync(SyncEvent::Failed(_))));
+        assert_eq!(fresh.blockchain.lock().unwrap().get_chain_length(), 1);
+
+        node.shutdown().await;
+        pruned.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_forked_node_switches_to_the_branch_with_more_work() {
+        let common = chain_of(6, PruningMode::Archive);
+        let mut ours = common.clone();
+        extend(&mut ours, "bob", 4);
+        let mut theirs = common;
+        extend(&mut theirs, "carol", 8);
+
+        let peer = Node::start(config(vec![]), shared(theirs.clone()))
+            .await
+            .unwrap();
+        let local = shared(ours);
+        let mut node = Node::start(config(peer.listen_addrs().await), local.clone())
+            .await
+            .unwrap();
+        let event = wait_for(&mut node, |e| matches!(e, NetworkEvent::Sync(_))).await;
+        assert_eq!(
+            event,
+            NetworkEvent::Sync(SyncEvent::Completed {
+                height: 13,
+                imported: 8,
+            })
+        );
+
+        {
+            let blockchain = local.blockchain.lock().unwrap();
+            assert_eq!(
+                blockchain.get_chain().last().unwrap().get_hash(),
+                theirs.get_chain().last().unwrap().get_hash()
+            );
+            assert_eq!(blockchain.get_balance("bob"), 0);
+            assert_eq!(blockchain.get_balance("carol"), theirs.get_balance("carol"));
+            assert!(blockchain.is_chain_valid());
+        }
+
+        node.shutdown().await;
+        peer.shutdown().await;
+    }
+}
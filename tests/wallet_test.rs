@@ -0,0 +1,59 @@
+use my_first_blockchain::wallet::{Wallet, WalletError};
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn wallet_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_keys_survive_a_save_and_sign_transfers() {
+        let path = wallet_path("wallet-keys");
+        let _ = fs::remove_file(&path);
+        assert!(Wallet::load(&path).unwrap().get_addresses().is_empty());
+
+        let mut wallet = Wallet::new();
+        let first = wallet.generate_key();
+        let second = wallet.generate_key();
+        assert_ne!(first, second);
+        wallet.save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let loaded = Wallet::load(&path).unwrap();
+        assert_eq!(loaded.get_addresses(), wallet.get_addresses());
+        let transaction = loaded.sign_transfer(&first, "bob", 10, 2, 3).unwrap();
+        assert_eq!(transaction.get_sender(), first);
+        assert_eq!((transaction.get_amount(), transaction.get_nonce()), (10, 3));
+        assert!(transaction.verify_signature());
+        assert_eq!(
+            loaded.sign_transfer("bob", &first, 1, 1, 0).unwrap_err(),
+            WalletError::UnknownAddress("bob".to_string())
+        );
+    }
+
+    #[test]
+    fn test_damaged_wallets_are_refused() {
+        let path = wallet_path("wallet-damaged");
+        let mut wallet = Wallet::new();
+        let address = wallet.generate_key();
+        wallet.save(&path).unwrap();
+
+        let data = fs::read_to_string(&path).unwrap();
+        let other = Wallet::new().generate_key();
+        fs::write(&path, data.replace(&address, &other)).unwrap();
+        assert!(matches!(Wallet::load(&path), Err(WalletError::Format(_))));
+        fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(Wallet::load(&path), Err(WalletError::Format(_))));
+    }
+}
@@ -0,0 +1,299 @@
+use my_first_blockchain::blockchain::{Block, Blockchain};
+use my_first_blockchain::events::EventBus;
+use my_first_blockchain::gossip::SharedChain;
+use my_first_blockchain::mempool::{Mempool, MempoolConfig};
+use my_first_blockchain::rpc::{TransactionView, INVALID_PARAMS, METHOD_NOT_FOUND};
+use my_first_blockchain::ws::{HeadView, ReorgView, WsServer};
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use my_first_blockchain::{
+        chain_spec::ChainSpec,
+        transaction::{Transaction, COINBASE_SIGNATURE},
+        utils::{generate_key_pair, public_key_to_address, sign_transaction_with_fee},
+    };
+    use secp256k1::SecretKey;
+    use serde_json::{json, Value};
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::{Message, WebSocket};
+
+    use super::*;
+
+    type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    fn mine(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let mut blockchain = blockchain.clone();
+        let arc_blockchain = Arc::new(Mutex::new(blockchain.clone()));
+        blockchain.mine_block(Arc::new(transactions), Arc::clone(&arc_blockchain));
+        let blockchain = arc_blockchain.lock().unwrap();
+        blockchain.get_chain().last().unwrap().clone()
+    }
+
+    fn funded_chain(address: &str) -> Blockchain {
+        let spec = ChainSpec {
+            difficulty: 2,
+            block_reward: 1_000,
+            ..ChainSpec::default()
+        };
+        let blockchain = Blockchain::with_spec(spec);
+        let mint = Transaction::new(
+            "coinbase".to_string(),
+            address.to_string(),
+            1_000,
+            0,
+            "coinbase".into(),
+        );
+        let block = mine(&blockchain, vec![mint]);
+        let mut blockchain = blockchain;
+        assert!(blockchain.add_block(block));
+        blockchain
+    }
+
+    // `count` transfers from `sender`, with nonces from 0 on.
+    fn transfers(key: SecretKey, sender: &str, count: u64) -> Vec<Transaction> {
+        (0..count)
+            .map(|nonce| {
+                let receiver = format!("receiver-{}", nonce);
+                Transaction::new_with_fee(
+                    sender.to_string(),
+                    receiver.clone(),
+                    1,
+                    1,
+                    nonce,
+                    sign_transaction_with_fee(key, sender.to_string(), receiver, 1, 1, nonce),
+                )
+            })
+            .collect()
+    }
+
+    // Reward paid to a made up miner, unique per `nonce`.
+    fn coinbase(nonce: u64) -> Transaction {
+        Transaction::new(
+            "coinbase".to_string(),
+            "miner".to_string(),
+            50,
+            nonce,
+            COINBASE_SIGNATURE.to_vec(),
+        )
+    }
+
+    // Serves events of `blockchain` and a fresh mempool over WebSockets.
+    fn serve(blockchain: &Blockchain) -> (WsServer, SharedChain) {
+        let bus = EventBus::default();
+        let mut blockchain = blockchain.clone();
+        blockchain.set_event_bus(bus.clone());
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        mempool.set_event_bus(bus.clone());
+        let server = WsServer::start("127.0.0.1:0".parse().unwrap(), bus).unwrap();
+        (server, SharedChain::new(blockchain, mempool))
+    }
+
+    // Mines `transactions` on top of the shared chain's tip.
+    fn extend(chain: &SharedChain, transactions: Vec<Transaction>) -> Block {
+        let mut blockchain = chain.blockchain.lock().unwrap();
+        let timestamp = blockchain.get_chain().last().unwrap().get_timestamp() + 1;
+        let block = blockchain.mine_next_block(transactions, timestamp).unwrap();
+        blockchain.try_add_block(block.clone()).unwrap();
+        block
+    }
+
+    fn pool(chain: &SharedChain, transaction: &Transaction) {
+        let blockchain = chain.blockchain.lock().unwrap();
+        let mut mempool = chain.mempool.lock().unwrap();
+        mempool
+            .add_transaction(transaction.clone(), blockchain.get_state())
+            .unwrap();
+    }
+
+    fn connect(addr: SocketAddr) -> Socket {
+        let (socket, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        set_timeout(&socket, Duration::from_secs(10));
+        socket
+    }
+
+    fn set_timeout(socket: &Socket, timeout: Duration) {
+        match socket.get_ref() {
+            MaybeTlsStream::Plain(stream) => stream.set_read_timeout(Some(timeout)).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn receive(socket: &mut Socket) -> Value {
+        loop {
+            if let Message::Text(text) = socket.read().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    fn request(socket: &mut Socket, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        socket.send(Message::Text(request.to_string())).unwrap();
+        receive(socket)
+    }
+
+    fn subscribe(socket: &mut Socket, params: Value) -> String {
+        let response = request(socket, "subscribe", params);
+        response["result"].as_str().unwrap().to_string()
+    }
+
+    // The next notification, as its subscription ID and payload.
+    fn notification(socket: &mut Socket) -> (String, Value) {
+        let message = receive(socket);
+        assert_eq!(message["method"], "subscription");
+        let params = &message["params"];
+        (
+            params["subscription"].as_str().unwrap().to_string(),
+            params["result"].clone(),
+        )
+    }
+
+    fn assert_quiet(socket: &mut Socket) {
+        set_timeout(socket, Duration::from_millis(300));
+        assert!(socket.read().is_err(), "unexpected message");
+    }
+
+    #[test]
+    fn test_new_heads_and_pending_transactions_are_pushed() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let (server, chain) = serve(&funded_chain(&sender));
+        let mut socket = connect(server.local_addr());
+        let heads = subscribe(&mut socket, json!(["newHeads"]));
+        let pending = subscribe(&mut socket, json!(["pendingTransactions"]));
+        assert_ne!(heads, pending);
+
+        let transaction = transfers(key, &sender, 1).remove(0);
+        pool(&chain, &transaction);
+        let (id, result) = notification(&mut socket);
+        assert_eq!(id, pending);
+        let view: TransactionView = serde_json::from_value(result).unwrap();
+        assert_eq!(view.id, transaction.id());
+        assert_eq!(view.block_hash, None);
+
+        let block = extend(&chain, vec![coinbase(2), transaction]);
+        let (id, result) = notification(&mut socket);
+        assert_eq!(id, heads);
+        let head: HeadView = serde_json::from_value(result).unwrap();
+        assert_eq!(head.hash, block.get_hash());
+        assert_eq!((head.height, head.transaction_count), (2, 2));
+        assert_quiet(&mut socket);
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_address_subscriptions_only_see_their_transactions() {
+        let (key, pubkey) = generate_key_pair();
+        let sender = public_key_to_address(&pubkey);
+        let (server, chain) = serve(&funded_chain(&sender));
+        let mut socket = connect(server.local_addr());
+        let watched = subscribe(&mut socket, json!(["addressTransactions", ["receiver-1"]]));
+
+        let transactions = transfers(key, &sender, 3);
+        for transaction in &transactions {
+            pool(&chain, transaction);
+        }
+        let (id, result) = notification(&mut socket);
+        assert_eq!(id, watched);
+        let view: TransactionView = serde_json::from_value(result).unwrap();
+        assert_eq!(view.id, transactions[1].id());
+        assert_eq!(view.block_hash, None);
+
+        // Mined, it comes again with its place in the chain.
+        let mut body = vec![coinbase(2)];
+        body.extend(transactions.iter().cloned());
+        let block = extend(&chain, body);
+        let (_, result) = notification(&mut socket);
+        let view: TransactionView = serde_json::from_value(result).unwrap();
+        assert_eq!(view.id, transactions[1].id());
+        assert_eq!(view.block_hash.as_deref(), Some(block.get_hash()));
+        assert_eq!((view.height, view.position), (Some(2), Some(2)));
+        assert_quiet(&mut socket);
+    }
+
+    #[test]
+    fn test_reorgs_are_pushed_with_the_new_heads() {
+        let (_, pubkey) = generate_key_pair();
+        let base = funded_chain(&public_key_to_address(&pubkey));
+        let (server, chain) = serve(&base);
+        let mut socket = connect(server.local_addr());
+        let reorgs = subscribe(&mut socket, json!(["reorgs"]));
+        let heads = subscribe(&mut socket, json!(["newHeads"]));
+
+        let abandoned = extend(&chain, vec![coinbase(2)]);
+        assert_eq!(notification(&mut socket).0, heads);
+
+        // A longer branch off height 1, built where nobody is listening.
+        let mut fork = base.clone();
+        let timestamp = abandoned.get_timestamp() + 1;
+        let first = fork.mine_next_block(vec![coinbase(12)], timestamp).unwrap();
+        fork.try_add_block(first.clone()).unwrap();
+        let second = fork
+            .mine_next_block(vec![coinbase(13)], timestamp + 1)
+            .unwrap();
+        chain
+            .blockchain
+            .lock()
+            .unwrap()
+            .reorganize(1, vec![first.clone(), second.clone()])
+            .unwrap();
+
+        let (id, result) = notification(&mut socket);
+        assert_eq!(id, reorgs);
+        let reorg: ReorgView = serde_json::from_value(result).unwrap();
+        assert_eq!(
+            reorg,
+            ReorgView {
+                fork_height: 1,
+                disconnected: vec![abandoned.get_hash().to_string()],
+                connected: vec![first.get_hash().to_string(), second.get_hash().to_string()],
+                tip_height: 3,
+            }
+        );
+        for block in [first, second] {
+            let (id, result) = notification(&mut socket);
+            assert_eq!(id, heads);
+            assert_eq!(result["hash"], block.get_hash());
+        }
+        assert_quiet(&mut socket);
+    }
+
+    #[test]
+    fn test_unsubscribe_and_bad_requests() {
+        let (_, pubkey) = generate_key_pair();
+        let (server, chain) = serve(&funded_chain(&public_key_to_address(&pubkey)));
+        let mut socket = connect(server.local_addr());
+
+        let response = request(&mut socket, "subscribe", json!(["everything"]));
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        let response = request(&mut socket, "subscribe", json!(["addressTransactions", []]));
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        let response = request(&mut socket, "poll", json!([]));
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let dropped = subscribe(&mut socket, json!(["newHeads"]));
+        let kept = subscribe(&mut socket, json!(["newHeads"]));
+        assert_eq!(
+            request(&mut socket, "unsubscribe", json!([dropped])),
+            json!({ "jsonrpc": "2.0", "result": true, "id": 1 })
+        );
+        assert_eq!(
+            request(&mut socket, "unsubscribe", json!([dropped]))["result"],
+            false
+        );
+        extend(&chain, vec![coinbase(2)]);
+        assert_eq!(notification(&mut socket).0, kept);
+        assert_quiet(&mut socket);
+
+        // Other clients get their own subscriptions.
+        let mut other = connect(server.local_addr());
+        extend(&chain, vec![coinbase(3)]);
+        assert_eq!(notification(&mut socket).0, kept);
+        assert_quiet(&mut other);
+    }
+}